@@ -1,13 +1,35 @@
+use std::sync::Arc;
+
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::commands::DragoonCommand;
+use crate::jobs::JobManager;
+use crate::metrics::Metrics;
+use crate::progress::ProgressRegistry;
+use crate::send_strategy::StrategyRegistry;
 
 pub(crate) struct AppState {
     pub cmd_sender: UnboundedSender<DragoonCommand>,
+    pub metrics: Arc<Metrics>,
+    pub send_strategies: Arc<StrategyRegistry>,
+    pub progress: Arc<ProgressRegistry>,
+    pub jobs: Arc<JobManager>,
 }
 
 impl AppState {
-    pub fn new(cmd_sender: UnboundedSender<DragoonCommand>) -> Self {
-        AppState { cmd_sender }
+    pub fn new(
+        cmd_sender: UnboundedSender<DragoonCommand>,
+        metrics: Arc<Metrics>,
+        send_strategies: Arc<StrategyRegistry>,
+        progress: Arc<ProgressRegistry>,
+        jobs: Arc<JobManager>,
+    ) -> Self {
+        AppState {
+            cmd_sender,
+            metrics,
+            send_strategies,
+            progress,
+            jobs,
+        }
     }
 }
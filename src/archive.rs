@@ -0,0 +1,79 @@
+//! Archiving of the directories, so a whole directory tree can be encoded and retrieved as a single file.
+//!
+//! An encode-file with `archive` packs the directory into a tar archive, with the paths relative to the directory,
+//! and encodes the archive like the content of a file: the hash of the file is the one of the archive. The entries
+//! are written in the order of their names and without their owner and modification time, so archiving the same
+//! tree twice gives the same hash. The symbolic links are archived as links, not followed.
+//!
+//! A get-file with `unpack` restores the tree from the archive into a directory named after the output, instead of
+//! writing the archive. The entries that would land outside of the directory are skipped.
+
+use anyhow::{format_err, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder, HeaderMode};
+
+use crate::error::DragoonError::FileExists;
+
+/// The tar archive of the directory `dir`
+pub(crate) async fn pack(dir: PathBuf) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || {
+        if !dir.is_dir() {
+            return Err(format_err!("{:?} is not a directory", dir));
+        }
+        let mut builder = Builder::new(vec![]);
+        builder.mode(HeaderMode::Deterministic);
+        builder.follow_symlinks(false);
+        append_tree(&mut builder, &dir, Path::new(""))?;
+        Ok(builder.into_inner()?)
+    })
+    .await?
+}
+
+/// Append the entries of `root/rel` to the archive, then the ones of its sub-directories
+fn append_tree(builder: &mut Builder<Vec<u8>>, root: &Path, rel: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(root.join(rel))?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let entry_rel = rel.join(entry.file_name());
+        builder.append_path_with_name(entry.path(), &entry_rel)?;
+        // the file type of the entry itself, a link to a directory is not followed
+        if entry.file_type()?.is_dir() {
+            append_tree(builder, root, &entry_rel)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unpack the tar archive `bytes` into the directory `output_path`.
+/// The tree is unpacked in a temporary directory first, so it is never seen half written, then moved in place,
+/// replacing what is at `output_path` with `overwrite`.
+pub(crate) async fn unpack(bytes: Vec<u8>, output_path: PathBuf, overwrite: bool) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let file_name = output_path
+            .file_name()
+            .ok_or_else(|| format_err!("{:?} can't be the name of a directory", output_path))?
+            .to_string_lossy()
+            .to_string();
+        let tmp_path = output_path.with_file_name(format!(".{}.tmp", file_name));
+        if tmp_path.exists() {
+            fs::remove_dir_all(&tmp_path)?;
+        }
+        if let Err(e) = Archive::new(bytes.as_slice()).unpack(&tmp_path) {
+            let _ = fs::remove_dir_all(&tmp_path);
+            return Err(format_err!("Could not unpack the archive: {}", e));
+        }
+        match fs::symlink_metadata(&output_path) {
+            Ok(_) if !overwrite => {
+                let _ = fs::remove_dir_all(&tmp_path);
+                return Err(FileExists(format!("{:?}", output_path)).into());
+            }
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&output_path)?,
+            Ok(_) => fs::remove_file(&output_path)?,
+            Err(_) => {}
+        }
+        fs::rename(&tmp_path, &output_path)?;
+        Ok(())
+    })
+    .await?
+}
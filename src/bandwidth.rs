@@ -0,0 +1,187 @@
+//! The bytes exchanged with each peer, counted by wrapping the muxer of every connection of the swarm.
+//!
+//! The bytes are counted on the substreams of the connections, so they are the bytes of all the protocols of the
+//! node, without the framing of the muxer and the encryption of the transport. They are counted by transport
+//! protocol, `tcp` or `quic`, and kept in memory until the node stops, across the identity rotations.
+
+use libp2p::core::muxing::{
+    StreamMuxer, StreamMuxerBox, StreamMuxerEvent, StreamMuxerExt, SubstreamBox,
+};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use utoipa::ToSchema;
+
+/// The bytes exchanged with a peer over a transport protocol, shared by all the connections to the peer
+#[derive(Debug, Default)]
+struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Bandwidth {
+    traffic: Mutex<HashMap<(PeerId, &'static str), Arc<Traffic>>>,
+}
+
+/// The bytes sent and received, in total and by transport protocol
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct BandwidthUsage {
+    pub(crate) sent: u64,
+    pub(crate) received: u64,
+    pub(crate) protocols: BTreeMap<String, ProtocolUsage>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct ProtocolUsage {
+    pub(crate) sent: u64,
+    pub(crate) received: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct BandwidthReport {
+    /// All the bytes exchanged by the node
+    pub(crate) total: BandwidthUsage,
+    /// The bytes exchanged with each peer, by base 58 peer id
+    pub(crate) peers: BTreeMap<String, BandwidthUsage>,
+}
+
+impl BandwidthUsage {
+    fn add(&mut self, protocol: &str, sent: u64, received: u64) {
+        self.sent += sent;
+        self.received += received;
+        let usage = self.protocols.entry(protocol.to_string()).or_default();
+        usage.sent += sent;
+        usage.received += received;
+    }
+}
+
+impl Bandwidth {
+    /// Count the bytes of the substreams of `muxer`, a connection to `peer_id` over `protocol`
+    pub(crate) fn wrap(
+        &self,
+        peer_id: PeerId,
+        protocol: &'static str,
+        muxer: StreamMuxerBox,
+    ) -> StreamMuxerBox {
+        let traffic = self
+            .traffic
+            .lock()
+            .unwrap()
+            .entry((peer_id, protocol))
+            .or_default()
+            .clone();
+        StreamMuxerBox::new(CountedMuxer {
+            inner: muxer,
+            traffic,
+        })
+    }
+
+    pub(crate) fn report(&self) -> BandwidthReport {
+        let mut report = BandwidthReport::default();
+        for ((peer_id, protocol), traffic) in self.traffic.lock().unwrap().iter() {
+            let sent = traffic.sent.load(Ordering::Relaxed);
+            let received = traffic.received.load(Ordering::Relaxed);
+            report.total.add(protocol, sent, received);
+            report
+                .peers
+                .entry(peer_id.to_base58())
+                .or_default()
+                .add(protocol, sent, received);
+        }
+        report
+    }
+}
+
+struct CountedMuxer {
+    inner: StreamMuxerBox,
+    traffic: Arc<Traffic>,
+}
+
+impl StreamMuxer for CountedMuxer {
+    type Substream = CountedSubstream;
+    type Error = io::Error;
+
+    fn poll_inbound(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let this = self.get_mut();
+        let inner = ready!(this.inner.poll_inbound_unpin(cx))?;
+        Poll::Ready(Ok(CountedSubstream {
+            inner,
+            traffic: this.traffic.clone(),
+        }))
+    }
+
+    fn poll_outbound(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let this = self.get_mut();
+        let inner = ready!(this.inner.poll_outbound_unpin(cx))?;
+        Poll::Ready(Ok(CountedSubstream {
+            inner,
+            traffic: this.traffic.clone(),
+        }))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().inner.poll_close_unpin(cx)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        self.get_mut().inner.poll_unpin(cx)
+    }
+}
+
+struct CountedSubstream {
+    inner: SubstreamBox,
+    traffic: Arc<Traffic>,
+}
+
+impl futures::AsyncRead for CountedSubstream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let read = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.traffic
+            .received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl futures::AsyncWrite for CountedSubstream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.traffic
+            .sent
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
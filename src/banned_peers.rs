@@ -0,0 +1,87 @@
+//! The peers banned from the node, kept in `banned_peers.json` so they stay banned after a restart.
+//!
+//! The connections of a banned peer are closed and refused by the `blocked_peers` behaviour of the swarm, it is
+//! left out of the send strategies and its block and info requests are not answered.
+
+use anyhow::Result;
+use libp2p::PeerId;
+use std::collections::BTreeSet;
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, warn};
+
+const BANNED_PEERS_FILE_NAME: &str = "banned_peers.json";
+
+#[derive(Debug, Default)]
+pub(crate) struct BannedPeers {
+    peers: BTreeSet<PeerId>,
+}
+
+impl BannedPeers {
+    /// Read the peers banned before the node stopped, none if there are no such peers or they cannot be read
+    pub(crate) fn load(file_dir: &Path) -> Self {
+        let path = file_dir.join(BANNED_PEERS_FILE_NAME);
+        let saved: BTreeSet<String> = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Could not parse the banned peers of {:?}: {}", path, e);
+                BTreeSet::new()
+            }),
+            Err(_) => BTreeSet::new(),
+        };
+        let peers: BTreeSet<PeerId> = saved
+            .iter()
+            .filter_map(|peer_id_base_58| {
+                let bytes = bs58::decode(peer_id_base_58).into_vec().ok()?;
+                PeerId::from_bytes(&bytes).ok()
+            })
+            .collect();
+        if !peers.is_empty() {
+            info!("{} peers were banned before the node stopped", peers.len());
+        }
+        BannedPeers { peers }
+    }
+
+    pub(crate) fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains(peer_id)
+    }
+
+    pub(crate) fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.iter()
+    }
+
+    /// False if the peer was already banned
+    pub(crate) async fn insert(&mut self, file_dir: &Path, peer_id: PeerId) -> bool {
+        let inserted = self.peers.insert(peer_id);
+        if inserted {
+            self.save(file_dir).await;
+        }
+        inserted
+    }
+
+    /// False if the peer was not banned
+    pub(crate) async fn remove(&mut self, file_dir: &Path, peer_id: &PeerId) -> bool {
+        let removed = self.peers.remove(peer_id);
+        if removed {
+            self.save(file_dir).await;
+        }
+        removed
+    }
+
+    /// Failing to save the peers only means they are not banned anymore after a restart
+    async fn save(&self, file_dir: &Path) {
+        if let Err(e) = self.write(file_dir).await {
+            warn!("Could not save the banned peers in {:?}: {}", file_dir, e);
+        }
+    }
+
+    async fn write(&self, file_dir: &Path) -> Result<()> {
+        let path = file_dir.join(BANNED_PEERS_FILE_NAME);
+        let saved: BTreeSet<String> = self.peers.iter().map(PeerId::to_base58).collect();
+        // written next to the file then renamed, so a crash never leaves a partial list
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&saved)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+//! The format of the blocks written on disk and sent to other peers.
+//!
+//! A block is framed with a header giving the version of the format and the curve the block was made over:
+//!
+//! ```text
+//! "dragoonfly-block" | version (1 byte) | curve id (1 byte) | serialized block
+//! ```
+//!
+//! so a block of another curve, or of a format the node does not know, is rejected with a clear error instead of
+//! being read as garbage. A block is named after the hash of the serialized block alone, so the framed block keeps
+//! the name it had before this format, and the blocks written before it, without header, are still read.
+
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
+use komodo::semi_avid::Block;
+
+use crate::curve::Curve;
+use crate::dragoon_swarm::hash_file;
+
+const MAGIC: &[u8] = b"dragoonfly-block";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 1;
+
+/// The curve the block was made over and the serialized block, `None` as curve for the blocks without header
+fn read_header(ser_block: &[u8]) -> Result<(Option<Curve>, &[u8])> {
+    let Some(framed) = ser_block.strip_prefix(MAGIC) else {
+        return Ok((None, ser_block));
+    };
+    if ser_block.len() < HEADER_SIZE {
+        return Err(format_err!("The header of the block is truncated"));
+    }
+    if framed[0] != VERSION {
+        return Err(format_err!(
+            "The block is in the version {} of the format, only the version {} is supported",
+            framed[0],
+            VERSION
+        ));
+    }
+    Ok((Some(Curve::from_id(framed[1])?), &ser_block[HEADER_SIZE..]))
+}
+
+/// Serialize the block with its header
+pub(crate) fn serialize<F, G>(block: &Block<F, G>) -> Result<Vec<u8>>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let curve = Curve::of::<F>().ok_or(format_err!("The block is over an unknown curve"))?;
+    let mut ser_block = Vec::with_capacity(HEADER_SIZE + block.serialized_size(Compress::Yes));
+    ser_block.extend_from_slice(MAGIC);
+    ser_block.push(VERSION);
+    ser_block.push(curve.id());
+    block.serialize_with_mode(&mut ser_block, Compress::Yes)?;
+    Ok(ser_block)
+}
+
+/// Deserialize a block, with or without header, checking it was made over the curve of `F`
+pub(crate) fn deserialize<F, G>(ser_block: &[u8], validate: Validate) -> Result<Block<F, G>>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let (block_curve, payload) = read_header(ser_block)?;
+    if let (Some(block_curve), Some(curve)) = (block_curve, Curve::of::<F>()) {
+        if block_curve != curve {
+            return Err(format_err!(
+                "The block was made over the curve {}, not {}",
+                block_curve,
+                curve
+            ));
+        }
+    }
+    Ok(Block::deserialize_with_mode(
+        payload,
+        Compress::Yes,
+        validate,
+    )?)
+}
+
+/// The name of a serialized block, the hash of the block without its header
+pub(crate) fn block_hash(ser_block: &[u8]) -> Result<String> {
+    let (_, payload) = read_header(ser_block)?;
+    Ok(hash_file(payload))
+}
+
+/// The SHA-256 of the commitments of the block, which all the blocks of a file share, recoded or not. The
+/// commitments are what a block serializes after its shard.
+pub(crate) fn commitment_digest<F, G>(block: &Block<F, G>) -> Result<String>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let mut ser_block = Vec::with_capacity(block.serialized_size(Compress::Yes));
+    block.serialize_with_mode(&mut ser_block, Compress::Yes)?;
+    let commitments = &ser_block[block.shard.serialized_size(Compress::Yes)..];
+    Ok(hash_file(commitments))
+}
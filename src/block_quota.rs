@@ -0,0 +1,275 @@
+//! Caps on the number of blocks stored by the node.
+//!
+//! Millions of tiny blocks can exhaust the inodes of a disk long before its space, so on top of the
+//! storage space, the node can limit how many blocks it stores in total and for each file.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs as sfs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::error::DragoonError;
+use crate::metrics::Metrics;
+use crate::mirror::{Manifest, Mirror, MirrorOp};
+
+/// The caps on the number of blocks, `None` means no cap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub(crate) struct BlockLimits {
+    pub(crate) max_blocks: Option<u64>,
+    pub(crate) max_blocks_per_file: Option<u64>,
+}
+
+/// Which of the caps would be exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BlockLimitScope {
+    Total,
+    PerFile,
+}
+
+impl std::fmt::Display for BlockLimitScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockLimitScope::Total => write!(f, "blocks in total"),
+            BlockLimitScope::PerFile => write!(f, "blocks per file"),
+        }
+    }
+}
+
+/// The caps and the current counts, as shown by node-info
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct BlockCountInfo {
+    pub(crate) max_blocks: Option<u64>,
+    pub(crate) max_blocks_per_file: Option<u64>,
+    pub(crate) blocks: u64,
+    pub(crate) files: u64,
+}
+
+/// The blocks stored by the node, by file hash
+#[derive(Debug)]
+pub(crate) struct BlockQuota {
+    limits: BlockLimits,
+    blocks: Mutex<HashMap<String, HashSet<String>>>,
+    mirror: Arc<Mirror>,
+    metrics: Arc<Metrics>,
+}
+
+impl BlockQuota {
+    /// Count the blocks already stored in `file_dir`, as FILE_DIR/FILE_HASH/blocks/BLOCK_HASH
+    pub(crate) fn new(
+        limits: BlockLimits,
+        file_dir: &Path,
+        mirror: Arc<Mirror>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let blocks = sfs::read_dir(file_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|file| {
+                let file_hash = file.file_name().to_string_lossy().to_string();
+                (file_hash, Self::scan_block_dir(&file.path().join("blocks")))
+            })
+            .filter(|(_, blocks)| !blocks.is_empty())
+            .collect();
+        let quota = BlockQuota {
+            limits,
+            blocks: Mutex::new(blocks),
+            mirror,
+            metrics,
+        };
+        quota.update_metrics(&quota.blocks.lock().unwrap());
+        quota
+    }
+
+    fn scan_block_dir(block_dir: &Path) -> HashSet<String> {
+        sfs::read_dir(block_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|block| block.file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn update_metrics(&self, blocks: &HashMap<String, HashSet<String>>) {
+        let total: usize = blocks.values().map(HashSet::len).sum();
+        self.metrics
+            .stored_blocks
+            .store(total as u64, Ordering::Relaxed);
+        self.metrics
+            .stored_files
+            .store(blocks.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Check that the file can have `file_blocks` blocks when the node stores `total_blocks` blocks
+    fn check(&self, file_hash: &str, file_blocks: u64, total_blocks: u64) -> Result<()> {
+        let exceeded = [
+            (
+                BlockLimitScope::PerFile,
+                self.limits.max_blocks_per_file,
+                file_blocks,
+            ),
+            (BlockLimitScope::Total, self.limits.max_blocks, total_blocks),
+        ]
+        .into_iter()
+        .find_map(|(scope, limit, count)| match limit {
+            Some(limit) if count > limit => Some((scope, limit, count)),
+            _ => None,
+        });
+        match exceeded {
+            Some((scope, limit, count)) => {
+                warn!(
+                    "Refusing to store the blocks of {}: {} would go over the limit of {} {}",
+                    file_hash, count, limit, scope
+                );
+                Metrics::incr(&self.metrics.too_many_blocks);
+                Err(DragoonError::TooManyBlocks {
+                    file_hash: file_hash.to_string(),
+                    scope,
+                    limit,
+                    requested: count,
+                }
+                .into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Reserve the place of a single block, e.g. one received from another peer.
+    ///
+    /// The reservation is given back when dropped, unless it is committed once the block is written.
+    pub(crate) fn reserve(
+        self: &Arc<Self>,
+        file_hash: &str,
+        block_hash: &str,
+    ) -> Result<BlockReservation> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let file_blocks = blocks.get(file_hash).map_or(0, HashSet::len) as u64;
+        if blocks
+            .get(file_hash)
+            .is_some_and(|file_blocks| file_blocks.contains(block_hash))
+        {
+            // the block replaces a copy we already count
+            return Ok(BlockReservation {
+                quota: None,
+                file_hash: file_hash.to_string(),
+                block_hash: block_hash.to_string(),
+            });
+        }
+        let total: u64 = blocks.values().map(|b| b.len() as u64).sum();
+        self.check(file_hash, file_blocks + 1, total + 1)?;
+        blocks
+            .entry(file_hash.to_string())
+            .or_default()
+            .insert(block_hash.to_string());
+        self.update_metrics(&blocks);
+        Ok(BlockReservation {
+            quota: Some(self.clone()),
+            file_hash: file_hash.to_string(),
+            block_hash: block_hash.to_string(),
+        })
+    }
+
+    fn release(&self, file_hash: &str, block_hash: &str) {
+        let mut blocks = self.blocks.lock().unwrap();
+        if let Some(file_blocks) = blocks.get_mut(file_hash) {
+            file_blocks.remove(block_hash);
+            if file_blocks.is_empty() {
+                blocks.remove(file_hash);
+            }
+        }
+        self.update_metrics(&blocks);
+    }
+
+    /// Check that encoding a file into `new_blocks` blocks stays under the caps.
+    /// With `replace`, the blocks the file already has are removed first.
+    pub(crate) fn check_encode(
+        &self,
+        file_hash: &str,
+        new_blocks: usize,
+        replace: bool,
+    ) -> Result<()> {
+        let blocks = self.blocks.lock().unwrap();
+        let current = blocks.get(file_hash).map_or(0, HashSet::len) as u64;
+        let total: u64 = blocks.values().map(|b| b.len() as u64).sum();
+        let (file_blocks, total) = if replace {
+            (new_blocks as u64, total - current + new_blocks as u64)
+        } else {
+            (current + new_blocks as u64, total + new_blocks as u64)
+        };
+        self.check(file_hash, file_blocks, total)
+    }
+
+    /// Count again the blocks of a file from its block directory, e.g. after encoding it
+    pub(crate) fn rescan_file(&self, file_hash: &str, block_dir: &Path) {
+        let file_blocks = Self::scan_block_dir(block_dir);
+        debug!("File {} now has {} blocks", file_hash, file_blocks.len());
+        self.mirror.record(MirrorOp::FileBlocks {
+            file_hash: file_hash.to_string(),
+            block_hashes: file_blocks.iter().cloned().collect(),
+        });
+        let mut blocks = self.blocks.lock().unwrap();
+        if file_blocks.is_empty() {
+            blocks.remove(file_hash);
+        } else {
+            blocks.insert(file_hash.to_string(), file_blocks);
+        }
+        self.update_metrics(&blocks);
+    }
+
+    /// The blocks stored for each file
+    pub(crate) fn manifest(&self) -> Manifest {
+        self.blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(file_hash, block_hashes)| {
+                (file_hash.clone(), block_hashes.iter().cloned().collect())
+            })
+            .collect()
+    }
+
+    pub(crate) fn info(&self) -> BlockCountInfo {
+        let blocks = self.blocks.lock().unwrap();
+        BlockCountInfo {
+            max_blocks: self.limits.max_blocks,
+            max_blocks_per_file: self.limits.max_blocks_per_file,
+            blocks: blocks.values().map(|b| b.len() as u64).sum(),
+            files: blocks.len() as u64,
+        }
+    }
+}
+
+/// The place of a block that is about to be written
+#[derive(Debug)]
+pub(crate) struct BlockReservation {
+    /// `None` when the block was already counted, so there is nothing to give back
+    quota: Option<Arc<BlockQuota>>,
+    file_hash: String,
+    block_hash: String,
+}
+
+impl BlockReservation {
+    /// Keep the block counted, once it has been written
+    pub(crate) fn commit(mut self) {
+        if let Some(quota) = self.quota.take() {
+            quota.mirror.record(MirrorOp::BlockStored {
+                file_hash: self.file_hash.clone(),
+                block_hash: self.block_hash.clone(),
+            });
+        }
+    }
+}
+
+impl Drop for BlockReservation {
+    fn drop(&mut self) {
+        if let Some(quota) = self.quota.take() {
+            quota.release(&self.file_hash, &self.block_hash);
+        }
+    }
+}
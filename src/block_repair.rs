@@ -0,0 +1,215 @@
+//! Repair of the files encoded by the node.
+//!
+//! Every `--repair-interval`, the node asks the providers of each file it encoded and provides which blocks
+//! of the file they still hold. When fewer than `--repair-threshold` times `k` distinct blocks are left on the
+//! other peers, new blocks are recoded from the local ones and sent to the network with `SendBlockList`.
+
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_serialize::Validate;
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use crate::block_store::BlockStore;
+use crate::commands::{DragoonCommand, Sender};
+use crate::dragoon_swarm::{get_block_dir, get_file_dir};
+use crate::file_locks::FileLocks;
+use crate::lifecycle::{self, LifecycleStage};
+use crate::metrics::Metrics;
+use crate::send_strategy_impl::{StrategyName, ROUND_ROBIN};
+
+const REPAIR_STEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) struct RepairConfig {
+    /// Time between two checks of the files encoded by the node
+    pub(crate) interval: Duration,
+    /// Number of distinct blocks to keep on the other peers, as a multiple of the `k` of the file
+    pub(crate) threshold: f64,
+}
+
+/// Parse a `--repair-threshold`, below `1` a file could not be decoded anymore before being repaired
+pub(crate) fn parse_threshold(s: &str) -> Result<f64, String> {
+    let threshold: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid threshold {:?}: {}", s, e))?;
+    if threshold < 1.0 || !threshold.is_finite() {
+        return Err(format!("the threshold must be at least 1, got {}", s));
+    }
+    Ok(threshold)
+}
+
+/// Send a command to the swarm and wait for its answer, at most [`REPAIR_STEP_TIMEOUT`]
+pub(crate) async fn ask<T, E>(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    command: impl FnOnce(Sender<T, E>) -> DragoonCommand,
+) -> Result<T>
+where
+    anyhow::Error: From<E>,
+{
+    let (sender, receiver) = oneshot::channel();
+    cmd_sender
+        .send(command(Sender::SenderOneS(sender)))
+        .map_err(|_| format_err!("Could not send the command to the swarm"))?;
+    let res = time::timeout(REPAIR_STEP_TIMEOUT, receiver)
+        .await
+        .map_err(|_| format_err!("No answer after {:?}", REPAIR_STEP_TIMEOUT))?
+        .map_err(|_| format_err!("The swarm dropped the command"))?;
+    Ok(res?)
+}
+
+/// The provided keys that are files encoded by the node
+async fn originated_files(file_dir: &PathBuf, provided_keys: Vec<String>) -> Vec<String> {
+    let mut files = vec![];
+    for file_hash in provided_keys {
+        let hash_dir = get_file_dir(file_dir, file_hash.clone());
+        let Ok(timeline) = lifecycle::timeline(&hash_dir, file_hash.clone()).await else {
+            continue;
+        };
+        if timeline
+            .events
+            .iter()
+            .any(|event| event.stage == LifecycleStage::Encoded)
+        {
+            files.push(file_hash);
+        }
+    }
+    files
+}
+
+/// Check the redundancy of each file encoded by the node and send new blocks of the ones below the threshold
+pub(crate) async fn repair_files<F, G>(
+    cmd_sender: UnboundedSender<DragoonCommand>,
+    file_dir: PathBuf,
+    local_peer_id: PeerId,
+    provided_keys: Vec<String>,
+    threshold: f64,
+    file_locks: Arc<FileLocks>,
+    block_store: Arc<BlockStore>,
+    metrics: Arc<Metrics>,
+) where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    for file_hash in originated_files(&file_dir, provided_keys).await {
+        match repair_file::<F, G>(
+            &cmd_sender,
+            &file_dir,
+            local_peer_id,
+            &file_hash,
+            threshold,
+            &file_locks,
+            &block_store,
+        )
+        .await
+        {
+            Ok(0) => {}
+            Ok(sent) => {
+                info!("Repaired {} with {} new blocks", file_hash, sent);
+                metrics
+                    .repaired_blocks
+                    .fetch_add(sent as u64, Ordering::Relaxed);
+            }
+            Err(e) => warn!("Could not repair {}: {}", file_hash, e),
+        }
+    }
+}
+
+/// Returns the number of new blocks sent to the network
+async fn repair_file<F, G>(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    file_dir: &PathBuf,
+    local_peer_id: PeerId,
+    file_hash: &str,
+    threshold: f64,
+    file_locks: &FileLocks,
+    block_store: &BlockStore,
+) -> Result<usize>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let k = {
+        let _read_guard = file_locks.read(file_hash).await;
+        let block_dir = get_block_dir(file_dir, file_hash.to_string());
+        let mut entries = tokio::fs::read_dir(&block_dir).await?;
+        let Some(entry) = entries.next_entry().await? else {
+            return Err(format_err!("There is no block left on this node"));
+        };
+        let block_hash = entry.file_name().to_string_lossy().to_string();
+        let blocks = block_store.read_blocks::<F, G>(&[block_hash], &block_dir, Validate::No)?;
+        blocks
+            .first()
+            .map(|(_, block)| block.shard.k as usize)
+            .ok_or_else(|| format_err!("Could not read a block"))?
+    };
+    let target = (k as f64 * threshold).ceil() as usize;
+
+    let providers = ask(cmd_sender, |sender| DragoonCommand::GetProviders {
+        key: file_hash.to_string(),
+        sender,
+    })
+    .await?;
+    let mut retrievable = HashSet::new();
+    for peer_id in providers
+        .into_iter()
+        .filter(|peer_id| *peer_id != local_peer_id)
+    {
+        match ask(cmd_sender, |sender| DragoonCommand::GetBlocksInfoFrom {
+            peer_id,
+            file_hash: file_hash.to_string(),
+            block_hash: None,
+            sender,
+        })
+        .await
+        {
+            Ok(info) => retrievable.extend(info.block_hashes),
+            Err(e) => debug!(
+                "{} did not say which blocks of {} it has: {}",
+                peer_id, file_hash, e
+            ),
+        }
+    }
+    debug!(
+        "{} distinct blocks of {} are left on the other peers, {} are wanted",
+        retrievable.len(),
+        file_hash,
+        target
+    );
+    if retrievable.len() >= target {
+        return Ok(0);
+    }
+
+    let new_blocks = ask(cmd_sender, |sender| DragoonCommand::RecodeBlocks {
+        file_hash: file_hash.to_string(),
+        block_hashes: vec![],
+        count: target - retrievable.len(),
+        sender,
+    })
+    .await?;
+    let count = new_blocks.len();
+    let send_ids = ask(cmd_sender, |sender| DragoonCommand::SendBlockList {
+        strategy_name: StrategyName::new(ROUND_ROBIN),
+        file_hash: file_hash.to_string(),
+        block_list: new_blocks,
+        weights: HashMap::new(),
+        timeout: None,
+        operation_id: None,
+        sender,
+    })
+    .await?;
+    debug!(
+        "Sent the {} new blocks of {} in {} sends",
+        count,
+        file_hash,
+        send_ids.len()
+    );
+    Ok(count)
+}
@@ -0,0 +1,460 @@
+//! Choose in which order the blocks of a file are requested during a get-file
+
+use ark_ff::PrimeField;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::systematic;
+
+/// Maximum number of blocks requested at the same time by a get-file, the next blocks are requested following the [`RetrievalOrder`]
+pub(crate) const MAX_BLOCKS_IN_FLIGHT: usize = 8;
+/// Number of blocks in flight an automatically tuned get-file starts with
+const INITIAL_AUTO_BLOCKS_IN_FLIGHT: usize = 2;
+/// Upper bound of the automatically tuned number of blocks in flight
+const MAX_AUTO_BLOCKS_IN_FLIGHT: usize = 64;
+/// Relative gain of throughput over the previous window needed to request one more block at the same time
+const THROUGHPUT_GAIN: f64 = 0.05;
+/// Weight of the last response in the response time of a provider
+const LATENCY_WEIGHT: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RetrievalOrder {
+    /// Request the blocks following their position in the block lists of the providers.
+    /// The lists are in the order the blocks are found on the disk of the providers, not in the order of the chunks
+    /// of the file, so this does not make the first bytes of the file available first
+    #[default]
+    Sequential,
+    /// Request first the blocks that have the least providers, so they are not lost if those providers leave
+    RarestFirst,
+}
+
+struct ScheduledBlock {
+    /// Smallest position of the block in the block lists of its providers, which says nothing of its chunk
+    position: usize,
+    providers: Vec<PeerId>,
+}
+
+#[derive(Default)]
+struct ProviderStats {
+    in_flight: usize,
+    /// Moving average of the time in seconds the provider took to send a block, unknown until it sent one
+    response_time: Option<f64>,
+}
+
+pub(crate) struct BlockScheduler {
+    order: RetrievalOrder,
+    blocks: HashMap<String, ScheduledBlock>,
+    requested: HashSet<String>,
+    providers: HashMap<PeerId, ProviderStats>,
+    /// The providers with a bad reputation, only asked for the blocks no other provider has
+    deprioritized: HashSet<PeerId>,
+    /// The provider each block in flight was requested from, and when
+    in_flight: HashMap<String, (PeerId, Instant)>,
+}
+
+impl BlockScheduler {
+    pub(crate) fn new(order: RetrievalOrder) -> Self {
+        BlockScheduler {
+            order,
+            blocks: HashMap::new(),
+            requested: HashSet::new(),
+            providers: HashMap::new(),
+            deprioritized: HashSet::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Add the blocks a provider has, in the order of its block list, which is the order of its disk
+    pub(crate) fn add_provider(&mut self, peer_id: PeerId, block_hashes: Vec<String>) {
+        for (position, block_hash) in block_hashes.into_iter().enumerate() {
+            let block = self
+                .blocks
+                .entry(block_hash)
+                .or_insert_with(|| ScheduledBlock {
+                    position,
+                    providers: vec![],
+                });
+            block.position = block.position.min(position);
+            if !block.providers.contains(&peer_id) {
+                block.providers.push(peer_id);
+            }
+        }
+    }
+
+    /// Request the blocks from the provider only when no other provider has them
+    pub(crate) fn deprioritize(&mut self, peer_id: PeerId) {
+        self.deprioritized.insert(peer_id);
+    }
+
+    /// Never request the block, e.g. because it is already on disk
+    pub(crate) fn skip(&mut self, block_hash: String) {
+        self.requested.insert(block_hash);
+    }
+
+    /// The next block to request and the provider to request it from, `None` if all the known blocks were already requested
+    pub(crate) fn next_block(&mut self) -> Option<(String, PeerId)> {
+        let (block_hash, block) = self
+            .blocks
+            .iter()
+            .filter(|(block_hash, _)| !self.requested.contains(*block_hash))
+            .min_by(|(hash_a, a), (hash_b, b)| {
+                let by_position = a.position.cmp(&b.position).then(hash_a.cmp(hash_b));
+                match self.order {
+                    RetrievalOrder::Sequential => by_position,
+                    RetrievalOrder::RarestFirst => {
+                        a.providers.len().cmp(&b.providers.len()).then(by_position)
+                    }
+                }
+            })?;
+        // spread the blocks on the providers, the fastest ones first, and try once the ones that did not answer yet,
+        // the deprioritized ones last
+        let provider = *block.providers.iter().min_by(|a, b| {
+            let by_reputation = self
+                .deprioritized
+                .contains(a)
+                .cmp(&self.deprioritized.contains(b));
+            let a = self.providers.get(a);
+            let b = self.providers.get(b);
+            let in_flight = |stats: Option<&ProviderStats>| stats.map_or(0, |s| s.in_flight);
+            let response_time =
+                |stats: Option<&ProviderStats>| stats.and_then(|s| s.response_time).unwrap_or(0.0);
+            by_reputation
+                .then(in_flight(a).cmp(&in_flight(b)))
+                .then(response_time(a).total_cmp(&response_time(b)))
+        })?;
+        let block_hash = block_hash.clone();
+        self.requested.insert(block_hash.clone());
+        self.providers.entry(provider).or_default().in_flight += 1;
+        self.in_flight
+            .insert(block_hash.clone(), (provider, Instant::now()));
+        Some((block_hash, provider))
+    }
+
+    /// The block was received from the provider it was requested from
+    pub(crate) fn block_received(&mut self, block_hash: &str) {
+        let Some((provider, requested_at)) = self.in_flight.remove(block_hash) else {
+            return;
+        };
+        let stats = self.providers.entry(provider).or_default();
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+        let elapsed = requested_at.elapsed().as_secs_f64();
+        stats.response_time = Some(match stats.response_time {
+            Some(response_time) => {
+                (1.0 - LATENCY_WEIGHT) * response_time + LATENCY_WEIGHT * elapsed
+            }
+            None => elapsed,
+        });
+    }
+
+    /// The provider the block was requested from failed to send a valid block, so it is requested again from another provider.
+    ///
+    /// Returns the provider that failed.
+    pub(crate) fn block_failed(&mut self, block_hash: &str) -> Option<PeerId> {
+        let (provider, _) = self.in_flight.remove(block_hash)?;
+        let stats = self.providers.entry(provider).or_default();
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+        if let Some(block) = self.blocks.get_mut(block_hash) {
+            block.providers.retain(|peer_id| *peer_id != provider);
+            if block.providers.is_empty() {
+                debug!("No provider is left for the block {}", block_hash);
+                self.blocks.remove(block_hash);
+            } else {
+                self.requested.remove(block_hash);
+            }
+        }
+        Some(provider)
+    }
+}
+
+/// Tracks which part of the file, starting from its first byte, can already be read from the systematic blocks received so far.
+///
+/// The elements of the file are spread over the chunks row by row, chunk `j` holding the elements `j`, `j + k`,
+/// `j + 2k`... so the first bytes only go up to the first missing chunk, in the first row, until all the chunks are
+/// there.
+pub(crate) struct ContiguousProgress {
+    chunks: Vec<bool>,
+    file_size: usize,
+    /// Bits of the file held by each element, see [`crate::packing::Packing::bits_per_element`]
+    bits_per_element: usize,
+    contiguous_bytes: usize,
+}
+
+impl ContiguousProgress {
+    pub(crate) fn new(k: usize, file_size: usize, bits_per_element: usize) -> Self {
+        ContiguousProgress {
+            chunks: vec![false; k],
+            file_size,
+            bits_per_element,
+            contiguous_bytes: 0,
+        }
+    }
+
+    /// Add a block given its linear combination, returns the new number of contiguous bytes available if it changed
+    pub(crate) fn add_block<F: PrimeField>(&mut self, linear_combination: &[F]) -> Option<usize> {
+        // only a block with a single coefficient of 1 holds a chunk of the file as is
+        match systematic::source_chunk(linear_combination) {
+            Some(chunk) if chunk < self.chunks.len() => self.chunks[chunk] = true,
+            _ => return None,
+        }
+        let contiguous_chunks = self.chunks.iter().take_while(|c| **c).count();
+        let contiguous_bytes = if contiguous_chunks == self.chunks.len() {
+            self.file_size
+        } else {
+            // only the elements of the first row before the missing chunk, a byte split between two elements is
+            // only counted once both are there
+            (contiguous_chunks * self.bits_per_element / 8).min(self.file_size)
+        };
+        if contiguous_bytes > self.contiguous_bytes {
+            self.contiguous_bytes = contiguous_bytes;
+            Some(contiguous_bytes)
+        } else {
+            None
+        }
+    }
+}
+
+/// The throughput of a get-file at the end of a window, along with the number of blocks in flight chosen for the next one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub(crate) struct ConcurrencySample {
+    /// Time since the start of the download
+    pub(crate) elapsed_ms: u64,
+    pub(crate) blocks_in_flight: usize,
+    /// Bytes per second received during the window
+    pub(crate) throughput: f64,
+}
+
+/// Chooses how many blocks a get-file requests at the same time.
+///
+/// When tuned automatically, the number of blocks in flight grows by one after each window of as many received
+/// blocks as long as the throughput grows, and is halved each time a block request fails (AIMD).
+/// Otherwise it stays at the parallelism given to the get-file, [`MAX_BLOCKS_IN_FLIGHT`] by default.
+pub(crate) struct ConcurrencyController {
+    auto: bool,
+    blocks_in_flight: usize,
+    max_blocks_in_flight: usize,
+    start: Instant,
+    window_start: Instant,
+    window_bytes: usize,
+    window_blocks: usize,
+    last_throughput: Option<f64>,
+    curve: Vec<ConcurrencySample>,
+}
+
+impl ConcurrencyController {
+    /// `parallelism` is the number of blocks in flight, or its upper bound when tuned automatically
+    pub(crate) fn new(auto: bool, parallelism: Option<usize>) -> Self {
+        let now = Instant::now();
+        let max_blocks_in_flight = match (auto, parallelism) {
+            (_, Some(parallelism)) => parallelism.max(1),
+            (true, None) => MAX_AUTO_BLOCKS_IN_FLIGHT,
+            (false, None) => MAX_BLOCKS_IN_FLIGHT,
+        };
+        ConcurrencyController {
+            auto,
+            blocks_in_flight: if auto {
+                INITIAL_AUTO_BLOCKS_IN_FLIGHT.min(max_blocks_in_flight)
+            } else {
+                max_blocks_in_flight
+            },
+            max_blocks_in_flight,
+            start: now,
+            window_start: now,
+            window_bytes: 0,
+            window_blocks: 0,
+            last_throughput: None,
+            curve: vec![],
+        }
+    }
+
+    /// The number of blocks to keep in flight
+    pub(crate) fn blocks_in_flight(&self) -> usize {
+        self.blocks_in_flight
+    }
+
+    /// Account for a received block of `size` bytes, returns the new sample if it ended a window
+    pub(crate) fn block_received(&mut self, size: usize) -> Option<ConcurrencySample> {
+        if !self.auto {
+            return None;
+        }
+        self.window_bytes += size;
+        self.window_blocks += 1;
+        if self.window_blocks < self.blocks_in_flight {
+            return None;
+        }
+        let throughput = self.window_bytes as f64 / self.window_start.elapsed().as_secs_f64();
+        let grew = self
+            .last_throughput
+            .is_none_or(|last| throughput > last * (1.0 + THROUGHPUT_GAIN));
+        if grew {
+            self.blocks_in_flight = (self.blocks_in_flight + 1).min(self.max_blocks_in_flight);
+        }
+        self.last_throughput = Some(throughput);
+        Some(self.end_window(throughput))
+    }
+
+    /// Back off after a failed block request, returns the new sample
+    pub(crate) fn block_failed(&mut self) -> Option<ConcurrencySample> {
+        if !self.auto {
+            return None;
+        }
+        self.blocks_in_flight = (self.blocks_in_flight / 2).max(1);
+        let throughput = self.last_throughput.unwrap_or_default();
+        Some(self.end_window(throughput))
+    }
+
+    fn end_window(&mut self, throughput: f64) -> ConcurrencySample {
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        self.window_blocks = 0;
+        let sample = ConcurrencySample {
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            blocks_in_flight: self.blocks_in_flight,
+            throughput,
+        };
+        self.curve.push(sample.clone());
+        sample
+    }
+
+    /// The samples taken since the start of the download
+    pub(crate) fn into_curve(self) -> Vec<ConcurrencySample> {
+        self.curve
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bls12_381::Fr;
+
+    use crate::packing::Packing;
+
+    fn hashes(hashes: &[&str]) -> Vec<String> {
+        hashes.iter().map(|hash| hash.to_string()).collect()
+    }
+
+    fn all_blocks(scheduler: &mut BlockScheduler) -> Vec<(String, PeerId)> {
+        std::iter::from_fn(|| scheduler.next_block()).collect()
+    }
+
+    #[test]
+    fn sequential_follows_the_block_lists() {
+        let mut scheduler = BlockScheduler::new(RetrievalOrder::Sequential);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        scheduler.add_provider(a, hashes(&["b0", "b1", "b2"]));
+        scheduler.add_provider(b, hashes(&["b3", "b2"]));
+        let blocks: Vec<String> = all_blocks(&mut scheduler)
+            .into_iter()
+            .map(|(block_hash, _)| block_hash)
+            .collect();
+        assert_eq!(blocks, hashes(&["b0", "b3", "b1", "b2"]));
+    }
+
+    #[test]
+    fn rarest_first_takes_the_blocks_with_the_least_providers() {
+        let mut scheduler = BlockScheduler::new(RetrievalOrder::RarestFirst);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        scheduler.add_provider(a, hashes(&["b0", "b1"]));
+        scheduler.add_provider(b, hashes(&["b0"]));
+        assert_eq!(scheduler.next_block(), Some((String::from("b1"), a)));
+        assert_eq!(
+            scheduler.next_block().map(|(block_hash, _)| block_hash),
+            Some(String::from("b0"))
+        );
+        assert_eq!(scheduler.next_block(), None);
+    }
+
+    #[test]
+    fn skipped_blocks_are_not_requested() {
+        let mut scheduler = BlockScheduler::new(RetrievalOrder::Sequential);
+        scheduler.add_provider(PeerId::random(), hashes(&["b0", "b1"]));
+        scheduler.skip(String::from("b0"));
+        let blocks: Vec<String> = all_blocks(&mut scheduler)
+            .into_iter()
+            .map(|(block_hash, _)| block_hash)
+            .collect();
+        assert_eq!(blocks, hashes(&["b1"]));
+    }
+
+    #[test]
+    fn blocks_are_spread_on_the_providers() {
+        let mut scheduler = BlockScheduler::new(RetrievalOrder::Sequential);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        scheduler.add_provider(a, hashes(&["b0", "b1"]));
+        scheduler.add_provider(b, hashes(&["b0", "b1"]));
+        let providers: HashSet<PeerId> = all_blocks(&mut scheduler)
+            .into_iter()
+            .map(|(_, provider)| provider)
+            .collect();
+        assert_eq!(providers, HashSet::from([a, b]));
+    }
+
+    #[test]
+    fn deprioritized_providers_are_asked_last() {
+        let mut scheduler = BlockScheduler::new(RetrievalOrder::Sequential);
+        let (bad, good) = (PeerId::random(), PeerId::random());
+        scheduler.add_provider(bad, hashes(&["b0", "b1", "b2"]));
+        scheduler.add_provider(good, hashes(&["b0", "b1"]));
+        scheduler.deprioritize(bad);
+        assert_eq!(
+            all_blocks(&mut scheduler),
+            vec![
+                (String::from("b0"), good),
+                (String::from("b1"), good),
+                (String::from("b2"), bad)
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_blocks_are_requested_from_another_provider() {
+        let mut scheduler = BlockScheduler::new(RetrievalOrder::Sequential);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        scheduler.add_provider(a, hashes(&["b0"]));
+        scheduler.add_provider(b, hashes(&["b0"]));
+        let (block_hash, first) = scheduler.next_block().unwrap();
+        assert_eq!(scheduler.block_failed(&block_hash), Some(first));
+        let (_, second) = scheduler.next_block().unwrap();
+        assert_ne!(first, second);
+        // no provider is left for the block
+        assert_eq!(scheduler.block_failed(&block_hash), Some(second));
+        assert_eq!(scheduler.next_block(), None);
+    }
+
+    #[test]
+    fn contiguous_progress_only_counts_the_first_chunks() {
+        // 31 bytes per element
+        let mut progress =
+            ContiguousProgress::new(3, 100, Packing::Truncate.bits_per_element::<Fr>());
+        let chunk = |i: usize| -> Vec<Fr> {
+            (0..3)
+                .map(|j| {
+                    if i == j {
+                        Fr::from(1u64)
+                    } else {
+                        Fr::from(0u64)
+                    }
+                })
+                .collect()
+        };
+        assert_eq!(progress.add_block(&chunk(1)), None);
+        assert_eq!(
+            progress.add_block(&[Fr::from(1u64), Fr::from(1u64), Fr::from(0u64)]),
+            None
+        );
+        // the first two elements, the third one is in the missing chunk
+        assert_eq!(progress.add_block(&chunk(0)), Some(62));
+        assert_eq!(progress.add_block(&chunk(2)), Some(100));
+
+        // 254 bits per element, the 64th byte is split between the second and the third elements
+        let mut progress =
+            ContiguousProgress::new(3, 100, Packing::BitPacked.bits_per_element::<Fr>());
+        assert_eq!(progress.add_block(&chunk(0)), Some(31));
+        assert_eq!(progress.add_block(&chunk(1)), Some(63));
+        assert_eq!(progress.add_block(&chunk(2)), Some(100));
+    }
+}
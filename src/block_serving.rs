@@ -0,0 +1,65 @@
+//! Limits on the block requests of the other peers the node serves at the same time.
+//!
+//! Each block request is read from disk and answered by a task of its own, at most `--max-served-block-requests`
+//! at the same time. The requests arriving while they are all busy wait for their turn, up to
+//! `--max-queued-block-requests` of them, and the next ones are answered right away with a busy response, so a
+//! flood of requests can't exhaust the file descriptors and the memory of the node. A peer answered busy requests
+//! the block from another provider.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BlockServingLimits {
+    /// Number of block requests read and answered at the same time
+    pub(crate) max_served: usize,
+    /// Number of block requests waiting for their turn, the next ones are answered busy
+    pub(crate) max_queued: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct BlockServing {
+    permits: Arc<Semaphore>,
+    /// The requests being served or waiting for their turn
+    admitted: AtomicUsize,
+    max_admitted: usize,
+}
+
+/// Held from the arrival of a request until it is answered
+#[derive(Debug)]
+pub(crate) struct Admission(Arc<BlockServing>);
+
+impl BlockServing {
+    pub(crate) fn new(limits: BlockServingLimits) -> Arc<Self> {
+        Arc::new(BlockServing {
+            permits: Arc::new(Semaphore::new(limits.max_served)),
+            admitted: AtomicUsize::new(0),
+            max_admitted: limits.max_served + limits.max_queued,
+        })
+    }
+
+    /// `None` when as many requests as allowed are already served or queued, the request should be answered busy
+    pub(crate) fn admit(self: &Arc<Self>) -> Option<Admission> {
+        self.admitted
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |admitted| {
+                (admitted < self.max_admitted).then_some(admitted + 1)
+            })
+            .ok()
+            .map(|_| Admission(self.clone()))
+    }
+}
+
+impl Admission {
+    /// Wait for the turn of the request, it is served for as long as the permit is held
+    pub(crate) async fn turn(&self) -> OwnedSemaphorePermit {
+        // the semaphore is never closed
+        self.0.permits.clone().acquire_owned().await.unwrap()
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.0.admitted.fetch_sub(1, Ordering::SeqCst);
+    }
+}
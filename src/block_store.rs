@@ -0,0 +1,193 @@
+//! Reading and writing the blocks on disk, encrypted when the node is started with `--encrypt-blocks`.
+//!
+//! An encrypted block is written as a header, a random nonce and the serialized block encrypted with AES-256-GCM
+//! under the storage key of the node, read from `--storage-key-file` or generated there on the first run. A block
+//! keeps the name of the hash of its serialized form, so the same block has the same name on all the nodes. The
+//! blocks written without encryption are still read as they are, so encryption can be turned on for a node that
+//! already stores blocks, while an encrypted block cannot be read by a node without the key.
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_serialize::Validate;
+use komodo::semi_avid::Block;
+use rand::RngCore;
+use std::fmt;
+use std::fs as sfs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::block_format;
+use crate::keystore::write_private;
+
+/// The storage key in the storage directory of the node, when `--storage-key-file` is not given
+pub(crate) const STORAGE_KEY_FILE_NAME: &str = "storage.key";
+
+/// Written at the start of the encrypted blocks, so they are told apart from the plain ones
+const ENCRYPTED_BLOCK_HEADER: &[u8] = b"dragoonfly-aes-gcm-1";
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+/// The AES-256-GCM cipher of the key in `key_path`, the key is generated and saved there if there is none yet
+pub(crate) fn load_or_generate_cipher(key_path: &Path) -> Result<Aes256Gcm> {
+    let key = load_or_generate_key(key_path)?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+/// The key in `key_path`, generated and saved there if there is none yet
+pub(crate) fn load_or_generate_key(key_path: &Path) -> Result<Vec<u8>> {
+    let key = if key_path.exists() {
+        info!("Loading the key from {:?}", key_path);
+        let key = sfs::read(key_path)?;
+        if key.len() != KEY_SIZE {
+            return Err(format_err!(
+                "The key in {:?} has {} bytes instead of {}",
+                key_path,
+                key.len(),
+                KEY_SIZE
+            ));
+        }
+        key
+    } else {
+        info!("Generating a new key in {:?}", key_path);
+        let mut key = vec![0u8; KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut key);
+        if let Some(parent) = key_path.parent() {
+            sfs::create_dir_all(parent)?;
+        }
+        write_private(key_path, &key)?;
+        key
+    };
+    Ok(key)
+}
+
+#[derive(Default)]
+pub(crate) struct BlockStore {
+    /// `None` when the blocks are written in plain
+    cipher: Option<Aes256Gcm>,
+}
+
+impl fmt::Debug for BlockStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockStore")
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+impl BlockStore {
+    /// Encrypt the blocks with the key of `key_path`, generated and saved there if there is no key yet
+    pub(crate) fn encrypted(key_path: &Path) -> Result<Self> {
+        Ok(BlockStore {
+            cipher: Some(load_or_generate_cipher(key_path)?),
+        })
+    }
+
+    /// The bytes to write on disk for the serialized block
+    pub(crate) fn seal(&self, ser_block: &[u8]) -> Result<Vec<u8>> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(ser_block.to_vec());
+        };
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), ser_block)
+            .map_err(|e| format_err!("Could not encrypt the block: {}", e))?;
+        let mut stored =
+            Vec::with_capacity(ENCRYPTED_BLOCK_HEADER.len() + NONCE_SIZE + ciphertext.len());
+        stored.extend_from_slice(ENCRYPTED_BLOCK_HEADER);
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        Ok(stored)
+    }
+
+    /// The serialized block from the bytes read on disk
+    pub(crate) fn unseal(&self, stored: Vec<u8>) -> Result<Vec<u8>> {
+        let Some(encrypted) = stored.strip_prefix(ENCRYPTED_BLOCK_HEADER) else {
+            return Ok(stored);
+        };
+        let cipher = self.cipher.as_ref().ok_or(format_err!(
+            "The block is encrypted, the node must be started with --encrypt-blocks and its storage key"
+        ))?;
+        if encrypted.len() < NONCE_SIZE {
+            return Err(format_err!("The encrypted block is truncated"));
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_SIZE);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| format_err!("Could not decrypt the block, wrong storage key?"))
+    }
+
+    /// Read the serialized block at `path`
+    pub(crate) fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.unseal(sfs::read(path)?)
+    }
+
+    pub(crate) async fn read_async(&self, path: &Path) -> Result<Vec<u8>> {
+        self.unseal(tokio::fs::read(path).await?)
+    }
+
+    pub(crate) async fn write_async(&self, path: &Path, ser_block: &[u8]) -> Result<()> {
+        tokio::fs::write(path, self.seal(ser_block)?).await?;
+        Ok(())
+    }
+
+    /// Read and deserialize the blocks of `block_dir`, along with their hash
+    pub(crate) fn read_blocks<F, G>(
+        &self,
+        block_hashes: &[String],
+        block_dir: &Path,
+        validate: Validate,
+    ) -> Result<Vec<(String, Block<F, G>)>>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        block_hashes
+            .iter()
+            .map(|block_hash| {
+                let ser_block = self.read(&block_dir.join(block_hash))?;
+                let block = block_format::deserialize(&ser_block, validate)?;
+                Ok((block_hash.clone(), block))
+            })
+            .collect()
+    }
+
+    /// Write the block in `block_dir`, named after its hash, returns its path
+    pub(crate) fn dump<F, G>(&self, block: &Block<F, G>, block_dir: &Path) -> Result<PathBuf>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        let ser_block = block_format::serialize(block)?;
+        let path = block_dir.join(block_format::block_hash(&ser_block)?);
+        sfs::write(&path, self.seal(&ser_block)?)?;
+        Ok(path)
+    }
+
+    /// Write the blocks in `block_dir`, returns their hashes
+    pub(crate) fn dump_blocks<F, G>(
+        &self,
+        blocks: &[Block<F, G>],
+        block_dir: &Path,
+    ) -> Result<Vec<String>>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        sfs::create_dir_all(block_dir)?;
+        blocks
+            .iter()
+            .map(|block| {
+                let path = self.dump(block, block_dir)?;
+                Ok(path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned())
+            })
+            .collect()
+    }
+}
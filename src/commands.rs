@@ -1,37 +1,321 @@
 //! Define all the commands that can be used by the network
 
 use anyhow::{self, format_err, Error, Result};
-use axum::extract::{Json, Path, State};
-use axum::http::StatusCode;
+use axum::body::{Body, Bytes};
+use axum::extract::multipart::MultipartError;
+use axum::extract::{Json, Multipart, Path, Query, State};
+use axum::http::{header, StatusCode};
 use axum::response::{self, IntoResponse, Response};
+use libp2p::request_response::ResponseChannel;
 use libp2p::swarm::NetworkInfo;
 use libp2p::{Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path as StdPath, PathBuf};
 use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::{
     mpsc,
     oneshot::{self, error::RecvError},
 };
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::app::AppState;
+use crate::bandwidth::BandwidthReport;
+use crate::block_quota::BlockCountInfo;
+use crate::block_repair::ask;
+use crate::block_scheduler::RetrievalOrder;
+use crate::compression::Compression;
 use crate::dragoon_swarm::BlockResponse;
-use crate::error::DragoonError;
+use crate::error::{DialErrorKind, DragoonError};
+use crate::file_announce::KnownFile;
+use crate::file_metadata::FileMetadata;
+use crate::lifecycle::FileLifecycle;
+use crate::manifest::FileManifest;
+use crate::mirror::MirrorStatus;
+use crate::packing::Packing;
 use crate::peer_block_info::PeerBlockInfo;
+use crate::peer_probe::ProbeReport;
+use crate::peer_quota::PeerQuotasInfo;
+use crate::peer_reputation::PeerReputation;
+use crate::progress;
+use crate::recent_events::RecentEvent;
+use crate::replication::{BlockLocation, GlobalBlockMap, ReplicationStatus};
+use crate::scrub::ScrubStatus;
 use crate::send_strategy::SendId;
 use crate::send_strategy_impl::StrategyName;
-use crate::to_serialize::{ConvertSer, JsonWrapper};
+use crate::storage_class::{StorageClass, StorageClassUsage};
+use crate::to_serialize::{ConvertSer, Envelope, JsonWrapper};
+
+/// Size of the chunks of the file sent in the body of a download
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
 
 // use komodo::linalg::Matrix;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
 pub(crate) enum EncodingMethod {
     Vandermonde,
     Random,
+    /// A Vandermonde encoding whose first `k` blocks are the chunks of the file as they are
+    Systematic,
 }
 //TODO impl Display to convert from String for axum when doing http-get requests ?
 
+/// The points a Vandermonde encoding matrix is built on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum EvaluationDomain {
+    /// The same points 0, 1, ..., n - 1 for all the files
+    #[default]
+    Indices,
+    /// Points derived from the hash of the file, so each file has its own domain
+    FileHash,
+    /// Points derived from the given domain seed
+    Seed,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct EncodeFileParams {
+    #[serde(default)]
+    domain: EvaluationDomain,
+    #[serde(default)]
+    domain_seed: Option<String>,
+    /// Compress the file before encoding it
+    #[serde(default)]
+    compression: Compression,
+    /// Pack the bytes of the file in field elements with this strategy
+    #[serde(default)]
+    packing: Packing,
+    /// Encrypt the file with the file key of the node before encoding it
+    #[serde(default)]
+    encrypt: bool,
+    /// Encode a tar archive of the directory at the path, see [`crate::archive`]
+    #[serde(default)]
+    archive: bool,
+    /// Id under which the progress of the encoding can be read with `/progress/{operation_id}`
+    operation_id: Option<String>,
+    /// Return the id of a job right away instead of waiting for the encoding
+    #[serde(default)]
+    background: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct EncodeFilesParams {
+    #[serde(default)]
+    domain: EvaluationDomain,
+    #[serde(default)]
+    domain_seed: Option<String>,
+    /// Compress the files before encoding them
+    #[serde(default)]
+    compression: Compression,
+    /// Pack the bytes of the files in field elements with this strategy
+    #[serde(default)]
+    packing: Packing,
+    /// Encrypt the files with the file key of the node before encoding them
+    #[serde(default)]
+    encrypt: bool,
+    /// Number of files encoded at once, 4 by default
+    parallelism: Option<usize>,
+}
+
+/// The encoding of one of the files of a [`DragoonCommand::EncodeFiles`]
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct EncodeFilesOutcome {
+    pub(crate) file_path: String,
+    pub(crate) encoded: Option<EncodedFile>,
+    pub(crate) error: Option<String>,
+}
+
+/// What get-file does with the reconstructed file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum GetFileOutput {
+    /// Write the file to disk
+    #[default]
+    File,
+    /// Only compute the hash of the file, without writing it
+    Hash,
+    /// Only check that the file can be decoded
+    None,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct GetFileParams {
+    #[serde(default)]
+    output: GetFileOutput,
+    #[serde(default)]
+    order: RetrievalOrder,
+    /// Replace the output file if it already exists
+    #[serde(default)]
+    overwrite: bool,
+    /// Tune the number of blocks requested at the same time from the measured throughput
+    #[serde(default)]
+    auto_concurrency: bool,
+    /// Number of blocks requested at the same time, its upper bound with `auto_concurrency`
+    parallelism: Option<usize>,
+    /// Decrypt the file with the file key of the node, for the files encoded with `encrypt`
+    #[serde(default)]
+    decrypt: bool,
+    /// Restore the directory tree of a file encoded with `archive` instead of writing the archive
+    #[serde(default)]
+    unpack: bool,
+    /// The `segments_root` returned by the last append-to-file, to get the segments listed by the metadata of the file
+    segments_root: Option<String>,
+    /// Seconds given to receive enough blocks, `--get-file-timeout` if not given
+    timeout: Option<u64>,
+    /// Id under which the progress of the download can be read with `/progress/{operation_id}`
+    operation_id: Option<String>,
+    /// Return the id of a job right away instead of waiting for the file
+    #[serde(default)]
+    background: bool,
+}
+
+/// The result of a get-file, depending on the requested [`GetFileOutput`]
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(untagged)]
+pub(crate) enum GetFileOutcome {
+    Written(#[schema(value_type = String)] PathBuf),
+    Hash(String),
+    Decodable(bool),
+}
+
+/// The blocks a file was encoded into, as returned by encode-file
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct EncodedFile {
+    pub(crate) file_hash: String,
+    pub(crate) block_hashes: Vec<String>,
+    /// SHA-256 of the commitments shared by all the blocks of the file
+    pub(crate) commitment_digest: String,
+}
+
+/// A segment appended to a file by an append-to-file
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct AppendedSegment {
+    #[serde(flatten)]
+    pub(crate) segment: EncodedFile,
+    /// Stands for the file with all its segments, see [`crate::manifest::segments_root`]
+    pub(crate) segments_root: String,
+}
+
+/// What was removed by a delete-file
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct DeleteFileOutcome {
+    pub(crate) file_hash: String,
+    /// Number of blocks of the file that were removed
+    pub(crate) blocks: usize,
+    /// Size of the removed blocks that had been received through send requests
+    pub(crate) freed_send_storage: usize,
+}
+
+/// What a [`DragoonCommand::StopProvide`] stopped
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct StopProvideOutcome {
+    pub(crate) key: String,
+    /// Whether the node was providing the key
+    pub(crate) was_provided: bool,
+    /// Number of StartProvide queries and refreshes of the key that were still running, and were canceled
+    pub(crate) canceled_queries: usize,
+    /// The keys the node still provides
+    pub(crate) still_provided: Vec<String>,
+}
+
+/// How a DialMultiple handles the failed dials
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DialMode {
+    /// Dial all the multiaddr at the same time, whatever the failures
+    #[default]
+    BestEffort,
+    /// Dial the multiaddr one after the other, and stop at the first failure
+    FailFast,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DialMultipleParams {
+    #[serde(default)]
+    mode: DialMode,
+}
+
+/// The outcome of the dial of one of the multiaddr of a DialMultiple
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct DialOutcome {
+    pub(crate) multiaddr: String,
+    pub(crate) success: bool,
+    pub(crate) peer_id: Option<String>,
+    pub(crate) error_kind: Option<DialErrorKind>,
+    pub(crate) error: Option<String>,
+    /// The addresses the name of the multiaddr resolved to, in the order they were dialed
+    pub(crate) resolved: Vec<String>,
+}
+
+impl DialOutcome {
+    pub(crate) fn new(multiaddr: String, res: Result<PeerId>) -> Self {
+        match res {
+            Ok(peer_id) => DialOutcome {
+                multiaddr,
+                success: true,
+                peer_id: Some(peer_id.to_base58()),
+                error_kind: None,
+                error: None,
+                resolved: vec![],
+            },
+            Err(e) => {
+                let error_kind = match e.downcast_ref::<DragoonError>() {
+                    Some(DragoonError::DialError { kind, .. }) => Some(*kind),
+                    _ => None,
+                };
+                DialOutcome {
+                    multiaddr,
+                    success: false,
+                    peer_id: None,
+                    error_kind,
+                    error: Some(e.to_string()),
+                    resolved: vec![],
+                }
+            }
+        }
+    }
+
+    pub(crate) fn skipped(multiaddr: String) -> Self {
+        DialOutcome {
+            multiaddr,
+            success: false,
+            peer_id: None,
+            error_kind: Some(DialErrorKind::Skipped),
+            error: Some(String::from("a previous dial failed")),
+            resolved: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ReannounceParams {
+    /// Also announce the files for which the node has blocks, even if they are not provided
+    #[serde(default)]
+    local_files: bool,
+}
+
+/// Whether a key could be announced again by [`DragoonCommand::ReannounceAll`]
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct ReannounceOutcome {
+    pub(crate) key: String,
+    pub(crate) success: bool,
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct RecentEventsParams {
+    /// Only return the events with a sequence number above this one
+    since: Option<u64>,
+}
+
 // Potential other commands:
 // - dial
 //
@@ -50,6 +334,19 @@ pub(crate) enum EncodingMethod {
 pub(crate) type SenderOneS<T, E = Error> = oneshot::Sender<Result<T, E>>;
 pub(crate) type SenderMPSC<T, E = Error> = mpsc::UnboundedSender<Result<T, E>>;
 
+/// The identity, storage and block counts of the node, as returned by node-info
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct NodeInfo {
+    /// The base 58 peer id of the node
+    pub(crate) peer_id: String,
+    pub(crate) label: String,
+    /// The usage of each storage class
+    pub(crate) storage_classes: HashMap<StorageClass, StorageClassUsage>,
+    pub(crate) block_counts: BlockCountInfo,
+    /// Whether the node paused the reception of the blocks sent by other peers
+    pub(crate) receiving_paused: bool,
+}
+
 #[derive(Debug)]
 pub(crate) enum Sender<T, E = Error> {
     SenderOneS(SenderOneS<T, E>),
@@ -82,6 +379,16 @@ pub(crate) enum DragoonCommand {
         multiaddr: String,
         sender: Sender<()>,
     },
+    /// Encode a file as a new segment of an encoded file, see [`crate::manifest`]
+    AppendToFile {
+        file_hash: String,
+        file_path: String,
+        sender: Sender<AppendedSegment>,
+    },
+    BanPeer {
+        peer_id: PeerId,
+        sender: Sender<bool>,
+    },
     Bootstrap {
         sender: Sender<()>,
     },
@@ -95,13 +402,29 @@ pub(crate) enum DragoonCommand {
         output_filename: String,
         sender: Sender<()>,
     },
+    DeleteFile {
+        file_hash: String,
+        sender: Sender<DeleteFileOutcome>,
+    },
+    DelegateGetFile {
+        peer_id: PeerId,
+        file_hash: String,
+        /// Replace the file if the node already has it
+        overwrite: bool,
+        sender: Sender<PathBuf>,
+    },
     DialMultiple {
         list_multiaddr: Vec<String>,
-        sender: Sender<()>,
+        mode: DialMode,
+        sender: Sender<Vec<DialOutcome>>,
     },
     DialSingle {
         multiaddr: String,
-        sender: Sender<()>,
+        sender: Sender<PeerId>,
+    },
+    DisconnectPeer {
+        peer_id: PeerId,
+        sender: Sender<bool>,
     },
     // DragoonPeers {
     //     sender: Sender<HashSet<PeerId>>,
@@ -118,11 +441,59 @@ pub(crate) enum DragoonCommand {
         encoding_method: EncodingMethod,
         encode_mat_k: usize,
         encode_mat_n: usize,
-        sender: Sender<(String, String)>,
+        domain: EvaluationDomain,
+        domain_seed: Option<String>,
+        compression: Compression,
+        packing: Packing,
+        encrypt: bool,
+        archive: bool,
+        operation_id: Option<String>,
+        sender: Sender<EncodedFile>,
+    },
+    EncodeFiles {
+        file_paths: Vec<String>,
+        replace_blocks: bool,
+        encoding_method: EncodingMethod,
+        encode_mat_k: usize,
+        encode_mat_n: usize,
+        domain: EvaluationDomain,
+        domain_seed: Option<String>,
+        compression: Compression,
+        packing: Packing,
+        encrypt: bool,
+        parallelism: Option<usize>,
+        sender: Sender<Vec<EncodeFilesOutcome>>,
+    },
+    FindBlock {
+        file_hash: String,
+        block_hash: String,
+        sender: Sender<BlockLocation>,
+    },
+    /// Add a segment encoded outside of the loop to the manifest of its file, see [`DragoonCommand::AppendToFile`]
+    FinishAppendToFile {
+        file_hash: String,
+        segment: EncodedFile,
+        sender: Sender<AppendedSegment>,
+    },
+    FinishIdentityRotation {
+        new_peer_id: PeerId,
+        sender: Sender<()>,
+    },
+    GeneratePowers {
+        max_degree: usize,
+        sender: Sender<()>,
     },
     GetAvailableStorage {
         sender: Sender<usize>,
     },
+    /// Ask a peer how much storage it has left for the blocks sent by other peers
+    GetAvailableStorageFrom {
+        peer_id: PeerId,
+        sender: Sender<usize>,
+    },
+    GetBandwidth {
+        sender: Sender<BandwidthReport>,
+    },
     GetBlockDir {
         file_hash: String,
         sender: Sender<PathBuf>,
@@ -132,11 +503,15 @@ pub(crate) enum DragoonCommand {
         file_hash: String,
         block_hash: String,
         save_to_disk: bool,
+        /// The providers the block is requested from, in order, when the request to the previous one fails
+        fallback_providers: Vec<PeerId>,
         sender: Sender<Option<BlockResponse>>,
     },
     GetBlocksInfoFrom {
         peer_id: PeerId,
         file_hash: String,
+        /// Only ask whether the peer has this block
+        block_hash: Option<String>,
         sender: Sender<PeerBlockInfo>,
     },
     GetBlockList {
@@ -149,28 +524,105 @@ pub(crate) enum DragoonCommand {
     GetFile {
         file_hash: String,
         output_filename: String,
-        sender: Sender<PathBuf>,
+        output: GetFileOutput,
+        order: RetrievalOrder,
+        overwrite: bool,
+        auto_concurrency: bool,
+        parallelism: Option<usize>,
+        decrypt: bool,
+        unpack: bool,
+        /// Only the segments of a file encoded by the node are retrieved without it, see [`crate::manifest`]
+        segments_root: Option<String>,
+        /// Seconds given to receive enough blocks, `--get-file-timeout` if not given
+        timeout: Option<u64>,
+        operation_id: Option<String>,
+        sender: Sender<GetFileOutcome>,
     },
     GetFileDir {
         file_hash: String,
         sender: Sender<PathBuf>,
     },
+    /// Read the metadata record of the file in the DHT, `None` if there is no valid one
+    GetFileMetadata {
+        file_hash: String,
+        sender: Sender<Option<FileMetadata>>,
+    },
+    GetGlobalBlockMap {
+        file_hash: String,
+        sender: Sender<GlobalBlockMap>,
+    },
     GetListeners {
         sender: Sender<Vec<Multiaddr>>,
     },
     GetNetworkInfo {
         sender: Sender<NetworkInfo>,
     },
+    GetPeerQuotas {
+        sender: Sender<PeerQuotasInfo>,
+    },
+    GetPeerScore {
+        peer_id: PeerId,
+        sender: Sender<PeerReputation>,
+    },
     GetProviders {
         key: String,
         sender: Sender<Vec<PeerId>>,
     },
+    /// The recent events with a sequence number above `since`, all of them if it is not given
+    GetRecentEvents {
+        since: Option<u64>,
+        sender: Sender<Vec<RecentEvent>>,
+    },
+    GetReplication {
+        file_hash: String,
+        sender: Sender<ReplicationStatus>,
+    },
+    KnownFiles {
+        sender: Sender<Vec<KnownFile>>,
+    },
+    Lifecycle {
+        file_hash: String,
+        sender: Sender<FileLifecycle>,
+    },
     Listen {
         multiaddr: String,
         sender: Sender<u64>,
     },
+    Manifest {
+        file_hash: String,
+        sender: Sender<FileManifest>,
+    },
+    MirrorStatus {
+        sender: Sender<MirrorStatus>,
+    },
     NodeInfo {
-        sender: Sender<(PeerId, String)>,
+        sender: Sender<NodeInfo>,
+    },
+    PauseReceiving {
+        sender: Sender<bool>,
+    },
+    ProbePeer {
+        peer_id: PeerId,
+        file_hash: String,
+        sender: Sender<ProbeReport>,
+    },
+    /// Announce a file encoded outside of the loop and publish its metadata
+    PublishEncodedFile {
+        file_hash: String,
+    },
+    ReannounceAll {
+        local_files: bool,
+        sender: Sender<Vec<ReannounceOutcome>>,
+    },
+    RecodeBlocks {
+        file_hash: String,
+        block_hashes: Vec<String>,
+        count: usize,
+        sender: Sender<Vec<String>>,
+    },
+    /// Announce again a provided key, unless it stopped being provided in the meantime
+    RefreshProvide {
+        key: String,
     },
     RemoveEntryFromSendBlockToSet {
         peer_id: PeerId,
@@ -181,25 +633,68 @@ pub(crate) enum DragoonCommand {
         listener_id: u64,
         sender: Sender<bool>,
     },
+    ResumeReceiving {
+        sender: Sender<bool>,
+    },
+    RotateIdentity {
+        sender: Sender<(PeerId, PeerId)>,
+    },
+    ScrubStatus {
+        sender: Sender<ScrubStatus>,
+    },
     SendBlockList {
         strategy_name: StrategyName,
         file_hash: String,
         block_list: Vec<String>,
+        /// The weight of each peer, by base 58 peer id, only for the UserWeighted strategy
+        weights: HashMap<String, u64>,
+        /// Seconds given to the first loop of the sends, `--send-block-list-timeout` if not given
+        timeout: Option<u64>,
+        operation_id: Option<String>,
         sender: Sender<Vec<SendId>, DragoonError>,
     },
+    /// Answer a block request of another peer with the block read by its task
+    SendBlockResponse {
+        peer_id: PeerId,
+        channel: ResponseChannel<BlockResponse>,
+        response: BlockResponse,
+        sender: Sender<(), DragoonError>,
+    },
     SendBlockTo {
         peer_id: PeerId,
         file_hash: String,
         block_hash: String,
+        storage_class: Option<StorageClass>,
+        ttl: Option<u64>,
         sender: Sender<(bool, SendId), DragoonError>,
     },
+    /// Set the storage the peer can use for the blocks it sends, back to the default quota if `None`
+    SetPeerQuota {
+        peer_id: PeerId,
+        quota: Option<usize>,
+        sender: Sender<()>,
+    },
+    /// Set the number of other peers each block of the file should be on, 0 to stop ensuring it
+    SetReplication {
+        file_hash: String,
+        replication: usize,
+        sender: Sender<()>,
+    },
+    /// Save the state of the node and close its connections, sent when the node stops, see [`crate::shutdown`]
+    Shutdown {
+        sender: Sender<()>,
+    },
     StartProvide {
         key: String,
         sender: Sender<()>,
     },
     StopProvide {
         key: String,
-        sender: Sender<()>,
+        sender: Sender<StopProvideOutcome>,
+    },
+    UnbanPeer {
+        peer_id: PeerId,
+        sender: Sender<bool>,
     },
 }
 
@@ -207,15 +702,31 @@ impl std::fmt::Display for DragoonCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             DragoonCommand::AddPeer { .. } => write!(f, "add-peer"),
+            DragoonCommand::AppendToFile { .. } => write!(f, "append-to-file"),
+            DragoonCommand::BanPeer { .. } => write!(f, "ban-peer"),
             DragoonCommand::Bootstrap { .. } => write!(f, "bootstrap"),
             DragoonCommand::ChangeAvailableSendStorage { .. } => {
                 write!(f, "change-available-send-storage")
             }
             DragoonCommand::DecodeBlocks { .. } => write!(f, "decode-blocks"),
+            DragoonCommand::DeleteFile { .. } => write!(f, "delete-file"),
+            DragoonCommand::DelegateGetFile { .. } => write!(f, "delegate-get-file"),
             DragoonCommand::DialMultiple { .. } => write!(f, "dial-multiple"),
             DragoonCommand::DialSingle { .. } => write!(f, "dial-single"),
+            DragoonCommand::DisconnectPeer { .. } => write!(f, "disconnect-peer"),
             DragoonCommand::EncodeFile { .. } => write!(f, "encode-file"),
+            DragoonCommand::EncodeFiles { .. } => write!(f, "encode-files"),
+            DragoonCommand::FindBlock { .. } => write!(f, "find-block"),
+            DragoonCommand::FinishAppendToFile { .. } => write!(f, "finish-append-to-file"),
+            DragoonCommand::FinishIdentityRotation { .. } => {
+                write!(f, "finish-identity-rotation")
+            }
+            DragoonCommand::GeneratePowers { .. } => write!(f, "generate-powers"),
             DragoonCommand::GetAvailableStorage { .. } => write!(f, "get-available-send-storage"),
+            DragoonCommand::GetAvailableStorageFrom { .. } => {
+                write!(f, "get-available-send-storage-from")
+            }
+            DragoonCommand::GetBandwidth { .. } => write!(f, "get-bandwidth"),
             DragoonCommand::GetBlockDir { .. } => write!(f, "get-block-dir"),
             DragoonCommand::GetBlockFrom { .. } => write!(f, "get-block-from"),
             DragoonCommand::GetBlocksInfoFrom { .. } => write!(f, "get-blocks-info-from"),
@@ -223,19 +734,43 @@ impl std::fmt::Display for DragoonCommand {
             DragoonCommand::GetConnectedPeers { .. } => write!(f, "get-connected-peers"),
             DragoonCommand::GetFile { .. } => write!(f, "get-file"),
             DragoonCommand::GetFileDir { .. } => write!(f, "get-file-dir"),
+            DragoonCommand::GetFileMetadata { .. } => write!(f, "get-file-metadata"),
+            DragoonCommand::GetGlobalBlockMap { .. } => write!(f, "get-global-block-map"),
             DragoonCommand::GetListeners { .. } => write!(f, "get-listener"),
             DragoonCommand::GetNetworkInfo { .. } => write!(f, "get-network-info"),
+            DragoonCommand::GetPeerQuotas { .. } => write!(f, "get-peer-quotas"),
+            DragoonCommand::GetPeerScore { .. } => write!(f, "get-peer-score"),
             DragoonCommand::GetProviders { .. } => write!(f, "get-providers"),
+            DragoonCommand::GetRecentEvents { .. } => write!(f, "get-recent-events"),
+            DragoonCommand::GetReplication { .. } => write!(f, "get-replication"),
+            DragoonCommand::KnownFiles { .. } => write!(f, "known-files"),
+            DragoonCommand::Lifecycle { .. } => write!(f, "lifecycle"),
             DragoonCommand::Listen { .. } => write!(f, "listen"),
+            DragoonCommand::Manifest { .. } => write!(f, "manifest"),
+            DragoonCommand::MirrorStatus { .. } => write!(f, "mirror-status"),
             DragoonCommand::NodeInfo { .. } => write!(f, "node-info"),
+            DragoonCommand::PauseReceiving { .. } => write!(f, "pause-receiving"),
+            DragoonCommand::ProbePeer { .. } => write!(f, "probe-peer"),
+            DragoonCommand::PublishEncodedFile { .. } => write!(f, "publish-encoded-file"),
+            DragoonCommand::ReannounceAll { .. } => write!(f, "reannounce-all"),
+            DragoonCommand::RecodeBlocks { .. } => write!(f, "recode-blocks"),
+            DragoonCommand::RefreshProvide { .. } => write!(f, "refresh-provide"),
             DragoonCommand::RemoveEntryFromSendBlockToSet { .. } => {
                 write!(f, "remove-entry-from-send-block-to-set")
             }
             DragoonCommand::RemoveListener { .. } => write!(f, "remove-listener"),
+            DragoonCommand::ResumeReceiving { .. } => write!(f, "resume-receiving"),
+            DragoonCommand::RotateIdentity { .. } => write!(f, "rotate-identity"),
+            DragoonCommand::ScrubStatus { .. } => write!(f, "scrub-status"),
             DragoonCommand::SendBlockList { .. } => write!(f, "send-block-list"),
+            DragoonCommand::SendBlockResponse { .. } => write!(f, "send-block-response"),
             DragoonCommand::SendBlockTo { .. } => write!(f, "send-block-to"),
+            DragoonCommand::SetPeerQuota { .. } => write!(f, "set-peer-quota"),
+            DragoonCommand::SetReplication { .. } => write!(f, "set-replication"),
+            DragoonCommand::Shutdown { .. } => write!(f, "shutdown"),
             DragoonCommand::StartProvide { .. } => write!(f, "start-provide"),
             DragoonCommand::StopProvide { .. } => write!(f, "stop-provide"),
+            DragoonCommand::UnbanPeer { .. } => write!(f, "unban-peer"),
         }
     }
 }
@@ -245,7 +780,7 @@ async fn command_res_match<E>(
     cmd_name: String,
 ) -> Response
 where
-    E: std::fmt::Debug + Send + Sync + 'static,
+    E: Into<Error>,
 {
     match receiver.await {
         Err(e) => handle_canceled(e, &cmd_name),
@@ -291,9 +826,59 @@ macro_rules! dragoon_command {
     };
 }
 
+/// Same as [`dragoon_command`] for the commands that can run in the background.
+/// With `background`, the command is started as a job, see [`crate::jobs`], and the id of the job is returned right away.
+/// The id of the job is the operation id of the command, one is drawn if none was given.
+macro_rules! dragoon_job_command {
+    ($state:expr, // the current state we are in
+        $background:expr, // whether to return the id of a job instead of the result of the command
+        $operation_id:ident, // the operation id of the command, also used as the job id
+        $variant:ident // the type of DragoonCommand we want to use
+        $(,)?
+        $($variant_args:ident),*) // the parameters for the given variant, besides the operation id
+         => {
+        {
+        if !$background {
+            dragoon_command!($state, $variant, $($variant_args,)* $operation_id)
+        } else {
+            let job_id = $operation_id.unwrap_or_else(progress::new_operation_id);
+            let $operation_id = Some(job_id.clone());
+            let (sender, receiver) = oneshot::channel();
+            let sender = Sender::SenderOneS(sender);
+            let cmd = DragoonCommand::$variant {$($variant_args,)* $operation_id, sender};
+            let cmd_name = cmd.to_string();
+            // registered before the command is sent, so the node can attach the tasks of the command to the job
+            if let Err(e) = $state.jobs.start(&job_id, &cmd_name) {
+                error!("{}", e);
+                return e.into_response();
+            }
+            info!("running command `{}` as the job {}", cmd_name, job_id);
+            let jobs = $state.jobs.clone();
+            let returned_job_id = job_id.clone();
+            match send_command(cmd, $state).await {
+                Some(response) => jobs.complete(&job_id, response).await,
+                None => {
+                    tokio::spawn(async move {
+                        let response = command_res_match(receiver, cmd_name).await;
+                        jobs.complete(&job_id, response).await;
+                    });
+                }
+            }
+            (StatusCode::ACCEPTED, response::Json(Envelope::ok(returned_job_id))).into_response()
+        }
+        }
+    };
+}
+
 // dragoon_command(state, DragoonCommand::Something, peerid, data)
 // Implementation of dragoon commands
 
+#[utoipa::path(
+    post,
+    path = "/add-peer",
+    request_body = String,
+    responses((status = 200, description = "Added the peer to the routing table, an empty string", body = Envelope<String>))
+)]
 pub(crate) async fn create_cmd_add_peer(
     State(state): State<Arc<AppState>>,
     Json(multiaddr): Json<String>,
@@ -302,11 +887,22 @@ pub(crate) async fn create_cmd_add_peer(
     dragoon_command!(state, AddPeer, multiaddr)
 }
 
+#[utoipa::path(
+    get,
+    path = "/bootstrap",
+    responses((status = 200, description = "Bootstrapped the DHT, an empty string", body = Envelope<String>))
+)]
 pub(crate) async fn create_cmd_bootstrap(State(state): State<Arc<AppState>>) -> Response {
     info!("running command `bootstrap`");
     dragoon_command!(state, Bootstrap)
 }
 
+#[utoipa::path(
+    post,
+    path = "/change-available-send-storage",
+    request_body = usize,
+    responses((status = 200, description = "The new storage available to the blocks sent by other peers", body = Envelope<String>))
+)]
 pub(crate) async fn create_cmd_change_available_send_storage(
     State(state): State<Arc<AppState>>,
     Json(new_storage_size): Json<usize>,
@@ -316,6 +912,12 @@ pub(crate) async fn create_cmd_change_available_send_storage(
 }
 
 // ! change this to not longer require block dir and block hashes but just the file hash
+#[utoipa::path(
+    post,
+    path = "/decode-blocks",
+    request_body(content = serde_json::Value, description = "[BLOCK_DIR, BLOCK_HASHES, OUTPUT_FILENAME]"),
+    responses((status = 200, description = "Wrote the decoded file, an empty string", body = Envelope<String>))
+)]
 pub(crate) async fn create_cmd_decode_blocks(
     State(state): State<Arc<AppState>>,
     Json((block_dir, block_hashes, output_filename)): Json<(String, Vec<String>, String)>,
@@ -330,14 +932,67 @@ pub(crate) async fn create_cmd_decode_blocks(
     )
 }
 
+#[utoipa::path(
+    delete,
+    path = "/file/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "What was removed", body = Envelope<DeleteFileOutcome>))
+)]
+pub(crate) async fn create_cmd_delete_file(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `delete_file`");
+    dragoon_command!(state, DeleteFile, file_hash)
+}
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DelegateGetFileParams {
+    /// Replace the file if the node already has it
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/delegate-get-file/{peer_id_base_58}/{file_hash}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer"), ("file_hash" = String, Path, description = "Hash of the file"), DelegateGetFileParams),
+    responses((status = 200, description = "The path of the file sent by the other peer and checked by the node", body = Envelope<String>))
+)]
+pub(crate) async fn create_cmd_delegate_get_file(
+    Path((peer_id_base_58, file_hash)): Path<(String, String)>,
+    Query(DelegateGetFileParams { overwrite }): Query<DelegateGetFileParams>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `delegate_get_file`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, DelegateGetFile, peer_id, file_hash, overwrite)
+}
+
+#[utoipa::path(
+    post,
+    path = "/dial-multiple",
+    params(DialMultipleParams),
+    request_body = Vec<String>,
+    responses((status = 200, description = "The outcome of the dial of each multiaddr", body = Envelope<Vec<DialOutcome>>))
+)]
 pub(crate) async fn create_cmd_dial_multiple(
+    Query(DialMultipleParams { mode }): Query<DialMultipleParams>,
     State(state): State<Arc<AppState>>,
     Json(list_multiaddr): Json<Vec<String>>,
 ) -> Response {
     info!("running command `dial-multiple`");
-    dragoon_command!(state, DialMultiple, list_multiaddr)
+    dragoon_command!(state, DialMultiple, list_multiaddr, mode)
 }
 
+#[utoipa::path(
+    post,
+    path = "/dial-single",
+    request_body = String,
+    responses((status = 200, description = "The base 58 peer id of the dialed peer", body = Envelope<String>))
+)]
 pub(crate) async fn create_cmd_dial_single(
     State(state): State<Arc<AppState>>,
     Json(multiaddr): Json<String>,
@@ -360,7 +1015,169 @@ pub(crate) async fn create_cmd_dial_single(
 //     dragoon_command!(state, DragoonSend, block_hash, block_path, peerid)
 // }
 
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DownloadParams {
+    /// Reconstruct the file again, even if it was already reconstructed by the node
+    #[serde(default)]
+    refresh: bool,
+    /// The `segments_root` returned by the last append-to-file, to get the segments listed by the metadata of the file
+    segments_root: Option<String>,
+    /// Seconds given to receive enough blocks, `--get-file-timeout` if not given
+    timeout: Option<u64>,
+    /// Id under which the progress of the reconstruction can be read with `/progress/{operation_id}`
+    operation_id: Option<String>,
+}
+
+/// The `timeout` of a get-file, download or send-block-list, a command given no time at all could only fail
+pub(crate) fn check_timeout(timeout: Option<u64>) -> Result<(), DragoonError> {
+    if timeout == Some(0) {
+        return Err(DragoonError::BadTimeout(String::from(
+            "The timeout must be at least 1 second",
+        )));
+    }
+    Ok(())
+}
+
+/// Stream the file at `path` as the body of the response
+async fn file_response(path: &StdPath, file_hash: &str) -> std::io::Result<Response> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let chunks = async_stream::stream! {
+        let mut buf = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+        loop {
+            match file.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(read) => yield Ok::<_, std::io::Error>(Bytes::copy_from_slice(&buf[..read])),
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    };
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                String::from("application/octet-stream"),
+            ),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_hash),
+            ),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/download/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file"), DownloadParams),
+    responses((status = 200, description = "The content of the file", body = Vec<u8>, content_type = "application/octet-stream"))
+)]
+pub(crate) async fn create_cmd_download(
+    Path(file_hash): Path<String>,
+    Query(DownloadParams {
+        refresh,
+        segments_root,
+        timeout,
+        operation_id,
+    }): Query<DownloadParams>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `download`");
+    if let Err(e) = check_timeout(timeout) {
+        return e.into_response();
+    }
+    // a file reconstructed by a previous download, or a get-file named after the hash
+    let cached_path = match ask(&state.cmd_sender, |sender| DragoonCommand::GetFileDir {
+        file_hash: file_hash.clone(),
+        sender,
+    })
+    .await
+    {
+        Ok(file_dir) => file_dir.join(&file_hash),
+        Err(e) => return handle_dragoon_error(e, "download"),
+    };
+    // a previous reconstruction may have left out the segments the root asks for
+    let path = if !refresh
+        && segments_root.is_none()
+        && tokio::fs::try_exists(&cached_path).await.unwrap_or(false)
+    {
+        debug!(
+            "Reusing the reconstruction of {} at {:?}",
+            file_hash, cached_path
+        );
+        cached_path
+    } else {
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::GetFile {
+            file_hash: file_hash.clone(),
+            output_filename: file_hash.clone(),
+            output: GetFileOutput::File,
+            order: RetrievalOrder::default(),
+            // the file is named after its hash, so an existing one is the same file
+            overwrite: true,
+            auto_concurrency: false,
+            parallelism: None,
+            decrypt: false,
+            unpack: false,
+            segments_root,
+            timeout,
+            operation_id,
+            sender: Sender::SenderOneS(sender),
+        };
+        if let Some(response) = send_command(cmd, state).await {
+            return response;
+        }
+        match receiver.await {
+            Ok(Ok(GetFileOutcome::Written(path))) => path,
+            Ok(Ok(outcome)) => {
+                return DragoonError::UnexpectedError(format!(
+                    "The file was not written by get-file: {:?}",
+                    outcome
+                ))
+                .into_response()
+            }
+            Ok(Err(e)) => return handle_dragoon_error(e, "download"),
+            Err(e) => return handle_canceled(e, "download"),
+        }
+    };
+    match file_response(&path, &file_hash).await {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = format!("Could not read the file {:?}: {}", path, e);
+            error!(err_msg);
+            DragoonError::UnexpectedError(err_msg).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/encode-file",
+    params(EncodeFileParams),
+    request_body(content = serde_json::Value, description = "[FILE_PATH, REPLACE_BLOCKS, ENCODING_METHOD, K, N]"),
+    responses(
+        (status = 200, description = "The blocks the file was encoded into", body = Envelope<EncodedFile>),
+        (status = 202, description = "The id of the job running the command in the background", body = Envelope<String>)
+    )
+)]
 pub(crate) async fn create_cmd_encode_file(
+    Query(EncodeFileParams {
+        domain,
+        domain_seed,
+        compression,
+        packing,
+        encrypt,
+        archive,
+        operation_id,
+        background,
+    }): Query<EncodeFileParams>,
     State(state): State<Arc<AppState>>,
     Json((file_path, replace_blocks, encoding_method, encode_mat_k, encode_mat_n)): Json<(
         String,
@@ -371,17 +1188,237 @@ pub(crate) async fn create_cmd_encode_file(
     )>,
 ) -> Response {
     info!("running command `encode_file`");
-    dragoon_command!(
+    dragoon_job_command!(
         state,
+        background,
+        operation_id,
         EncodeFile,
         file_path,
         replace_blocks,
         encoding_method,
         encode_mat_k,
-        encode_mat_n
+        encode_mat_n,
+        domain,
+        domain_seed,
+        compression,
+        packing,
+        encrypt,
+        archive
     )
 }
 
+#[utoipa::path(
+    post,
+    path = "/encode-files",
+    params(EncodeFilesParams),
+    request_body(content = serde_json::Value, description = "[FILE_PATHS, REPLACE_BLOCKS, ENCODING_METHOD, K, N], a directory in FILE_PATHS stands for the files directly in it"),
+    responses((status = 200, description = "The blocks each file was encoded into, or why it could not be", body = Envelope<Vec<EncodeFilesOutcome>>))
+)]
+pub(crate) async fn create_cmd_encode_files(
+    Query(EncodeFilesParams {
+        domain,
+        domain_seed,
+        compression,
+        packing,
+        encrypt,
+        parallelism,
+    }): Query<EncodeFilesParams>,
+    State(state): State<Arc<AppState>>,
+    Json((file_paths, replace_blocks, encoding_method, encode_mat_k, encode_mat_n)): Json<(
+        Vec<String>,
+        bool,
+        EncodingMethod,
+        usize,
+        usize,
+    )>,
+) -> Response {
+    info!("running command `encode_files`");
+    dragoon_command!(
+        state,
+        EncodeFiles,
+        file_paths,
+        replace_blocks,
+        encoding_method,
+        encode_mat_k,
+        encode_mat_n,
+        domain,
+        domain_seed,
+        compression,
+        packing,
+        encrypt,
+        parallelism
+    )
+}
+
+/// The encoding of an uploaded file, the same as the post body and parameters of an encode-file
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct UploadAndEncodeParams {
+    k: usize,
+    n: usize,
+    encoding_method: EncodingMethod,
+    #[serde(default)]
+    replace_blocks: bool,
+    #[serde(default)]
+    domain: EvaluationDomain,
+    domain_seed: Option<String>,
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default)]
+    packing: Packing,
+    #[serde(default)]
+    encrypt: bool,
+    operation_id: Option<String>,
+}
+
+/// A body going over `--http-upload-max-body-size` is only noticed while it is read
+fn upload_error(e: MultipartError) -> DragoonError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        DragoonError::PayloadTooLarge(e.to_string())
+    } else {
+        DragoonError::BadUpload(e.to_string())
+    }
+}
+
+/// Write the `file` field of the multipart body in `upload_dir` as it is received, under the name given by the
+/// client so it ends up in the manifest of the file, returns the path and the size of the file
+async fn write_upload(
+    multipart: &mut Multipart,
+    upload_dir: &StdPath,
+) -> Result<(PathBuf, u64), DragoonError> {
+    while let Some(mut field) = multipart.next_field().await.map_err(upload_error)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        // only the last component, a client can't write outside of the upload directory
+        let file_name = field
+            .file_name()
+            .and_then(|file_name| StdPath::new(file_name).file_name())
+            .map(|file_name| file_name.to_os_string())
+            .unwrap_or_else(|| "upload".into());
+        let upload_path = upload_dir.join(file_name);
+        tokio::fs::create_dir_all(upload_dir)
+            .await
+            .map_err(|e| DragoonError::UnexpectedError(e.to_string()))?;
+        let mut file = tokio::fs::File::create(&upload_path)
+            .await
+            .map_err(|e| DragoonError::UnexpectedError(e.to_string()))?;
+        let mut size = 0;
+        while let Some(chunk) = field.chunk().await.map_err(upload_error)? {
+            size += chunk.len() as u64;
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| DragoonError::UnexpectedError(e.to_string()))?;
+        }
+        file.flush()
+            .await
+            .map_err(|e| DragoonError::UnexpectedError(e.to_string()))?;
+        return Ok((upload_path, size));
+    }
+    Err(DragoonError::BadUpload(String::from(
+        "The multipart body has no `file` field",
+    )))
+}
+
+#[utoipa::path(
+    post,
+    path = "/upload-and-encode",
+    params(UploadAndEncodeParams),
+    request_body(content = Vec<u8>, content_type = "multipart/form-data", description = "The file, in a `file` field"),
+    responses((status = 200, description = "The blocks the uploaded file was encoded into", body = Envelope<EncodedFile>))
+)]
+pub(crate) async fn create_cmd_upload_and_encode(
+    Query(UploadAndEncodeParams {
+        k: encode_mat_k,
+        n: encode_mat_n,
+        encoding_method,
+        replace_blocks,
+        domain,
+        domain_seed,
+        compression,
+        packing,
+        encrypt,
+        operation_id,
+    }): Query<UploadAndEncodeParams>,
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Response {
+    info!("running command `upload_and_encode`");
+    let upload_dir = std::env::temp_dir().join(format!(
+        "dragoonfly-upload-{}",
+        progress::new_operation_id()
+    ));
+    let upload_path = match write_upload(&mut multipart, &upload_dir).await {
+        Ok((upload_path, size)) => {
+            info!("Received an upload of {} bytes in {:?}", size, upload_path);
+            upload_path
+        }
+        Err(e) => {
+            error!("{}", e);
+            let _ = tokio::fs::remove_dir_all(&upload_dir).await;
+            return e.into_response();
+        }
+    };
+    let file_path = upload_path.to_string_lossy().to_string();
+    let archive = false;
+    let response = dragoon_command!(
+        state,
+        EncodeFile,
+        file_path,
+        replace_blocks,
+        encoding_method,
+        encode_mat_k,
+        encode_mat_n,
+        domain,
+        domain_seed,
+        compression,
+        packing,
+        encrypt,
+        archive,
+        operation_id
+    );
+    // the blocks are all that is kept of the file, like for a file encoded from the disk of the node
+    if let Err(e) = tokio::fs::remove_dir_all(&upload_dir).await {
+        warn!("Could not remove the upload {:?}: {}", upload_dir, e);
+    }
+    response
+}
+
+#[utoipa::path(
+    post,
+    path = "/append-to-file/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the encoded file")),
+    request_body(content = String, description = "Path of the file to append, on the node"),
+    responses((status = 200, description = "The blocks the appended segment was encoded into and the new segments root", body = Envelope<AppendedSegment>))
+)]
+pub(crate) async fn create_cmd_append_to_file(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(file_path): Json<String>,
+) -> Response {
+    info!("running command `append_to_file`");
+    dragoon_command!(state, AppendToFile, file_hash, file_path)
+}
+
+#[utoipa::path(
+    post,
+    path = "/generate-powers",
+    request_body = usize,
+    responses((status = 200, description = "Wrote the powers, an empty string", body = Envelope<String>))
+)]
+pub(crate) async fn create_cmd_generate_powers(
+    State(state): State<Arc<AppState>>,
+    Json(max_degree): Json<usize>,
+) -> Response {
+    info!("running command `generate_powers`");
+    dragoon_command!(state, GeneratePowers, max_degree)
+}
+
+#[utoipa::path(
+    get,
+    path = "/get-available-send-storage",
+    responses((status = 200, description = "The storage available to the blocks sent by other peers", body = Envelope<usize>))
+)]
 pub(crate) async fn create_cmd_get_available_storage(
     State(state): State<Arc<AppState>>,
 ) -> Response {
@@ -389,6 +1426,19 @@ pub(crate) async fn create_cmd_get_available_storage(
     dragoon_command!(state, GetAvailableStorage)
 }
 
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct GetBlockFromParams {
+    /// Base 58 peer ids separated by commas, requested in order when the previous peer fails to send the block
+    fallback_providers: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/get-block-from/{peer_id_base_58}/{file_hash}/{block_hash}/{save_to_disk}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer"), ("file_hash" = String, Path, description = "Hash of the file"), ("block_hash" = String, Path, description = "Hash of the block"), ("save_to_disk" = bool, Path, description = "Write the block to disk"), GetBlockFromParams),
+    responses((status = 200, description = "The block", body = Envelope<BlockResponse>))
+)]
 pub(crate) async fn create_cmd_get_block_from(
     Path((peer_id_base_58, file_hash, block_hash, save_to_disk)): Path<(
         String,
@@ -396,31 +1446,76 @@ pub(crate) async fn create_cmd_get_block_from(
         String,
         bool,
     )>,
+    Query(GetBlockFromParams { fallback_providers }): Query<GetBlockFromParams>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
     info!("running command `get_block_from`");
     let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
     let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    let fallback_providers = fallback_providers
+        .iter()
+        .flat_map(|peers| peers.split(','))
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| {
+            let bytes = bs58::decode(peer).into_vec().unwrap();
+            PeerId::from_bytes(&bytes).unwrap()
+        })
+        .collect();
     dragoon_command!(
         state,
         GetBlockFrom,
         peer_id,
         file_hash,
         block_hash,
-        save_to_disk
+        save_to_disk,
+        fallback_providers
     )
 }
 
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct GetBlocksInfoFromParams {
+    /// Only ask whether the other peer has this block, the list is empty if it does not
+    block_hash: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/get-blocks-info-from/{peer_id_base_58}/{file_hash}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer"), ("file_hash" = String, Path, description = "Hash of the file"), GetBlocksInfoFromParams),
+    responses((status = 200, description = "The blocks of the file held by the other peer", body = Envelope<PeerBlockInfo>))
+)]
 pub(crate) async fn create_cmd_get_blocks_info_from(
     Path((peer_id_base_58, file_hash)): Path<(String, String)>,
+    Query(GetBlocksInfoFromParams { block_hash }): Query<GetBlocksInfoFromParams>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
     info!("running command `get_blocks_info_from`");
     let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
     let peer_id = PeerId::from_bytes(&bytes).unwrap();
-    dragoon_command!(state, GetBlocksInfoFrom, peer_id, file_hash)
+    dragoon_command!(state, GetBlocksInfoFrom, peer_id, file_hash, block_hash)
+}
+
+#[utoipa::path(
+    get,
+    path = "/find-block/{file_hash}/{block_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file"), ("block_hash" = String, Path, description = "Hash of the block")),
+    responses((status = 200, description = "The peers holding the block", body = Envelope<BlockLocation>))
+)]
+pub(crate) async fn create_cmd_find_block(
+    Path((file_hash, block_hash)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `find_block`");
+    dragoon_command!(state, FindBlock, file_hash, block_hash)
 }
 
+#[utoipa::path(
+    get,
+    path = "/get-block-list/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "The hashes of the blocks of the file held by the node", body = Envelope<Vec<String>>))
+)]
 pub(crate) async fn create_cmd_get_block_list(
     Path(file_hash): Path<String>,
     State(state): State<Arc<AppState>>,
@@ -429,24 +1524,81 @@ pub(crate) async fn create_cmd_get_block_list(
     dragoon_command!(state, GetBlockList, file_hash)
 }
 
+#[utoipa::path(
+    get,
+    path = "/get-connected-peers",
+    responses((status = 200, description = "The base 58 peer ids of the connected peers", body = Envelope<Vec<String>>))
+)]
 pub(crate) async fn create_cmd_get_connected_peers(State(state): State<Arc<AppState>>) -> Response {
     info!("running command `get_connected_peers`");
     dragoon_command!(state, GetConnectedPeers)
 }
 
+#[utoipa::path(
+    get,
+    path = "/get-file/{file_hash}/{output_filename}",
+    params(("file_hash" = String, Path, description = "Hash of the file"), ("output_filename" = String, Path, description = "Name of the written file"), GetFileParams),
+    responses(
+        (status = 200, description = "The path of the file, its hash or whether it can be decoded, depending on `output`", body = Envelope<GetFileOutcome>),
+        (status = 202, description = "The id of the job running the command in the background", body = Envelope<String>)
+    )
+)]
 pub(crate) async fn create_cmd_get_file(
     Path((file_hash, output_filename)): Path<(String, String)>,
+    Query(GetFileParams {
+        output,
+        order,
+        overwrite,
+        auto_concurrency,
+        parallelism,
+        decrypt,
+        unpack,
+        segments_root,
+        timeout,
+        operation_id,
+        background,
+    }): Query<GetFileParams>,
     State(state): State<Arc<AppState>>,
 ) -> Response {
     info!("running command get_file");
-    dragoon_command!(state, GetFile, file_hash, output_filename)
+    if let Err(e) = check_timeout(timeout) {
+        return e.into_response();
+    }
+    dragoon_job_command!(
+        state,
+        background,
+        operation_id,
+        GetFile,
+        file_hash,
+        output_filename,
+        output,
+        order,
+        overwrite,
+        auto_concurrency,
+        parallelism,
+        decrypt,
+        unpack,
+        segments_root,
+        timeout
+    )
 }
 
+#[utoipa::path(
+    get,
+    path = "/get-listeners",
+    responses((status = 200, description = "The multiaddr the node listens on", body = Envelope<Vec<String>>))
+)]
 pub(crate) async fn create_cmd_get_listeners(State(state): State<Arc<AppState>>) -> Response {
     info!("running command `get_listeners`");
     dragoon_command!(state, GetListeners)
 }
 
+#[utoipa::path(
+    post,
+    path = "/get-providers",
+    request_body = String,
+    responses((status = 200, description = "The base 58 peer ids of the providers of the key", body = Envelope<Vec<String>>))
+)]
 pub(crate) async fn create_cmd_get_providers(
     State(state): State<Arc<AppState>>,
     Json(key): Json<String>,
@@ -455,7 +1607,7 @@ pub(crate) async fn create_cmd_get_providers(
     dragoon_command!(state, GetProviders, key)
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub(crate) struct SerNetworkInfo {
     peers: usize,
     pending: u32,
@@ -484,11 +1636,141 @@ impl SerNetworkInfo {
     }
 }
 
+/// A block sent, or planned to be sent, to a peer, see [`SendId`]
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SerSendId {
+    /// The base 58 peer id of the peer
+    peer_id: String,
+    file_hash: String,
+    block_hash: String,
+    /// Number of times the block was sent to the peer, 0 while the send is only planned
+    attempts: u32,
+}
+
+impl SerSendId {
+    pub(crate) fn new(send_id: &SendId) -> Self {
+        SerSendId {
+            peer_id: send_id.peer_id.to_base58(),
+            file_hash: send_id.file_hash.clone(),
+            block_hash: send_id.block_hash.clone(),
+            attempts: send_id.attempts,
+        }
+    }
+}
+
+/// The outcome of a send-block-to
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SerSendBlockTo {
+    /// Whether the peer accepted to store the block
+    accepted: bool,
+    send_id: SerSendId,
+}
+
+impl SerSendBlockTo {
+    pub(crate) fn new((accepted, send_id): &(bool, SendId)) -> Self {
+        SerSendBlockTo {
+            accepted: *accepted,
+            send_id: SerSendId::new(send_id),
+        }
+    }
+}
+
+/// The identity of the node before and after a rotate-identity
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SerIdentityRotation {
+    old_peer_id: String,
+    new_peer_id: String,
+}
+
+impl SerIdentityRotation {
+    pub(crate) fn new((old_peer_id, new_peer_id): &(PeerId, PeerId)) -> Self {
+        SerIdentityRotation {
+            old_peer_id: old_peer_id.to_base58(),
+            new_peer_id: new_peer_id.to_base58(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/get-network-info",
+    responses((status = 200, description = "The peers and connections of the node", body = Envelope<SerNetworkInfo>))
+)]
 pub(crate) async fn create_cmd_get_network_info(State(state): State<Arc<AppState>>) -> Response {
     info!("running command `get_network_info`");
     dragoon_command!(state, GetNetworkInfo)
 }
 
+#[utoipa::path(
+    get,
+    path = "/bandwidth",
+    responses((status = 200, description = "The bytes exchanged in total and with each peer", body = Envelope<BandwidthReport>))
+)]
+pub(crate) async fn create_cmd_get_bandwidth(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `get_bandwidth`");
+    dragoon_command!(state, GetBandwidth)
+}
+
+#[utoipa::path(
+    get,
+    path = "/peer-quotas",
+    responses((status = 200, description = "The quota and usage of each peer", body = Envelope<PeerQuotasInfo>))
+)]
+pub(crate) async fn create_cmd_get_peer_quotas(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `get_peer_quotas`");
+    dragoon_command!(state, GetPeerQuotas)
+}
+
+#[utoipa::path(
+    get,
+    path = "/peer-score/{peer_id_base_58}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer")),
+    responses((status = 200, description = "The reputation of the peer", body = Envelope<PeerReputation>))
+)]
+pub(crate) async fn create_cmd_get_peer_score(
+    Path(peer_id_base_58): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `get_peer_score`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, GetPeerScore, peer_id)
+}
+
+#[utoipa::path(
+    get,
+    path = "/get-replication/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "The replication of the file", body = Envelope<ReplicationStatus>))
+)]
+pub(crate) async fn create_cmd_get_replication(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `get_replication`");
+    dragoon_command!(state, GetReplication, file_hash)
+}
+
+#[utoipa::path(
+    get,
+    path = "/get-global-block-map/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "The peers holding each block of the file", body = Envelope<GlobalBlockMap>))
+)]
+pub(crate) async fn create_cmd_get_global_block_map(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `get_global_block_map`");
+    dragoon_command!(state, GetGlobalBlockMap, file_hash)
+}
+
+#[utoipa::path(
+    get,
+    path = "/listen/{multiaddr}",
+    params(("multiaddr" = String, Path, description = "Multiaddr to listen on")),
+    responses((status = 200, description = "The id of the listener", body = Envelope<u64>))
+)]
 pub(crate) async fn create_cmd_listen(
     Path(multiaddr): Path<String>,
     State(state): State<Arc<AppState>>,
@@ -497,11 +1779,114 @@ pub(crate) async fn create_cmd_listen(
     dragoon_command!(state, Listen, multiaddr)
 }
 
+#[utoipa::path(
+    get,
+    path = "/recent-events",
+    params(RecentEventsParams),
+    responses((status = 200, description = "The last events of the node, the oldest first", body = Envelope<Vec<RecentEvent>>))
+)]
+pub(crate) async fn create_cmd_get_recent_events(
+    Query(RecentEventsParams { since }): Query<RecentEventsParams>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `get_recent_events`");
+    dragoon_command!(state, GetRecentEvents, since)
+}
+
+#[utoipa::path(
+    get,
+    path = "/known-files",
+    responses((status = 200, description = "The files announced on the network", body = Envelope<Vec<KnownFile>>))
+)]
+pub(crate) async fn create_cmd_known_files(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `known_files`");
+    dragoon_command!(state, KnownFiles)
+}
+
+#[utoipa::path(
+    get,
+    path = "/lifecycle/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "The local timeline of the file", body = Envelope<FileLifecycle>))
+)]
+pub(crate) async fn create_cmd_lifecycle(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `lifecycle`");
+    dragoon_command!(state, Lifecycle, file_hash)
+}
+
+#[utoipa::path(
+    get,
+    path = "/manifest/{file_hash}",
+    params(("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "How the file was encoded by the node", body = Envelope<FileManifest>))
+)]
+pub(crate) async fn create_cmd_manifest(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `manifest`");
+    dragoon_command!(state, Manifest, file_hash)
+}
+
+#[utoipa::path(
+    get,
+    path = "/mirror-status",
+    responses((status = 200, description = "The state of the mirroring", body = Envelope<MirrorStatus>))
+)]
+pub(crate) async fn create_cmd_mirror_status(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `mirror_status`");
+    dragoon_command!(state, MirrorStatus)
+}
+
+#[utoipa::path(
+    get,
+    path = "/node-info",
+    responses((status = 200, description = "The identity, storage and block counts of the node", body = Envelope<NodeInfo>))
+)]
 pub(crate) async fn create_cmd_node_info(State(state): State<Arc<AppState>>) -> Response {
     info!("running command `node_info`");
     dragoon_command!(state, NodeInfo)
 }
 
+#[utoipa::path(
+    get,
+    path = "/probe-peer/{peer_id_base_58}/{file_hash}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer"), ("file_hash" = String, Path, description = "Hash of the file")),
+    responses((status = 200, description = "The outcome of the probe", body = Envelope<ProbeReport>))
+)]
+pub(crate) async fn create_cmd_probe_peer(
+    Path((peer_id_base_58, file_hash)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `probe_peer`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, ProbePeer, peer_id, file_hash)
+}
+
+#[utoipa::path(
+    post,
+    path = "/reannounce-all",
+    params(ReannounceParams),
+    responses((status = 200, description = "Whether each key could be announced again", body = Envelope<Vec<ReannounceOutcome>>))
+)]
+pub(crate) async fn create_cmd_reannounce_all(
+    Query(ReannounceParams { local_files }): Query<ReannounceParams>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `reannounce_all`");
+    dragoon_command!(state, ReannounceAll, local_files)
+}
+
+#[utoipa::path(
+    post,
+    path = "/remove-listener",
+    request_body = u64,
+    responses((status = 200, description = "Whether the listener was removed", body = Envelope<bool>))
+)]
 pub(crate) async fn create_cmd_remove_listener(
     State(state): State<Arc<AppState>>,
     Json(listener_id): Json<u64>,
@@ -510,24 +1895,241 @@ pub(crate) async fn create_cmd_remove_listener(
     dragoon_command!(state, RemoveListener, listener_id)
 }
 
+#[utoipa::path(
+    post,
+    path = "/pause-receiving",
+    responses((status = 200, description = "Whether the reception was running before", body = Envelope<bool>))
+)]
+pub(crate) async fn create_cmd_pause_receiving(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `pause_receiving`");
+    dragoon_command!(state, PauseReceiving)
+}
+
+#[utoipa::path(
+    post,
+    path = "/resume-receiving",
+    responses((status = 200, description = "Whether the reception was paused before", body = Envelope<bool>))
+)]
+pub(crate) async fn create_cmd_resume_receiving(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `resume_receiving`");
+    dragoon_command!(state, ResumeReceiving)
+}
+
+#[utoipa::path(
+    post,
+    path = "/rotate-identity",
+    responses((status = 200, description = "The old and new peer ids of the node", body = Envelope<SerIdentityRotation>))
+)]
+pub(crate) async fn create_cmd_rotate_identity(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `rotate_identity`");
+    dragoon_command!(state, RotateIdentity)
+}
+
+#[utoipa::path(
+    get,
+    path = "/scrub-status",
+    responses((status = 200, description = "The last scrub and the quarantined blocks", body = Envelope<ScrubStatus>))
+)]
+pub(crate) async fn create_cmd_scrub_status(State(state): State<Arc<AppState>>) -> Response {
+    info!("running command `scrub_status`");
+    dragoon_command!(state, ScrubStatus)
+}
+
+#[utoipa::path(
+    post,
+    path = "/recode-blocks",
+    request_body(content = serde_json::Value, description = "[FILE_HASH, BLOCK_HASHES, COUNT]"),
+    responses((status = 200, description = "The hashes of the recoded blocks", body = Envelope<Vec<String>>))
+)]
+pub(crate) async fn create_cmd_recode_blocks(
+    State(state): State<Arc<AppState>>,
+    Json((file_hash, block_hashes, count)): Json<(String, Vec<String>, usize)>,
+) -> Response {
+    info!("running command `recode_blocks`");
+    dragoon_command!(state, RecodeBlocks, file_hash, block_hashes, count)
+}
+
+/// The body of a send-block-list, the weights of the peers can be left out unless the strategy is UserWeighted
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SendBlockListBody(
+    StrategyName,
+    String,
+    Vec<String>,
+    #[serde(default)] HashMap<String, u64>,
+);
+
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SendBlockListParams {
+    /// Seconds given to the first loop of the sends, `--send-block-list-timeout` if not given
+    timeout: Option<u64>,
+    /// Id under which the progress of the sending can be read with `/progress/{operation_id}`
+    operation_id: Option<String>,
+    /// Return the id of a job right away instead of waiting for the blocks to be sent
+    #[serde(default)]
+    background: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/send-block-list",
+    params(SendBlockListParams),
+    request_body(content = serde_json::Value, description = "[STRATEGY_NAME, FILE_HASH, BLOCK_HASHES, WEIGHTS]"),
+    responses(
+        (status = 200, description = "Where each block was sent", body = Envelope<Vec<SerSendId>>),
+        (status = 202, description = "The id of the job running the command in the background", body = Envelope<String>)
+    )
+)]
 pub(crate) async fn create_cmd_send_block_list(
+    Query(SendBlockListParams {
+        timeout,
+        operation_id,
+        background,
+    }): Query<SendBlockListParams>,
     State(state): State<Arc<AppState>>,
-    Json((strategy_name, file_hash, block_list)): Json<(StrategyName, String, Vec<String>)>,
+    Json(SendBlockListBody(strategy_name, file_hash, block_list, weights)): Json<SendBlockListBody>,
 ) -> Response {
     info!("running command `send_block_list`");
-    dragoon_command!(state, SendBlockList, strategy_name, file_hash, block_list)
+    if let Err(e) = check_timeout(timeout) {
+        return e.into_response();
+    }
+    if let Err(e) = state.send_strategies.get(&strategy_name) {
+        error!("{}", e);
+        return e.into_response();
+    }
+    dragoon_job_command!(
+        state,
+        background,
+        operation_id,
+        SendBlockList,
+        strategy_name,
+        file_hash,
+        block_list,
+        weights,
+        timeout
+    )
 }
 
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SendBlockToParams {
+    storage_class: Option<StorageClass>,
+    /// Seconds the receiver should keep the block
+    ttl: Option<u64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/send-block-to",
+    params(SendBlockToParams),
+    request_body(content = serde_json::Value, description = "[PEER_ID, FILE_HASH, BLOCK_HASH]"),
+    responses((status = 200, description = "Whether the other peer accepted the block", body = Envelope<SerSendBlockTo>))
+)]
 pub(crate) async fn create_cmd_send_block_to(
     State(state): State<Arc<AppState>>,
+    Query(SendBlockToParams { storage_class, ttl }): Query<SendBlockToParams>,
     Json((peer_id_base_58, file_hash, block_hash)): Json<(String, String, String)>,
 ) -> Response {
     info!("running command `send_block_to`");
     let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
     let peer_id = PeerId::from_bytes(&bytes).unwrap();
-    dragoon_command!(state, SendBlockTo, peer_id, block_hash, file_hash)
+    dragoon_command!(
+        state,
+        SendBlockTo,
+        peer_id,
+        block_hash,
+        file_hash,
+        storage_class,
+        ttl
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/set-peer-quota/{peer_id_base_58}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer")),
+    request_body = Option<usize>,
+    responses((status = 200, description = "Set the quota, an empty string", body = Envelope<String>))
+)]
+pub(crate) async fn create_cmd_set_peer_quota(
+    Path(peer_id_base_58): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(quota): Json<Option<usize>>,
+) -> Response {
+    info!("running command `set_peer_quota`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, SetPeerQuota, peer_id, quota)
 }
 
+#[utoipa::path(
+    post,
+    path = "/ban-peer/{peer_id_base_58}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer")),
+    responses((status = 200, description = "Whether the peer was not banned before", body = Envelope<bool>))
+)]
+pub(crate) async fn create_cmd_ban_peer(
+    Path(peer_id_base_58): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `ban_peer`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, BanPeer, peer_id)
+}
+
+#[utoipa::path(
+    post,
+    path = "/unban-peer/{peer_id_base_58}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer")),
+    responses((status = 200, description = "Whether the peer was banned before", body = Envelope<bool>))
+)]
+pub(crate) async fn create_cmd_unban_peer(
+    Path(peer_id_base_58): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `unban_peer`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, UnbanPeer, peer_id)
+}
+
+#[utoipa::path(
+    post,
+    path = "/disconnect-peer/{peer_id_base_58}",
+    params(("peer_id_base_58" = String, Path, description = "Base 58 peer id of the other peer")),
+    responses((status = 200, description = "Whether the node was connected to the peer", body = Envelope<bool>))
+)]
+pub(crate) async fn create_cmd_disconnect_peer(
+    Path(peer_id_base_58): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `disconnect_peer`");
+    let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
+    let peer_id = PeerId::from_bytes(&bytes).unwrap();
+    dragoon_command!(state, DisconnectPeer, peer_id)
+}
+
+#[utoipa::path(
+    post,
+    path = "/set-replication/{file_hash}/{replication}",
+    params(("file_hash" = String, Path, description = "Hash of the file"), ("replication" = usize, Path, description = "Number of other peers that should hold each block")),
+    responses((status = 200, description = "Set the replication factor, an empty string", body = Envelope<String>))
+)]
+pub(crate) async fn create_cmd_set_replication(
+    Path((file_hash, replication)): Path<(String, usize)>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    info!("running command `set_replication`");
+    dragoon_command!(state, SetReplication, file_hash, replication)
+}
+
+#[utoipa::path(
+    post,
+    path = "/start-provide",
+    request_body = String,
+    responses((status = 200, description = "Provides the key, an empty string", body = Envelope<String>))
+)]
 pub(crate) async fn create_cmd_start_provide(
     State(state): State<Arc<AppState>>,
     Json(key): Json<String>,
@@ -536,6 +2138,12 @@ pub(crate) async fn create_cmd_start_provide(
     dragoon_command!(state, StartProvide, key)
 }
 
+#[utoipa::path(
+    post,
+    path = "/stop-provide",
+    request_body = String,
+    responses((status = 200, description = "Whether the key was provided, and the keys still provided", body = Envelope<StopProvideOutcome>))
+)]
 pub(crate) async fn create_cmd_stop_provide(
     State(state): State<Arc<AppState>>,
     Json(key): Json<String>,
@@ -546,13 +2154,21 @@ pub(crate) async fn create_cmd_stop_provide(
 
 // End of dragoon command implementation
 
+/// The error of a command, answered with the kind and status of the [`DragoonError`] it is, if any
 fn handle_dragoon_error<E>(err: E, command: &str) -> Response
 where
-    E: std::fmt::Debug + Send + Sync + 'static,
+    E: Into<Error>,
 {
-    let err_msg = format!("Got error from command `{}`: {:?}", command, err);
-    error!(err_msg);
-    DragoonError::UnexpectedError(err_msg).into_response()
+    let err: Error = err.into();
+    error!("Got error from command `{}`: {:?}", command, err);
+    match err.downcast::<DragoonError>() {
+        Ok(dragoon_error) => dragoon_error.into_response(),
+        Err(err) => DragoonError::UnexpectedError(format!(
+            "Got error from command `{}`: {:#}",
+            command, err
+        ))
+        .into_response(),
+    }
 }
 
 fn handle_canceled(err: RecvError, command: &str) -> Response {
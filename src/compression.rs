@@ -0,0 +1,145 @@
+//! Compression of the files before they are encoded.
+//!
+//! An encode-file with a `compression` compresses the content of the file before it is encrypted, if asked, and
+//! split into field elements, so fewer and smaller blocks are made for compressible files. The algorithm is recorded
+//! in the manifest of the file and in its metadata in the DHT, and only they tell whether a decoded file is
+//! decompressed, by get-file or decode-blocks: the content of a file that was not compressed is never looked at.
+//! The algorithm is also written in a header in front of the compressed bytes, checked against the recorded one.
+
+use anyhow::{format_err, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Written at the start of the compressed files, followed by the id of the algorithm
+const COMPRESSED_FILE_HEADER: &[u8] = b"dragoonfly-compressed-1";
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Lz4 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::Lz4),
+            id => Err(format_err!("Unknown compression algorithm {}", id)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+/// Compress the content of a file, left as it is without compression
+pub(crate) fn compress(compression: Compression, bytes: Vec<u8>) -> Result<Vec<u8>> {
+    let compressed = match compression {
+        Compression::None => return Ok(bytes),
+        Compression::Zstd => zstd::bulk::compress(&bytes, ZSTD_LEVEL)?,
+        Compression::Lz4 => lz4_flex::compress_prepend_size(&bytes),
+    };
+    Ok([COMPRESSED_FILE_HEADER, &[compression.id()], &compressed].concat())
+}
+
+/// Whether the decoded file starts with the header of `compression`, always true for [`Compression::None`]
+pub(crate) fn has_header(compression: Compression, bytes: &[u8]) -> bool {
+    if compression == Compression::None {
+        return true;
+    }
+    bytes
+        .strip_prefix(COMPRESSED_FILE_HEADER)
+        .and_then(|compressed| compressed.first())
+        .is_some_and(|&id| id == compression.id())
+}
+
+/// Decompress a decoded file with the `compression` recorded for it when it was encoded, left as it is without one
+pub(crate) fn decompress(compression: Compression, bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if compression == Compression::None {
+        return Ok(bytes);
+    }
+    let compressed = bytes
+        .strip_prefix(COMPRESSED_FILE_HEADER)
+        .ok_or_else(|| format_err!("The file was not compressed with {}", compression))?;
+    let (&id, compressed) = compressed
+        .split_first()
+        .ok_or(format_err!("The compressed file is truncated"))?;
+    let header_compression = Compression::from_id(id)?;
+    if header_compression != compression {
+        return Err(format_err!(
+            "The file was compressed with {} instead of {}",
+            header_compression,
+            compression
+        ));
+    }
+    match compression {
+        Compression::None => Ok(compressed.to_vec()),
+        Compression::Zstd => Ok(zstd::stream::decode_all(compressed)?),
+        Compression::Lz4 => lz4_flex::decompress_size_prepended(compressed)
+            .map_err(|e| format_err!("Could not decompress the file: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compressible() -> Vec<u8> {
+        b"dragoonfly ".repeat(1000)
+    }
+
+    #[test]
+    fn roundtrip() {
+        for compression in [Compression::None, Compression::Zstd, Compression::Lz4] {
+            let compressed = compress(compression, compressible()).unwrap();
+            assert_eq!(decompress(compression, compressed).unwrap(), compressible());
+        }
+    }
+
+    #[test]
+    fn none_leaves_the_bytes_as_they_are() {
+        assert_eq!(
+            compress(Compression::None, compressible()).unwrap(),
+            compressible()
+        );
+        // a file that was not compressed is never looked at, even if it starts like a compressed one
+        let bytes = compress(Compression::Zstd, compressible()).unwrap();
+        assert_eq!(decompress(Compression::None, bytes.clone()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn compressed_bytes_are_smaller() {
+        for compression in [Compression::Zstd, Compression::Lz4] {
+            assert!(compress(compression, compressible()).unwrap().len() < compressible().len());
+        }
+    }
+
+    #[test]
+    fn decompress_checks_the_recorded_compression() {
+        let zstd = compress(Compression::Zstd, compressible()).unwrap();
+        assert!(decompress(Compression::Lz4, zstd).is_err());
+        assert!(decompress(Compression::Zstd, compressible()).is_err());
+        assert!(decompress(Compression::Zstd, COMPRESSED_FILE_HEADER.to_vec()).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Configuration file of the node, given with `--config`.
+//!
+//! The file is in TOML and its keys are the names of the options of the command line, e.g. `storage-space = 10`.
+//! An option given on the command line overrides the value of the file. The file also lists the multiaddr the
+//! node listens on and the peers it bootstraps from when it starts, like `--listen` and `--bootstrap-peer`.
+
+use anyhow::{format_err, Result};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tracing::{info, warn};
+
+use crate::commands::{DragoonCommand, Sender};
+use crate::Units;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct NodeConfig {
+    /// Multiaddr the node listens on when it starts
+    pub(crate) listen: Vec<String>,
+    /// Multiaddr of the peers dialed and added to the routing table when the node starts, ending with their peer id
+    pub(crate) bootstrap_peer: Vec<String>,
+    pub(crate) storage_dir: Option<PathBuf>,
+    pub(crate) storage_space: Option<usize>,
+    pub(crate) storage_unit: Option<Units>,
+    pub(crate) powers_path: Option<PathBuf>,
+    pub(crate) label: Option<String>,
+    pub(crate) network_name: Option<String>,
+    pub(crate) ip_port: Option<SocketAddr>,
+    pub(crate) grpc_ip_port: Option<SocketAddr>,
+    pub(crate) tls_cert: Option<PathBuf>,
+    pub(crate) tls_key: Option<PathBuf>,
+    pub(crate) control_socket: Option<PathBuf>,
+    pub(crate) control_socket_only: bool,
+    pub(crate) http_max_body_size: Option<usize>,
+    pub(crate) http_upload_max_body_size: Option<usize>,
+    /// Maximum size of the body of the requests to specific routes, by route name, e.g. `send-block-list`. The
+    /// limits given on the command line are added to these ones.
+    pub(crate) http_route_max_body_size: HashMap<String, usize>,
+    pub(crate) http_rate_limit: Option<u32>,
+    pub(crate) http_rate_burst: Option<u32>,
+    pub(crate) http_request_timeout: Option<u64>,
+    pub(crate) dns_timeout: Option<u64>,
+    pub(crate) get_file_timeout: Option<u64>,
+    pub(crate) send_block_list_timeout: Option<u64>,
+}
+
+impl NodeConfig {
+    pub(crate) async fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .await
+            .map_err(|e| format_err!("Could not read the configuration file {:?}: {}", path, e))?;
+        let config = toml::from_str(&content)
+            .map_err(|e| format_err!("Could not parse the configuration file {:?}: {}", path, e))?;
+        info!("Loaded the configuration file {:?}", path);
+        Ok(config)
+    }
+}
+
+/// The value given on the command line, else the one of the configuration file, else the default of the option
+pub(crate) fn merge<T>(matches: &ArgMatches, id: &str, cli: T, file: Option<T>) -> T {
+    match (matches.value_source(id), file) {
+        (Some(ValueSource::CommandLine), _) | (_, None) => cli,
+        (_, Some(file)) => file,
+    }
+}
+
+/// Send a command to the swarm and wait for its answer
+async fn ask<T>(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    command: impl FnOnce(Sender<T>) -> DragoonCommand,
+) -> Result<T> {
+    let (sender, receiver) = oneshot::channel();
+    cmd_sender
+        .send(command(Sender::SenderOneS(sender)))
+        .map_err(|_| format_err!("Could not send the command to the swarm"))?;
+    receiver
+        .await
+        .map_err(|_| format_err!("The swarm dropped the command"))?
+}
+
+/// Bootstrap from the peers in the background, they may not be up yet and only give a warning
+pub(crate) fn start_node(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    bootstrap_peers: Vec<String>,
+) {
+    if !bootstrap_peers.is_empty() {
+        tokio::spawn(bootstrap(cmd_sender.clone(), bootstrap_peers));
+    }
+}
+
+/// Dial the bootstrap peers and add them to the routing table, then bootstrap the DHT from them
+async fn bootstrap(cmd_sender: UnboundedSender<DragoonCommand>, bootstrap_peers: Vec<String>) {
+    let mut added = 0;
+    for multiaddr in bootstrap_peers {
+        if let Err(e) = ask(&cmd_sender, |sender| DragoonCommand::AddPeer {
+            multiaddr: multiaddr.clone(),
+            sender,
+        })
+        .await
+        {
+            warn!("Could not add the bootstrap peer {}: {}", multiaddr, e);
+            continue;
+        }
+        added += 1;
+        match ask(&cmd_sender, |sender| DragoonCommand::DialSingle {
+            multiaddr: multiaddr.clone(),
+            sender,
+        })
+        .await
+        {
+            Ok(peer_id) => info!("Connected to the bootstrap peer {}", peer_id),
+            Err(e) => warn!("Could not dial the bootstrap peer {}: {}", multiaddr, e),
+        }
+    }
+    if added == 0 {
+        warn!("None of the bootstrap peers could be added");
+        return;
+    }
+    match ask(&cmd_sender, |sender| DragoonCommand::Bootstrap { sender }).await {
+        Ok(()) => info!("Bootstrapping from {} peers", added),
+        Err(e) => warn!("Could not bootstrap: {}", e),
+    }
+}
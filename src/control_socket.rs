@@ -0,0 +1,42 @@
+//! The unix socket the http interface is served on for the tools running on the machine of the node, given with
+//! `--control-socket`.
+//!
+//! The routes, limits and authentication are the same as on `--ip-port`. The socket is only readable and writable
+//! by the user running the node, and a socket left by a previous run is replaced.
+
+use anyhow::{format_err, Result};
+use axum::Router;
+use std::fs::{self as sfs, Permissions};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::shutdown;
+
+/// Bind the socket and spawn the server, binding fails if the path is taken by something else than a socket
+pub(crate) fn serve(path: &Path, router: Router, stop: watch::Receiver<bool>) -> Result<()> {
+    if let Ok(metadata) = sfs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format_err!(
+                "Could not bind the control socket {:?}: the path exists and is not a socket",
+                path
+            ));
+        }
+        sfs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| format_err!("Could not bind the control socket {:?}: {}", path, e))?;
+    sfs::set_permissions(path, Permissions::from_mode(0o600))?;
+    info!("Spawning the http server on the control socket {:?}", path);
+    tokio::spawn(async move {
+        if let Err(error) = axum::serve(listener, router)
+            .with_graceful_shutdown(shutdown::stopping(stop))
+            .await
+        {
+            error!("control socket server error: {}", error);
+        }
+    });
+    Ok(())
+}
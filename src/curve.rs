@@ -0,0 +1,139 @@
+//! The elliptic curve the node encodes, proves and verifies the blocks over, chosen with `--curve`.
+//!
+//! The whole network is generic over the curve, so the curve only picks the types the network runs with. The
+//! blocks of one curve cannot be verified nor decoded with another one, so all the nodes exchanging the blocks of
+//! a file must run with the same curve, and with powers made for it.
+
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_poly::univariate::DensePolynomial;
+use std::any::TypeId;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::dragoon_swarm::DragoonNetwork;
+use crate::powers_file;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Curve {
+    #[default]
+    #[value(name = "bls12-381")]
+    Bls12_381,
+    #[value(name = "bn254")]
+    Bn254,
+    #[value(name = "bls12-377")]
+    Bls12_377,
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Curve::Bls12_381 => write!(f, "bls12-381"),
+            Curve::Bn254 => write!(f, "bn254"),
+            Curve::Bls12_377 => write!(f, "bls12-377"),
+        }
+    }
+}
+
+impl Curve {
+    /// The curve of the scalar field `F`, if the node can run over it
+    pub(crate) fn of<F: PrimeField>() -> Option<Self> {
+        let field = TypeId::of::<F>();
+        if field == TypeId::of::<ark_bls12_381::Fr>() {
+            Some(Curve::Bls12_381)
+        } else if field == TypeId::of::<ark_bn254::Fr>() {
+            Some(Curve::Bn254)
+        } else if field == TypeId::of::<ark_bls12_377::Fr>() {
+            Some(Curve::Bls12_377)
+        } else {
+            None
+        }
+    }
+
+    /// The id of the curve in the powers files, see [`crate::powers_file`]
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Curve::Bls12_381 => 1,
+            Curve::Bn254 => 2,
+            Curve::Bls12_377 => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Curve::Bls12_381),
+            2 => Ok(Curve::Bn254),
+            3 => Ok(Curve::Bls12_377),
+            id => Err(format_err!("Unknown curve {}", id)),
+        }
+    }
+
+    /// Check that the powers are made for the curve, then spawn the network over it
+    pub(crate) async fn spawn_network(
+        self,
+        network: DragoonNetwork,
+        powers_path: PathBuf,
+    ) -> Result<()> {
+        info!("Running the network over the curve {}", self);
+        match self {
+            Curve::Bls12_381 => {
+                spawn_network::<ark_bls12_381::Fr, ark_bls12_381::G1Projective>(
+                    self,
+                    network,
+                    powers_path,
+                )
+                .await
+            }
+            Curve::Bn254 => {
+                spawn_network::<ark_bn254::Fr, ark_bn254::G1Projective>(self, network, powers_path)
+                    .await
+            }
+            Curve::Bls12_377 => {
+                spawn_network::<ark_bls12_377::Fr, ark_bls12_377::G1Projective>(
+                    self,
+                    network,
+                    powers_path,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Write the powers of `input` for the curve to `output` in the format of [`powers_file`]
+    pub(crate) async fn convert_powers(self, input: &Path, output: &Path) -> Result<()> {
+        match self {
+            Curve::Bls12_381 => {
+                powers_file::convert::<ark_bls12_381::Fr, ark_bls12_381::G1Projective>(
+                    input, output, self,
+                )
+                .await
+            }
+            Curve::Bn254 => {
+                powers_file::convert::<ark_bn254::Fr, ark_bn254::G1Projective>(input, output, self)
+                    .await
+            }
+            Curve::Bls12_377 => {
+                powers_file::convert::<ark_bls12_377::Fr, ark_bls12_377::G1Projective>(
+                    input, output, self,
+                )
+                .await
+            }
+        }
+    }
+}
+
+async fn spawn_network<F, G>(
+    curve: Curve,
+    network: DragoonNetwork,
+    powers_path: PathBuf,
+) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    powers_file::check::<F, G>(&powers_path, curve).await?;
+    tokio::spawn(network.run::<F, G, DensePolynomial<F>>());
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Rank diagnostics of a set of blocks, used to explain why a file could not be reconstructed
+
+use ark_ff::PrimeField;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::row_echelon::RowEchelon;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct DecodeDiagnostics {
+    /// Number of linearly independent blocks needed to decode the file
+    pub(crate) k: usize,
+    /// Rank of the matrix made of the linear combinations of all the given blocks
+    pub(crate) rank: usize,
+    /// Blocks that increased the rank, in the order they were given
+    pub(crate) independent_blocks: Vec<String>,
+    /// Blocks whose linear combination can be obtained from the previous blocks, they are useless for decoding
+    pub(crate) dependent_blocks: Vec<String>,
+    /// Minimal number of extra independent blocks that must be downloaded before decoding can succeed
+    pub(crate) missing_independent_blocks: usize,
+}
+
+impl DecodeDiagnostics {
+    /// Computes the rank of the linear combinations of the blocks, see [`RowEchelon`].
+    /// `block_hashes` and `linear_combinations` must be in the same order.
+    pub(crate) fn new<F: PrimeField>(
+        k: usize,
+        block_hashes: &[String],
+        linear_combinations: Vec<Vec<F>>,
+    ) -> Self {
+        let mut basis = RowEchelon::<F>::new();
+        let mut independent_blocks = vec![];
+        let mut dependent_blocks = vec![];
+
+        for (block_hash, combination) in block_hashes.iter().zip(linear_combinations) {
+            if basis.add_row(combination) {
+                independent_blocks.push(block_hash.clone());
+            } else {
+                dependent_blocks.push(block_hash.clone());
+            }
+        }
+
+        let rank = basis.rank();
+        DecodeDiagnostics {
+            k,
+            rank,
+            independent_blocks,
+            dependent_blocks,
+            missing_independent_blocks: k.saturating_sub(rank),
+        }
+    }
+}
+
+impl fmt::Display for DecodeDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rank {} out of the {} required ({} more independent blocks needed), linearly dependent blocks: {:?}",
+            self.rank, self.k, self.missing_independent_blocks, self.dependent_blocks
+        )
+    }
+}
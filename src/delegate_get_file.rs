@@ -0,0 +1,560 @@
+//! Delegated retrieval: a node asks a better connected peer to run a get-file on its behalf,
+//! the peer then streams the reconstructed file back to the node
+//!
+//! The delegate is not trusted: the node writes the file to a temporary file and checks its hash before moving it in
+//! place. The file written by a get-file is not the one of the hash when it was decompressed or has segments, so the
+//! delegate sends the blocks it received instead, and the node decodes and checks them with a get-file of its own.
+
+use anyhow::{format_err, Result};
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use libp2p::{PeerId, Stream, StreamProtocol};
+use libp2p_stream::IncomingStreams;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use strum::FromRepr;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt as TokioAsyncWriteExt;
+use tokio::sync::{mpsc::UnboundedSender, oneshot, Semaphore};
+use tracing::{debug, error, info, warn};
+
+use crate::block_format;
+use crate::block_scheduler::RetrievalOrder;
+use crate::block_store::BlockStore;
+use crate::commands::{DragoonCommand, GetFileOutcome, GetFileOutput, Sender};
+use crate::dragoon_swarm::{hash_file, move_into_place};
+use crate::error::DragoonError::{DelegationRejected, FileHashMismatch};
+
+pub(crate) const DELEGATE_GET_FILE_PROTOCOL: StreamProtocol =
+    StreamProtocol::new("/delegate-get-file/2.0.0");
+
+const MAX_REQUEST_SIZE: usize = 1024; // max size in bytes for a delegation request
+const MAX_ERROR_SIZE: usize = 64 * 1024; // max size in bytes for the error message of a failed retrieval
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Which peers are allowed to ask this node to retrieve a file on their behalf
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DelegationConfig {
+    pub(crate) allowed_peers: HashSet<PeerId>,
+    pub(crate) allow_all: bool,
+}
+
+impl DelegationConfig {
+    fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        self.allow_all || self.allowed_peers.contains(peer_id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DelegateGetFileRequest {
+    file_hash: String,
+}
+
+#[derive(Debug, Clone, Copy, FromRepr)]
+#[repr(u8)]
+enum ExchangeCode {
+    AcceptDelegation,
+    RejectDelegation,
+    /// The reconstructed file follows, its hash is the one of the request
+    SendingFile,
+    /// The blocks of the file follow, the reconstructed file is not the one of the hash
+    SendingBlocks,
+    RetrievalFailed,
+}
+
+async fn read_exchange_code(stream: &mut Stream) -> Result<ExchangeCode> {
+    let mut ser_code = [0u8; 1];
+    stream.read_exact(&mut ser_code).await?;
+    ExchangeCode::from_repr(ser_code[0])
+        .ok_or(format_err!("Unknown ExchangeCode variant {}", ser_code[0]))
+}
+
+async fn write_exchange_code(stream: &mut Stream, code: ExchangeCode) -> Result<()> {
+    stream.write_all(&[code as u8]).await?;
+    Ok(())
+}
+
+/// Logs the progress of the transfer each time another tenth of the file, or of its blocks, went through
+fn log_progress(
+    file_hash: &str,
+    peer_id: &PeerId,
+    done: u64,
+    total: u64,
+    unit: &str,
+    last_tenth: &mut u64,
+) {
+    let tenth = (done * 10).checked_div(total).unwrap_or(10);
+    if tenth > *last_tenth {
+        *last_tenth = tenth;
+        info!(
+            "Delegated retrieval of file {} with {}: {}/{} {} ({}%)",
+            file_hash,
+            peer_id,
+            done,
+            total,
+            unit,
+            tenth * 10
+        );
+    }
+}
+
+/// What the delegate sent, once it is on disk
+enum Received {
+    /// The file, checked against its hash, at the temporary path
+    File,
+    /// The blocks of the file, in its block directory
+    Blocks,
+}
+
+// -------------------- REQUESTER -------------------- //
+
+/// Ask `delegate` to retrieve the file, and write it at `file_path` once it is checked
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn delegate_get_file(
+    mut stream: Stream,
+    delegate: PeerId,
+    file_hash: String,
+    file_path: PathBuf,
+    overwrite: bool,
+    block_dir: PathBuf,
+    block_store: &BlockStore,
+    cmd_sender: UnboundedSender<DragoonCommand>,
+) -> Result<PathBuf> {
+    let tmp_path = file_path.with_file_name(format!(".{}.delegated.tmp", file_hash));
+    let res = delegate_get_file_inner(
+        &mut stream,
+        delegate,
+        &file_hash,
+        &tmp_path,
+        &block_dir,
+        block_store,
+    )
+    .await;
+    let _ = stream.close().await;
+    match res {
+        Ok(Received::File) => {
+            move_into_place(&tmp_path, &file_path, overwrite).await?;
+            Ok(file_path)
+        }
+        Ok(Received::Blocks) => {
+            info!(
+                "Decoding the file {} from the blocks sent by {}",
+                file_hash, delegate
+            );
+            decode_received_blocks(cmd_sender, file_hash, overwrite).await
+        }
+        Err(e) => {
+            // do not leave a partial file behind
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            Err(e)
+        }
+    }
+}
+
+async fn delegate_get_file_inner(
+    stream: &mut Stream,
+    delegate: PeerId,
+    file_hash: &str,
+    tmp_path: &Path,
+    block_dir: &Path,
+    block_store: &BlockStore,
+) -> Result<Received> {
+    let ser_request = serde_json::to_vec(&DelegateGetFileRequest {
+        file_hash: file_hash.to_string(),
+    })?;
+    stream
+        .write_all(&usize::to_be_bytes(ser_request.len()))
+        .await?;
+    stream.write_all(&ser_request).await?;
+
+    match read_exchange_code(stream).await? {
+        ExchangeCode::AcceptDelegation => {
+            info!(
+                "{} accepted to retrieve the file {} for us, waiting for the file",
+                delegate, file_hash
+            );
+        }
+        ExchangeCode::RejectDelegation => {
+            return Err(DelegationRejected(format!(
+                "{} refused to retrieve the file {} for us",
+                delegate, file_hash
+            ))
+            .into());
+        }
+        a => return Err(format_err!("Unexpected ExchangeCode variant {:?}", a)),
+    }
+
+    match read_exchange_code(stream).await? {
+        ExchangeCode::SendingFile => {
+            receive_file(stream, delegate, file_hash, tmp_path).await?;
+            Ok(Received::File)
+        }
+        ExchangeCode::SendingBlocks => {
+            receive_blocks(stream, delegate, file_hash, block_dir, block_store).await?;
+            Ok(Received::Blocks)
+        }
+        ExchangeCode::RetrievalFailed => {
+            let mut ser_error_size = [0u8; size_of::<usize>()];
+            stream.read_exact(&mut ser_error_size).await?;
+            let error_size = usize::from_be_bytes(ser_error_size).min(MAX_ERROR_SIZE);
+            let mut ser_error = vec![0u8; error_size];
+            stream.read_exact(&mut ser_error).await?;
+            Err(format_err!(
+                "{} could not retrieve the file {}: {}",
+                delegate,
+                file_hash,
+                String::from_utf8_lossy(&ser_error)
+            ))
+        }
+        a => Err(format_err!("Unexpected ExchangeCode variant {:?}", a)),
+    }
+}
+
+/// Write the file sent by the delegate at `tmp_path` and check its hash
+async fn receive_file(
+    stream: &mut Stream,
+    delegate: PeerId,
+    file_hash: &str,
+    tmp_path: &Path,
+) -> Result<()> {
+    let mut ser_file_size = [0u8; size_of::<u64>()];
+    stream.read_exact(&mut ser_file_size).await?;
+    let file_size = u64::from_be_bytes(ser_file_size);
+    debug!(
+        "Receiving the file {} of {} bytes from {} at {:?}",
+        file_hash, file_size, delegate, tmp_path
+    );
+
+    let mut file = File::create(tmp_path).await?;
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut received = 0u64;
+    let mut last_tenth = 0;
+    while received < file_size {
+        let to_read = (file_size - received).min(CHUNK_SIZE as u64) as usize;
+        stream.read_exact(&mut chunk[..to_read]).await?;
+        file.write_all(&chunk[..to_read]).await?;
+        received += to_read as u64;
+        log_progress(
+            file_hash,
+            &delegate,
+            received,
+            file_size,
+            "bytes",
+            &mut last_tenth,
+        );
+    }
+    file.sync_all().await?;
+
+    let received_hash = hash_file(&tokio::fs::read(tmp_path).await?);
+    if received_hash != file_hash {
+        warn!(
+            "The file sent by {} has the hash {} instead of {}",
+            delegate, received_hash, file_hash
+        );
+        return Err(FileHashMismatch {
+            expected: file_hash.to_string(),
+            decoded: received_hash,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Write the blocks sent by the delegate in the block directory of the file, they are checked when they are decoded
+async fn receive_blocks(
+    stream: &mut Stream,
+    delegate: PeerId,
+    file_hash: &str,
+    block_dir: &Path,
+    block_store: &BlockStore,
+) -> Result<()> {
+    let mut ser_number_of_blocks = [0u8; size_of::<u64>()];
+    stream.read_exact(&mut ser_number_of_blocks).await?;
+    let number_of_blocks = u64::from_be_bytes(ser_number_of_blocks);
+    debug!(
+        "Receiving {} blocks of the file {} from {} in {:?}",
+        number_of_blocks, file_hash, delegate, block_dir
+    );
+
+    tokio::fs::create_dir_all(block_dir).await?;
+    let mut last_tenth = 0;
+    for received in 1..=number_of_blocks {
+        let mut ser_block_size = [0u8; size_of::<u64>()];
+        stream.read_exact(&mut ser_block_size).await?;
+        let mut ser_block = vec![];
+        (&mut *stream)
+            .take(u64::from_be_bytes(ser_block_size))
+            .read_to_end(&mut ser_block)
+            .await?;
+        let block_hash = block_format::block_hash(&ser_block)?;
+        block_store
+            .write_async(&block_dir.join(block_hash), &ser_block)
+            .await?;
+        log_progress(
+            file_hash,
+            &delegate,
+            received,
+            number_of_blocks,
+            "blocks",
+            &mut last_tenth,
+        );
+    }
+    Ok(())
+}
+
+/// Decode the file from the blocks on disk with a get-file, which checks the blocks and the hash of the file
+async fn decode_received_blocks(
+    cmd_sender: UnboundedSender<DragoonCommand>,
+    file_hash: String,
+    overwrite: bool,
+) -> Result<PathBuf> {
+    let (get_file_sender, get_file_recv) = oneshot::channel();
+    cmd_sender
+        .send(DragoonCommand::GetFile {
+            file_hash: file_hash.clone(),
+            output_filename: file_hash.clone(),
+            output: GetFileOutput::File,
+            order: RetrievalOrder::default(),
+            overwrite,
+            auto_concurrency: false,
+            parallelism: None,
+            decrypt: false,
+            unpack: false,
+            segments_root: None,
+            timeout: None,
+            operation_id: None,
+            sender: Sender::SenderOneS(get_file_sender),
+        })
+        .map_err(|_| {
+            format_err!(
+                "Could not send the command to decode the file {}",
+                file_hash
+            )
+        })?;
+    match get_file_recv.await?? {
+        GetFileOutcome::Written(file_path) => Ok(file_path),
+        outcome => Err(format_err!(
+            "The get file did not write the file: {:?}",
+            outcome
+        )),
+    }
+}
+
+// -------------------- DELEGATE -------------------- //
+
+#[derive(Clone)]
+pub(crate) struct DelegateGetFileHandler {}
+
+/// An async handler to spawn on a node to answer the delegated retrievals of other nodes
+impl DelegateGetFileHandler {
+    pub(crate) fn run(
+        mut incoming_streams: IncomingStreams,
+        cmd_sender: UnboundedSender<DragoonCommand>,
+        config: Arc<DelegationConfig>,
+        block_store: Arc<BlockStore>,
+    ) -> Result<()> {
+        tokio::spawn(async move {
+            //allow at most 4 delegated retrievals at once, each of them is a full get-file
+            let max_delegations = 4;
+            let semaphore = Arc::new(Semaphore::new(max_delegations));
+            loop {
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                if let Some((peer, stream)) = incoming_streams.next().await {
+                    let cmd_sender = cmd_sender.clone();
+                    let config = config.clone();
+                    let block_store = block_store.clone();
+                    tokio::spawn(async move {
+                        match Self::handle_delegation(stream, peer, cmd_sender, config, &block_store)
+                            .await
+                        {
+                            Ok(_) => debug!("Finished the delegated retrieval for {}", peer),
+                            Err(e) => error!(
+                                "The delegated retrieval for {} has been dropped due to an handling error: {}",
+                                peer, e
+                            ),
+                        }
+                        drop(permit);
+                    });
+                } else {
+                    debug!("We are done with the streams for the delegated retrievals");
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn handle_delegation(
+        mut stream: Stream,
+        peer: PeerId,
+        cmd_sender: UnboundedSender<DragoonCommand>,
+        config: Arc<DelegationConfig>,
+        block_store: &BlockStore,
+    ) -> Result<()> {
+        let res =
+            Self::handle_delegation_inner(&mut stream, peer, cmd_sender, config, block_store).await;
+        stream.close().await?;
+        res
+    }
+
+    async fn handle_delegation_inner(
+        stream: &mut Stream,
+        peer: PeerId,
+        cmd_sender: UnboundedSender<DragoonCommand>,
+        config: Arc<DelegationConfig>,
+        block_store: &BlockStore,
+    ) -> Result<()> {
+        let mut ser_request_size = [0u8; size_of::<usize>()];
+        stream.read_exact(&mut ser_request_size).await?;
+        let request_size = usize::from_be_bytes(ser_request_size);
+        if request_size > MAX_REQUEST_SIZE {
+            return Err(format_err!(
+                "The delegation request's size of {} was bigger than the maximum size of {}",
+                request_size,
+                MAX_REQUEST_SIZE,
+            ));
+        }
+        let mut ser_request = vec![0u8; request_size];
+        stream.read_exact(&mut ser_request).await?;
+        let DelegateGetFileRequest { file_hash } = serde_json::from_slice(&ser_request)?;
+
+        if !config.is_allowed(&peer) {
+            warn!(
+                "Rejected the delegated retrieval of file {} from {}, the peer is not allowed to delegate",
+                file_hash, peer
+            );
+            write_exchange_code(stream, ExchangeCode::RejectDelegation).await?;
+            return Ok(());
+        }
+        write_exchange_code(stream, ExchangeCode::AcceptDelegation).await?;
+        info!(
+            "Starting the delegated retrieval of file {} for {}",
+            file_hash, peer
+        );
+
+        let (get_file_sender, get_file_recv) = oneshot::channel();
+        if cmd_sender
+            .send(DragoonCommand::GetFile {
+                file_hash: file_hash.clone(),
+                output_filename: file_hash.clone(),
+                output: GetFileOutput::File,
+                order: RetrievalOrder::default(),
+                // the file is named after its hash, so an existing one is the same file
+                overwrite: true,
+                auto_concurrency: false,
+                parallelism: None,
+                decrypt: false,
+                unpack: false,
+                segments_root: None,
+                timeout: None,
+                operation_id: None,
+                sender: Sender::SenderOneS(get_file_sender),
+            })
+            .is_err()
+        {
+            let err_msg = "Could not send the command to get the file";
+            write_failure(stream, err_msg).await?;
+            return Err(format_err!(err_msg));
+        }
+        let file_path = match get_file_recv.await {
+            Ok(Ok(GetFileOutcome::Written(file_path))) => file_path,
+            Ok(Ok(outcome)) => {
+                let err_msg = format!("The get file did not write the file: {:?}", outcome);
+                write_failure(stream, &err_msg).await?;
+                return Err(format_err!(err_msg));
+            }
+            Ok(Err(e)) => {
+                write_failure(stream, &e.to_string()).await?;
+                return Err(e);
+            }
+            Err(e) => {
+                let err_msg = format!("The get file command was canceled: {}", e);
+                write_failure(stream, &err_msg).await?;
+                return Err(format_err!(err_msg));
+            }
+        };
+
+        let bytes = tokio::fs::read(&file_path).await?;
+        if hash_file(&bytes) == file_hash {
+            Self::send_file(stream, peer, &file_hash, &bytes).await
+        } else {
+            // decompressed or followed by its segments, the peer could not check it against its hash
+            let block_dir = file_path
+                .parent()
+                .ok_or_else(|| format_err!("The file {:?} has no directory", file_path))?
+                .join("blocks");
+            Self::send_blocks(stream, peer, &file_hash, &block_dir, block_store).await
+        }
+    }
+
+    async fn send_file(
+        stream: &mut Stream,
+        peer: PeerId,
+        file_hash: &str,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let file_size = bytes.len() as u64;
+        write_exchange_code(stream, ExchangeCode::SendingFile).await?;
+        stream.write_all(&u64::to_be_bytes(file_size)).await?;
+        let mut sent = 0u64;
+        let mut last_tenth = 0;
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            stream.write_all(chunk).await?;
+            sent += chunk.len() as u64;
+            log_progress(file_hash, &peer, sent, file_size, "bytes", &mut last_tenth);
+        }
+        Ok(())
+    }
+
+    /// Send the blocks kept by the get-file, all of them were checked against their proof when they were received
+    async fn send_blocks(
+        stream: &mut Stream,
+        peer: PeerId,
+        file_hash: &str,
+        block_dir: &Path,
+        block_store: &BlockStore,
+    ) -> Result<()> {
+        let mut block_hashes = vec![];
+        let mut entries = tokio::fs::read_dir(block_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            block_hashes.push(entry.file_name().to_string_lossy().to_string());
+        }
+        let number_of_blocks = block_hashes.len() as u64;
+        write_exchange_code(stream, ExchangeCode::SendingBlocks).await?;
+        stream
+            .write_all(&u64::to_be_bytes(number_of_blocks))
+            .await?;
+        let mut sent = 0u64;
+        let mut last_tenth = 0;
+        for block_hash in block_hashes {
+            let ser_block = block_store.read_async(&block_dir.join(&block_hash)).await?;
+            stream
+                .write_all(&u64::to_be_bytes(ser_block.len() as u64))
+                .await?;
+            stream.write_all(&ser_block).await?;
+            sent += 1;
+            log_progress(
+                file_hash,
+                &peer,
+                sent,
+                number_of_blocks,
+                "blocks",
+                &mut last_tenth,
+            );
+        }
+        Ok(())
+    }
+}
+
+async fn write_failure(stream: &mut Stream, err_msg: &str) -> Result<()> {
+    write_exchange_code(stream, ExchangeCode::RetrievalFailed).await?;
+    let ser_error = err_msg.as_bytes();
+    let ser_error = &ser_error[..ser_error.len().min(MAX_ERROR_SIZE)];
+    stream
+        .write_all(&usize::to_be_bytes(ser_error.len()))
+        .await?;
+    stream.write_all(ser_error).await?;
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Record store of the DHT that survives a restart of the node.
+//!
+//! The records are kept in a [`MemoryStore`], and the keys of the provider records are tracked next to it
+//! so the whole store can be listed. The store and the routing table are written to `dht_store.json`,
+//! every `--dht-snapshot-interval` and when the node stops, then put back when the node starts, so a
+//! restarted node knows its peers and the providers right away instead of waiting for the DHT walks.
+
+use anyhow::Result;
+use chrono::Utc;
+use libp2p::kad::store::{self, MemoryStore, RecordStore};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tracing::{info, warn};
+
+const DHT_STORE_FILE_NAME: &str = "dht_store.json";
+
+pub(crate) fn snapshot_path(file_dir: &Path) -> PathBuf {
+    file_dir.join(DHT_STORE_FILE_NAME)
+}
+
+pub(crate) struct DhtStore {
+    inner: MemoryStore,
+    provider_keys: HashSet<RecordKey>,
+}
+
+impl DhtStore {
+    pub(crate) fn new(local_peer_id: PeerId) -> Self {
+        DhtStore {
+            inner: MemoryStore::new(local_peer_id),
+            provider_keys: Default::default(),
+        }
+    }
+
+    /// All the provider records of the store, ours and the ones of the other peers
+    fn all_providers(&self) -> Vec<ProviderRecord> {
+        self.provider_keys
+            .iter()
+            .flat_map(|key| self.inner.providers(key))
+            .collect()
+    }
+}
+
+impl RecordStore for DhtStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        self.inner.put(r)
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k)
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        let key = record.key.clone();
+        self.inner.add_provider(record)?;
+        self.provider_keys.insert(key);
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p);
+        if self.inner.providers(k).is_empty() {
+            self.provider_keys.remove(k);
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct DhtSnapshot {
+    routing_table: Vec<SnapshotPeer>,
+    records: Vec<SnapshotRecord>,
+    providers: Vec<SnapshotProvider>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotPeer {
+    peer_id_base_58: String,
+    addrs: Vec<Multiaddr>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<String>,
+    /// Unix time in seconds after which the record is dropped
+    expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotProvider {
+    key: Vec<u8>,
+    provider_base_58: String,
+    addresses: Vec<Multiaddr>,
+    expires_at: Option<i64>,
+}
+
+fn parse_peer_id(peer_id_base_58: &str) -> Option<PeerId> {
+    let bytes = bs58::decode(peer_id_base_58).into_vec().ok()?;
+    PeerId::from_bytes(&bytes).ok()
+}
+
+/// The monotonic expiry of the store as a unix time that is still meaningful after a restart
+fn to_unix_time(expires: Option<Instant>) -> Option<i64> {
+    expires.map(|expires| {
+        let remaining = expires.saturating_duration_since(Instant::now());
+        Utc::now().timestamp() + remaining.as_secs() as i64
+    })
+}
+
+/// `None` if the time has passed
+fn from_unix_time(expires_at: Option<i64>) -> Option<Option<Instant>> {
+    match expires_at {
+        None => Some(None),
+        Some(expires_at) => {
+            let remaining = expires_at - Utc::now().timestamp();
+            (remaining > 0).then(|| Some(Instant::now() + Duration::from_secs(remaining as u64)))
+        }
+    }
+}
+
+impl DhtSnapshot {
+    pub(crate) fn take(store: &DhtStore, routing_table: Vec<(PeerId, Vec<Multiaddr>)>) -> Self {
+        DhtSnapshot {
+            routing_table: routing_table
+                .into_iter()
+                .map(|(peer_id, addrs)| SnapshotPeer {
+                    peer_id_base_58: peer_id.to_base58(),
+                    addrs,
+                })
+                .collect(),
+            records: store
+                .records()
+                .map(|record| SnapshotRecord {
+                    key: record.key.to_vec(),
+                    value: record.value.clone(),
+                    publisher: record.publisher.map(|peer_id| peer_id.to_base58()),
+                    expires_at: to_unix_time(record.expires),
+                })
+                .collect(),
+            providers: store
+                .all_providers()
+                .into_iter()
+                .map(|record| SnapshotProvider {
+                    key: record.key.to_vec(),
+                    provider_base_58: record.provider.to_base58(),
+                    addresses: record.addresses,
+                    expires_at: to_unix_time(record.expires),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) async fn save(&self, path: &Path) -> Result<()> {
+        // written next to the snapshot then renamed, so a crash never leaves a partial snapshot
+        let mut tmp_path = path.to_path_buf();
+        tmp_path.set_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+
+    /// Read the snapshot of the last run, an empty one if there is none or it cannot be read
+    pub(crate) fn load(path: &Path) -> Self {
+        match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Could not parse the DHT snapshot {:?}: {}", path, e);
+                DhtSnapshot::default()
+            }),
+            Err(_) => DhtSnapshot::default(),
+        }
+    }
+
+    /// The peers of the routing table
+    pub(crate) fn peers(&self) -> impl Iterator<Item = (PeerId, &[Multiaddr])> {
+        self.routing_table
+            .iter()
+            .filter_map(|peer| Some((parse_peer_id(&peer.peer_id_base_58)?, &peer.addrs[..])))
+    }
+
+    /// Put the records and the provider records that did not expire back in the store
+    pub(crate) fn restore(&self, store: &mut DhtStore) {
+        let mut restored = 0;
+        for record in &self.records {
+            let Some(expires) = from_unix_time(record.expires_at) else {
+                continue;
+            };
+            let restored_record = Record {
+                key: RecordKey::new(&record.key),
+                value: record.value.clone(),
+                publisher: record.publisher.as_deref().and_then(parse_peer_id),
+                expires,
+            };
+            match store.put(restored_record) {
+                Ok(()) => restored += 1,
+                Err(e) => warn!("Could not restore a record of the DHT: {:?}", e),
+            }
+        }
+        for provider in &self.providers {
+            let (Some(expires), Some(provider_id)) = (
+                from_unix_time(provider.expires_at),
+                parse_peer_id(&provider.provider_base_58),
+            ) else {
+                continue;
+            };
+            let record = ProviderRecord {
+                key: RecordKey::new(&provider.key),
+                provider: provider_id,
+                expires,
+                addresses: provider.addresses.clone(),
+            };
+            match store.add_provider(record) {
+                Ok(()) => restored += 1,
+                Err(e) => warn!("Could not restore a provider record of the DHT: {:?}", e),
+            }
+        }
+        info!(
+            "Restored {} records and {} peers of the DHT",
+            restored,
+            self.routing_table.len()
+        );
+    }
+}
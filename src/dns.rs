@@ -0,0 +1,137 @@
+//! Resolution of the `/dns`, `/dns4` and `/dns6` components of the multiaddr to dial.
+//!
+//! The names are resolved by the node before dialing, instead of by the transport, so the timeout, the
+//! preferred address family and the caching of the answers can be configured.
+//! `/dnsaddr` components are left to the transport, they are resolved through TXT records.
+
+use anyhow::{format_err, Result};
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{debug, info};
+
+/// The address family tried first when a `/dns` name resolves to both
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub(crate) enum DnsFamily {
+    /// Keep the order of the system resolver
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DnsConfig {
+    pub(crate) timeout: Duration,
+    pub(crate) prefer: DnsFamily,
+    /// How long an answer is reused, the cache is disabled when zero
+    pub(crate) cache_ttl: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct DnsResolver {
+    config: DnsConfig,
+    cache: Mutex<HashMap<String, (Instant, Vec<IpAddr>)>>,
+}
+
+/// Whether the multiaddr has a name that is resolved by [`DnsResolver::resolve`]
+pub(crate) fn needs_resolution(multiaddr: &Multiaddr) -> bool {
+    multiaddr.iter().any(|protocol| {
+        matches!(
+            protocol,
+            Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_)
+        )
+    })
+}
+
+impl DnsResolver {
+    pub(crate) fn new(config: DnsConfig) -> Self {
+        DnsResolver {
+            config,
+            cache: Default::default(),
+        }
+    }
+
+    /// The multiaddr with its name replaced by each of the addresses it resolves to, in the order they should be dialed
+    pub(crate) async fn resolve(&self, multiaddr: &Multiaddr) -> Result<Vec<Multiaddr>> {
+        let Some((position, name, family)) =
+            multiaddr
+                .iter()
+                .enumerate()
+                .find_map(|(position, protocol)| match protocol {
+                    Protocol::Dns(name) => Some((position, name.to_string(), DnsFamily::Any)),
+                    Protocol::Dns4(name) => Some((position, name.to_string(), DnsFamily::Ipv4)),
+                    Protocol::Dns6(name) => Some((position, name.to_string(), DnsFamily::Ipv6)),
+                    _ => None,
+                })
+        else {
+            return Ok(vec![multiaddr.clone()]);
+        };
+
+        let mut ips: Vec<IpAddr> = self
+            .lookup(&name)
+            .await?
+            .into_iter()
+            .filter(|ip| match family {
+                DnsFamily::Any => true,
+                DnsFamily::Ipv4 => ip.is_ipv4(),
+                DnsFamily::Ipv6 => ip.is_ipv6(),
+            })
+            .collect();
+        match self.config.prefer {
+            DnsFamily::Any => {}
+            DnsFamily::Ipv4 => ips.sort_by_key(|ip| ip.is_ipv6()),
+            DnsFamily::Ipv6 => ips.sort_by_key(|ip| ip.is_ipv4()),
+        }
+        if ips.is_empty() {
+            return Err(format_err!(
+                "{} has no address of the family asked by {}",
+                name,
+                multiaddr
+            ));
+        }
+
+        Ok(ips
+            .into_iter()
+            .map(|ip| {
+                multiaddr
+                    .iter()
+                    .enumerate()
+                    .map(|(i, protocol)| match (i == position, ip) {
+                        (false, _) => protocol,
+                        (true, IpAddr::V4(ip)) => Protocol::Ip4(ip),
+                        (true, IpAddr::V6(ip)) => Protocol::Ip6(ip),
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    async fn lookup(&self, name: &str) -> Result<Vec<IpAddr>> {
+        if let Some((resolved_at, ips)) = self.cache.lock().unwrap().get(name) {
+            if resolved_at.elapsed() < self.config.cache_ttl {
+                debug!("Using the cached addresses of {}: {:?}", name, ips);
+                return Ok(ips.clone());
+            }
+        }
+
+        let ips: Vec<IpAddr> =
+            time::timeout(self.config.timeout, tokio::net::lookup_host((name, 0)))
+                .await
+                .map_err(|_| format_err!("No answer for {} after {:?}", name, self.config.timeout))?
+                .map_err(|e| format_err!("Could not resolve {}: {}", name, e))?
+                .map(|socket_addr| socket_addr.ip())
+                .collect();
+        info!("{} resolved to {:?}", name, ips);
+        if !self.config.cache_ttl.is_zero() {
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, (resolved_at, _)| resolved_at.elapsed() < self.config.cache_ttl);
+            cache.insert(name.to_string(), (Instant::now(), ips.clone()));
+        }
+        Ok(ips)
+    }
+}
@@ -1,60 +1,126 @@
 use anyhow::{self, format_err, Result};
 use futures::pin_mut;
 use futures::prelude::*;
-use futures::stream::{self as f_stream, BoxStream, FusedStream};
+use futures::stream::{BoxStream, FusedStream, FuturesUnordered};
 use libp2p::core::ConnectedPoint;
 use tokio::fs as tfs;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::{
     mpsc::{self, UnboundedReceiver, UnboundedSender},
-    oneshot,
+    oneshot, Semaphore,
 };
+use tokio::task::JoinHandle;
 use tokio::time;
 
-use libp2p::core::transport::ListenerId;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::{Boxed, ListenerId};
+use libp2p::core::{upgrade, Transport as _};
 use libp2p::identity::Keypair;
-use libp2p::kad::{QueryId, QueryResult};
+use libp2p::kad::{store::RecordStore, QueryId, QueryResult};
 use libp2p::request_response::{Event, Message, OutboundRequestId, ResponseChannel};
 use libp2p::{
+    allow_block_list::{self, BlockedPeers},
     core::Multiaddr,
+    gossipsub::{self, IdentTopic},
     identify, kad,
     multiaddr::Protocol,
-    noise,
+    noise, ping, quic,
     request_response::{self, ProtocolSupport},
-    swarm::{NetworkBehaviour, Swarm, SwarmEvent},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        NetworkBehaviour, Swarm, SwarmEvent,
+    },
     tcp, yamux, PeerId, StreamProtocol, TransportError,
 };
 use libp2p_stream as stream;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs as sfs;
-use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::pin::Pin;
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicUsize, Ordering},
     Arc,
 };
 use std::time::Duration;
 use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
 
-use crate::commands::{sender_send_match, DragoonCommand, EncodingMethod, Sender, SenderMPSC};
+use crate::archive;
+use crate::bandwidth::Bandwidth;
+use crate::banned_peers::BannedPeers;
+use crate::block_format;
+use crate::block_quota::{BlockLimits, BlockQuota};
+use crate::block_repair::{self, RepairConfig};
+use crate::block_scheduler::{
+    BlockScheduler, ConcurrencyController, ContiguousProgress, RetrievalOrder,
+};
+use crate::block_serving::{BlockServing, BlockServingLimits};
+use crate::block_store::BlockStore;
+use crate::commands::{
+    sender_send_match, AppendedSegment, DeleteFileOutcome, DialMode, DialOutcome, DragoonCommand,
+    EncodeFilesOutcome, EncodedFile, EncodingMethod, EvaluationDomain, GetFileOutcome,
+    GetFileOutput, NodeInfo, ReannounceOutcome, Sender, SenderMPSC, StopProvideOutcome,
+};
+use crate::compression::{self, Compression};
+use crate::decode_diagnostics::DecodeDiagnostics;
+use crate::delegate_get_file::{
+    self, DelegateGetFileHandler, DelegationConfig, DELEGATE_GET_FILE_PROTOCOL,
+};
+use crate::dht_store::{self, DhtSnapshot, DhtStore};
+use crate::dns::{self, DnsConfig, DnsResolver};
+use crate::error::DialErrorKind;
 use crate::error::DragoonError::{
-    self, BadListener, BootstrapError, CouldNotSendBlockResponse, CouldNotSendInfoResponse,
-    DialError, NoParentDirectory, ProviderError, SendBlockToAlreadyStarted, SendBlockToError,
+    self, BadListener, BadStrategyWeights, BootstrapError, CouldNotSendBlockResponse,
+    CouldNotSendInfoResponse, DecodeError, DialError, FileExists, FileHashMismatch,
+    IdentityRotationError, NoParentDirectory, ProviderError, SendBlockOpenStreamError,
+    SendBlockToAlreadyStarted, SendBlockToError, UnknownPeer,
+};
+use crate::file_announce::{FileAnnouncement, KnownFiles};
+use crate::file_encryption;
+use crate::file_locks::FileLocks;
+use crate::file_metadata::{self, FileMetadata};
+use crate::identity_rotation::{
+    save_identity_link, IdentityLink, IdentityRotationAck, IdentityRotationNotice,
 };
+use crate::jobs::JobManager;
+use crate::keystore::Keystore;
+use crate::known_peers::KnownPeers;
+use crate::lifecycle::{self, LifecycleStage};
+use crate::manifest::{self, FileManifest};
+use crate::metrics::Metrics;
+use crate::mirror::{self, Mirror, MirrorConfig, MIRROR_PROTOCOL};
+use crate::packing::{self, Packing, PackingReport};
 use crate::peer_block_info::PeerBlockInfo;
-use crate::send_block_to::{self, SendBlockHandler};
-use crate::send_strategy::{SendId, SendStrategy};
-use crate::send_strategy_impl::{self, StrategyName};
+use crate::peer_exchange::{
+    NodeRole, PexPeer, PexRequest, PexResponse, MAX_PEX_PEERS, PEX_TARGET_CONNECTIONS,
+};
+use crate::peer_probe;
+use crate::peer_quota::PeerQuotas;
+use crate::peer_reputation::{PeerBehaviour, PeerReputations};
+use crate::pending::PendingMap;
+use crate::powers_cache::PowersCache;
+use crate::powers_file;
+use crate::progress::{ProgressHandle, ProgressRegistry};
+use crate::provided_keys::ProvidedKeys;
+use crate::recent_events::{RecentEventKind, RecentEvents};
+use crate::replication::{self, ReplicationFactors};
+use crate::row_echelon::RowEchelon;
+use crate::scrub::Scrubber;
+use crate::send_block_to::{
+    self, BlockExpiryConfig, OpenStreamFailure, SendAccounting, SendBlockHandler, SendRetryPolicy,
+};
+use crate::send_strategy::{PeerKnowledge, SendId, StrategyRegistry};
+use crate::send_strategy_impl::StrategyName;
+use crate::shutdown::InFlight;
+use crate::storage_class::{StorageClass, StorageClasses};
+use crate::systematic;
+use crate::wire_codec::{self, WireFormat};
 
 use komodo::{
     self,
     algebra::linalg::Matrix,
-    fec::{self, Shard},
-    fs,
+    fec::Shard,
     semi_avid::{verify, Block},
-    zk::Powers,
 };
 
 use resolve_path::PathResolveExt;
@@ -63,63 +129,198 @@ use rs_merkle::{algorithms::Sha256, Hasher};
 use ark_ec::CurveGroup;
 use ark_ff::PrimeField;
 use ark_poly::DenseUVPolynomial;
-use ark_serialize::{CanonicalDeserialize, Compress, Validate};
+use ark_serialize::{CanonicalSerialize, Compress, Validate};
 use ark_std::ops::Div;
 
-const SEND_BLOCK_PROTOCOL: StreamProtocol = StreamProtocol::new("/send-block/1.0.0");
+/// The version 2 of the send protocol, see [`crate::wire_codec`]
+pub(crate) const SEND_BLOCK_PROTOCOL: StreamProtocol = StreamProtocol::new("/send-block/2.0.0");
+pub(crate) const SEND_BLOCK_PROTOCOL_V1: StreamProtocol = StreamProtocol::new("/send-block/1.0.0");
 pub(crate) const SEND_BLOCK_FILE_NAME: &str = "send_block_list.txt";
+const IDENTITY_ROTATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a rotation waiting for the storage to be free checks it again
+const IDENTITY_ROTATION_RETRY: Duration = Duration::from_secs(1);
+/// How long a rotation waits for the storage to be free, once the peers were notified, before giving up
+const IDENTITY_ROTATION_MAX_WAIT: Duration = Duration::from_secs(60);
+/// Delay between two announcements of a ReannounceAll, so the DHT is not flooded with queries
+const REANNOUNCE_DELAY: Duration = Duration::from_millis(100);
+/// Time given to the connections to close when the node stops
+const CLOSE_CONNECTIONS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Number of the peers known before a restart that are dialed when the node starts
+const KNOWN_PEERS_TO_DIAL: usize = 3;
+/// Time given to a get-file to read the metadata of the file in the DHT, before going on without it
+const FILE_METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval at which the known peers are written, if they changed
+const KNOWN_PEERS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Number of files an encode-files encodes at once when it is not told
+const ENCODE_FILES_PARALLELISM: usize = 4;
+
+/// Time given to the commands that wait for several other nodes
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CommandTimeouts {
+    /// Time given to a get-file to receive enough blocks to decode the file
+    pub(crate) get_file: Duration,
+    /// Time given to the first loop of a send-block-list, before the rejected blocks are sent again
+    pub(crate) send_block_list: Duration,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct BlockRequest {
     file_hash: String,
     block_hash: String,
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub(crate) struct BlockResponse {
     pub(crate) file_hash: String,
     pub(crate) block_hash: String,
     pub(crate) block_data: Vec<u8>,
+    /// The id following the file across the commands and the nodes, see [`crate::lifecycle`]
+    #[serde(default)]
+    pub(crate) correlation_id: Option<String>,
+    /// The node serves too many block requests, the block was not sent, see [`crate::block_serving`]
+    #[serde(default)]
+    pub(crate) busy: bool,
+}
+
+/// A block requested with GetBlockFrom, waiting for the answer of the current provider
+struct PendingBlockRequest {
+    file_hash: String,
+    block_hash: String,
+    save_to_disk: bool,
+    /// The providers to request the block from next, in order, if the current one fails
+    fallback_providers: VecDeque<PeerId>,
+    sender: Sender<Option<BlockResponse>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct PeerBlockInfoRequest {
     file_hash: String,
+    /// Only list this block, if the peer has it
+    #[serde(default)]
+    block_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct PeerBlockInfoResponse(PeerBlockInfo);
 
-pub(crate) async fn create_swarm(id_keys: Keypair) -> Result<Swarm<DragoonBehaviour>> {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AvailableStorageRequest;
+
+/// The storage the peer has left for the blocks sent by other peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AvailableStorageResponse(usize);
+
+/// The transports a node can listen and dial on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Transport {
+    /// TCP, secured with noise and multiplexed with yamux, e.g. `/ip4/127.0.0.1/tcp/31200`
+    Tcp,
+    /// QUIC, e.g. `/ip4/127.0.0.1/udp/31200/quic-v1`
+    Quic,
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Quic => write!(f, "quic"),
+        }
+    }
+}
+
+/// Combine the chosen transports into one, that uses the transport matching the multiaddr, the bytes of its
+/// connections being counted by `bandwidth`
+fn build_transport(
+    key: &Keypair,
+    transports: &[Transport],
+    bandwidth: &Arc<Bandwidth>,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    let mut combined: Option<Boxed<(PeerId, StreamMuxerBox)>> = None;
+    for transport in transports {
+        let bandwidth = bandwidth.clone();
+        let next = match transport {
+            Transport::Tcp => tcp::async_io::Transport::new(tcp::Config::default())
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(noise::Config::new(key)?)
+                .multiplex(yamux::Config::default())
+                .map(move |(peer_id, muxer), _| {
+                    let muxer = bandwidth.wrap(peer_id, "tcp", StreamMuxerBox::new(muxer));
+                    (peer_id, muxer)
+                })
+                .boxed(),
+            Transport::Quic => quic::async_std::Transport::new(quic::Config::new(key))
+                .map(move |(peer_id, muxer), _| {
+                    let muxer = bandwidth.wrap(peer_id, "quic", StreamMuxerBox::new(muxer));
+                    (peer_id, muxer)
+                })
+                .boxed(),
+        };
+        combined = Some(match combined {
+            None => next,
+            Some(combined) => combined
+                .or_transport(next)
+                .map(|either, _| either.into_inner())
+                .boxed(),
+        });
+    }
+    combined.ok_or(format_err!("The node needs at least one transport"))
+}
+
+pub(crate) async fn create_swarm(
+    id_keys: Keypair,
+    transports: &[Transport],
+    files_topic: &IdentTopic,
+    bandwidth: &Arc<Bandwidth>,
+) -> Result<Swarm<DragoonBehaviour>> {
     let peer_id = id_keys.public().to_peer_id();
+    let transport = build_transport(&id_keys, transports, bandwidth)?;
+    let gossipsub = gossipsub::Behaviour::new(
+        gossipsub::MessageAuthenticity::Signed(id_keys.clone()),
+        gossipsub::Config::default(),
+    )
+    .map_err(|e| format_err!("Could not create the gossipsub behaviour: {}", e))?;
 
     let mut swarm = libp2p::SwarmBuilder::with_existing_identity(id_keys)
         .with_async_std()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
+        .with_other_transport(|_| transport)?
         .with_behaviour(|key| DragoonBehaviour {
-            kademlia: kad::Behaviour::new(
-                peer_id,
-                kad::store::MemoryStore::new(key.public().to_peer_id()),
-            ),
+            kademlia: kad::Behaviour::new(peer_id, DhtStore::new(key.public().to_peer_id())),
             identify: identify::Behaviour::new(identify::Config::new(
                 "/ipfs/id/1.0.0".to_string(),
                 key.public(),
             )),
-            request_block: request_response::cbor::Behaviour::new(
+            request_block: wire_codec::Behaviour::new(
+                wire_codec::protocols("/block-exchange/1", "/block-exchange/2"),
+                request_response::Config::default(),
+            ),
+            request_info: wire_codec::Behaviour::new(
+                wire_codec::protocols("/peer-info/1", "/peer-info/2"),
+                request_response::Config::default(),
+            ),
+            request_storage: request_response::cbor::Behaviour::new(
+                [(
+                    StreamProtocol::new("/send-storage/1"),
+                    ProtocolSupport::Full,
+                )],
+                request_response::Config::default(),
+            ),
+            send_block: stream::Behaviour::new(),
+            identity_rotation: request_response::cbor::Behaviour::new(
                 [(
-                    StreamProtocol::new("/block-exchange/1"),
+                    StreamProtocol::new("/identity-rotation/1"),
                     ProtocolSupport::Full,
                 )],
                 request_response::Config::default(),
             ),
-            request_info: request_response::cbor::Behaviour::new(
-                [(StreamProtocol::new("/peer-info/1"), ProtocolSupport::Full)],
+            peer_exchange: request_response::cbor::Behaviour::new(
+                [(
+                    StreamProtocol::new("/peer-exchange/1"),
+                    ProtocolSupport::Full,
+                )],
                 request_response::Config::default(),
             ),
-            send_block: stream::Behaviour::new(),
+            ping: ping::Behaviour::new(ping::Config::new()),
+            gossipsub,
+            blocked_peers: Default::default(),
         })?
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60 * 60)))
         .build();
@@ -128,83 +329,294 @@ pub(crate) async fn create_swarm(id_keys: Keypair) -> Result<Swarm<DragoonBehavi
         .behaviour_mut()
         .kademlia
         .set_mode(Some(kad::Mode::Server));
+    swarm.behaviour_mut().gossipsub.subscribe(files_topic)?;
 
     Ok(swarm)
 }
 
 #[derive(NetworkBehaviour)]
 pub(crate) struct DragoonBehaviour {
-    request_block: request_response::cbor::Behaviour<BlockRequest, BlockResponse>,
-    request_info: request_response::cbor::Behaviour<PeerBlockInfoRequest, PeerBlockInfoResponse>,
+    request_block: wire_codec::Behaviour<BlockRequest, BlockResponse>,
+    request_info: wire_codec::Behaviour<PeerBlockInfoRequest, PeerBlockInfoResponse>,
+    request_storage:
+        request_response::cbor::Behaviour<AvailableStorageRequest, AvailableStorageResponse>,
     identify: identify::Behaviour,
-    kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    kademlia: kad::Behaviour<DhtStore>,
     send_block: stream::Behaviour,
+    identity_rotation:
+        request_response::cbor::Behaviour<IdentityRotationNotice, IdentityRotationAck>,
+    peer_exchange: request_response::cbor::Behaviour<PexRequest, PexResponse>,
+    ping: ping::Behaviour,
+    gossipsub: gossipsub::Behaviour,
+    /// Closes and refuses the connections of the banned peers
+    blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
+}
+
+/// An identity rotation waiting for the connected peers to acknowledge the change before switching the swarm
+struct PendingIdentityRotation {
+    new_keypair: Keypair,
+    link: IdentityLink,
+    pending_notices: HashSet<OutboundRequestId>,
+    sender: Sender<(PeerId, PeerId)>,
+    started_at: time::Instant,
 }
 
 pub(crate) struct DragoonNetwork {
     swarm: Swarm<DragoonBehaviour>,
+    keypair: Keypair,
+    /// Where the keypair is persisted, `None` when it is derived from a seed
+    keystore: Option<Keystore>,
+    /// The transports of the swarm, to build it again after an identity rotation
+    transports: Vec<Transport>,
+    /// Resolves the names of the multiaddr before they are dialed
+    dns_resolver: Arc<DnsResolver>,
     label: String,
     command_receiver: mpsc::UnboundedReceiver<DragoonCommand>,
     command_sender: mpsc::UnboundedSender<DragoonCommand>,
-    listeners: HashMap<u64, ListenerId>,
+    listeners: HashMap<u64, (ListenerId, Multiaddr)>,
+    /// The directory holding the directory of each identity of the node
+    storage_dir: PathBuf,
     file_dir: PathBuf,
-    powers_path: PathBuf,
+    /// The powers of `--powers-path`, read again when the file changes
+    powers: Arc<PowersCache>,
     current_available_storage_for_send: Arc<AtomicUsize>,
     current_total_size_of_blocks_on_disk: Arc<AtomicUsize>,
-    known_peer_id: HashSet<PeerId>,
-    pending_dial: HashMap<String, Sender<()>>,
+    /// Whether the blocks sent by other peers are rejected, the blocks already stored are still served
+    receiving_paused: Arc<AtomicBool>,
+    /// Owns the writes to the list and the total size of the blocks received through send requests
+    send_accounting: Arc<tokio::sync::Mutex<SendAccounting>>,
+    known_peers: KnownPeers,
+    banned_peers: BannedPeers,
+    /// The last round trip time measured with a ping to each peer
+    peer_rtts: HashMap<PeerId, Duration>,
+    /// Label and role of the peers, as learned through peer exchange
+    known_peer_info: HashMap<PeerId, PexPeer>,
+    /// The topic the files encoded in the network are announced on
+    files_topic: IdentTopic,
+    /// The files announced on the topic
+    known_files: KnownFiles,
+    pending_dial: PendingMap<String, Sender<PeerId>>,
     pending_send_block_to: HashSet<(PeerId, String)>,
-    pending_start_providing: HashMap<kad::QueryId, Sender<()>>,
-    pending_get_providers: HashMap<kad::QueryId, SenderMPSC<HashSet<PeerId>>>,
-    pending_request_block_info: HashMap<OutboundRequestId, Sender<PeerBlockInfo>>,
-    pending_request_block: HashMap<OutboundRequestId, (bool, Sender<Option<BlockResponse>>)>,
+    /// The keys the node provides, announced again every `provide_refresh_interval`
+    provided_keys: ProvidedKeys,
+    /// The replication factor of the files, ensured along with the repair of the files
+    replication_factors: ReplicationFactors,
+    provide_refresh_interval: Duration,
+    /// The last refresh of the provided keys, a new one is not started before it is done
+    provide_refresh_task: Option<JoinHandle<()>>,
+    dht_snapshot_interval: Duration,
+    repair_config: RepairConfig,
+    /// The last check of the redundancy of the files encoded by the node, a new one is not started before it is done
+    repair_task: Option<JoinHandle<()>>,
+    scrub_interval: Duration,
+    scrubber: Arc<Scrubber>,
+    /// The last scrub of the stored blocks, a new one is not started before it is done
+    scrub_task: Option<JoinHandle<()>>,
+    send_retry_policy: SendRetryPolicy,
+    send_strategies: Arc<StrategyRegistry>,
+    progress: Arc<ProgressRegistry>,
+    jobs: Arc<JobManager>,
+    /// The key being provided by each StartProvide query, so StopProvide can cancel them
+    pending_start_providing: PendingMap<kad::QueryId, (String, Sender<()>)>,
+    /// The key of each refresh of a provider record still running, so StopProvide can cancel them too
+    pending_refresh_provide: PendingMap<kad::QueryId, String>,
+    pending_get_providers: PendingMap<kad::QueryId, SenderMPSC<HashSet<PeerId>>>,
+    /// The file each query of a metadata record is for
+    pending_get_file_metadata: PendingMap<kad::QueryId, (String, Sender<Option<FileMetadata>>)>,
+    pending_request_block_info: PendingMap<OutboundRequestId, Sender<PeerBlockInfo>>,
+    pending_request_storage: PendingMap<OutboundRequestId, Sender<usize>>,
+    pending_request_block: PendingMap<OutboundRequestId, PendingBlockRequest>,
+    pending_identity_rotation: Option<PendingIdentityRotation>,
+    pending_pex_dial: HashSet<PeerId>,
+    delegation_config: Arc<DelegationConfig>,
+    storage_classes: Arc<StorageClasses>,
+    /// Reads and writes the blocks, encrypted or not
+    block_store: Arc<BlockStore>,
+    /// The key the files are encrypted with before being encoded, see [`file_encryption`]
+    file_key_path: PathBuf,
+    block_quota: Arc<BlockQuota>,
+    /// The storage each peer can use for the blocks it sends to the node, and how much it uses
+    peer_quotas: Arc<PeerQuotas>,
+    mirror: Arc<Mirror>,
+    /// The tasks mirroring to and from other nodes, bound to the current swarm
+    mirror_tasks: Vec<JoinHandle<()>>,
+    gc_interval: Duration,
+    /// The last collection of the expired blocks, a new one is not started before it is done
+    gc_task: Option<JoinHandle<()>>,
+    command_timeouts: CommandTimeouts,
+    /// Bounds the block requests of the other peers served at the same time
+    block_serving: Arc<BlockServing>,
+    /// The bytes exchanged with each peer, counted by the transports of the swarm
+    bandwidth: Arc<Bandwidth>,
+    /// The last events of the node, see [`crate::recent_events`]
+    recent_events: Arc<RecentEvents>,
+    /// The outcome of the probes of each peer
+    peer_reputations: Arc<PeerReputations>,
+    file_locks: Arc<FileLocks>,
+    /// The block exchanges of send requests in flight, waited for when the node stops
+    in_flight: Arc<InFlight>,
+    /// Set by the shutdown, the loop of the network then stops
+    stopped: bool,
+    metrics: Arc<Metrics>,
     //TODO add a pending_request_file using the hash as a key
 }
 
 impl DragoonNetwork {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        swarm: Swarm<DragoonBehaviour>,
+        mut swarm: Swarm<DragoonBehaviour>,
         command_receiver: mpsc::UnboundedReceiver<DragoonCommand>,
         command_sender: mpsc::UnboundedSender<DragoonCommand>,
-        powers_path: PathBuf,
+        powers: Arc<PowersCache>,
         total_available_storage_for_send: usize,
-        peer_id: PeerId,
+        keypair: Keypair,
+        keystore: Option<Keystore>,
+        storage_dir: PathBuf,
+        transports: Vec<Transport>,
+        files_topic: IdentTopic,
+        dns_config: DnsConfig,
         maybe_label: Option<String>,
         replace: bool,
+        delegation_config: DelegationConfig,
+        storage_classes: StorageClasses,
+        block_store: BlockStore,
+        file_key_path: PathBuf,
+        block_limits: BlockLimits,
+        default_peer_quota: Option<usize>,
+        mirror_config: MirrorConfig,
+        block_expiry: BlockExpiryConfig,
+        command_timeouts: CommandTimeouts,
+        block_serving_limits: BlockServingLimits,
+        bandwidth: Arc<Bandwidth>,
+        recent_events_capacity: usize,
+        provide_refresh_interval: Duration,
+        dht_snapshot_interval: Duration,
+        repair_config: RepairConfig,
+        scrub_interval: Duration,
+        send_retry_policy: SendRetryPolicy,
+        send_strategies: Arc<StrategyRegistry>,
+        progress: Arc<ProgressRegistry>,
+        jobs: Arc<JobManager>,
+        in_flight: Arc<InFlight>,
+        metrics: Arc<Metrics>,
     ) -> Self {
+        let peer_id = keypair.public().to_peer_id();
+        *metrics.storage_class_usage.lock().unwrap() = storage_classes.scan_usage();
         let label = if let Some(label) = maybe_label {
             label
         } else {
             peer_id.to_base58()
         };
+        let file_dir = Self::create_block_dir(&storage_dir, peer_id, replace).unwrap();
+        let provided_keys = ProvidedKeys::load(&file_dir);
+        let replication_factors = ReplicationFactors::load(&file_dir);
+        let dht_snapshot = DhtSnapshot::load(&dht_store::snapshot_path(&file_dir));
+        let known_peers = KnownPeers::load(&file_dir);
+        let banned_peers = BannedPeers::load(&file_dir);
+        for peer_id in banned_peers.peers() {
+            swarm.behaviour_mut().blocked_peers.block_peer(*peer_id);
+        }
+        let kademlia = &mut swarm.behaviour_mut().kademlia;
+        dht_snapshot.restore(kademlia.store_mut());
+        for (peer_id, addrs) in dht_snapshot.peers() {
+            for addr in addrs {
+                kademlia.add_address(&peer_id, addr.clone());
+            }
+        }
+        for (peer_id, addrs) in known_peers.last_seen() {
+            for addr in addrs {
+                kademlia.add_address(&peer_id, addr);
+            }
+        }
+        let current_total_size_of_blocks_on_disk = Arc::new(AtomicUsize::new(0));
+        let mirror = Arc::new(Mirror::new(mirror_config));
+        let block_quota = BlockQuota::new(block_limits, &file_dir, mirror.clone(), metrics.clone());
+        let peer_quotas = Arc::new(PeerQuotas::new(default_peer_quota));
         Self {
             swarm,
+            keypair,
+            keystore,
+            transports,
+            dns_resolver: Arc::new(DnsResolver::new(dns_config)),
             label,
             command_receiver,
             command_sender,
             listeners: HashMap::new(),
-            file_dir: Self::create_block_dir(peer_id, replace).unwrap(),
-            powers_path,
+            storage_dir,
+            file_dir,
+            powers,
             current_available_storage_for_send: Arc::new(AtomicUsize::new(
                 total_available_storage_for_send,
             )),
-            current_total_size_of_blocks_on_disk: Arc::new(AtomicUsize::new(0)),
-            known_peer_id: Default::default(),
-            pending_dial: Default::default(),
+            current_total_size_of_blocks_on_disk: current_total_size_of_blocks_on_disk.clone(),
+            receiving_paused: Default::default(),
+            send_accounting: Arc::new(tokio::sync::Mutex::new(SendAccounting::new(
+                current_total_size_of_blocks_on_disk,
+                peer_quotas.clone(),
+                block_expiry.default_ttl,
+            ))),
+            known_peers,
+            banned_peers,
+            peer_rtts: Default::default(),
+            known_peer_info: Default::default(),
+            files_topic,
+            known_files: Default::default(),
+            pending_dial: PendingMap::new("dial"),
             pending_send_block_to: Default::default(),
-            pending_start_providing: Default::default(),
-            pending_get_providers: Default::default(),
-            pending_request_block_info: Default::default(),
-            pending_request_block: Default::default(),
+            provided_keys,
+            replication_factors,
+            provide_refresh_interval,
+            provide_refresh_task: None,
+            dht_snapshot_interval,
+            repair_config,
+            repair_task: None,
+            scrub_interval,
+            scrubber: Default::default(),
+            scrub_task: None,
+            send_retry_policy,
+            send_strategies,
+            progress,
+            jobs,
+            pending_start_providing: PendingMap::new("start providing"),
+            pending_refresh_provide: PendingMap::new("refresh provide"),
+            pending_get_providers: PendingMap::new("get providers"),
+            pending_get_file_metadata: PendingMap::new("get file metadata"),
+            pending_request_block_info: PendingMap::new("request block info"),
+            pending_request_storage: PendingMap::new("request available storage"),
+            pending_request_block: PendingMap::new("request block"),
+            pending_identity_rotation: None,
+            pending_pex_dial: Default::default(),
+            delegation_config: Arc::new(delegation_config),
+            storage_classes: Arc::new(storage_classes),
+            block_store: Arc::new(block_store),
+            file_key_path,
+            block_quota: Arc::new(block_quota),
+            peer_quotas,
+            mirror,
+            mirror_tasks: vec![],
+            gc_interval: block_expiry.gc_interval,
+            gc_task: None,
+            command_timeouts,
+            block_serving: BlockServing::new(block_serving_limits),
+            bandwidth,
+            recent_events: Arc::new(RecentEvents::new(recent_events_capacity)),
+            peer_reputations: Default::default(),
+            file_locks: Default::default(),
+            in_flight,
+            stopped: false,
+            metrics,
         }
     }
 
-    fn create_block_dir(peer_id: PeerId, replace: bool) -> std::io::Result<PathBuf> {
+    fn create_block_dir(
+        storage_dir: &Path,
+        peer_id: PeerId,
+        replace: bool,
+    ) -> std::io::Result<PathBuf> {
         // * change the replace bool to be read from CLI
-        let base_path = format!("~/.share/dragoonfly/{}/files", peer_id.to_base58())
-            .resolve()
-            .into_owned(); // * needs to be changed to allow taking the base path as argument from CLI
+        let base_path: PathBuf = [get_node_dir(storage_dir, peer_id), PathBuf::from("files")]
+            .iter()
+            .collect();
         if replace {
             let _ = sfs::remove_dir_all(&base_path); // ignore the error if the directory does not exist
         }
@@ -224,58 +636,29 @@ impl DragoonNetwork {
         Ok(base_path)
     }
 
-    fn get_current_available_storage(&mut self) -> Result<(Arc<AtomicUsize>, Arc<AtomicUsize>)> {
-        let current_available_storage = self.current_available_storage_for_send.clone();
-        let total_block_size_on_disk = self.current_total_size_of_blocks_on_disk.clone();
-        let send_block_file_list: PathBuf =
-            [self.file_dir.clone(), PathBuf::from(SEND_BLOCK_FILE_NAME)]
-                .iter()
-                .collect();
-        fn create_new_send_list(mut file: sfs::File) {
-            file.write_all(format!("Total: {}\n", 0).as_bytes())
-                .unwrap();
-        }
-
-        match sfs::File::create_new(send_block_file_list.clone()) {
-            Ok(file) => create_new_send_list(file),
-            Err(_) => {
-                // * the file already exists
-                match BufReader::new(sfs::File::open(send_block_file_list.clone()).unwrap())
-                    .lines()
-                    .nth(0)
-                {
-                    Some(first_line) => {
-                        let first_line = first_line.unwrap();
-                        let re = regex::Regex::new(r"Total: ([0-9]*)$").unwrap();
-                        let already_used_size = re
-                            .captures(&first_line)
-                            .unwrap()
-                            .get(1)
-                            .unwrap()
-                            .as_str()
-                            .parse::<usize>()
-                            .unwrap();
-                        total_block_size_on_disk.store(already_used_size, Ordering::SeqCst);
-                        let total_size = current_available_storage.load(Ordering::SeqCst);
-                        match total_size.checked_sub(already_used_size) {
-                            Some(new_size) => {info!("The total available storage is {} after deducting the already used storage", new_size); current_available_storage.store(new_size, Ordering::SeqCst);},
-                            None => panic!("The total size allowed for send blocks is already smaller than the total size used by blocks received by send, that are currently stored on disk"),
-                        }
-                    }
-                    None => {
-                        // the file exists but for some reason we didn't find the first line
-                        // move the existing file to a .old to prevent erasing a file, then create a new one and write into it
-                        let mut new_path = send_block_file_list.clone();
-                        new_path.push(PathBuf::from(".old"));
-                        sfs::rename(send_block_file_list.clone(), new_path).unwrap();
-                        let file = sfs::File::create_new(send_block_file_list).unwrap();
-                        create_new_send_list(file)
-                    }
-                }
+    /// Deduce the size of the blocks already received through send requests from the available storage
+    async fn get_current_available_storage(&mut self) -> Result<()> {
+        let already_used_size = self
+            .send_accounting
+            .lock()
+            .await
+            .load(&self.file_dir)
+            .await?;
+        let total_size = self
+            .current_available_storage_for_send
+            .load(Ordering::SeqCst);
+        match total_size.checked_sub(already_used_size) {
+            Some(new_size) => {
+                info!(
+                    "The total available storage is {} after deducting the already used storage",
+                    new_size
+                );
+                self.current_available_storage_for_send
+                    .store(new_size, Ordering::SeqCst);
             }
+            None => panic!("The total size allowed for send blocks is already smaller than the total size used by blocks received by send, that are currently stored on disk"),
         }
-
-        Ok((current_available_storage, total_block_size_on_disk))
+        Ok(())
     }
 
     pub async fn run<F, G, P>(mut self)
@@ -286,79 +669,278 @@ impl DragoonNetwork {
         for<'a, 'b> &'a P: Div<&'b P, Output = P>,
     {
         info!("Starting Dragoon Network");
-        let incoming_send_streams = self
-            .swarm
-            .behaviour()
-            .send_block
-            .new_control()
-            .accept(SEND_BLOCK_PROTOCOL)
-            .unwrap();
-        let (current_available_storage, total_block_size_on_disk) =
-            match self.get_current_available_storage() {
-                Ok(val) => val,
-                Err(e) => {
-                    error!("{:?}", e);
-                    panic!()
-                }
-            };
+        if let Err(e) = self.get_current_available_storage().await {
+            error!("{:?}", e);
+            panic!()
+        }
 
         // starts a new task to handle the receiving end of sending blocks
-        SendBlockHandler::run::<F, G, P>(
-            incoming_send_streams,
-            self.powers_path.clone(),
-            self.file_dir.clone(),
-            current_available_storage,
-            total_block_size_on_disk,
-        )
-        .unwrap();
-        loop {
+        self.start_send_block_handler::<F, G, P>().unwrap();
+        // starts a new task to handle the files other nodes ask us to retrieve for them
+        self.start_delegate_get_file_handler().unwrap();
+        // starts the tasks mirroring the manifest to the follower and from the primary
+        self.start_mirror_handlers().unwrap();
+        let mut gc_ticker = time::interval(self.gc_interval);
+        gc_ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        // the first tick is immediate, so the keys provided before a restart are provided again
+        let mut provide_refresh_ticker = time::interval(self.provide_refresh_interval);
+        provide_refresh_ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut dht_snapshot_ticker = time::interval_at(
+            time::Instant::now() + self.dht_snapshot_interval,
+            self.dht_snapshot_interval,
+        );
+        dht_snapshot_ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut repair_ticker = time::interval_at(
+            time::Instant::now() + self.repair_config.interval,
+            self.repair_config.interval,
+        );
+        repair_ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut scrub_ticker = time::interval_at(
+            time::Instant::now() + self.scrub_interval,
+            self.scrub_interval,
+        );
+        scrub_ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut known_peers_ticker = time::interval_at(
+            time::Instant::now() + KNOWN_PEERS_SAVE_INTERVAL,
+            KNOWN_PEERS_SAVE_INTERVAL,
+        );
+        known_peers_ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        self.dial_known_peers();
+        while !self.stopped {
             tokio::select! {
                 e = self.swarm.next() => self.handle_event(e.expect("Swarm stream to be infinite.")).await,
+                _ = gc_ticker.tick() => self.collect_expired_blocks(),
+                _ = provide_refresh_ticker.tick() => self.refresh_provided_keys(),
+                _ = dht_snapshot_ticker.tick() => {
+                    if let Err(e) = self.save_dht_snapshot().await {
+                        warn!("Could not save the DHT snapshot: {}", e);
+                    }
+                }
+                _ = repair_ticker.tick() => self.repair_files::<F,G>(),
+                _ = scrub_ticker.tick() => self.scrub_blocks::<F,G>(),
+                _ = known_peers_ticker.tick() => self.known_peers.save(&self.file_dir).await,
                 cmd = self.command_receiver.recv() =>  match cmd {
                     Some(c) => self.handle_command::<F,G,P>(c).await,
-                    None => return,
+                    None => break,
                 }
             }
         }
+        // the peers seen since the last tick
+        self.known_peers.save(&self.file_dir).await;
+        info!("Stopped the Dragoon Network");
+    }
+
+    /// Dial the peers seen last before a restart, so the node has connections before any bootstrap
+    fn dial_known_peers(&mut self) {
+        for (peer_id, addrs) in self
+            .known_peers
+            .last_seen()
+            .into_iter()
+            .take(KNOWN_PEERS_TO_DIAL)
+        {
+            info!("Dialing {}, known before the node restarted", peer_id);
+            if let Err(e) = self.swarm.dial(
+                DialOpts::peer_id(peer_id)
+                    .addresses(addrs)
+                    .condition(PeerCondition::DisconnectedAndNotDialing)
+                    .build(),
+            ) {
+                warn!("Could not dial the known peer {}: {}", peer_id, e);
+            }
+        }
+    }
+
+    /// Accept the send block protocol on the current swarm and spawn the task receiving the blocks
+    fn start_send_block_handler<F, G, P>(&mut self) -> Result<()>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+        P: DenseUVPolynomial<F>,
+        for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+    {
+        let mut control = self.swarm.behaviour().send_block.new_control();
+        let incoming_send_streams = control
+            .accept(SEND_BLOCK_PROTOCOL)
+            .map_err(|e| format_err!("Could not accept the send block protocol: {:?}", e))?
+            .map(|(peer, stream)| (peer, stream, WireFormat::Bincode));
+        let incoming_v1_send_streams = control
+            .accept(SEND_BLOCK_PROTOCOL_V1)
+            .map_err(|e| format_err!("Could not accept the send block protocol: {:?}", e))?
+            .map(|(peer, stream)| (peer, stream, WireFormat::Json));
+        SendBlockHandler::run::<F, G, P>(
+            futures::stream::select(incoming_send_streams, incoming_v1_send_streams),
+            self.powers.clone(),
+            self.file_dir.clone(),
+            self.current_available_storage_for_send.clone(),
+            self.receiving_paused.clone(),
+            self.send_accounting.clone(),
+            self.storage_classes.clone(),
+            self.block_store.clone(),
+            self.block_quota.clone(),
+            self.peer_quotas.clone(),
+            self.metrics.clone(),
+            self.in_flight.clone(),
+            self.recent_events.clone(),
+        )
+    }
+
+    /// Accept the delegated retrieval protocol on the current swarm and spawn the task answering the requests
+    fn start_delegate_get_file_handler(&mut self) -> Result<()> {
+        let incoming_delegation_streams = self
+            .swarm
+            .behaviour()
+            .send_block
+            .new_control()
+            .accept(DELEGATE_GET_FILE_PROTOCOL)
+            .map_err(|e| {
+                format_err!("Could not accept the delegated retrieval protocol: {:?}", e)
+            })?;
+        DelegateGetFileHandler::run(
+            incoming_delegation_streams,
+            self.command_sender.clone(),
+            self.delegation_config.clone(),
+            self.block_store.clone(),
+        )
+    }
+
+    /// Spawn the tasks mirroring to the follower and from the primary on the current swarm, stopping the previous ones
+    fn start_mirror_handlers(&mut self) -> Result<()> {
+        for task in self.mirror_tasks.drain(..) {
+            task.abort();
+        }
+        let mut control = self.swarm.behaviour().send_block.new_control();
+        let MirrorConfig { follower, primary } = self.mirror.config().clone();
+        if let Some(follower) = follower {
+            self.mirror_tasks.push(mirror::run_primary(
+                control.clone(),
+                follower,
+                self.mirror.clone(),
+                self.block_quota.clone(),
+                self.current_available_storage_for_send.clone(),
+            ));
+        }
+        if let Some(primary) = primary {
+            let incoming_mirror_streams = control
+                .accept(MIRROR_PROTOCOL)
+                .map_err(|e| format_err!("Could not accept the mirror protocol: {:?}", e))?;
+            self.mirror_tasks.push(mirror::run_follower(
+                incoming_mirror_streams,
+                primary,
+                self.mirror.clone(),
+            ));
+        }
+        Ok(())
     }
 
-    async fn handle_query_result(&mut self, result: QueryResult, id: QueryId) {
+    /// `last` is set on the last event of the query, which completes its pending entry
+    async fn handle_query_result(&mut self, result: QueryResult, id: QueryId, last: bool) {
         match result {
             kad::QueryResult::StartProviding(Ok(result_ok)) => {
                 info!("Started providing {:?}", result_ok);
-                if let Some(sender) = self.pending_start_providing.remove(&id) {
+                if self.pending_refresh_provide.contains(&id) {
+                    self.pending_refresh_provide.complete(&id);
+                } else if let Some((_, sender)) = self.pending_start_providing.complete(&id) {
                     debug!("Sending empty response");
                     sender_send_match(sender, Ok(()), String::from("StartProviding"));
-                } else {
-                    warn!("Could not find id = {} in the start providers", id);
+                }
+            }
+            kad::QueryResult::StartProviding(Err(e)) => {
+                error!("Could not start providing: {:?}", e);
+                if self.pending_refresh_provide.contains(&id) {
+                    self.pending_refresh_provide.complete(&id);
+                } else if let Some((key, sender)) = self.pending_start_providing.complete(&id) {
+                    let err = ProviderError(format!("Could not provide {}: {:?}", key, e));
+                    sender_send_match(
+                        sender,
+                        Err(format_err!(err)),
+                        String::from("StartProviding"),
+                    );
+                }
+            }
+            kad::QueryResult::PutRecord(Ok(kad::PutRecordOk { key })) => {
+                info!("Published the record {:?}", key)
+            }
+            kad::QueryResult::PutRecord(Err(e)) => {
+                warn!("Could not publish the record {:?}: {}", e.key(), e)
+            }
+            kad::QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(kad::PeerRecord {
+                record,
+                ..
+            }))) => {
+                let Some((file_hash, _)) = self.pending_get_file_metadata.progress(&id) else {
+                    return;
+                };
+                match file_metadata::open(&record, file_hash) {
+                    Ok(metadata) => {
+                        if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id)
+                        {
+                            query.finish();
+                        }
+                        if let Some((_, sender)) = self.pending_get_file_metadata.complete(&id) {
+                            sender_send_match(
+                                sender,
+                                Ok(Some(metadata)),
+                                String::from("GetFileMetadata"),
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Ignoring a metadata record of the file {}: {}",
+                            file_hash, e
+                        );
+                        if last {
+                            if let Some((_, sender)) = self.pending_get_file_metadata.complete(&id)
+                            {
+                                sender_send_match(
+                                    sender,
+                                    Ok(None),
+                                    String::from("GetFileMetadata"),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            kad::QueryResult::GetRecord(res) => {
+                if let Err(e) = res {
+                    debug!("No metadata record found: {}", e);
+                }
+                if let Some((_, sender)) = self.pending_get_file_metadata.complete(&id) {
+                    sender_send_match(sender, Ok(None), String::from("GetFileMetadata"));
                 }
             }
             kad::QueryResult::GetProviders(get_providers_result) => {
                 if let Ok(res) = get_providers_result {
                     match res {
                         kad::GetProvidersOk::FoundProviders { providers, .. } => {
-                            if let Some(sender) = self.pending_get_providers.get(&id) {
+                            if let Some(sender) = self.pending_get_providers.progress(&id) {
                                 if sender.send(Ok(providers)).is_err() {
                                     error!("Could not send the result of the kademlia Found Providers query result");
                                 }
                             }
+                            if last {
+                                debug!(
+                                    "Closing the channel for getting new providers for id {:?}",
+                                    id
+                                );
+                                drop(self.pending_get_providers.complete(&id));
+                            }
                         }
                         kad::GetProvidersOk::FinishedWithNoAdditionalRecord { .. } => {
                             info!("kad finished get providers ");
-                            if let Some(sender) = self.pending_get_providers.remove(&id) {
+                            if let Some(sender) = self.pending_get_providers.complete(&id) {
                                 debug!(
                                     "Closing the channel for getting new providers for id {:?}",
                                     id
                                 );
                                 drop(sender);
-                            } else {
-                                error!("could not find {} in the providers query list", id);
                             }
                         }
                     }
                 } else {
                     info!("Could not get the providers");
-                    if let Some(sender) = self.pending_get_providers.remove(&id) {
+                    if let Some(sender) = self.pending_get_providers.complete(&id) {
                         if let Some(mut query_id) =
                             self.swarm.behaviour_mut().kademlia.query_mut(&id)
                         {
@@ -376,8 +958,6 @@ impl DragoonNetwork {
                                 error!("Could not send error for the kademlia GetProviders query result");
                             }
                         }
-                    } else {
-                        error!("could not find {} in the providers", id);
                     }
                 }
             }
@@ -406,10 +986,12 @@ impl DragoonNetwork {
                 _ => {}
             },
             SwarmEvent::Behaviour(DragoonBehaviourEvent::Kademlia(
-                kad::Event::OutboundQueryProgressed { id, result, .. },
+                kad::Event::OutboundQueryProgressed {
+                    id, result, step, ..
+                },
             )) => {
                 debug!("outbound query progressed");
-                self.handle_query_result(result, id).await
+                self.handle_query_result(result, id, step.last).await
             }
             SwarmEvent::Behaviour(DragoonBehaviourEvent::Identify(identify::Event::Sent {
                 peer_id,
@@ -425,57 +1007,106 @@ impl DragoonNetwork {
                         .behaviour_mut()
                         .kademlia
                         .add_address(&peer_id, addr.clone());
-                    self.known_peer_id.insert(peer_id);
+                    self.known_peers.insert(peer_id, info.listen_addrs.clone());
                     info!("Added peer {}", peer_id);
                 } else {
                     error!("Peer {} not added, no listen address", peer_id);
                 }
             }
             SwarmEvent::Behaviour(DragoonBehaviourEvent::RequestBlock(Event::Message {
-                peer: _,
+                peer,
                 message,
             })) => match message {
+                Message::Request { .. } if self.banned_peers.contains(&peer) => {
+                    warn!("Not sending a block to the banned peer {}", peer)
+                }
                 Message::Request {
                     request, channel, ..
-                } => {
-                    if let Err(e) = self.message_request(request, channel).await {
-                        error!("{}", e)
-                    }
+                } => self.message_request(peer, request, channel),
+                // being busy is not held against the peer
+                Message::Response {
+                    request_id,
+                    response,
+                } if response.busy => {
+                    self.block_request_failed(peer, request_id, "the peer is busy")
                 }
                 Message::Response {
                     request_id,
                     response,
                 } => {
-                    if let Some((save_to_disk, sender)) =
-                        self.pending_request_block.remove(&request_id)
+                    self.peer_reputations
+                        .record(peer, PeerBehaviour::BlockServed);
+                    self.recent_events.record(
+                        RecentEventKind::BlockFetched,
+                        Some(peer),
+                        format!(
+                            "Block {} of file {}",
+                            response.block_hash, response.file_hash
+                        ),
+                    );
+                    if let Some(PendingBlockRequest {
+                        save_to_disk,
+                        sender,
+                        ..
+                    }) = self.pending_request_block.complete(&request_id)
                     {
                         if save_to_disk {
                             let BlockResponse {
                                 file_hash,
                                 block_hash,
                                 block_data,
+                                correlation_id,
+                                ..
                             } = response;
-                            let save_path = get_block_dir(&self.file_dir, file_hash);
-                            let res = match tfs::create_dir_all(&save_path).await {
-                                Ok(_) => {
-                                    let file_path: PathBuf =
-                                        [save_path, PathBuf::from(block_hash)].iter().collect();
-                                    match tfs::write(&file_path, block_data).await {
-                                        Ok(_) => Ok(None),
-                                        Err(e) => {
-                                            let err_msg = format!(
-                                                "Could not write the data to {:?}: {}",
-                                                file_path, e
-                                            );
-                                            error!(err_msg);
-                                            Err(format_err!(err_msg))
+                            let reservation = self.block_quota.reserve(&file_hash, &block_hash);
+                            let save_path = get_block_dir(&self.file_dir, file_hash.clone());
+                            let res = match reservation {
+                                Err(e) => Err(e),
+                                Ok(reservation) => match tfs::create_dir_all(&save_path).await {
+                                    Ok(_) => {
+                                        let file_path: PathBuf =
+                                            [save_path, PathBuf::from(block_hash.clone())]
+                                                .iter()
+                                                .collect();
+                                        match self
+                                            .block_store
+                                            .write_async(&file_path, &block_data)
+                                            .await
+                                        {
+                                            Ok(_) => {
+                                                reservation.commit();
+                                                let hash_dir =
+                                                    get_file_dir(&self.file_dir, file_hash.clone());
+                                                lifecycle::adopt(
+                                                    &hash_dir,
+                                                    correlation_id.as_deref(),
+                                                )
+                                                .await;
+                                                lifecycle::record(
+                                                    &hash_dir,
+                                                    &file_hash,
+                                                    LifecycleStage::BlockFetched,
+                                                    Some(peer.to_base58()),
+                                                    Some(block_hash),
+                                                )
+                                                .await;
+                                                Ok(None)
+                                            }
+                                            Err(e) => {
+                                                let err_msg = format!(
+                                                    "Could not write the data to {:?}: {}",
+                                                    file_path, e
+                                                );
+                                                error!(err_msg);
+                                                Err(format_err!(err_msg))
+                                            }
                                         }
                                     }
-                                }
-                                Err(e) => {
-                                    error!("{}", e);
-                                    Err(format_err!(e))
-                                }
+                                    Err(e) => {
+                                        error!("{}", e);
+                                        Err(format_err!(e))
+                                    }
+                                },
                             };
                             sender_send_match(
                                 sender,
@@ -489,23 +1120,36 @@ impl DragoonNetwork {
                                 format!("message response {}", request_id),
                             )
                         }
-                    } else {
-                        error!(
-                            "Could no find the sender associated with {} for the message response",
-                            request_id
-                        );
                     }
                 }
             },
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::RequestBlock(
+                Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                    ..
+                },
+            )) => {
+                let behaviour = match error {
+                    request_response::OutboundFailure::Timeout => PeerBehaviour::Timeout,
+                    _ => PeerBehaviour::BlockRequestFailed,
+                };
+                self.peer_reputations.record(peer, behaviour);
+                self.block_request_failed(peer, request_id, error);
+            }
             SwarmEvent::Behaviour(DragoonBehaviourEvent::RequestInfo(Event::Message {
-                peer: _,
+                peer,
                 message,
             })) => match message {
+                Message::Request { .. } if self.banned_peers.contains(&peer) => {
+                    warn!("Not sending block info to the banned peer {}", peer)
+                }
                 Message::Request {
                     request, channel, ..
                 } => {
                     debug!("Received a request for block info: {:?}", request);
-                    if let Err(e) = self.info_request(request, channel).await {
+                    if let Err(e) = self.info_request(peer, request, channel).await {
                         error!("{}", e)
                     }
                 }
@@ -513,106 +1157,676 @@ impl DragoonNetwork {
                     request_id,
                     response,
                 } => {
-                    if let Some(sender) = self.pending_request_block_info.remove(&request_id) {
+                    if let Some(sender) = self.pending_request_block_info.complete(&request_id) {
                         sender_send_match(
                             sender,
                             Ok(response.0),
                             format!("info response {}", request_id),
                         );
-                    } else {
-                        error!(
-                            "Could no find the sender associated with {} for the info response",
-                            request_id
+                    }
+                }
+            },
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::RequestInfo(Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            })) => {
+                if let Some(sender) = self.pending_request_block_info.complete(&request_id) {
+                    let err_msg = format!("Could not get the blocks info from {}: {}", peer, error);
+                    error!(err_msg);
+                    sender_send_match(
+                        sender,
+                        Err(format_err!(err_msg)),
+                        format!("info response {}", request_id),
+                    );
+                }
+            }
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::RequestStorage(Event::Message {
+                peer,
+                message,
+            })) => match message {
+                Message::Request { channel, .. } => {
+                    let available_storage = self
+                        .current_available_storage_for_send
+                        .load(Ordering::Relaxed);
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .request_storage
+                        .send_response(channel, AvailableStorageResponse(available_storage))
+                        .is_err()
+                    {
+                        error!("Could not send the available storage to {}", peer);
+                    }
+                }
+                Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(sender) = self.pending_request_storage.complete(&request_id) {
+                        sender_send_match(
+                            sender,
+                            Ok(response.0),
+                            format!("storage response {}", request_id),
                         );
                     }
                 }
             },
-            SwarmEvent::ConnectionEstablished {
-                peer_id, endpoint, ..
-            } => match endpoint {
-                ConnectedPoint::Dialer { address, .. } => {
-                    if let Some(sender) = self.pending_dial.remove(&address.to_string()) {
-                        sender_send_match(sender, Ok(()), format!("dial {}", address));
-                    } else {
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::RequestStorage(
+                Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                    ..
+                },
+            )) => {
+                if let Some(sender) = self.pending_request_storage.complete(&request_id) {
+                    let err_msg =
+                        format!("Could not get the available storage of {}: {}", peer, error);
+                    error!(err_msg);
+                    sender_send_match(
+                        sender,
+                        Err(format_err!(err_msg)),
+                        format!("storage response {}", request_id),
+                    );
+                }
+            }
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::IdentityRotation(Event::Message {
+                peer,
+                message,
+            })) => match message {
+                Message::Request {
+                    request, channel, ..
+                } => {
+                    let accepted = self.handle_identity_rotation_notice(peer, request);
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .identity_rotation
+                        .send_response(channel, IdentityRotationAck(accepted))
+                        .is_err()
+                    {
                         error!(
-                                "Could no find the sender associated with the multiaddr dial {} for the dial response (this might be due to a double dial attempt to the same node)",
-                                address
-                            );
+                            "Could not acknowledge the identity rotation notice of {}",
+                            peer
+                        );
                     }
                 }
-                ConnectedPoint::Listener { .. } => debug!(
-                    "The node with peer id {:?} established a connection with us",
-                    peer_id
-                ),
+                Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    debug!(
+                        "Peer {} answered {:?} to the identity rotation notice",
+                        peer, response
+                    );
+                    self.identity_rotation_notice_done(request_id);
+                }
             },
-            e => warn!("[unknown event] {:?}", e),
-        }
-    }
-
-    fn read_block_from_disk(block_hash: String, block_dir: PathBuf) -> Result<Vec<u8>>
-where {
-        let ser_block = sfs::read(block_dir.join(block_hash))?;
-        Ok(ser_block)
-    }
-
-    async fn message_request(
-        &mut self,
-        request: BlockRequest,
-        channel: ResponseChannel<BlockResponse>,
-    ) -> Result<()> {
-        let BlockRequest {
-            file_hash,
-            block_hash,
-        } = request;
-        let block_dir = get_block_dir(&self.file_dir.clone(), file_hash.clone());
-        info!(
-            "Searching blocks for the file {0} inside {1:?}",
-            file_hash.clone(),
-            block_dir
-        );
-        let ser_block = Self::read_block_from_disk(block_hash.clone(), block_dir)?;
-        debug!(
-            "Read block {0} for file {1}, got: {2:?}",
-            block_hash, file_hash, ser_block
-        );
-        let channel_info = format!("{:?}", &channel);
-        self.swarm
-            .behaviour_mut()
-            .request_block
-            .send_response(
-                channel,
-                BlockResponse {
-                    file_hash: file_hash.clone(),
-                    block_hash: block_hash.clone(),
-                    block_data: ser_block,
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::IdentityRotation(
+                Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                    ..
                 },
-            )
-            .map_err(|_| CouldNotSendBlockResponse(block_hash, file_hash, channel_info).into())
+            )) => {
+                warn!(
+                    "Could not notify {} of the identity rotation: {}",
+                    peer, error
+                );
+                self.identity_rotation_notice_done(request_id);
+            }
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::PeerExchange(Event::Message {
+                peer,
+                message,
+            })) => match message {
+                Message::Request {
+                    request, channel, ..
+                } => {
+                    let response = self.build_pex_response(peer, request);
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .peer_exchange
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        error!("Could not send the peer exchange response to {}", peer);
+                    }
+                }
+                Message::Response { response, .. } => self.handle_pex_response(peer, response),
+            },
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::PeerExchange(
+                Event::OutboundFailure { peer, error, .. },
+            )) => warn!("Peer exchange with {} failed: {}", peer, error),
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::Gossipsub(
+                gossipsub::Event::Message {
+                    propagation_source,
+                    message,
+                    ..
+                },
+            )) => self.handle_file_announcement(propagation_source, message),
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::Ping(ping::Event {
+                peer,
+                result,
+                ..
+            })) => match result {
+                Ok(rtt) => {
+                    debug!("Round trip time to {}: {:?}", peer, rtt);
+                    self.peer_rtts.insert(peer, rtt);
+                }
+                Err(e) => debug!("Could not ping {}: {}", peer, e),
+            },
+            SwarmEvent::Behaviour(DragoonBehaviourEvent::Gossipsub(event)) => {
+                debug!("[gossipsub] {:?}", event)
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.recent_events.record(
+                    RecentEventKind::ConnectionEstablished,
+                    Some(peer_id),
+                    format!("Connected on {}", endpoint.get_remote_address()),
+                );
+                if num_established.get() == 1 {
+                    // ask every new peer for the peers it knows
+                    self.swarm.behaviour_mut().peer_exchange.send_request(
+                        &peer_id,
+                        PexRequest {
+                            max_peers: MAX_PEX_PEERS,
+                        },
+                    );
+                }
+                match endpoint {
+                    ConnectedPoint::Dialer { address, .. } => {
+                        if let Some(sender) = self.pending_dial.complete(&address.to_string()) {
+                            sender_send_match(sender, Ok(peer_id), format!("dial {}", address));
+                        } else if self.pending_pex_dial.remove(&peer_id) {
+                            debug!("Connected to {} learned through peer exchange", peer_id);
+                        } else {
+                            error!(
+                                "Could no find the sender associated with the multiaddr dial {} for the dial response (this might be due to a double dial attempt to the same node)",
+                                address
+                            );
+                        }
+                    }
+                    ConnectedPoint::Listener { .. } => debug!(
+                        "The node with peer id {:?} established a connection with us",
+                        peer_id
+                    ),
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                endpoint,
+                cause,
+                ..
+            } => {
+                let message = match cause {
+                    Some(cause) => format!(
+                        "Connection on {} closed: {}",
+                        endpoint.get_remote_address(),
+                        cause
+                    ),
+                    None => format!("Connection on {} closed", endpoint.get_remote_address()),
+                };
+                debug!("{} with {}", message, peer_id);
+                self.recent_events.record(
+                    RecentEventKind::ConnectionClosed,
+                    Some(peer_id),
+                    message,
+                );
+            }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => {
+                let message = format!(
+                    "Incoming connection from {} failed: {}",
+                    send_back_addr, error
+                );
+                warn!(message);
+                self.recent_events
+                    .record(RecentEventKind::ConnectionFailed, None, message);
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                self.recent_events.record(
+                    RecentEventKind::ConnectionFailed,
+                    peer_id,
+                    format!("Dial failed: {}", error),
+                );
+                self.handle_outgoing_connection_error(peer_id, error)
+            }
+            e => warn!("[unknown event] {:?}", e),
+        }
+    }
+
+    /// Put the signed metadata of a newly encoded file in the DHT, see [`file_metadata`]
+    fn put_file_metadata(&mut self, metadata: FileMetadata) {
+        let record = match file_metadata::record(&metadata, &self.keypair) {
+            Ok(record) => record,
+            Err(e) => {
+                error!(
+                    "Could not sign the metadata of {}: {}",
+                    metadata.file_hash, e
+                );
+                return;
+            }
+        };
+        if let Err(e) = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .put_record(record, kad::Quorum::One)
+        {
+            warn!(
+                "Could not store the metadata of {}: {:?}",
+                metadata.file_hash, e
+            );
+        }
+    }
+
+    /// Announce a file the node encoded and publish its metadata, both taken from the manifest of the file, which
+    /// keeps the segments appended to the file if it was encoded before
+    async fn publish_encoded_file(&mut self, file_hash: &str) {
+        let hash_dir = get_file_dir(&self.file_dir, file_hash.to_string());
+        let Some(manifest) = manifest::read(&hash_dir).await else {
+            warn!(
+                "There is no manifest for the encoded file {}, it is not announced",
+                file_hash
+            );
+            return;
+        };
+        self.announce_file(FileAnnouncement {
+            file_hash: manifest.file_hash.clone(),
+            k: manifest.k,
+            n: manifest.n,
+            size: manifest.size,
+        });
+        self.put_file_metadata(manifest.metadata());
+    }
+
+    /// Publish a newly encoded file on the topic of the network, and add it to the local index
+    fn announce_file(&mut self, announcement: FileAnnouncement) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        match serde_json::to_vec(&announcement) {
+            Ok(data) => match self
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(self.files_topic.clone(), data)
+            {
+                Ok(_) => info!("Announced the file {}", announcement.file_hash),
+                // no peer is subscribed yet, the file is still known locally
+                Err(gossipsub::PublishError::InsufficientPeers) => {
+                    debug!("No peer to announce the file {} to", announcement.file_hash)
+                }
+                Err(e) => warn!(
+                    "Could not announce the file {}: {}",
+                    announcement.file_hash, e
+                ),
+            },
+            Err(e) => error!(
+                "Could not serialize the announcement of {}: {}",
+                announcement.file_hash, e
+            ),
+        }
+        self.known_files.record(local_peer_id, announcement);
+    }
+
+    fn handle_file_announcement(
+        &mut self,
+        propagation_source: PeerId,
+        message: gossipsub::Message,
+    ) {
+        if message.topic != self.files_topic.hash() {
+            debug!("Ignoring a message on the topic {}", message.topic);
+            return;
+        }
+        let peer_id = message.source.unwrap_or(propagation_source);
+        match serde_json::from_slice::<FileAnnouncement>(&message.data) {
+            Ok(announcement) => {
+                info!("{} announced the file {}", peer_id, announcement.file_hash);
+                self.known_files.record(peer_id, announcement);
+            }
+            Err(e) => warn!("Invalid file announcement from {}: {}", peer_id, e),
+        }
+    }
+
+    /// Reply to the dials waiting for a connection on the addresses that failed
+    fn handle_outgoing_connection_error(
+        &mut self,
+        peer_id: Option<PeerId>,
+        error: libp2p::swarm::DialError,
+    ) {
+        if let Some(peer_id) = peer_id {
+            if self.pending_pex_dial.remove(&peer_id) {
+                debug!(
+                    "Could not connect to {} learned through peer exchange: {}",
+                    peer_id, error
+                );
+                return;
+            }
+        }
+        let failed_addresses: Vec<Multiaddr> = match &error {
+            libp2p::swarm::DialError::Transport(errors) => {
+                errors.iter().map(|(address, _)| address.clone()).collect()
+            }
+            libp2p::swarm::DialError::WrongPeerId { endpoint, .. }
+            | libp2p::swarm::DialError::LocalPeerId { endpoint } => {
+                vec![endpoint.get_remote_address().clone()]
+            }
+            _ => vec![],
+        };
+        let kind = DialErrorKind::classify(&error);
+        let mut replied = false;
+        for address in failed_addresses {
+            let multiaddr = address.to_string();
+            if let Some(sender) = self.pending_dial.complete(&multiaddr) {
+                let err = DialError {
+                    multiaddr: multiaddr.clone(),
+                    kind,
+                    context: error.to_string(),
+                };
+                sender_send_match(sender, Err(err.into()), format!("dial {}", multiaddr));
+                replied = true;
+            }
+        }
+        if !replied {
+            warn!("Outgoing connection to {:?} failed: {}", peer_id, error);
+        }
+    }
+
+    /// Information about this node, as sent in the peer exchange responses
+    fn own_pex_peer(&self) -> PexPeer {
+        let role = if self
+            .current_available_storage_for_send
+            .load(Ordering::Relaxed)
+            > 0
+        {
+            NodeRole::Storage
+        } else {
+            NodeRole::Client
+        };
+        PexPeer {
+            peer_id_base_58: self.swarm.local_peer_id().to_base58(),
+            addrs: self.swarm.listeners().cloned().collect(),
+            label: Some(self.label.clone()),
+            role: Some(role),
+        }
+    }
+
+    /// Share the peers of our routing table that we are connected to, they are known to be reachable
+    fn build_pex_response(&mut self, requester: PeerId, request: PexRequest) -> PexResponse {
+        let max_peers = request.max_peers.min(MAX_PEX_PEERS);
+        let mut peers = vec![];
+        for bucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                let peer_id = *entry.node.key.preimage();
+                if peer_id != requester {
+                    peers.push((peer_id, entry.node.value.iter().cloned().collect()));
+                }
+            }
+        }
+        let peers = peers
+            .into_iter()
+            .filter(|(peer_id, _)| self.swarm.is_connected(peer_id))
+            .take(max_peers)
+            .map(|(peer_id, addrs): (PeerId, Vec<Multiaddr>)| {
+                let known_info = self.known_peer_info.get(&peer_id);
+                PexPeer {
+                    peer_id_base_58: peer_id.to_base58(),
+                    addrs,
+                    label: known_info.and_then(|info| info.label.clone()),
+                    role: known_info.and_then(|info| info.role),
+                }
+            })
+            .collect();
+        PexResponse {
+            own: self.own_pex_peer(),
+            peers,
+        }
+    }
+
+    /// Add the peers learned from `peer` to the routing table, and dial them if we don't have enough connections yet
+    fn handle_pex_response(&mut self, peer: PeerId, response: PexResponse) {
+        let PexResponse { own, peers } = response;
+        debug!("Got {} peers from {} by peer exchange", peers.len(), peer);
+        if own.peer_id() == Some(peer) {
+            // first hand information, always more accurate than what other peers told us
+            self.known_peer_info.insert(peer, own);
+        }
+        let local_peer_id = *self.swarm.local_peer_id();
+        for pex_peer in peers {
+            let Some(peer_id) = pex_peer.peer_id() else {
+                warn!(
+                    "Invalid peer id {} received by peer exchange from {}",
+                    pex_peer.peer_id_base_58, peer
+                );
+                continue;
+            };
+            if peer_id == local_peer_id || pex_peer.addrs.is_empty() {
+                continue;
+            }
+            for addr in pex_peer.addrs.iter() {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, addr.clone());
+            }
+            self.known_peers.insert(peer_id, pex_peer.addrs.clone());
+            let addrs = pex_peer.addrs.clone();
+            self.known_peer_info.entry(peer_id).or_insert(pex_peer);
+            if !self.swarm.is_connected(&peer_id)
+                && !self.pending_pex_dial.contains(&peer_id)
+                && self.swarm.connected_peers().count() + self.pending_pex_dial.len()
+                    < PEX_TARGET_CONNECTIONS
+            {
+                match self
+                    .swarm
+                    .dial(DialOpts::peer_id(peer_id).addresses(addrs).build())
+                {
+                    Ok(()) => {
+                        self.pending_pex_dial.insert(peer_id);
+                    }
+                    Err(e) => debug!(
+                        "Could not dial {} learned through peer exchange: {}",
+                        peer_id, e
+                    ),
+                }
+            }
+        }
+    }
+
+    fn read_block_from_disk(
+        block_store: &BlockStore,
+        block_hash: String,
+        block_dir: PathBuf,
+    ) -> Result<Vec<u8>> {
+        block_store.read(&block_dir.join(block_hash))
+    }
+
+    /// Answer the block request from a task of its own, or right away that the node is busy when too many block
+    /// requests are already served or queued, see [`crate::block_serving`]
+    fn message_request(
+        &mut self,
+        peer: PeerId,
+        request: BlockRequest,
+        channel: ResponseChannel<BlockResponse>,
+    ) {
+        let BlockRequest {
+            file_hash,
+            block_hash,
+        } = request;
+        let Some(admission) = self.block_serving.admit() else {
+            warn!(
+                "Too many block requests, answering {} that the node is busy",
+                peer
+            );
+            Metrics::incr(&self.metrics.busy_block_requests);
+            let busy = BlockResponse {
+                file_hash,
+                block_hash,
+                block_data: vec![],
+                correlation_id: None,
+                busy: true,
+            };
+            if self
+                .swarm
+                .behaviour_mut()
+                .request_block
+                .send_response(channel, busy)
+                .is_err()
+            {
+                warn!("Could not answer {} that the node is busy", peer);
+            }
+            return;
+        };
+        let cmd_sender = self.command_sender.clone();
+        let file_locks = self.file_locks.clone();
+        let block_store = self.block_store.clone();
+        let block_dir = get_block_dir(&self.file_dir, file_hash.clone());
+        let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
+        let recent_events = self.recent_events.clone();
+        tokio::spawn(async move {
+            let _turn = admission.turn().await;
+            let res: Result<()> = async {
+                info!(
+                    "Searching blocks for the file {0} inside {1:?}",
+                    file_hash, block_dir
+                );
+                let ser_block = {
+                    let _read_guard = file_locks.try_read(&file_hash)?;
+                    Self::read_block_from_disk(&block_store, block_hash.clone(), block_dir)?
+                };
+                debug!(
+                    "Read block {0} for file {1}, got: {2:?}",
+                    block_hash, file_hash, ser_block
+                );
+                let correlation_id = lifecycle::correlation_id(&hash_dir).await;
+                let (sender, receiver) = oneshot::channel();
+                cmd_sender
+                    .send(DragoonCommand::SendBlockResponse {
+                        peer_id: peer,
+                        channel,
+                        response: BlockResponse {
+                            file_hash: file_hash.clone(),
+                            block_hash: block_hash.clone(),
+                            block_data: ser_block,
+                            correlation_id,
+                            busy: false,
+                        },
+                        sender: Sender::SenderOneS(sender),
+                    })
+                    .map_err(|_| {
+                        format_err!("The block {} could not be sent to {}", block_hash, peer)
+                    })?;
+                receiver.await??;
+                lifecycle::record(
+                    &hash_dir,
+                    &file_hash,
+                    LifecycleStage::BlockServed,
+                    Some(peer.to_base58()),
+                    Some(block_hash),
+                )
+                .await;
+                Ok(())
+            }
+            .await;
+            if let Err(e) = res {
+                error!("{}", e);
+                recent_events.record(RecentEventKind::Error, Some(peer), e.to_string());
+            }
+        });
+    }
+
+    /// Request the block from the next fallback provider, or fail the request if there is none left
+    fn block_request_failed(
+        &mut self,
+        peer: PeerId,
+        request_id: OutboundRequestId,
+        error: impl std::fmt::Display,
+    ) {
+        if let Some(mut pending) = self.pending_request_block.complete(&request_id) {
+            if let Some(next_peer_id) = pending.fallback_providers.pop_front() {
+                warn!(
+                    "Could not get the block {} from {}, requesting it from {}: {}",
+                    pending.block_hash, peer, next_peer_id, error
+                );
+                self.request_block(next_peer_id, pending);
+            } else {
+                let err_msg = format!("Could not get the block from {}: {}", peer, error);
+                error!(err_msg);
+                self.recent_events
+                    .record(RecentEventKind::Error, Some(peer), err_msg.clone());
+                sender_send_match(
+                    pending.sender,
+                    Err(format_err!(err_msg)),
+                    format!("message response {}", request_id),
+                );
+            }
+        }
     }
 
     async fn info_request(
         &mut self,
+        peer: PeerId,
         request: PeerBlockInfoRequest,
         channel: ResponseChannel<PeerBlockInfoResponse>,
     ) -> Result<()> {
-        let PeerBlockInfoRequest { file_hash } = request;
-        let block_hashes = Self::get_block_list(self.file_dir.clone(), file_hash.clone()).await?;
+        let PeerBlockInfoRequest {
+            file_hash,
+            block_hash,
+        } = request;
+        let block_hashes = {
+            let _read_guard = self.file_locks.try_read(&file_hash)?;
+            Self::get_block_list(self.file_dir.clone(), file_hash.clone()).await
+        };
+        // the peers looking for a block also ask the peers without the file, which answer they do not have it
+        let block_hashes = match (block_hashes, &block_hash) {
+            (Ok(mut block_hashes), Some(block_hash)) => {
+                block_hashes.retain(|hash| hash == block_hash);
+                block_hashes
+            }
+            (Ok(block_hashes), None) => block_hashes,
+            (Err(_), Some(_)) => vec![],
+            (Err(e), None) => return Err(e),
+        };
         debug!(
             "A peer requested the blocks for file {}, node has : {:?}",
             file_hash, block_hashes
         );
         let channel_info = format!("{:?}", &channel);
+        let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
         let peer_block_info = PeerBlockInfo {
             peer_id_base_58: self.swarm.local_peer_id().to_base58(),
             file_hash: file_hash.clone(),
             block_hashes,
             block_sizes: None,
+            storage_class: None,
+            correlation_id: lifecycle::correlation_id(&hash_dir).await,
+            ttl: None,
         };
         self.swarm
             .behaviour_mut()
             .request_info
             .send_response(channel, PeerBlockInfoResponse(peer_block_info))
-            .map_err(|_| CouldNotSendInfoResponse(file_hash, channel_info).into())
+            .map_err(|_| CouldNotSendInfoResponse(file_hash.clone(), channel_info))?;
+        lifecycle::record(
+            &hash_dir,
+            &file_hash,
+            LifecycleStage::InfoServed,
+            Some(peer.to_base58()),
+            None,
+        )
+        .await;
+        Ok(())
     }
 
     async fn handle_command<F, G, P>(&mut self, cmd: DragoonCommand)
@@ -623,6 +1837,16 @@ where {
         for<'a, 'b> &'a P: Div<&'b P, Output = P>,
     {
         debug!("[cmd] {:?}", cmd);
+        // the answers to the block requests are already recorded as served blocks, and reading the events is not one
+        if !matches!(
+            cmd,
+            DragoonCommand::SendBlockResponse { .. }
+                | DragoonCommand::RemoveEntryFromSendBlockToSet { .. }
+                | DragoonCommand::GetRecentEvents { .. }
+        ) {
+            self.recent_events
+                .record(RecentEventKind::Command, None, cmd.to_string());
+        }
         match cmd {
             DragoonCommand::Listen { multiaddr, sender } => {
                 let res = self.listen(multiaddr).await;
@@ -665,96 +1889,176 @@ where {
             DragoonCommand::GetFile {
                 file_hash,
                 output_filename,
+                output,
+                order,
+                overwrite,
+                auto_concurrency,
+                parallelism,
+                decrypt,
+                unpack,
+                segments_root,
+                timeout,
+                operation_id,
                 sender,
             } => {
                 info!("Starting to get the file {}", file_hash);
                 let cmd_sender = self.command_sender.clone();
-                let powers_path = self.powers_path.clone();
-                tokio::spawn(async move {
+                let powers = self.powers.clone();
+                let timeout = timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(self.command_timeouts.get_file);
+                let metrics = self.metrics.clone();
+                let block_store = self.block_store.clone();
+                let peer_reputations = self.peer_reputations.clone();
+                // the key is only needed to decrypt the file
+                let file_key_path = decrypt.then(|| self.file_key_path.clone());
+                let progress = self.progress.start(operation_id, "get-file");
+                let job_id = progress.operation_id().to_string();
+                let task = tokio::spawn(async move {
                     let res = Self::get_file::<F, G, P>(
                         cmd_sender,
                         file_hash.clone(),
                         output_filename,
-                        powers_path,
+                        output,
+                        order,
+                        overwrite,
+                        unpack,
+                        auto_concurrency,
+                        parallelism,
+                        segments_root,
+                        powers,
+                        timeout,
+                        metrics,
+                        block_store,
+                        peer_reputations,
+                        file_key_path,
+                        &progress,
                     )
                     .await;
+                    progress.finish(&res);
                     sender_send_match(sender, res, format!("GetFile {}", file_hash));
                 });
+                self.jobs.attach(&job_id, task.abort_handle());
             }
+            DragoonCommand::DelegateGetFile {
+                peer_id,
+                file_hash,
+                overwrite,
+                sender,
+            } => self.delegate_get_file(peer_id, file_hash, overwrite, sender),
             DragoonCommand::DialSingle { multiaddr, sender } => {
-                if !self.pending_dial.contains_key(&multiaddr) {
-                    let res = self.dial(multiaddr.clone()).await;
-                    if res.is_err() {
-                        sender_send_match(sender, res, String::from("DialSingle (error)"));
+                if multiaddr
+                    .parse::<Multiaddr>()
+                    .is_ok_and(|addr| dns::needs_resolution(&addr))
+                {
+                    // the resolved addresses come back as DialSingle commands, so the loop is not blocked by the resolution
+                    let cmd_sender = self.command_sender.clone();
+                    let dns_resolver = self.dns_resolver.clone();
+                    tokio::spawn(async move {
+                        let (_, res) =
+                            Self::dial_resolved(&cmd_sender, &dns_resolver, multiaddr).await;
+                        sender_send_match(sender, res, String::from("DialSingle"));
+                    });
+                } else if !self.pending_dial.contains(&multiaddr) {
+                    if let Err(e) = self.dial(multiaddr.clone()).await {
+                        sender_send_match(sender, Err(e), String::from("DialSingle (error)"));
                     } else {
                         // need to check again even though we already did, because there was an await inbetween (and thus a potential modification of the hash_map)
-                        if let std::collections::hash_map::Entry::Vacant(e) =
-                            self.pending_dial.entry(multiaddr.clone())
-                        {
-                            e.insert(sender);
+                        if !self.pending_dial.contains(&multiaddr) {
+                            self.pending_dial.insert(multiaddr.clone(), sender);
                         } else {
                             error!("Another dial attempt to {} modified the list of pending dial while waiting for this dial to complete", multiaddr)
                         }
                     }
                 } else {
                     warn!("Tried to double dial on multiaddr {}", multiaddr);
+                    let err = DialError {
+                        multiaddr: multiaddr.clone(),
+                        kind: DialErrorKind::AlreadyDialing,
+                        context: String::from("a dial to this multiaddr is already pending"),
+                    };
+                    sender_send_match(sender, Err(err.into()), String::from("DialSingle (error)"));
                 }
             }
             DragoonCommand::DialMultiple {
                 list_multiaddr,
+                mode,
                 sender,
             } => {
-                let (dial_send, mut dial_recv) = mpsc::unbounded_channel();
-                for multiaddr in list_multiaddr {
-                    let sender = dial_send.clone();
-                    let cmd_sender = self.command_sender.clone();
-                    if cmd_sender
-                        .send(DragoonCommand::DialSingle {
-                            multiaddr: multiaddr.clone(),
-                            sender: Sender::SenderMPSC(sender),
-                        })
-                        .is_err()
-                    {
-                        error!(
-                            "Could not send the dial command for the multiaddr {}",
-                            multiaddr
-                        );
-                    }
-                }
+                let cmd_sender = self.command_sender.clone();
+                let dns_resolver = self.dns_resolver.clone();
                 tokio::spawn(async move {
-                    let mut err_list = vec![];
-                    while let Some(res) = dial_recv.recv().await {
-                        if res.is_err() {
-                            err_list.push(res);
+                    let mut outcomes = vec![];
+                    match mode {
+                        DialMode::BestEffort => {
+                            outcomes =
+                                future::join_all(list_multiaddr.into_iter().map(|multiaddr| {
+                                    Self::dial_outcome(&cmd_sender, &dns_resolver, multiaddr)
+                                }))
+                                .await;
+                        }
+                        DialMode::FailFast => {
+                            let mut failed = false;
+                            for multiaddr in list_multiaddr {
+                                if failed {
+                                    outcomes.push(DialOutcome::skipped(multiaddr));
+                                    continue;
+                                }
+                                let outcome =
+                                    Self::dial_outcome(&cmd_sender, &dns_resolver, multiaddr).await;
+                                failed = !outcome.success;
+                                outcomes.push(outcome);
+                            }
                         }
                     }
-                    let final_res = match err_list[..] {
-                        [] => Ok(()),
-                        _ => Err(format_err!(
-                            "Dial to all supplied multiaddr failed, got the following errors: {:?}",
-                            err_list
-                        )),
-                    };
-                    // making the error msg first to not have to clone to prevent borrowed value move
-                    let err_msg = format!(
-                        "Could not send the result of the dial_multiple operation: {:?}",
-                        final_res
-                    );
-                    sender_send_match(sender, final_res, err_msg);
+                    sender_send_match(sender, Ok(outcomes), String::from("DialMultiple"));
                 });
             }
             DragoonCommand::AddPeer { multiaddr, sender } => {
                 let res = self.add_peer(multiaddr).await;
                 sender_send_match(sender, res, String::from("AddPeer"));
             }
+            DragoonCommand::AppendToFile {
+                file_hash,
+                file_path,
+                sender,
+            } => {
+                self.append_to_file::<F, G, P>(file_hash, file_path, sender)
+                    .await
+            }
+            DragoonCommand::FinishAppendToFile {
+                file_hash,
+                segment,
+                sender,
+            } => {
+                let res = self.finish_append_to_file(file_hash, segment).await;
+                sender_send_match(sender, res, String::from("AppendToFile"));
+            }
             DragoonCommand::StartProvide { key, sender } => {
+                let hash_dir = get_file_dir(&self.file_dir, key.clone());
+                if hash_dir.exists() {
+                    lifecycle::record(&hash_dir, &key, LifecycleStage::Provided, None, None).await;
+                }
                 if let Ok(query_id) = self
                     .swarm
                     .behaviour_mut()
                     .kademlia
                     .start_providing(key.clone().into_bytes().into())
                 {
-                    self.pending_start_providing.insert(query_id, sender);
+                    self.provided_keys.insert(&self.file_dir, key.clone()).await;
+                    if let Some((key, sender)) =
+                        self.pending_start_providing.insert(query_id, (key, sender))
+                    {
+                        let err = ProviderError(format!(
+                            "The query {} to provide {} was replaced by another one",
+                            query_id, key
+                        ));
+                        sender_send_match(
+                            sender,
+                            Err(format_err!(err)),
+                            String::from("StartProvide"),
+                        );
+                    }
                 } else {
                     error!("Could not provide {}", key);
                     let err = ProviderError(format!("Could not provide {}", key));
@@ -763,53 +2067,195 @@ where {
                     sender_send_match(sender, Err(format_err!(err)), String::from("StartProvide"));
                 }
             }
-            DragoonCommand::StopProvide { key, sender } => {
-                self.swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .stop_providing(&key.clone().into_bytes().into());
-                //? need to remove from pending_start_providing ? how ? we don't have the queryID
-                sender_send_match(sender, Ok(()), "StopProvide".to_string())
-            }
-            DragoonCommand::GetProviders { key, sender } => {
-                let mut provider_stream = self.get_providers(key);
-                tokio::spawn(async move {
-                    // instead of returning the stream directly through the Sender, put it in a Vec format so it's easier to read for the person getting it
-                    let mut all_providers = Vec::<PeerId>::default();
-                    while let Some(provider) = provider_stream.next().await {
-                        all_providers.push(provider);
-                    }
-                    sender_send_match(sender, Ok(all_providers), String::from("GetProviders"));
-                });
-            }
-            DragoonCommand::Bootstrap { sender } => {
-                let res = self.bootstrap().await;
-                sender_send_match(sender, res, String::from("Bootstrap"));
-            }
-            DragoonCommand::GetBlockFrom {
+            DragoonCommand::ProbePeer {
                 peer_id,
+                file_hash,
+                sender,
+            } => {
+                let cmd_sender = self.command_sender.clone();
+                let powers = self.powers.clone();
+                let peer_reputations = self.peer_reputations.clone();
+                tokio::spawn(async move {
+                    let report = peer_probe::probe_peer::<F, G, P>(
+                        cmd_sender,
+                        peer_id,
+                        file_hash,
+                        powers,
+                        &peer_reputations,
+                    )
+                    .await;
+                    sender_send_match(sender, Ok(report), String::from("ProbePeer"));
+                });
+            }
+            DragoonCommand::GetReplication { file_hash, sender } => {
+                let cmd_sender = self.command_sender.clone();
+                let local_peer_id = *self.swarm.local_peer_id();
+                let desired = self.replication_factors.get(&file_hash);
+                tokio::spawn(async move {
+                    let res = replication::replication_status(
+                        &cmd_sender,
+                        local_peer_id,
+                        file_hash,
+                        desired,
+                    )
+                    .await;
+                    sender_send_match(sender, res, String::from("GetReplication"));
+                });
+            }
+            DragoonCommand::FindBlock {
                 file_hash,
                 block_hash,
-                save_to_disk,
                 sender,
             } => {
-                let request_id = self.swarm.behaviour_mut().request_block.send_request(
-                    &peer_id,
-                    BlockRequest {
+                let cmd_sender = self.command_sender.clone();
+                let local_peer_id = *self.swarm.local_peer_id();
+                tokio::spawn(async move {
+                    let res =
+                        replication::find_block(&cmd_sender, local_peer_id, file_hash, block_hash)
+                            .await;
+                    sender_send_match(sender, res, String::from("FindBlock"));
+                });
+            }
+            DragoonCommand::GetGlobalBlockMap { file_hash, sender } => {
+                let cmd_sender = self.command_sender.clone();
+                let local_peer_id = *self.swarm.local_peer_id();
+                tokio::spawn(async move {
+                    let res =
+                        replication::global_block_map(&cmd_sender, local_peer_id, file_hash).await;
+                    sender_send_match(sender, res, String::from("GetGlobalBlockMap"));
+                });
+            }
+            DragoonCommand::SetReplication {
+                file_hash,
+                replication,
+                sender,
+            } => {
+                self.replication_factors
+                    .set(&self.file_dir, file_hash.clone(), replication)
+                    .await;
+                if replication > 0 {
+                    let cmd_sender = self.command_sender.clone();
+                    let local_peer_id = *self.swarm.local_peer_id();
+                    tokio::spawn(replication::replicate_files(
+                        cmd_sender,
+                        local_peer_id,
+                        vec![(file_hash, replication)],
+                    ));
+                }
+                sender_send_match(sender, Ok(()), String::from("SetReplication"));
+            }
+            DragoonCommand::ReannounceAll {
+                local_files,
+                sender,
+            } => self.reannounce_all(local_files, sender),
+            DragoonCommand::RefreshProvide { key } => {
+                if !self.provided_keys.contains(&key) {
+                    debug!("{} stopped being provided before it was refreshed", key);
+                } else {
+                    match self
+                        .swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .start_providing(key.clone().into_bytes().into())
+                    {
+                        Ok(query_id) => {
+                            self.pending_refresh_provide.insert(query_id, key);
+                        }
+                        Err(e) => {
+                            warn!("Could not refresh the provider record of {}: {:?}", key, e)
+                        }
+                    }
+                }
+            }
+            DragoonCommand::RecodeBlocks {
+                file_hash,
+                block_hashes,
+                count,
+                sender,
+            } => {
+                let file_dir = self.file_dir.clone();
+                let powers = self.powers.clone();
+                let file_locks = self.file_locks.clone();
+                let block_quota = self.block_quota.clone();
+                let block_store = self.block_store.clone();
+                tokio::spawn(async move {
+                    let res = Self::recode_blocks::<F, G, P>(
+                        file_dir,
                         file_hash,
-                        block_hash,
-                    },
-                );
-                self.pending_request_block
-                    .insert(request_id, (save_to_disk, sender));
+                        block_hashes,
+                        count,
+                        powers,
+                        file_locks,
+                        block_quota,
+                        block_store,
+                    )
+                    .await;
+                    sender_send_match(sender, res, String::from("RecodeBlocks"));
+                });
+            }
+            DragoonCommand::Shutdown { sender } => {
+                self.shutdown().await;
+                sender_send_match(sender, Ok(()), String::from("Shutdown"));
+            }
+            DragoonCommand::StopProvide { key, sender } => {
+                let res = self.stop_provide(key).await;
+                sender_send_match(sender, res, "StopProvide".to_string())
+            }
+            DragoonCommand::GetFileMetadata { file_hash, sender } => {
+                let query_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .get_record(file_metadata::record_key(&file_hash));
+                self.pending_get_file_metadata
+                    .insert(query_id, (file_hash, sender));
+            }
+            DragoonCommand::GetProviders { key, sender } => {
+                let mut provider_stream = self.get_providers(key);
+                tokio::spawn(async move {
+                    // instead of returning the stream directly through the Sender, put it in a Vec format so it's easier to read for the person getting it
+                    let mut all_providers = Vec::<PeerId>::default();
+                    while let Some(provider) = provider_stream.next().await {
+                        all_providers.push(provider);
+                    }
+                    sender_send_match(sender, Ok(all_providers), String::from("GetProviders"));
+                });
+            }
+            DragoonCommand::Bootstrap { sender } => {
+                let res = self.bootstrap().await;
+                sender_send_match(sender, res, String::from("Bootstrap"));
             }
+            DragoonCommand::GetBlockFrom {
+                peer_id,
+                file_hash,
+                block_hash,
+                save_to_disk,
+                fallback_providers,
+                sender,
+            } => self.request_block(
+                peer_id,
+                PendingBlockRequest {
+                    file_hash,
+                    block_hash,
+                    save_to_disk,
+                    fallback_providers: fallback_providers.into(),
+                    sender,
+                },
+            ),
             DragoonCommand::GetBlocksInfoFrom {
                 peer_id,
                 file_hash,
+                block_hash,
                 sender,
-            } => self.get_blocks_info_from(peer_id, file_hash, sender),
+            } => self.get_blocks_info_from(peer_id, file_hash, block_hash, sender),
+            DragoonCommand::GetAvailableStorageFrom { peer_id, sender } => {
+                self.get_available_storage_from(peer_id, sender)
+            }
             DragoonCommand::GetBlockList { file_hash, sender } => {
-                let res = Self::get_block_list(self.file_dir.clone(), file_hash).await;
+                let res = match self.file_locks.try_read(&file_hash) {
+                    Ok(_read_guard) => Self::get_block_list(self.file_dir.clone(), file_hash).await,
+                    Err(e) => Err(e),
+                };
                 sender_send_match(sender, res, String::from("GetBlocksInfoFrom"));
             }
             DragoonCommand::DecodeBlocks {
@@ -818,50 +2264,372 @@ where {
                 output_filename,
                 sender,
             } => {
+                let block_dir = PathBuf::from(block_dir);
+                // the blocks only belong to a known file when they are in our own file directory
+                let file_hash = block_dir
+                    .strip_prefix(&self.file_dir)
+                    .ok()
+                    .and_then(|path| path.iter().next())
+                    .map(|file_hash| file_hash.to_string_lossy().to_string());
+                let manifest = match &file_hash {
+                    Some(file_hash) => {
+                        manifest::read(&get_file_dir(&self.file_dir, file_hash.clone())).await
+                    }
+                    None => None,
+                };
                 let res = Self::decode_blocks::<F, G>(
-                    PathBuf::from(block_dir),
+                    &self.block_store,
+                    block_dir,
                     &block_hashes,
                     output_filename,
+                    true,
+                    manifest.as_ref(),
+                    &self.file_key_path,
                 )
                 .await;
+                self.metrics
+                    .record_stats(|stats| stats.record_decode(file_hash.as_deref(), res.is_ok()));
+                if let (Some(file_hash), Ok(_)) = (&file_hash, &res) {
+                    let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
+                    lifecycle::record(&hash_dir, file_hash, LifecycleStage::Decoded, None, None)
+                        .await;
+                }
                 sender_send_match(sender, res, String::from("DecodeBlocks"));
             }
+            DragoonCommand::DeleteFile { file_hash, sender } => {
+                if let Err(e) = self.stop_provide(file_hash.clone()).await {
+                    warn!("Could not stop providing {}: {}", file_hash, e);
+                }
+                let file_dir = self.file_dir.clone();
+                let send_accounting = self.send_accounting.clone();
+                let current_available_storage_for_send =
+                    self.current_available_storage_for_send.clone();
+                let storage_classes = self.storage_classes.clone();
+                let block_quota = self.block_quota.clone();
+                let file_locks = self.file_locks.clone();
+                let metrics = self.metrics.clone();
+                tokio::spawn(async move {
+                    let res = Self::delete_file(
+                        file_dir,
+                        file_hash,
+                        send_accounting,
+                        current_available_storage_for_send,
+                        storage_classes,
+                        block_quota,
+                        file_locks,
+                        metrics,
+                    )
+                    .await;
+                    sender_send_match(sender, res, String::from("DeleteFile"));
+                });
+            }
             DragoonCommand::EncodeFile {
                 file_path,
                 replace_blocks,
                 encoding_method,
                 encode_mat_k,
                 encode_mat_n,
+                domain,
+                domain_seed,
+                compression,
+                packing,
+                encrypt,
+                archive,
+                operation_id,
                 sender,
             } => {
-                let res = Self::encode_file::<F, G, P>(
+                let progress = self.progress.start(operation_id, "encode-file");
+                // run in its own task so a job can abort it and the loop is not blocked, the file is announced once it is done
+                let task = tokio::spawn(Self::encode_file::<F, G, P>(
                     self.file_dir.clone(),
                     file_path,
+                    archive,
                     replace_blocks,
                     encoding_method,
                     encode_mat_k,
                     encode_mat_n,
-                    self.powers_path.clone(),
-                )
-                .await;
-                sender_send_match(sender, res, String::from("EncodeFile"));
+                    domain,
+                    domain_seed,
+                    compression,
+                    packing,
+                    encrypt.then(|| self.file_key_path.clone()),
+                    self.powers.clone(),
+                    self.metrics.clone(),
+                    self.file_locks.clone(),
+                    self.block_quota.clone(),
+                    self.block_store.clone(),
+                    progress.clone(),
+                ));
+                self.jobs
+                    .attach(progress.operation_id(), task.abort_handle());
+                let cmd_sender = self.command_sender.clone();
+                tokio::spawn(async move {
+                    let res = task
+                        .await
+                        .unwrap_or_else(|e| Err(format_err!("The encoding was canceled: {}", e)));
+                    progress.finish(&res);
+                    if let Ok(encoded) = &res {
+                        // queued before the answer, so the next commands of the client see the file announced
+                        if cmd_sender
+                            .send(DragoonCommand::PublishEncodedFile {
+                                file_hash: encoded.file_hash.clone(),
+                            })
+                            .is_err()
+                        {
+                            error!(
+                                "Could not send the command to announce the file {}",
+                                encoded.file_hash
+                            );
+                        }
+                    }
+                    sender_send_match(sender, res, String::from("EncodeFile"));
+                });
+            }
+            DragoonCommand::EncodeFiles {
+                file_paths,
+                replace_blocks,
+                encoding_method,
+                encode_mat_k,
+                encode_mat_n,
+                domain,
+                domain_seed,
+                compression,
+                packing,
+                encrypt,
+                parallelism,
+                sender,
+            } => {
+                let cmd_sender = self.command_sender.clone();
+                let file_dir = self.file_dir.clone();
+                let file_key_path = encrypt.then(|| self.file_key_path.clone());
+                let powers = self.powers.clone();
+                let metrics = self.metrics.clone();
+                let file_locks = self.file_locks.clone();
+                let block_quota = self.block_quota.clone();
+                let block_store = self.block_store.clone();
+                let progress_registry = self.progress.clone();
+                let workers = Arc::new(Semaphore::new(
+                    parallelism.unwrap_or(ENCODE_FILES_PARALLELISM).max(1),
+                ));
+                // the files are encoded outside of the loop, each one is announced once it is done
+                tokio::spawn(async move {
+                    let mut tasks = vec![];
+                    for file_path in expand_file_paths(file_paths).await {
+                        let workers = workers.clone();
+                        let file_dir = file_dir.clone();
+                        let domain_seed = domain_seed.clone();
+                        let file_key_path = file_key_path.clone();
+                        let powers = powers.clone();
+                        let metrics = metrics.clone();
+                        let file_locks = file_locks.clone();
+                        let block_quota = block_quota.clone();
+                        let block_store = block_store.clone();
+                        let progress_registry = progress_registry.clone();
+                        let task_file_path = file_path.clone();
+                        let task = tokio::spawn(async move {
+                            let _permit = workers.acquire_owned().await.unwrap();
+                            let progress = progress_registry.start(None, "encode-file");
+                            let res = Self::encode_file::<F, G, P>(
+                                file_dir,
+                                task_file_path,
+                                false,
+                                replace_blocks,
+                                encoding_method,
+                                encode_mat_k,
+                                encode_mat_n,
+                                domain,
+                                domain_seed,
+                                compression,
+                                packing,
+                                file_key_path,
+                                powers,
+                                metrics,
+                                file_locks,
+                                block_quota,
+                                block_store,
+                                progress.clone(),
+                            )
+                            .await;
+                            progress.finish(&res);
+                            res
+                        });
+                        tasks.push((file_path, task));
+                    }
+                    let mut outcomes = vec![];
+                    for (file_path, task) in tasks {
+                        let res = task.await.unwrap_or_else(|e| {
+                            Err(format_err!("The encoding was canceled: {}", e))
+                        });
+                        match res {
+                            Ok(encoded) => {
+                                if cmd_sender
+                                    .send(DragoonCommand::PublishEncodedFile {
+                                        file_hash: encoded.file_hash.clone(),
+                                    })
+                                    .is_err()
+                                {
+                                    error!(
+                                        "Could not send the command to announce the file {}",
+                                        encoded.file_hash
+                                    );
+                                }
+                                outcomes.push(EncodeFilesOutcome {
+                                    file_path,
+                                    encoded: Some(encoded),
+                                    error: None,
+                                });
+                            }
+                            Err(e) => {
+                                warn!("Could not encode {}: {}", file_path, e);
+                                outcomes.push(EncodeFilesOutcome {
+                                    file_path,
+                                    encoded: None,
+                                    error: Some(e.to_string()),
+                                });
+                            }
+                        }
+                    }
+                    sender_send_match(sender, Ok(outcomes), String::from("EncodeFiles"));
+                });
+            }
+            DragoonCommand::PublishEncodedFile { file_hash } => {
+                self.publish_encoded_file(&file_hash).await;
             }
             DragoonCommand::GetBlockDir { file_hash, sender } => {
                 let res = Ok(get_block_dir(&self.file_dir.clone(), file_hash));
                 sender_send_match(sender, res, String::from("GetBlockDir"));
             }
+            DragoonCommand::KnownFiles { sender } => {
+                sender_send_match(
+                    sender,
+                    Ok(self.known_files.list()),
+                    String::from("KnownFiles"),
+                );
+            }
+            DragoonCommand::Lifecycle { file_hash, sender } => {
+                let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
+                let res = lifecycle::timeline(&hash_dir, file_hash).await;
+                sender_send_match(sender, res, String::from("Lifecycle"));
+            }
+            DragoonCommand::Manifest { file_hash, sender } => {
+                let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
+                let res = manifest::read(&hash_dir)
+                    .await
+                    .ok_or_else(|| format_err!("There is no manifest for the file {}", file_hash));
+                sender_send_match(sender, res, String::from("Manifest"));
+            }
             DragoonCommand::GetFileDir { file_hash, sender } => {
                 let res = Ok(get_file_dir(&self.file_dir.clone(), file_hash));
                 sender_send_match(sender, res, String::from("GetFileDir"));
             }
+            DragoonCommand::MirrorStatus { sender } => {
+                let res = Ok(self.mirror.status(&self.block_quota.manifest()));
+                sender_send_match(sender, res, String::from("MirrorStatus"));
+            }
             DragoonCommand::NodeInfo { sender } => {
-                let res = Ok((*(self.swarm.local_peer_id()), self.label.clone()));
+                let storage_class_usage = self.metrics.storage_class_usage.lock().unwrap().clone();
+                let res = Ok(NodeInfo {
+                    peer_id: self.swarm.local_peer_id().to_base58(),
+                    label: self.label.clone(),
+                    storage_classes: storage_class_usage,
+                    block_counts: self.block_quota.info(),
+                    receiving_paused: self.receiving_paused.load(Ordering::SeqCst),
+                });
                 sender_send_match(sender, res, String::from("NodeInfo"));
             }
+            DragoonCommand::ScrubStatus { sender } => {
+                let res = Ok(self.scrubber.status(&self.file_dir));
+                sender_send_match(sender, res, String::from("ScrubStatus"));
+            }
+            DragoonCommand::GetRecentEvents { since, sender } => {
+                sender_send_match(
+                    sender,
+                    Ok(self.recent_events.since(since)),
+                    String::from("GetRecentEvents"),
+                );
+            }
+            DragoonCommand::GetBandwidth { sender } => {
+                sender_send_match(
+                    sender,
+                    Ok(self.bandwidth.report()),
+                    String::from("GetBandwidth"),
+                );
+            }
+            DragoonCommand::GetPeerQuotas { sender } => {
+                sender_send_match(
+                    sender,
+                    Ok(self.peer_quotas.info()),
+                    String::from("GetPeerQuotas"),
+                );
+            }
+            DragoonCommand::BanPeer { peer_id, sender } => {
+                let banned = self.banned_peers.insert(&self.file_dir, peer_id).await;
+                // also closes the connections to the peer
+                self.swarm.behaviour_mut().blocked_peers.block_peer(peer_id);
+                info!("Banned the peer {}", peer_id);
+                sender_send_match(sender, Ok(banned), String::from("BanPeer"));
+            }
+            DragoonCommand::UnbanPeer { peer_id, sender } => {
+                let unbanned = self.banned_peers.remove(&self.file_dir, &peer_id).await;
+                self.swarm
+                    .behaviour_mut()
+                    .blocked_peers
+                    .unblock_peer(peer_id);
+                info!("Unbanned the peer {}", peer_id);
+                sender_send_match(sender, Ok(unbanned), String::from("UnbanPeer"));
+            }
+            DragoonCommand::DisconnectPeer { peer_id, sender } => {
+                let connected = self.swarm.disconnect_peer_id(peer_id).is_ok();
+                info!("Disconnecting from {}", peer_id);
+                sender_send_match(sender, Ok(connected), String::from("DisconnectPeer"));
+            }
+            DragoonCommand::GetPeerScore { peer_id, sender } => {
+                let res = self
+                    .peer_reputations
+                    .get(&peer_id)
+                    .ok_or_else(|| UnknownPeer(peer_id.to_base58()).into());
+                sender_send_match(sender, res, String::from("GetPeerScore"));
+            }
+            DragoonCommand::SetPeerQuota {
+                peer_id,
+                quota,
+                sender,
+            } => {
+                let send_accounting = self.send_accounting.clone();
+                let file_dir = self.file_dir.clone();
+                tokio::spawn(async move {
+                    let res = send_accounting
+                        .lock()
+                        .await
+                        .set_quota(&file_dir, &peer_id.to_base58(), quota)
+                        .await;
+                    match &res {
+                        Ok(()) => info!("Set the quota of the peer {} to {:?}", peer_id, quota),
+                        Err(e) => error!("Could not set the quota of the peer {}: {}", peer_id, e),
+                    }
+                    sender_send_match(sender, res, String::from("SetPeerQuota"));
+                });
+            }
+            DragoonCommand::PauseReceiving { sender } => {
+                let changed = !self.receiving_paused.swap(true, Ordering::SeqCst);
+                if changed {
+                    info!("Paused the reception of the blocks sent by other peers");
+                }
+                sender_send_match(sender, Ok(changed), String::from("PauseReceiving"));
+            }
+            DragoonCommand::ResumeReceiving { sender } => {
+                let changed = self.receiving_paused.swap(false, Ordering::SeqCst);
+                if changed {
+                    info!("Resumed the reception of the blocks sent by other peers");
+                }
+                sender_send_match(sender, Ok(changed), String::from("ResumeReceiving"));
+            }
             DragoonCommand::SendBlockTo {
                 peer_id,
                 file_hash,
                 block_hash,
+                storage_class,
+                ttl,
                 sender,
             } => {
                 // check if we are already trying to send this given block to this peer
@@ -871,13 +2639,14 @@ where {
                 {
                     self.pending_send_block_to
                         .insert((peer_id, block_hash.clone()));
-                    self.send_block_to(peer_id, block_hash, file_hash, sender);
+                    self.send_block_to(peer_id, block_hash, file_hash, storage_class, ttl, sender);
                     //TODO remove the entry from the hash table once we are done, use a command ?
                 } else {
                     let send_id = SendId {
                         peer_id,
                         file_hash,
                         block_hash,
+                        attempts: 0,
                     };
                     let err = Err(SendBlockToAlreadyStarted { send_id });
 
@@ -888,59 +2657,106 @@ where {
                 strategy_name,
                 file_hash,
                 block_list,
+                weights,
+                timeout,
+                operation_id,
                 sender,
             } => {
-                let number_of_blocks_to_send = block_list.len();
-                //not my proudest line with a dynamic type cast
-                let send_stream: Pin<Box<dyn FusedStream<Item = SendId> + Send>> =
-                    match strategy_name {
-                        StrategyName::Random => {
-                            let known_peers = self.known_peer_id.clone().into_iter();
-                            let peer_input_stream = f_stream::iter(known_peers).fuse();
-                            let size_of_block_list = block_list.len();
-                            let block_input_stream = f_stream::iter(
-                                vec![file_hash; size_of_block_list]
-                                    .into_iter()
-                                    .zip(block_list),
-                            )
-                            .fuse();
-                            let random_distribution =
-                                Box::<send_strategy_impl::random::RandomDistribution>::default();
-                            Box::pin(random_distribution.get_send_stream(
-                                Box::pin(peer_input_stream),
-                                Box::pin(block_input_stream),
-                            ))
-                        }
-                        StrategyName::RoundRobin => {
-                            let mut known_peers =
-                                self.known_peer_id.clone().into_iter().collect::<Vec<_>>();
-                            //sort to ensure the ordering for the tests is not random
-                            known_peers.sort();
-                            let known_peers = known_peers.into_iter();
-                            let peer_input_stream = f_stream::iter(known_peers).fuse();
-                            let size_of_block_list = block_list.len();
-                            let block_input_stream = f_stream::iter(
-                                vec![file_hash; size_of_block_list]
-                                    .into_iter()
-                                    .zip(block_list),
-                            )
-                            .fuse();
-                            let robin_distribution = Box::<
-                                send_strategy_impl::round_robin::RobinDistribution,
-                            >::default();
-                            Box::pin(robin_distribution.get_send_stream(
-                                Box::pin(peer_input_stream),
-                                Box::pin(block_input_stream),
-                            ))
+                let strategy = match self.send_strategies.get(&strategy_name) {
+                    Ok(strategy) => strategy,
+                    Err(e) => {
+                        error!("{}", e);
+                        sender_send_match(sender, Err(e), String::from("SendBlockList"));
+                        return;
+                    }
+                };
+                let factory = strategy.factory.clone();
+                let needs_available_storage = strategy.needs_available_storage;
+                let weights =
+                    match self.weighted_peers(&strategy_name, strategy.takes_weights, weights) {
+                        Ok(weights) => weights,
+                        Err(e) => {
+                            error!("{}", e);
+                            sender_send_match(sender, Err(e), String::from("SendBlockList"));
+                            return;
                         }
                     };
+                let mut peers = PeerKnowledge {
+                    known_peers: self
+                        .known_peers
+                        .ids()
+                        .filter(|peer_id| {
+                            !self.banned_peers.contains(peer_id)
+                                && !self.peer_reputations.is_low(peer_id)
+                        })
+                        .copied()
+                        .collect(),
+                    available_storage: HashMap::new(),
+                    rtts: self.peer_rtts.clone(),
+                    weights,
+                };
+                let number_of_blocks_to_send = block_list.len();
+                let block_inputs = vec![file_hash; number_of_blocks_to_send]
+                    .into_iter()
+                    .zip(block_list)
+                    .collect();
                 let cmd_sender = self.command_sender.clone();
-                tokio::spawn(async move {
-                    let res =
-                        Self::send_block_list(number_of_blocks_to_send, send_stream, cmd_sender)
-                            .await;
+                let timeout = timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(self.command_timeouts.send_block_list);
+                let progress = self.progress.start(operation_id, "send-block-list");
+                let job_id = progress.operation_id().to_string();
+                let task = tokio::spawn(async move {
+                    if needs_available_storage {
+                        peers.available_storage = Self::available_storage_of(
+                            cmd_sender.clone(),
+                            peers.known_peers.clone(),
+                        )
+                        .await;
+                    }
+                    let send_stream = factory(peers, block_inputs);
+                    let res = Self::send_block_list(
+                        number_of_blocks_to_send,
+                        send_stream,
+                        timeout,
+                        cmd_sender,
+                        &progress,
+                    )
+                    .await;
+                    progress.finish(&res);
                     sender_send_match(sender, res, String::from("SendBlockList"));
                 });
+                self.jobs.attach(&job_id, task.abort_handle());
+            }
+            DragoonCommand::SendBlockResponse {
+                peer_id,
+                channel,
+                response,
+                sender,
+            } => {
+                let channel_info = format!("{:?}", &channel);
+                let block_hash = response.block_hash.clone();
+                let file_hash = response.file_hash.clone();
+                let res = self
+                    .swarm
+                    .behaviour_mut()
+                    .request_block
+                    .send_response(channel, response)
+                    .map_err(|_| {
+                        CouldNotSendBlockResponse(
+                            block_hash.clone(),
+                            file_hash.clone(),
+                            channel_info,
+                        )
+                    });
+                if res.is_ok() {
+                    self.recent_events.record(
+                        RecentEventKind::BlockServed,
+                        Some(peer_id),
+                        format!("Block {} of file {}", block_hash, file_hash),
+                    );
+                }
+                sender_send_match(sender, res, String::from("SendBlockResponse"));
             }
             DragoonCommand::RemoveEntryFromSendBlockToSet {
                 peer_id,
@@ -989,53 +2805,468 @@ where {
                     String::from("ChangeAvailableSendStorage"),
                 )
             }
-        }
-    }
-
-    async fn listen(&mut self, multiaddr: String) -> Result<u64> {
-        if let Ok(addr) = multiaddr.parse() {
-            match self.swarm.listen_on(addr) {
-                Ok(listener_id) => {
-                    info!("Listening on {}", multiaddr);
-
-                    let id = regex::Regex::new(r"ListenerId\((\d+)\)")
-                        .unwrap()
-                        .captures(&format!("{:?}", listener_id))
-                        .unwrap()
-                        .get(1)
-                        .unwrap()
-                        .as_str()
-                        .parse::<u64>()
-                        .unwrap();
-                    self.listeners.insert(id, listener_id);
-
-                    Ok(id)
+            DragoonCommand::RotateIdentity { sender } => {
+                if self.pending_identity_rotation.is_some() {
+                    let err = IdentityRotationError(
+                        "An identity rotation is already in progress".to_string(),
+                    );
+                    sender_send_match(sender, Err(err.into()), String::from("RotateIdentity"));
+                } else if let Some(user) = self.storage_user() {
+                    let err = IdentityRotationError(format!(
+                        "The storage of the node is in use by {}, try again once it is done",
+                        user
+                    ));
+                    sender_send_match(sender, Err(err.into()), String::from("RotateIdentity"));
+                } else {
+                    self.start_identity_rotation(sender);
                 }
-                Err(te) => {
-                    let err_msg = match te {
-                        TransportError::Other(e) => e.to_string(),
-                        TransportError::MultiaddrNotSupported(addr) => {
-                            format!("multiaddr {} not supported", addr)
+            }
+            DragoonCommand::FinishIdentityRotation {
+                new_peer_id,
+                sender,
+            } => {
+                // the rotation can be finished either by the last acknowledgement or by the timeout, only the first one does something
+                if let Some(pending) = self
+                    .pending_identity_rotation
+                    .take_if(|pending| pending.new_keypair.public().to_peer_id() == new_peer_id)
+                {
+                    // the tasks started since the rotation was accepted still use the directory of the old identity
+                    match self.storage_user() {
+                        Some(user) if pending.started_at.elapsed() < IDENTITY_ROTATION_MAX_WAIT => {
+                            debug!("Waiting for {} before moving the storage of the node", user);
+                            self.pending_identity_rotation = Some(pending);
+                            self.request_finish_identity_rotation(
+                                new_peer_id,
+                                Some(IDENTITY_ROTATION_RETRY),
+                            );
                         }
-                    };
-                    error!(err_msg);
-                    Err(BadListener(err_msg).into())
+                        Some(user) => {
+                            let err = IdentityRotationError(format!(
+                                "The storage of the node is still in use by {}, giving up the rotation",
+                                user
+                            ));
+                            error!("{}", err);
+                            sender_send_match(
+                                pending.sender,
+                                Err(err.into()),
+                                String::from("RotateIdentity"),
+                            );
+                        }
+                        None => {
+                            let res = self
+                                .finish_identity_rotation::<F, G, P>(
+                                    pending.new_keypair,
+                                    pending.link,
+                                )
+                                .await;
+                            sender_send_match(pending.sender, res, String::from("RotateIdentity"));
+                        }
+                    }
                 }
+                sender_send_match(sender, Ok(()), String::from("FinishIdentityRotation"));
+            }
+            DragoonCommand::GeneratePowers { max_degree, sender } => {
+                let powers = self.powers.clone();
+                tokio::spawn(async move {
+                    let res = powers_file::generate::<F, G>(max_degree, powers.path()).await;
+                    sender_send_match(sender, res, String::from("GeneratePowers"));
+                });
             }
-        } else {
-            let err_msg = format!("Could not parse {}", multiaddr);
-            error!(err_msg);
-            Err(BadListener(err_msg).into())
         }
     }
 
-    async fn remove_listener(&mut self, listener_id: u64) -> Result<bool> {
-        if let Some(listener) = self.listeners.get(&listener_id) {
-            Ok(self.swarm.remove_listener(*listener))
-        } else {
-            let err_msg = format!("Listener {} not found", listener_id);
-            error!(err_msg);
-            Err(BadListener(err_msg).into())
+    /// Generate the new keypair and tell all the connected peers about it.
+    /// The swarm is only switched once they all answered, or after a timeout.
+    fn start_identity_rotation(&mut self, sender: Sender<(PeerId, PeerId)>) {
+        let new_keypair = Keypair::generate_ed25519();
+        let link = match IdentityLink::new(&self.keypair, &new_keypair) {
+            Ok(link) => link,
+            Err(e) => {
+                let err = IdentityRotationError(format!("Could not sign the identity link: {}", e));
+                error!("{}", err);
+                sender_send_match(sender, Err(err.into()), String::from("RotateIdentity"));
+                return;
+            }
+        };
+        info!(
+            "Rotating identity from {} to {}",
+            link.old_peer_id, link.new_peer_id
+        );
+        let listen_addrs = self.swarm.listeners().cloned().collect::<Vec<Multiaddr>>();
+        let connected_peers = self
+            .swarm
+            .connected_peers()
+            .cloned()
+            .collect::<Vec<PeerId>>();
+        let mut pending_notices = HashSet::new();
+        for peer_id in connected_peers {
+            let request_id = self.swarm.behaviour_mut().identity_rotation.send_request(
+                &peer_id,
+                IdentityRotationNotice {
+                    link: link.clone(),
+                    listen_addrs: listen_addrs.clone(),
+                },
+            );
+            pending_notices.insert(request_id);
+        }
+        let no_peer_to_notify = pending_notices.is_empty();
+        let new_peer_id = new_keypair.public().to_peer_id();
+        self.pending_identity_rotation = Some(PendingIdentityRotation {
+            new_keypair,
+            link,
+            pending_notices,
+            sender,
+            started_at: time::Instant::now(),
+        });
+        if no_peer_to_notify {
+            self.request_finish_identity_rotation(new_peer_id, None);
+        } else {
+            self.request_finish_identity_rotation(new_peer_id, Some(IDENTITY_ROTATION_TIMEOUT));
+        }
+    }
+
+    fn request_finish_identity_rotation(&self, new_peer_id: PeerId, delay: Option<Duration>) {
+        let cmd_sender = self.command_sender.clone();
+        tokio::spawn(async move {
+            if let Some(delay) = delay {
+                time::sleep(delay).await;
+            }
+            let (finish_sender, finish_receiver) = oneshot::channel();
+            if cmd_sender
+                .send(DragoonCommand::FinishIdentityRotation {
+                    new_peer_id,
+                    sender: Sender::SenderOneS(finish_sender),
+                })
+                .is_err()
+            {
+                error!("Could not send the command to finish the identity rotation");
+            }
+            let _ = finish_receiver.await;
+        });
+    }
+
+    fn identity_rotation_notice_done(&mut self, request_id: OutboundRequestId) {
+        let finished_rotation = match self.pending_identity_rotation.as_mut() {
+            Some(pending) => {
+                if pending.pending_notices.remove(&request_id) && pending.pending_notices.is_empty()
+                {
+                    Some(pending.new_keypair.public().to_peer_id())
+                } else {
+                    None
+                }
+            }
+            None => {
+                warn!(
+                    "Got an answer for the identity rotation notice {} but no rotation is pending",
+                    request_id
+                );
+                None
+            }
+        };
+        if let Some(new_peer_id) = finished_rotation {
+            debug!("All the peers have been notified of the identity rotation");
+            self.request_finish_identity_rotation(new_peer_id, None);
+        }
+    }
+
+    /// Replace the routing information about a peer that announced it changed identity
+    fn handle_identity_rotation_notice(
+        &mut self,
+        peer: PeerId,
+        notice: IdentityRotationNotice,
+    ) -> bool {
+        match notice.link.verify() {
+            Ok((old_peer_id, new_peer_id)) if old_peer_id == peer => {
+                info!(
+                    "Peer {} rotated its identity, it is now {}",
+                    old_peer_id, new_peer_id
+                );
+                self.known_peers.remove(&old_peer_id);
+                self.known_peers
+                    .insert(new_peer_id, notice.listen_addrs.clone());
+                if let Some(mut pex_peer) = self.known_peer_info.remove(&old_peer_id) {
+                    pex_peer.peer_id_base_58 = new_peer_id.to_base58();
+                    self.known_peer_info.insert(new_peer_id, pex_peer);
+                }
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .remove_peer(&old_peer_id);
+                for addr in notice.listen_addrs {
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&new_peer_id, addr);
+                }
+                true
+            }
+            Ok((old_peer_id, _)) => {
+                warn!(
+                    "Peer {} sent an identity rotation notice for another peer ({})",
+                    peer, old_peer_id
+                );
+                false
+            }
+            Err(e) => {
+                warn!("Invalid identity rotation notice from {}: {}", peer, e);
+                false
+            }
+        }
+    }
+
+    /// What still uses the files of the node, which would be moved under its feet by an identity rotation
+    fn storage_user(&self) -> Option<String> {
+        if let Some(operation) = self.progress.running().first() {
+            return Some(format!(
+                "the {} {}",
+                operation.command, operation.operation_id
+            ));
+        }
+        if self.file_locks.any_held() {
+            return Some(String::from("a task reading the blocks of a file"));
+        }
+        [
+            (&self.gc_task, "the garbage collection"),
+            (&self.scrub_task, "the scrub"),
+            (&self.repair_task, "the repair"),
+        ]
+        .into_iter()
+        .find(|(task, _)| task.as_ref().is_some_and(|task| !task.is_finished()))
+        .map(|(_, user)| format!("{} of the blocks", user))
+    }
+
+    /// Answer the requests waiting for the old swarm with an error, their ids are meaningless for the new one
+    fn fail_pending_requests(&mut self) {
+        let err = || -> anyhow::Error {
+            IdentityRotationError(String::from(
+                "The node changed identity before the request was answered",
+            ))
+            .into()
+        };
+        for (address, sender) in self.pending_dial.complete_all() {
+            sender_send_match(sender, Err(err()), format!("dial {}", address));
+        }
+        for (query_id, (_, sender)) in self.pending_start_providing.complete_all() {
+            sender_send_match(sender, Err(err()), format!("start provide {:?}", query_id));
+        }
+        for (query_id, sender) in self.pending_get_providers.complete_all() {
+            if sender.send(Err(err())).is_err() {
+                error!(
+                    "Could not send the error of the GetProviders query {:?}",
+                    query_id
+                );
+            }
+        }
+        for (query_id, (_, sender)) in self.pending_get_file_metadata.complete_all() {
+            sender_send_match(
+                sender,
+                Err(err()),
+                format!("get file metadata {:?}", query_id),
+            );
+        }
+        for (request_id, sender) in self.pending_request_block_info.complete_all() {
+            sender_send_match(sender, Err(err()), format!("info response {}", request_id));
+        }
+        for (request_id, sender) in self.pending_request_storage.complete_all() {
+            sender_send_match(
+                sender,
+                Err(err()),
+                format!("storage response {}", request_id),
+            );
+        }
+        for (request_id, pending) in self.pending_request_block.complete_all() {
+            sender_send_match(
+                pending.sender,
+                Err(err()),
+                format!("message response {}", request_id),
+            );
+        }
+    }
+
+    /// Move the storage of the node to the directory of the new identity, then replace the swarm with one using the new keypair.
+    /// Listeners, routing table and provided keys are carried over, and the previously connected peers are dialed again.
+    async fn finish_identity_rotation<F, G, P>(
+        &mut self,
+        new_keypair: Keypair,
+        link: IdentityLink,
+    ) -> Result<(PeerId, PeerId)>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+        P: DenseUVPolynomial<F>,
+        for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+    {
+        let old_peer_id = *self.swarm.local_peer_id();
+        let new_peer_id = new_keypair.public().to_peer_id();
+
+        let listen_addrs = self
+            .listeners
+            .values()
+            .map(|(_, addr)| addr.clone())
+            .collect::<Vec<Multiaddr>>();
+        let provided_keys = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .store_mut()
+            .provided()
+            .map(|record| record.key.clone())
+            .collect::<Vec<kad::RecordKey>>();
+        let routing_table = self.routing_table();
+        let connected_peers = self
+            .swarm
+            .connected_peers()
+            .cloned()
+            .collect::<Vec<PeerId>>();
+
+        let old_node_dir = get_node_dir(&self.storage_dir, old_peer_id);
+        let new_node_dir = get_node_dir(&self.storage_dir, new_peer_id);
+        info!(
+            "Moving the node directory from {:?} to {:?}",
+            old_node_dir, new_node_dir
+        );
+        tfs::rename(&old_node_dir, &new_node_dir)
+            .await
+            .map_err(|e| {
+                IdentityRotationError(format!(
+                    "Could not move {:?} to {:?}: {}",
+                    old_node_dir, new_node_dir, e
+                ))
+            })?;
+        save_identity_link(&new_node_dir, link)?;
+        if let Some(keystore) = &self.keystore {
+            // keep the new identity after a restart
+            keystore.save(&new_keypair)?;
+        }
+        self.file_dir = [new_node_dir, PathBuf::from("files")].iter().collect();
+
+        let new_swarm = create_swarm(
+            new_keypair.clone(),
+            &self.transports,
+            &self.files_topic,
+            &self.bandwidth,
+        )
+        .await?;
+        // dropping the old swarm closes its listeners and connections
+        drop(std::mem::replace(&mut self.swarm, new_swarm));
+        for peer_id in self.banned_peers.peers() {
+            self.swarm
+                .behaviour_mut()
+                .blocked_peers
+                .block_peer(*peer_id);
+        }
+        self.keypair = new_keypair;
+        if self.label == old_peer_id.to_base58() {
+            self.label = new_peer_id.to_base58();
+        }
+        // the ids of the old swarm are meaningless for the new one
+        self.fail_pending_requests();
+        self.pending_dial.clear();
+        self.pending_start_providing.clear();
+        self.pending_refresh_provide.clear();
+        self.pending_get_providers.clear();
+        self.pending_get_file_metadata.clear();
+        self.pending_request_block_info.clear();
+        self.pending_request_storage.clear();
+        self.pending_request_block.clear();
+        self.pending_pex_dial.clear();
+
+        self.start_send_block_handler::<F, G, P>()?;
+        self.start_delegate_get_file_handler()?;
+        self.start_mirror_handlers()?;
+        self.listeners.clear();
+        for addr in listen_addrs {
+            if let Err(e) = self.listen(addr.to_string()).await {
+                error!(
+                    "Could not listen on {} again after the identity rotation: {}",
+                    addr, e
+                );
+            }
+        }
+        for (peer_id, addrs) in routing_table {
+            for addr in addrs {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, addr);
+            }
+        }
+        for key in provided_keys {
+            if let Err(e) = self.swarm.behaviour_mut().kademlia.start_providing(key) {
+                error!("Could not provide again after the identity rotation: {}", e);
+            }
+        }
+        for peer_id in connected_peers {
+            if let Err(e) = self.swarm.dial(peer_id) {
+                warn!(
+                    "Could not dial {} again after the identity rotation: {}",
+                    peer_id, e
+                );
+            }
+        }
+        info!("Identity rotation done, the node is now {}", new_peer_id);
+        Ok((old_peer_id, new_peer_id))
+    }
+
+    /// Listen on the multiaddr given when the node starts, before the loop of the network runs, failing at the first
+    /// multiaddr the node cannot listen on
+    pub(crate) async fn listen_on_start(&mut self, listen: &[String]) -> Result<()> {
+        for multiaddr in listen {
+            if let Err(e) = self.listen(multiaddr.clone()).await {
+                let reason = match e.downcast_ref::<DragoonError>() {
+                    Some(BadListener(msg)) => msg.clone(),
+                    _ => e.to_string(),
+                };
+                return Err(format_err!("Could not listen on {}: {}", multiaddr, reason));
+            }
+        }
+        Ok(())
+    }
+
+    async fn listen(&mut self, multiaddr: String) -> Result<u64> {
+        if let Ok(addr) = multiaddr.parse::<Multiaddr>() {
+            match self.swarm.listen_on(addr.clone()) {
+                Ok(listener_id) => {
+                    info!("Listening on {}", multiaddr);
+
+                    let id = regex::Regex::new(r"ListenerId\((\d+)\)")
+                        .unwrap()
+                        .captures(&format!("{:?}", listener_id))
+                        .unwrap()
+                        .get(1)
+                        .unwrap()
+                        .as_str()
+                        .parse::<u64>()
+                        .unwrap();
+                    self.listeners.insert(id, (listener_id, addr));
+
+                    Ok(id)
+                }
+                Err(te) => {
+                    let err_msg = match te {
+                        TransportError::Other(e) => e.to_string(),
+                        TransportError::MultiaddrNotSupported(addr) => {
+                            format!("multiaddr {} not supported", addr)
+                        }
+                    };
+                    error!(err_msg);
+                    Err(BadListener(err_msg).into())
+                }
+            }
+        } else {
+            let err_msg = format!("Could not parse {}", multiaddr);
+            error!(err_msg);
+            Err(BadListener(err_msg).into())
+        }
+    }
+
+    async fn remove_listener(&mut self, listener_id: u64) -> Result<bool> {
+        if let Some((listener, _)) = self.listeners.get(&listener_id) {
+            Ok(self.swarm.remove_listener(*listener))
+        } else {
+            let err_msg = format!("Listener {} not found", listener_id);
+            error!(err_msg);
+            Err(BadListener(err_msg).into())
         }
     }
 
@@ -1047,19 +3278,52 @@ where {
     /// - If it can reconstruct the file, it will close the requests for block info and blocks to all the peers it contacted, construct the file, write it to disk and send the path where the file was written to the user
     /// - If it can't reconstruct the file yet, given the block combination it got from block info, it will try to find the combination of blocks that will allow for file reconstruction with a minimal block download (ie using the max number of already downloaded blocks it can)
     /// - If even after all that it still can't find a combination of blocks that works, it will exit with an error
+    #[allow(clippy::too_many_arguments)]
     async fn get_file<F, G, P>(
         cmd_sender: mpsc::UnboundedSender<DragoonCommand>,
         file_hash: String,
         output_filename: String,
-        powers_path: PathBuf,
-    ) -> Result<PathBuf>
+        output: GetFileOutput,
+        order: RetrievalOrder,
+        overwrite: bool,
+        unpack: bool,
+        auto_concurrency: bool,
+        parallelism: Option<usize>,
+        segments_root: Option<String>,
+        powers: Arc<PowersCache>,
+        timeout: Duration,
+        metrics: Arc<Metrics>,
+        block_store: Arc<BlockStore>,
+        peer_reputations: Arc<PeerReputations>,
+        file_key_path: Option<PathBuf>,
+        progress: &ProgressHandle,
+    ) -> Result<GetFileOutcome>
     where
         F: PrimeField,
         G: CurveGroup<ScalarField = F>,
         P: DenseUVPolynomial<F>,
         for<'a, 'b> &'a P: Div<&'b P, Output = P>,
     {
+        progress.stage("reading the metadata");
+        // a file encoded by this node has a manifest, with the same metadata as the one in the DHT. Anyone can put a
+        // record in the DHT, so only the manifest is trusted, the metadata of a record is a hint
+        let (mut metadata, metadata_trusted) =
+            match Self::get_manifest(&cmd_sender, &file_hash).await {
+                Some(manifest) => (Some(manifest.metadata()), true),
+                None => (
+                    Self::get_file_metadata(&cmd_sender, &file_hash).await,
+                    false,
+                ),
+            };
+        if let Some(metadata) = &metadata {
+            info!(
+                "Get file {}: {} blocks out of {} are needed, from the metadata of the file",
+                file_hash, metadata.k, metadata.n
+            );
+            progress.blocks_total(metadata.k);
+        }
         info!("Get file: getting providers of file {}", file_hash);
+        progress.stage("finding providers");
         let (get_prov_sender, get_prov_recv) = oneshot::channel();
         if cmd_sender
             .send(DragoonCommand::GetProviders {
@@ -1115,6 +3379,16 @@ where {
         };
         let file_dir = get_file_dir_recv.await??;
         debug!("Will write the file in {:?}", file_dir);
+        let output_path: PathBuf = [file_dir.clone(), PathBuf::from(&output_filename)]
+            .iter()
+            .collect();
+        // fail before downloading anything, the check is done again when the file is written
+        if output == GetFileOutput::File
+            && !overwrite
+            && tokio::fs::try_exists(&output_path).await?
+        {
+            return Err(FileExists(format!("{:?}", output_path)).into());
+        }
 
         let (info_sender, info_receiver) = mpsc::unbounded_channel();
 
@@ -1123,16 +3397,14 @@ where {
             file_hash, provider_list
         );
 
-        if provider_list.is_empty() {
-            return Err(format_err!("The provider list for the file {} is empty; \nTip: did the nodes with blocks of the file use `start-provide` ?", file_hash));
-        }
-
+        let no_providers = provider_list.is_empty();
         for peer_id in provider_list {
             let err_msg = format!("Could not send the command to request the list of blocks from peer {} for the get_file request for {}", peer_id, file_hash);
             if cmd_sender
                 .send(DragoonCommand::GetBlocksInfoFrom {
                     peer_id,
                     file_hash: file_hash.clone(),
+                    block_hash: None,
                     sender: Sender::SenderMPSC(info_sender.clone()),
                 })
                 .is_err()
@@ -1143,17 +3415,39 @@ where {
         debug!("Finished requesting block info list for file {}", file_hash);
         drop(info_sender);
 
-        //TODO change this to keep in memory other providers of the same block in case the first one fails (a hash map maybe ?)
-
+        // resume from the blocks kept by a previous get-file of the same file
+        let resumed_blocks =
+            Self::verified_blocks_on_disk::<F, G, P>(&block_store, &block_dir, &powers).await?;
+        if !resumed_blocks.is_empty() {
+            info!(
+                "Get file {}: resuming from {} blocks already on disk",
+                file_hash,
+                resumed_blocks.len()
+            );
+        } else if no_providers {
+            // the blocks on disk may be enough without any provider, e.g. the ones sent by the delegate of a delegated
+            // retrieval
+            return Err(format_err!("The provider list for the file {} is empty; \nTip: did the nodes with blocks of the file use `start-provide` ?", file_hash));
+        }
         let mut block_hashes_on_disk = vec![];
+        let mut concurrency = ConcurrencyController::new(auto_concurrency, parallelism);
 
+        #[allow(clippy::too_many_arguments)]
         async fn download_first_k_blocks<F, G, P>(
             mut info_receiver: UnboundedReceiver<Result<PeerBlockInfo>>,
-            powers_path: PathBuf,
+            powers: Arc<PowersCache>,
             block_hashes_on_disk: &mut Vec<String>,
             cmd_sender: UnboundedSender<DragoonCommand>,
             file_hash: String,
             block_dir: PathBuf,
+            order: RetrievalOrder,
+            concurrency: &mut ConcurrencyController,
+            resumed_blocks: Vec<(String, Block<F, G>)>,
+            block_store: &BlockStore,
+            peer_reputations: &PeerReputations,
+            metadata: &mut Option<FileMetadata>,
+            metadata_trusted: bool,
+            progress: &ProgressHandle,
         ) -> Result<()>
         where
             F: PrimeField,
@@ -1161,13 +3455,83 @@ where {
             P: DenseUVPolynomial<F>,
             for<'a, 'b> &'a P: Div<&'b P, Output = P>,
         {
-            let mut already_request_block = vec![];
-            let powers = get_powers(powers_path).await?;
-            let mut number_of_blocks_written: u32 = 0;
+            let mut scheduler = BlockScheduler::new(order);
+            let mut contiguous_progress = None;
+            // the packing of the metadata is only used to tell how far the file can be read
+            let bits_per_element = metadata
+                .as_ref()
+                .map(|metadata| metadata.packing)
+                .unwrap_or_default()
+                .bits_per_element::<F>();
+            let powers = powers.get::<F, G>().await?;
+            let mut independent_blocks = RowEchelon::<F>::new();
 
-            let (block_sender, mut block_receiver) = mpsc::unbounded_channel();
+            for (block_hash, block) in resumed_blocks {
+                scheduler.skip(block_hash.clone());
+                if !independent_blocks.add_row(block.shard.linear_combination.clone()) {
+                    debug!(
+                        "The block {} on disk is a combination of the other ones, it is not used to reconstruct {}",
+                        block_hash, file_hash
+                    );
+                    continue;
+                }
+                let contiguous = contiguous_progress.get_or_insert_with(|| {
+                    progress.blocks_total(block.shard.k as usize);
+                    ContiguousProgress::new(
+                        block.shard.k as usize,
+                        block.shard.size,
+                        bits_per_element,
+                    )
+                });
+                contiguous.add_block(&block.shard.linear_combination);
+                progress.block_done(block.compressed_size());
+                block_hashes_on_disk.push(block_hash);
+                if independent_blocks.rank() >= block.shard.k as usize {
+                    debug!(
+                        "The blocks on disk are enough to reconstruct the file {}",
+                        file_hash
+                    );
+                    return Ok(());
+                }
+            }
+
+            // the block requests in flight, along with the hash of the block they are for
+            let mut pending_blocks = FuturesUnordered::new();
 
             'download_first_k_blocks: loop {
+                // only keep a few blocks in flight, so the blocks are received in the order given by the scheduler
+                while pending_blocks.len() < concurrency.blocks_in_flight() {
+                    let Some((block_hash, peer_id)) = scheduler.next_block() else {
+                        break;
+                    };
+                    debug!(
+                        "Requesting the block {} from {} for file {}",
+                        block_hash, peer_id, file_hash
+                    );
+                    let (block_sender, block_receiver) = oneshot::channel();
+                    if cmd_sender
+                        .send(DragoonCommand::GetBlockFrom {
+                            peer_id,
+                            file_hash: file_hash.clone(),
+                            block_hash: block_hash.clone(),
+                            save_to_disk: false,
+                            // the scheduler chooses the next provider itself, so it knows which one failed
+                            fallback_providers: vec![],
+                            sender: Sender::SenderOneS(block_sender),
+                        })
+                        .is_err()
+                    {
+                        error!("Could not send the command to get the block {} from peer {} for file {}", block_hash, peer_id, file_hash);
+                        scheduler.block_failed(&block_hash);
+                    } else {
+                        pending_blocks.push(async move {
+                            let response = block_receiver.await.unwrap_or_else(|e| {
+                                Err(format_err!("The block request was canceled: {}", e))
+                            });
+                            (block_hash, response)
+                        });
+                    }
+                }
                 tokio::select! {
                         biased;
                         Some(response) = info_receiver.recv() => {
@@ -1178,79 +3542,134 @@ where {
                                 })?;
                                 let PeerBlockInfo { peer_id_base_58, file_hash, block_hashes, .. } = response;
                                 debug!("Got block list from {} for file {} : {:?}", peer_id_base_58, file_hash, block_hashes);
-                                let blocks_to_request: Vec<String> = block_hashes
-                                        .into_iter()
-                                        .filter(|x| !already_request_block.contains(x)) // do not request the block if it's already requested
-                                        .collect();
-                                debug!("Requesting the following blocks from {} for file {} : {:?}", peer_id_base_58, file_hash, blocks_to_request);
                                 let bytes = bs58::decode(peer_id_base_58).into_vec().unwrap();
                                 let peer_id = PeerId::from_bytes(&bytes).unwrap();
-                                for block_hash in blocks_to_request {
-                                    let err_msg = format!("Could not send the command to get the block {} from peer {} for file {}", block_hash, peer_id, file_hash);
-                                    if cmd_sender.send(DragoonCommand::GetBlockFrom {peer_id, file_hash: file_hash.clone(), block_hash: block_hash.clone(), save_to_disk: false, sender: Sender::SenderMPSC(block_sender.clone())}).is_err() {
-                                        error!(err_msg);
-                                    }
-                                    else {
-                                        already_request_block.push(block_hash);
-                                    }
-
+                                if peer_reputations.is_low(&peer_id) {
+                                    debug!("{} has a low score, its blocks of {} are requested last", peer_id, file_hash);
+                                    scheduler.deprioritize(peer_id);
                                 }
+                                scheduler.add_provider(peer_id, block_hashes);
                         },
-                        Some(response) = block_receiver.recv() => {
-                            //TODO change this unwrap
-                            let maybe_block_response = response.unwrap();
+                        Some((block_hash, response)) = pending_blocks.next() => {
+                            let maybe_block_response = match response {
+                                Ok(maybe_block_response) => maybe_block_response,
+                                Err(e) => {
+                                    error!("Could not get a block in get-file, got error: {}", e);
+                                    scheduler.block_failed(&block_hash);
+                                    if let Some(sample) = concurrency.block_failed() {
+                                        info!("Get file {}: backing off to {} blocks in flight", file_hash, sample.blocks_in_flight);
+                                    }
+                                    continue 'download_first_k_blocks
+                                }
+                            };
                             if let Some(block_response) = maybe_block_response {
-                                let block: Block<F,G> = match Block::deserialize_with_mode(&block_response.block_data[..], Compress::Yes, Validate::Yes) {
+                                if let Some(sample) = concurrency.block_received(block_response.block_data.len()) {
+                                    info!("Get file {}: {:.0} B/s, {} blocks in flight", file_hash, sample.throughput, sample.blocks_in_flight);
+                                }
+                                let block: Block<F,G> = match block_format::deserialize(&block_response.block_data, Validate::Yes) {
                                     Ok(block) => block,
                                     Err(e) => {error!("Could not deserialize a block in get-file, got error: {}", e);
+                                if let Some(provider) = scheduler.block_failed(&block_hash) {
+                                    peer_reputations.record(provider, PeerBehaviour::InvalidBlock);
+                                }
                                 continue 'download_first_k_blocks}
                                 };
+                                // cheaper than checking the proof, and does not need any block to know what to expect
+                                let matches_metadata = metadata.as_ref().map(|metadata| {
+                                    block.shard.k as usize == metadata.k
+                                        && block_format::commitment_digest(&block).is_ok_and(|digest| digest == metadata.commitment_digest)
+                                });
+                                if matches_metadata == Some(false) && metadata_trusted {
+                                    error!("The block {} does not match the manifest of the file {}", block_response.block_hash, file_hash);
+                                    if let Some(provider) = scheduler.block_failed(&block_hash) {
+                                        peer_reputations.record(provider, PeerBehaviour::InvalidBlock);
+                                    }
+                                    continue 'download_first_k_blocks;
+                                }
                                 debug!("Got a block for the file {} : {} ", file_hash, block_response.block_hash);
                                 let number_of_blocks_to_reconstruct_file = block.shard.k;
                                 debug!("Number of blocks to reconstruct file {} : {}", file_hash, number_of_blocks_to_reconstruct_file);
                                 if verify::<F,G,P>(&block, &powers)? {
-                                    //TODO check if the new block is not linearly dependant with the other blocks already on disk
+                                    if matches_metadata == Some(false) {
+                                        // the record may have been put by anyone, a block with a valid proof is trusted over it
+                                        warn!("The block {} has a valid proof but does not match the metadata record of the file {}, the record is not used anymore", block_response.block_hash, file_hash);
+                                        *metadata = None;
+                                    }
+                                    scheduler.block_received(&block_hash);
+                                    if !independent_blocks.add_row(block.shard.linear_combination.clone()) {
+                                        debug!("The block {} for file {} is a combination of the blocks already held, requesting another one", block_response.block_hash, file_hash);
+                                        continue 'download_first_k_blocks;
+                                    }
                                     debug!("Block {} for file {} was verified successfully; Now dumping to disk", block_response.block_hash, file_hash);
-                                    let _ = fs::dump(&block, &block_dir, None, Compress::Yes)?;
-                                    number_of_blocks_written += 1;
+                                    let _ = block_store.dump(&block, &block_dir)?;
+                                    let contiguous = contiguous_progress.get_or_insert_with(|| {
+                                        progress.blocks_total(block.shard.k as usize);
+                                        ContiguousProgress::new(block.shard.k as usize, block.shard.size, bits_per_element)
+                                    });
+                                    if let Some(contiguous_bytes) = contiguous.add_block(&block.shard.linear_combination) {
+                                        info!("Get file {}: bytes 0..{} out of {} are available", file_hash, contiguous_bytes, block.shard.size);
+                                    }
+                                    progress.block_done(block_response.block_data.len());
+                                    if let Some(hash_dir) = block_dir.parent() {
+                                        lifecycle::adopt(hash_dir, block_response.correlation_id.as_deref()).await;
+                                        lifecycle::record(hash_dir, &file_hash, LifecycleStage::BlockFetched, None, Some(block_response.block_hash.clone())).await;
+                                    }
                                     block_hashes_on_disk.push(block_response.block_hash);
-                                    if number_of_blocks_written >= number_of_blocks_to_reconstruct_file {
-                                        debug!("Received exactly {} blocks, pausing block download and trying to reconstruct the file {}", number_of_blocks_to_reconstruct_file, file_hash);
+                                    if independent_blocks.rank() >= number_of_blocks_to_reconstruct_file as usize {
+                                        debug!("Received {} independent blocks, pausing block download and trying to reconstruct the file {}", number_of_blocks_to_reconstruct_file, file_hash);
                                         //TODO properly stop downloads ? drop/close receiver ?
                                         break 'download_first_k_blocks;
                                     }
                                 }
                                 else {
-                                    //TODO ask the block again ? change provider ?
-                                    todo!()
+                                    let provider = scheduler.block_failed(&block_hash);
+                                    warn!("The block {} sent by {:?} for file {} is invalid, requesting it from another provider", block_hash, provider, file_hash);
+                                    if let Some(provider) = provider {
+                                        peer_reputations.record(provider, PeerBehaviour::InvalidBlock);
+                                    }
                                 }
                             }
                             else {
-                                error!("No block response was sent when using get file, the node might have saved it to disk")
+                                error!("No block response was sent when using get file, the node might have saved it to disk");
+                                scheduler.block_failed(&block_hash);
                             }
 
                         }
+                        else => {
+                            return Err(format_err!("All the providers answered or failed before enough blocks of {} were received", file_hash));
+                        }
 
                 }
             }
             Ok(())
         }
 
-        let timeout_duration = Duration::from_secs(10);
-
-        match time::timeout(
-            timeout_duration,
+        progress.stage("downloading");
+        let download_res = time::timeout(
+            timeout,
             download_first_k_blocks::<F, G, P>(
                 info_receiver,
-                powers_path,
+                powers,
                 &mut block_hashes_on_disk,
-                cmd_sender,
-                file_hash,
+                cmd_sender.clone(),
+                file_hash.clone(),
                 block_dir.clone(),
+                order,
+                &mut concurrency,
+                resumed_blocks,
+                &block_store,
+                &peer_reputations,
+                &mut metadata,
+                metadata_trusted,
+                progress,
             ),
         )
-        .await
-        {
+        .await;
+        if auto_concurrency {
+            let curve = concurrency.into_curve();
+            metrics.record_stats(|stats| stats.record_concurrency_curve(&file_hash, curve));
+        }
+        match download_res {
             Ok(res) => {
                 match res {
                     Ok(_) => {} //nothing to do
@@ -1264,24 +3683,138 @@ where {
                 }
             }
             Err(_) => {
-                let err_msg = "Getting the required amount of blocks to make the file timed-out, not enough blocks to make the file";
+                let err_msg = format!("Getting the required amount of blocks to make the file timed-out, not enough blocks to make the file; the {} blocks on disk are kept and a new get-file resumes from them", block_hashes_on_disk.len());
                 error!(err_msg);
                 return Err(format_err!(err_msg));
             }
         }
 
-        let _ = Self::decode_blocks::<F, G>(
-            block_dir.clone(),
-            &block_hashes_on_disk,
-            output_filename.clone(),
-        )
-        .await;
-
+        progress.stage("decoding");
+        // the blocks are only checked against their commitments, which could be the ones of another file
+        let decode_and_check = |packing: Packing| {
+            Self::decode_bytes::<F, G>(&block_store, &block_dir, &block_hashes_on_disk, packing)
+                .and_then(|bytes| {
+                    let decoded_hash = hash_file(&bytes);
+                    if decoded_hash != file_hash {
+                        warn!(
+                            "The decoded file has the hash {} instead of {}",
+                            decoded_hash, file_hash
+                        );
+                        return Err(FileHashMismatch {
+                            expected: file_hash.clone(),
+                            decoded: decoded_hash,
+                        }
+                        .into());
+                    }
+                    Ok(bytes)
+                })
+        };
+        let packing = metadata
+            .as_ref()
+            .map_or(Packing::default(), |metadata| metadata.packing);
+        let mut verified_bytes = decode_and_check(packing);
+        if verified_bytes.is_err() && !metadata_trusted {
+            // the packing of a record is only a hint, the hash of the file tells which packing is the right one
+            for other_packing in Packing::ALL.into_iter().filter(|p| *p != packing) {
+                if let Ok(bytes) = decode_and_check(other_packing) {
+                    warn!(
+                        "The file {} was packed with {} instead of the {} of its metadata",
+                        file_hash, other_packing, packing
+                    );
+                    verified_bytes = Ok(bytes);
+                    break;
+                }
+            }
+        }
+        let verified_bytes = verified_bytes
+            .and_then(|bytes| match &file_key_path {
+                // the hash is the one of the ciphertext, checked above
+                Some(file_key_path) => file_encryption::decrypt(file_key_path, &bytes),
+                None => Ok(bytes),
+            })
+            .and_then(|bytes| {
+                // a file without metadata is given as it was encoded
+                let compression = metadata
+                    .as_ref()
+                    .map_or(Compression::None, |metadata| metadata.compression);
+                if !metadata_trusted && !compression::has_header(compression, &bytes) {
+                    warn!(
+                        "The file {} is not compressed with the {} of its metadata, it is given as it was encoded",
+                        file_hash, compression
+                    );
+                    return Ok(bytes);
+                }
+                compression::decompress(compression, bytes)
+            });
+        // the hash of the file does not cover the segments of a record, the root given by the user does
+        let segments = metadata
+            .as_ref()
+            .map_or(&[][..], |metadata| metadata.segments.as_slice());
+        let verified_bytes = match (verified_bytes, segments_root) {
+            (Ok(_), Some(segments_root))
+                if manifest::segments_root(&file_hash, segments) != segments_root =>
+            {
+                Err(format_err!(
+                    "The segments of the file {} in its metadata do not match the segments root {}",
+                    file_hash,
+                    segments_root
+                ))
+            }
+            (Ok(bytes), None) if !metadata_trusted && !segments.is_empty() => {
+                warn!(
+                    "The metadata of the file {} lists {} segments, they are only retrieved with the segments root of the file",
+                    file_hash,
+                    segments.len()
+                );
+                Ok(bytes)
+            }
+            (Ok(bytes), _) if !segments.is_empty() => {
+                progress.stage("getting the segments");
+                Self::append_segments(
+                    &cmd_sender,
+                    bytes,
+                    segments,
+                    order,
+                    file_key_path.is_some(),
+                    timeout,
+                )
+                .await
+            }
+            (verified_bytes, _) => verified_bytes,
+        };
+        let decode_res = match (verified_bytes, output) {
+            (Ok(bytes), GetFileOutput::File) if unpack => {
+                archive::unpack(bytes, output_path.clone(), overwrite)
+                    .await
+                    .map(|_| GetFileOutcome::Written(output_path))
+            }
+            (Ok(bytes), GetFileOutput::File) => {
+                Self::write_decoded_file(&block_dir, &bytes, &output_filename, overwrite)
+                    .await
+                    .map(|_| GetFileOutcome::Written(output_path))
+            }
+            (Ok(_), GetFileOutput::Hash) => Ok(GetFileOutcome::Hash(file_hash.clone())),
+            (Ok(_), GetFileOutput::None) => Ok(GetFileOutcome::Decodable(true)),
+            (Err(e), _) => Err(e),
+        };
+        metrics.record_stats(|stats| {
+            stats.record_get_file(&file_hash, block_hashes_on_disk.len() as u64);
+            stats.record_decode(Some(&file_hash), decode_res.is_ok());
+        });
+        if let (Some(hash_dir), Ok(_)) = (block_dir.parent(), &decode_res) {
+            lifecycle::record(hash_dir, &file_hash, LifecycleStage::Decoded, None, None).await;
+        }
         //TODO if it fails, keep requesting block info, try to check which matrix is invertible taking k-1 blocks already on disk and one more that isn't
         //TODO if it fails, do the same with k-2, etc...
         //TODO when a combination of the blocks that works is found, request the missing blocks
-        Ok([file_dir, PathBuf::from(output_filename)].iter().collect())
-        //Ok(PathBuf::from(format!("{:?}/{}", file_dir, output_filename)))
+        decode_res.map_err(|e| {
+            let err_msg = format!(
+                "Could not reconstruct the file from the downloaded blocks {:?}: {}",
+                block_hashes_on_disk, e
+            );
+            error!(err_msg);
+            e.context(err_msg)
+        })
     }
 
     async fn dial(&mut self, multiaddr: String) -> Result<()> {
@@ -1289,15 +3822,112 @@ where {
             match self.swarm.dial(addr) {
                 Ok(()) => Ok(()),
                 Err(de) => {
-                    let err_msg = format!("Could not dial {0}: {1}", multiaddr, de);
-                    error!(err_msg);
-                    Err(DialError(err_msg).into())
+                    error!("Could not dial {0}: {1}", multiaddr, de);
+                    Err(DialError {
+                        kind: DialErrorKind::classify(&de),
+                        multiaddr,
+                        context: de.to_string(),
+                    }
+                    .into())
                 }
             }
         } else {
-            let err_msg = format!("Could not parse {}", multiaddr);
-            error!(err_msg);
-            Err(BadListener(err_msg).into())
+            error!("Could not parse {}", multiaddr);
+            Err(DialError {
+                multiaddr,
+                kind: DialErrorKind::InvalidMultiaddr,
+                context: String::from("could not parse the multiaddr"),
+            }
+            .into())
+        }
+    }
+
+    /// Send a DialSingle for the multiaddr, the receiver gets the peer id of the dialed node
+    fn request_dial(
+        cmd_sender: &mpsc::UnboundedSender<DragoonCommand>,
+        multiaddr: &str,
+    ) -> Option<oneshot::Receiver<Result<PeerId>>> {
+        let (dial_sender, dial_recv) = oneshot::channel();
+        match cmd_sender.send(DragoonCommand::DialSingle {
+            multiaddr: multiaddr.to_string(),
+            sender: Sender::SenderOneS(dial_sender),
+        }) {
+            Ok(()) => Some(dial_recv),
+            Err(_) => {
+                error!(
+                    "Could not send the dial command for the multiaddr {}",
+                    multiaddr
+                );
+                None
+            }
+        }
+    }
+
+    /// Dial the multiaddr, resolving its name first if it has one
+    async fn dial_outcome(
+        cmd_sender: &mpsc::UnboundedSender<DragoonCommand>,
+        dns_resolver: &DnsResolver,
+        multiaddr: String,
+    ) -> DialOutcome {
+        match multiaddr.parse::<Multiaddr>() {
+            Ok(addr) if dns::needs_resolution(&addr) => {
+                let (resolved, res) =
+                    Self::dial_resolved(cmd_sender, dns_resolver, multiaddr.clone()).await;
+                DialOutcome {
+                    resolved,
+                    ..DialOutcome::new(multiaddr, res)
+                }
+            }
+            _ => {
+                let dial_recv = Self::request_dial(cmd_sender, &multiaddr);
+                DialOutcome::new(multiaddr, Self::wait_dial(dial_recv).await)
+            }
+        }
+    }
+
+    /// Resolve the name of the multiaddr, then dial the resolved addresses one after the other until one succeeds.
+    /// Returns the resolved addresses along with the outcome of the dial.
+    async fn dial_resolved(
+        cmd_sender: &mpsc::UnboundedSender<DragoonCommand>,
+        dns_resolver: &DnsResolver,
+        multiaddr: String,
+    ) -> (Vec<String>, Result<PeerId>) {
+        let resolved = match multiaddr.parse::<Multiaddr>() {
+            Ok(addr) => dns_resolver.resolve(&addr).await,
+            Err(e) => Err(e.into()),
+        };
+        let resolved: Vec<String> = match resolved {
+            Ok(resolved) => resolved.iter().map(|addr| addr.to_string()).collect(),
+            Err(e) => {
+                error!("Could not resolve {}: {}", multiaddr, e);
+                let err = DialError {
+                    multiaddr,
+                    kind: DialErrorKind::DnsResolution,
+                    context: e.to_string(),
+                };
+                return (vec![], Err(err.into()));
+            }
+        };
+        let mut res = Err(format_err!("{} resolved to no address", multiaddr));
+        for address in &resolved {
+            res = Self::wait_dial(Self::request_dial(cmd_sender, address)).await;
+            match &res {
+                Ok(_) => break,
+                Err(e) => debug!(
+                    "Dial of {}, resolved from {}, failed: {}",
+                    address, multiaddr, e
+                ),
+            }
+        }
+        (resolved, res)
+    }
+
+    async fn wait_dial(dial_recv: Option<oneshot::Receiver<Result<PeerId>>>) -> Result<PeerId> {
+        match dial_recv {
+            Some(dial_recv) => dial_recv
+                .await
+                .map_err(|e| format_err!("The dial was canceled: {}", e))?,
+            None => Err(format_err!("Could not send the dial command")),
         }
     }
 
@@ -1318,6 +3948,233 @@ where {
         }
     }
 
+    /// Get the segments appended to a file, in order, each with a get-file of its own, and add them after its bytes
+    async fn append_segments(
+        cmd_sender: &UnboundedSender<DragoonCommand>,
+        mut bytes: Vec<u8>,
+        segments: &[String],
+        order: RetrievalOrder,
+        decrypt: bool,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        for segment in segments {
+            info!("Getting the segment {}", segment);
+            let (sender, receiver) = oneshot::channel();
+            cmd_sender
+                .send(DragoonCommand::GetFile {
+                    file_hash: segment.clone(),
+                    // named after its hash, so an existing one is the same segment
+                    output_filename: segment.clone(),
+                    output: GetFileOutput::File,
+                    order,
+                    overwrite: true,
+                    auto_concurrency: false,
+                    parallelism: None,
+                    decrypt,
+                    unpack: false,
+                    // the segments of a segment are not retrieved
+                    segments_root: None,
+                    timeout: Some(timeout.as_secs()),
+                    operation_id: None,
+                    sender: Sender::SenderOneS(sender),
+                })
+                .map_err(|_| {
+                    format_err!("Could not send the command to get the segment {}", segment)
+                })?;
+            match receiver.await?? {
+                GetFileOutcome::Written(segment_path) => {
+                    bytes.extend(tokio::fs::read(&segment_path).await?)
+                }
+                outcome => {
+                    return Err(format_err!(
+                        "The get file of the segment {} did not write it: {:?}",
+                        segment,
+                        outcome
+                    ))
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Encode `file_path` as a new segment of the file, with the same encoding, and add it to the manifest of the file.
+    /// The segment is provided if the file is.
+    async fn append_to_file<F, G, P>(
+        &mut self,
+        file_hash: String,
+        file_path: String,
+        sender: Sender<AppendedSegment>,
+    ) where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+        P: DenseUVPolynomial<F>,
+        for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+    {
+        let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
+        let Some(manifest) = manifest::read(&hash_dir).await else {
+            let err = format_err!(
+                "There is no manifest for the file {}, only the node that encoded a file can append to it",
+                file_hash
+            );
+            sender_send_match(sender, Err(err), String::from("AppendToFile"));
+            return;
+        };
+        // the data after the end of an archive would be lost when it is unpacked
+        if manifest.archive {
+            let err = format_err!(
+                "The file {} is the archive of a directory, nothing can be appended to it",
+                file_hash
+            );
+            sender_send_match(sender, Err(err), String::from("AppendToFile"));
+            return;
+        }
+        let progress = self.progress.start(None, "append-to-file");
+        let task = tokio::spawn(Self::encode_file::<F, G, P>(
+            self.file_dir.clone(),
+            file_path,
+            false,
+            false,
+            manifest.encoding_method,
+            manifest.k,
+            manifest.n,
+            EvaluationDomain::default(),
+            None,
+            manifest.compression,
+            manifest.packing,
+            manifest.encrypted.then(|| self.file_key_path.clone()),
+            self.powers.clone(),
+            self.metrics.clone(),
+            self.file_locks.clone(),
+            self.block_quota.clone(),
+            self.block_store.clone(),
+            progress.clone(),
+        ));
+        self.jobs
+            .attach(progress.operation_id(), task.abort_handle());
+        // the segment is encoded outside of the loop, the manifest of the file is only updated by the loop
+        let cmd_sender = self.command_sender.clone();
+        tokio::spawn(async move {
+            let res = task
+                .await
+                .unwrap_or_else(|e| Err(format_err!("The encoding was canceled: {}", e)));
+            progress.finish(&res);
+            match res {
+                Ok(segment) => {
+                    if let Err(e) = cmd_sender.send(DragoonCommand::FinishAppendToFile {
+                        file_hash,
+                        segment,
+                        sender,
+                    }) {
+                        error!("Could not send the command to finish the append: {}", e);
+                    }
+                }
+                Err(e) => sender_send_match(sender, Err(e), String::from("AppendToFile")),
+            }
+        });
+    }
+
+    /// Add the segment to the manifest of the file, read again so the appends running at the same time are all kept,
+    /// then publish the metadata of both and provide the segment if the file is provided
+    async fn finish_append_to_file(
+        &mut self,
+        file_hash: String,
+        segment: EncodedFile,
+    ) -> Result<AppendedSegment> {
+        let hash_dir = get_file_dir(&self.file_dir, file_hash.clone());
+        let mut manifest = manifest::read(&hash_dir)
+            .await
+            .ok_or_else(|| format_err!("The manifest of the file {} is gone", file_hash))?;
+        let segment_dir = get_file_dir(&self.file_dir, segment.file_hash.clone());
+        let segment_manifest = manifest::read(&segment_dir).await;
+        // the segments of a segment are not retrieved, and a file can't be a segment of itself
+        if segment.file_hash == file_hash
+            || segment_manifest
+                .as_ref()
+                .is_some_and(|segment_manifest| !segment_manifest.segments.is_empty())
+        {
+            return Err(format_err!(
+                "The file {} has segments of its own, it can't be appended to {}",
+                segment.file_hash,
+                file_hash
+            ));
+        }
+        info!(
+            "Appended the segment {} to the file {}",
+            segment.file_hash, file_hash
+        );
+        manifest.segments.push(segment.file_hash.clone());
+        manifest::write(&hash_dir, &manifest).await?;
+        if let Some(segment_manifest) = segment_manifest {
+            self.put_file_metadata(segment_manifest.metadata());
+        }
+        self.put_file_metadata(manifest.metadata());
+        if self.provided_keys.contains(&file_hash) {
+            match self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .start_providing(segment.file_hash.clone().into_bytes().into())
+            {
+                Ok(query_id) => {
+                    self.provided_keys
+                        .insert(&self.file_dir, segment.file_hash.clone())
+                        .await;
+                    // no one waits for it, like for a refresh
+                    self.pending_refresh_provide
+                        .insert(query_id, segment.file_hash.clone());
+                }
+                Err(e) => warn!(
+                    "Could not provide the segment {}: {:?}",
+                    segment.file_hash, e
+                ),
+            }
+        }
+        Ok(AppendedSegment {
+            segments_root: manifest::segments_root(&file_hash, &manifest.segments),
+            segment,
+        })
+    }
+
+    /// The manifest of the file, `None` if the file was not encoded by this node
+    async fn get_manifest(
+        cmd_sender: &UnboundedSender<DragoonCommand>,
+        file_hash: &str,
+    ) -> Option<FileManifest> {
+        let (sender, receiver) = oneshot::channel();
+        cmd_sender
+            .send(DragoonCommand::Manifest {
+                file_hash: file_hash.to_string(),
+                sender: Sender::SenderOneS(sender),
+            })
+            .ok()?;
+        receiver.await.ok()?.ok()
+    }
+
+    /// The metadata of the file in the DHT, `None` if there is no valid one or it could not be read in time
+    async fn get_file_metadata(
+        cmd_sender: &UnboundedSender<DragoonCommand>,
+        file_hash: &str,
+    ) -> Option<FileMetadata> {
+        let (sender, receiver) = oneshot::channel();
+        cmd_sender
+            .send(DragoonCommand::GetFileMetadata {
+                file_hash: file_hash.to_string(),
+                sender: Sender::SenderOneS(sender),
+            })
+            .ok()?;
+        match time::timeout(FILE_METADATA_TIMEOUT, receiver).await {
+            Ok(Ok(Ok(metadata))) => metadata,
+            Ok(_) => None,
+            Err(_) => {
+                warn!(
+                    "The metadata of the file {} could not be read in time",
+                    file_hash
+                );
+                None
+            }
+        }
+    }
+
     /// This returns the Stream instead of sending it back through the Sender so it can be handled later
     fn get_providers(&mut self, key: String) -> BoxStream<'static, PeerId> {
         let query_id = self
@@ -1326,6 +4183,7 @@ where {
             .kademlia
             .get_providers(key.into_bytes().into());
         let (m_sender, mut m_receiver) = mpsc::unbounded_channel::<Result<HashSet<PeerId>>>();
+        // a replaced entry drops its channel, which ends the stream of its providers
         self.pending_get_providers.insert(query_id, m_sender);
         let providers = async_stream::stream! {
             let mut current_providers: HashSet<PeerId> = Default::default();
@@ -1341,6 +4199,287 @@ where {
         providers.boxed()
     }
 
+    /// Announce again all the provided keys, and the files the node has blocks of if `local_files` is set.
+    /// The StartProvide are sent one at a time every [`REANNOUNCE_DELAY`], the outcome of each key is sent back once they all finished.
+    fn reannounce_all(&mut self, local_files: bool, sender: Sender<Vec<ReannounceOutcome>>) {
+        let mut keys: BTreeSet<String> = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .store_mut()
+            .provided()
+            .map(|record| String::from_utf8_lossy(record.key.as_ref()).to_string())
+            .collect();
+        if local_files {
+            for entry in sfs::read_dir(&self.file_dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let file_hash = entry.file_name().to_string_lossy().to_string();
+                let has_blocks = sfs::read_dir(get_block_dir(&self.file_dir, file_hash.clone()))
+                    .map(|mut blocks| blocks.next().is_some())
+                    .unwrap_or(false);
+                if has_blocks {
+                    keys.insert(file_hash);
+                }
+            }
+        }
+        info!("Announcing again {} keys", keys.len());
+        let cmd_sender = self.command_sender.clone();
+        tokio::spawn(async move {
+            let mut pending = vec![];
+            for key in keys {
+                let (provide_sender, provide_recv) = oneshot::channel();
+                if cmd_sender
+                    .send(DragoonCommand::StartProvide {
+                        key: key.clone(),
+                        sender: Sender::SenderOneS(provide_sender),
+                    })
+                    .is_err()
+                {
+                    error!("Could not send the command to announce {} again", key);
+                    continue;
+                }
+                pending.push((key, provide_recv));
+                time::sleep(REANNOUNCE_DELAY).await;
+            }
+            let mut outcomes = vec![];
+            for (key, provide_recv) in pending {
+                let res = match provide_recv.await {
+                    Ok(res) => res,
+                    Err(e) => Err(format_err!("The StartProvide was canceled: {}", e)),
+                };
+                if let Err(e) = &res {
+                    warn!("Could not announce {} again: {}", key, e);
+                }
+                outcomes.push(ReannounceOutcome {
+                    key,
+                    success: res.is_ok(),
+                    error: res.err().map(|e| e.to_string()),
+                });
+            }
+            sender_send_match(sender, Ok(outcomes), String::from("ReannounceAll"));
+        });
+    }
+
+    /// The peers of the kademlia routing table, with their addresses
+    fn routing_table(&mut self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let mut routing_table: Vec<(PeerId, Vec<Multiaddr>)> = vec![];
+        for bucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                routing_table.push((
+                    *entry.node.key.preimage(),
+                    entry.node.value.iter().cloned().collect(),
+                ));
+            }
+        }
+        routing_table
+    }
+
+    /// Save the state of the node and close its connections, the loop of the network stops afterwards.
+    /// The block exchanges in flight are waited for before, see [`crate::shutdown`].
+    async fn shutdown(&mut self) {
+        info!("Stopping the Dragoon Network");
+        if let Err(e) = self.send_accounting.lock().await.close().await {
+            warn!(
+                "Could not flush the accounting of the received blocks: {}",
+                e
+            );
+        }
+        if let Err(e) = self.save_dht_snapshot().await {
+            warn!("Could not save the DHT snapshot: {}", e);
+        }
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer_id in peers {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+        let closed = time::timeout(CLOSE_CONNECTIONS_TIMEOUT, async {
+            while self.swarm.network_info().num_peers() > 0 {
+                self.swarm.next().await;
+            }
+        })
+        .await;
+        if closed.is_err() {
+            warn!(
+                "{} connections were still open after {:?}",
+                self.swarm.network_info().num_peers(),
+                CLOSE_CONNECTIONS_TIMEOUT
+            );
+        }
+        self.stopped = true;
+    }
+
+    /// Write the records and the routing table of the DHT to disk, to be restored on the next start
+    async fn save_dht_snapshot(&mut self) -> Result<()> {
+        let routing_table = self.routing_table();
+        let snapshot = DhtSnapshot::take(
+            self.swarm.behaviour_mut().kademlia.store_mut(),
+            routing_table,
+        );
+        snapshot
+            .save(&dht_store::snapshot_path(&self.file_dir))
+            .await?;
+        debug!("Saved the DHT snapshot");
+        Ok(())
+    }
+
+    /// Announce again all the provided keys, one every [`REANNOUNCE_DELAY`], so their provider records do not expire
+    fn refresh_provided_keys(&mut self) {
+        if self
+            .provide_refresh_task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            debug!("The previous refresh of the provided keys is not done yet");
+            return;
+        }
+        let keys = self.provided_keys.keys();
+        if keys.is_empty() {
+            return;
+        }
+        info!("Refreshing the provider records of {} keys", keys.len());
+        let cmd_sender = self.command_sender.clone();
+        self.provide_refresh_task = Some(tokio::spawn(async move {
+            for key in keys {
+                if cmd_sender
+                    .send(DragoonCommand::RefreshProvide { key })
+                    .is_err()
+                {
+                    error!("Could not send the command to refresh a provided key");
+                    return;
+                }
+                time::sleep(REANNOUNCE_DELAY).await;
+            }
+        }));
+    }
+
+    /// Spawn the check of the redundancy of the files encoded by the node, see [`block_repair`]
+    fn repair_files<F, G>(&mut self)
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        if self
+            .repair_task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            debug!("The previous repair of the files is not done yet");
+            return;
+        }
+        let cmd_sender = self.command_sender.clone();
+        let local_peer_id = *self.swarm.local_peer_id();
+        let repair = block_repair::repair_files::<F, G>(
+            cmd_sender.clone(),
+            self.file_dir.clone(),
+            local_peer_id,
+            self.provided_keys.keys(),
+            self.repair_config.threshold,
+            self.file_locks.clone(),
+            self.block_store.clone(),
+            self.metrics.clone(),
+        );
+        let factors = self.replication_factors.factors();
+        self.repair_task = Some(tokio::spawn(async move {
+            repair.await;
+            replication::replicate_files(cmd_sender, local_peer_id, factors).await;
+        }));
+    }
+
+    /// Spawn a check of the integrity of all the stored blocks
+    fn scrub_blocks<F, G>(&mut self)
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        if self
+            .scrub_task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            debug!("The previous scrub of the blocks is not done yet");
+            return;
+        }
+        let scrub = self.scrubber.clone().scrub::<F, G>(
+            self.file_dir.clone(),
+            self.powers.clone(),
+            self.file_locks.clone(),
+            self.block_quota.clone(),
+            self.block_store.clone(),
+            self.metrics.clone(),
+        );
+        self.scrub_task = Some(tokio::spawn(async move {
+            if let Err(e) = scrub.await {
+                error!("Could not scrub the blocks: {}", e);
+            }
+        }));
+    }
+
+    /// Stop providing the key, cancel the StartProvide queries and the refreshes still running for it, and forget it
+    /// so it is not refreshed anymore.
+    /// Returns whether the key was provided, and the keys that are still provided by the node.
+    async fn stop_provide(&mut self, key: String) -> Result<StopProvideOutcome> {
+        let tracked = self.provided_keys.remove(&self.file_dir, &key).await;
+        let record_key: kad::RecordKey = key.clone().into_bytes().into();
+        let in_store = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .store_mut()
+            .provided()
+            .any(|record| record.key == record_key);
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .stop_providing(&record_key);
+
+        // a refresh still running would announce the key again to the peers it has not reached yet
+        let canceled_refreshes = self
+            .pending_refresh_provide
+            .complete_where(|_, refreshed_key| *refreshed_key == key);
+        for (query_id, _) in &canceled_refreshes {
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(query_id) {
+                query.finish();
+            }
+        }
+
+        let canceled_queries = self
+            .pending_start_providing
+            .complete_where(|_, (provided_key, _)| *provided_key == key);
+        let canceled = canceled_queries.len() + canceled_refreshes.len();
+        for (query_id, (_, sender)) in canceled_queries {
+            if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&query_id) {
+                query.finish();
+            }
+            let err = ProviderError(format!(
+                "Stopped providing {} before the query {} could finish",
+                key, query_id
+            ));
+            sender_send_match(sender, Err(format_err!(err)), String::from("StartProvide"));
+        }
+
+        let still_provided: Vec<String> = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .store_mut()
+            .provided()
+            .map(|record| String::from_utf8_lossy(record.key.as_ref()).to_string())
+            .collect();
+        if still_provided.contains(&key) {
+            let err_msg = format!("The provider record for {} is still in the store", key);
+            error!(err_msg);
+            return Err(ProviderError(err_msg).into());
+        }
+        Ok(StopProvideOutcome {
+            key,
+            was_provided: tracked || in_store,
+            canceled_queries: canceled,
+            still_provided,
+        })
+    }
+
     async fn bootstrap(&mut self) -> Result<()> {
         match self.swarm.behaviour_mut().kademlia.bootstrap() {
             Ok(_) => Ok(()),
@@ -1349,20 +4488,125 @@ where {
                 Err(BootstrapError(nkp.to_string()).into())
             }
         }
-    }
-
-    fn get_blocks_info_from(
-        &mut self,
-        peer_id: PeerId,
-        file_hash: String,
-        sender: Sender<PeerBlockInfo>,
-    ) {
-        let request_id = self
-            .swarm
-            .behaviour_mut()
-            .request_info
-            .send_request(&peer_id, PeerBlockInfoRequest { file_hash });
-        self.pending_request_block_info.insert(request_id, sender);
+    }
+
+    fn get_blocks_info_from(
+        &mut self,
+        peer_id: PeerId,
+        file_hash: String,
+        block_hash: Option<String>,
+        sender: Sender<PeerBlockInfo>,
+    ) {
+        let request_id = self.swarm.behaviour_mut().request_info.send_request(
+            &peer_id,
+            PeerBlockInfoRequest {
+                file_hash,
+                block_hash,
+            },
+        );
+        if let Some(sender) = self.pending_request_block_info.insert(request_id, sender) {
+            sender_send_match(
+                sender,
+                Err(format_err!("The request {} was replaced", request_id)),
+                format!("info response {}", request_id),
+            );
+        }
+    }
+
+    fn get_available_storage_from(&mut self, peer_id: PeerId, sender: Sender<usize>) {
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .request_storage
+            .send_request(&peer_id, AvailableStorageRequest);
+        if let Some(sender) = self.pending_request_storage.insert(request_id, sender) {
+            sender_send_match(
+                sender,
+                Err(format_err!("The request {} was replaced", request_id)),
+                format!("storage response {}", request_id),
+            );
+        }
+    }
+
+    /// Check the weights given to a SendBlockList, which only go with a strategy taking them and must all be for known peers
+    fn weighted_peers(
+        &self,
+        strategy_name: &StrategyName,
+        takes_weights: bool,
+        weights: HashMap<String, u64>,
+    ) -> Result<HashMap<PeerId, u64>, DragoonError> {
+        if !takes_weights {
+            if !weights.is_empty() {
+                return Err(BadStrategyWeights(format!(
+                    "the {} strategy does not take weights",
+                    strategy_name
+                )));
+            }
+            return Ok(HashMap::new());
+        }
+        let mut weighted_peers = HashMap::new();
+        for (peer_id_base_58, weight) in weights {
+            let peer_id = bs58::decode(&peer_id_base_58)
+                .into_vec()
+                .ok()
+                .and_then(|bytes| PeerId::from_bytes(&bytes).ok())
+                .ok_or_else(|| {
+                    BadStrategyWeights(format!("{} is not a peer id", peer_id_base_58))
+                })?;
+            if !self.known_peers.contains(&peer_id) {
+                return Err(BadStrategyWeights(format!(
+                    "{} is not a known peer",
+                    peer_id
+                )));
+            }
+            if self.banned_peers.contains(&peer_id) {
+                return Err(BadStrategyWeights(format!("{} is banned", peer_id)));
+            }
+            weighted_peers.insert(peer_id, weight);
+        }
+        if weighted_peers.values().all(|weight| *weight == 0) {
+            return Err(BadStrategyWeights(String::from(
+                "at least one peer needs a positive weight",
+            )));
+        }
+        Ok(weighted_peers)
+    }
+
+    /// Ask the peers how much storage they have left for the blocks sent by other peers.
+    /// The peers that do not answer are left out.
+    async fn available_storage_of(
+        cmd_sender: mpsc::UnboundedSender<DragoonCommand>,
+        peers: Vec<PeerId>,
+    ) -> HashMap<PeerId, usize> {
+        let mut requests = FuturesUnordered::new();
+        for peer_id in peers {
+            let (sender, receiver) = oneshot::channel();
+            if cmd_sender
+                .send(DragoonCommand::GetAvailableStorageFrom {
+                    peer_id,
+                    sender: Sender::SenderOneS(sender),
+                })
+                .is_err()
+            {
+                error!(
+                    "Could not send the command to get the available storage of {}",
+                    peer_id
+                );
+                continue;
+            }
+            requests.push(async move { (peer_id, receiver.await) });
+        }
+        let mut available_storage = HashMap::new();
+        while let Some((peer_id, res)) = requests.next().await {
+            match res {
+                Ok(Ok(storage)) => {
+                    available_storage.insert(peer_id, storage);
+                }
+                Ok(Err(e)) => warn!("Leaving {} out of the distribution: {}", peer_id, e),
+                Err(e) => warn!("Leaving {} out of the distribution: {}", peer_id, e),
+            }
+        }
+        available_storage
     }
 
     async fn get_block_list(file_dir: PathBuf, file_hash: String) -> Result<Vec<String>> {
@@ -1383,26 +4627,244 @@ where {
         Ok(block_names)
     }
 
+    /// Combine verified blocks of a file into `count` new blocks, written next to them.
+    /// All the blocks of the file are combined when no block is given.
+    ///
+    /// Returns the hashes of the new blocks.
+    async fn recode_blocks<F, G, P>(
+        file_dir: PathBuf,
+        file_hash: String,
+        block_hashes: Vec<String>,
+        count: usize,
+        powers: Arc<PowersCache>,
+        file_locks: Arc<FileLocks>,
+        block_quota: Arc<BlockQuota>,
+        block_store: Arc<BlockStore>,
+    ) -> Result<Vec<String>>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+        P: DenseUVPolynomial<F>,
+        for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+    {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let block_dir = get_block_dir(&file_dir, file_hash.clone());
+        // the new blocks must not be written while the blocks are read to be sent to other peers
+        let _write_guard = file_locks.write(&file_hash).await;
+        if !tokio::fs::try_exists(&block_dir).await? {
+            return Err(format_err!(
+                "There is no block of {} on this node",
+                file_hash
+            ));
+        }
+        let block_hashes = if block_hashes.is_empty() {
+            Self::get_block_list(file_dir, file_hash.clone()).await?
+        } else {
+            block_hashes
+        };
+        let blocks = block_store.read_blocks::<F, G>(&block_hashes, &block_dir, Validate::Yes)?;
+        let powers = powers.get::<F, G>().await?;
+        for (block_hash, block) in &blocks {
+            if !verify::<F, G, P>(block, &powers)? {
+                return Err(format_err!(
+                    "The block {} of {} is invalid, it can't be recoded",
+                    block_hash,
+                    file_hash
+                ));
+            }
+        }
+        let blocks: Vec<Block<F, G>> = blocks.into_iter().map(|(_, block)| block).collect();
+        block_quota.check_encode(&file_hash, count, false)?;
+
+        info!(
+            "Recoding {} blocks of {} into {} new blocks",
+            blocks.len(),
+            file_hash,
+            count
+        );
+        let new_blocks = {
+            let mut rng = rand::thread_rng();
+            (0..count)
+                .map(|_| {
+                    komodo::semi_avid::recode(&blocks, &mut rng)?
+                        .ok_or(format_err!("There is no block of {} to recode", file_hash))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        let mut new_block_hashes = vec![];
+        for block in &new_blocks {
+            let path = block_store.dump(block, &block_dir)?;
+            if let Some(block_hash) = path.file_name() {
+                new_block_hashes.push(block_hash.to_string_lossy().to_string());
+            }
+        }
+        block_quota.rescan_file(&file_hash, &block_dir);
+        Ok(new_block_hashes)
+    }
+
+    /// The blocks of a file already in `block_dir` with a valid proof, the invalid ones are removed so they are downloaded again
+    async fn verified_blocks_on_disk<F, G, P>(
+        block_store: &BlockStore,
+        block_dir: &PathBuf,
+        powers: &PowersCache,
+    ) -> Result<Vec<(String, Block<F, G>)>>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+        P: DenseUVPolynomial<F>,
+        for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+    {
+        let mut block_hashes = vec![];
+        let mut entries = tokio::fs::read_dir(block_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            block_hashes.push(entry.file_name().to_string_lossy().to_string());
+        }
+        if block_hashes.is_empty() {
+            return Ok(vec![]);
+        }
+        block_hashes.sort();
+        let powers = powers.get::<F, G>().await?;
+        let mut blocks = vec![];
+        for block_hash in block_hashes {
+            let valid = match block_store.read_blocks::<F, G>(
+                std::slice::from_ref(&block_hash),
+                block_dir,
+                Validate::Yes,
+            ) {
+                Ok(mut read) => match read.pop() {
+                    Some((_, block)) if verify::<F, G, P>(&block, &powers)? => Some(block),
+                    _ => None,
+                },
+                Err(e) => {
+                    warn!("Could not read the block {} on disk: {}", block_hash, e);
+                    None
+                }
+            };
+            match valid {
+                Some(block) => blocks.push((block_hash, block)),
+                None => {
+                    warn!(
+                        "Removing the invalid block {} from {:?}",
+                        block_hash, block_dir
+                    );
+                    tokio::fs::remove_file(block_dir.join(&block_hash)).await?;
+                }
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Decode the blocks in memory, without writing the file, and unpack its bytes from the field elements with `packing`
+    fn decode_bytes<F, G>(
+        block_store: &BlockStore,
+        block_dir: &PathBuf,
+        block_hashes: &[String],
+        packing: Packing,
+    ) -> Result<Vec<u8>>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        let blocks = block_store.read_blocks::<F, G>(block_hashes, block_dir, Validate::Yes)?;
+        let shards: Vec<Shard<F>> = blocks.into_iter().map(|b| b.1.shard).collect();
+        let k = shards.first().map(|shard| shard.k as usize).unwrap_or(0);
+        let linear_combinations: Vec<Vec<F>> = shards
+            .iter()
+            .map(|shard| shard.linear_combination.clone())
+            .collect();
+        if let Some(vec_bytes) = systematic::decode(packing, &shards) {
+            debug!(
+                "The systematic blocks in {:?} are read without decoding",
+                block_dir
+            );
+            return Ok(vec_bytes);
+        }
+        let vec_bytes = match packing::decode::<F>(packing, shards) {
+            Ok(vec_bytes) => vec_bytes,
+            Err(e) => {
+                let diagnostics = DecodeDiagnostics::new(k, block_hashes, linear_combinations);
+                error!(
+                    "Could not decode the blocks in {:?}: {}",
+                    block_dir, diagnostics
+                );
+                return Err(DecodeError {
+                    diagnostics,
+                    context: e.to_string(),
+                }
+                .into());
+            }
+        };
+        Ok(vec_bytes)
+    }
+
+    /// Decode the blocks into `output_filename`, next to the block directory.
+    /// With the manifest of the file, fewer blocks than the file needs are refused before reading any of them, and a
+    /// file encoded with `encrypt` is decrypted with the file key in `file_key_path` before it is decompressed.
+    /// The file is written to a temporary file first, so it is never seen half written, then moved in place,
+    /// unless it already exists and `overwrite` is not set.
     async fn decode_blocks<F, G>(
+        block_store: &BlockStore,
         block_dir: PathBuf,
         block_hashes: &[String],
         output_filename: String,
+        overwrite: bool,
+        manifest: Option<&FileManifest>,
+        file_key_path: &Path,
     ) -> Result<()>
     where
         F: PrimeField,
         G: CurveGroup<ScalarField = F>,
     {
-        let blocks =
-            fs::read_blocks::<F, G>(block_hashes, &block_dir, Compress::Yes, Validate::Yes)?;
-        let shards: Vec<Shard<F>> = blocks.into_iter().map(|b| b.1.shard).collect();
-        let vec_bytes = fec::decode::<F>(shards)?;
-        if let Some(parent_dir_path) = Path::new(&block_dir).parent() {
+        if let Some(manifest) = manifest {
+            if block_hashes.len() < manifest.k {
+                return Err(format_err!(
+                    "The file {} needs {} blocks to be decoded, {} were given",
+                    manifest.file_hash,
+                    manifest.k,
+                    block_hashes.len()
+                ));
+            }
+        }
+        let mut vec_bytes = Self::decode_bytes::<F, G>(
+            block_store,
+            &block_dir,
+            block_hashes,
+            manifest.map_or(Packing::default(), |manifest| manifest.packing),
+        )?;
+        if manifest.is_some_and(|manifest| manifest.encrypted) {
+            vec_bytes = file_encryption::decrypt(file_key_path, &vec_bytes)?;
+        }
+        let vec_bytes = compression::decompress(
+            manifest.map_or(Compression::None, |manifest| manifest.compression),
+            vec_bytes,
+        )?;
+        Self::write_decoded_file(&block_dir, &vec_bytes, &output_filename, overwrite).await
+    }
+
+    /// Write the decoded bytes into `output_filename`, next to the block directory, see [`Self::decode_blocks`]
+    async fn write_decoded_file(
+        block_dir: &Path,
+        vec_bytes: &[u8],
+        output_filename: &str,
+        overwrite: bool,
+    ) -> Result<()> {
+        if let Some(parent_dir_path) = block_dir.parent() {
             let file_path: PathBuf = [parent_dir_path, Path::new(&output_filename)]
                 .iter()
                 .collect();
+            let tmp_path: PathBuf = [
+                parent_dir_path,
+                Path::new(&format!(".{}.tmp", output_filename)),
+            ]
+            .iter()
+            .collect();
             info!("Trying to create a file at {:?}", file_path);
-            let mut file = tokio::fs::File::create(file_path).await?;
-            file.write_all(vec_bytes.as_slice()).await?;
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            file.write_all(vec_bytes).await?;
+            file.sync_all().await?;
+            move_into_place(&tmp_path, &file_path, overwrite).await?;
         } else {
             error!("Parent of the block directory does not exist");
             let err = NoParentDirectory(format!("{:?}", block_dir));
@@ -1411,62 +4873,374 @@ where {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn encode_file<F, G, P>(
         output_file_dir: PathBuf,
         file_path: String,
+        archive: bool,
         replace_blocks: bool,
         encoding_method: EncodingMethod,
         encode_mat_k: usize,
         encode_mat_n: usize,
-        powers_path: PathBuf,
-    ) -> Result<(String, String)>
+        domain: EvaluationDomain,
+        domain_seed: Option<String>,
+        compression: Compression,
+        packing: Packing,
+        file_key_path: Option<PathBuf>,
+        powers: Arc<PowersCache>,
+        metrics: Arc<Metrics>,
+        file_locks: Arc<FileLocks>,
+        block_quota: Arc<BlockQuota>,
+        block_store: Arc<BlockStore>,
+        progress: ProgressHandle,
+    ) -> Result<EncodedFile>
     where
         F: PrimeField,
         G: CurveGroup<ScalarField = F>,
         P: DenseUVPolynomial<F>,
         for<'a, 'b> &'a P: Div<&'b P, Output = P>,
     {
-        info!("Reading file to convert from {:?}", file_path);
-        let bytes = tokio::fs::read(&file_path).await?;
-        let file_hash = Sha256::hash(&bytes)
-            .iter()
-            .map(|x| format!("{:x}", x))
-            .collect::<Vec<_>>()
-            .join("");
+        progress.stage("reading");
+        let mut bytes = if archive {
+            info!("Archiving the directory to convert from {:?}", file_path);
+            archive::pack(PathBuf::from(&file_path)).await?
+        } else {
+            info!("Reading file to convert from {:?}", file_path);
+            tokio::fs::read(&file_path).await?
+        };
+        let file_size = bytes.len();
+        if compression != Compression::None {
+            let size = bytes.len();
+            bytes = compression::compress(compression, bytes)?;
+            info!(
+                "Compressed the file with {} from {} to {} bytes",
+                compression,
+                size,
+                bytes.len()
+            );
+        }
+        if let Some(file_key_path) = &file_key_path {
+            info!(
+                "Encrypting the file with the file key in {:?}",
+                file_key_path
+            );
+            bytes = file_encryption::encrypt(file_key_path, &bytes)?;
+        }
+        // the hash of the ciphertext when the file is encrypted, so the hash of the plaintext is never announced
+        let file_hash = hash_file(&bytes);
         let encoding_mat = match encoding_method {
             EncodingMethod::Vandermonde => {
-                let points: Vec<F> = (0..encode_mat_n)
-                    .map(|i| F::from_le_bytes_mod_order(&i.to_le_bytes()))
-                    .collect();
+                let points: Vec<F> =
+                    evaluation_points(domain, domain_seed, &file_hash, encode_mat_n)?;
                 Matrix::vandermonde(&points, encode_mat_k)?
             }
+            EncodingMethod::Systematic => {
+                let points: Vec<F> =
+                    evaluation_points(domain, domain_seed, &file_hash, encode_mat_n)?;
+                systematic::encoding_matrix(&points, encode_mat_k)?
+            }
             EncodingMethod::Random => {
+                if domain != EvaluationDomain::Indices || domain_seed.is_some() {
+                    return Err(format_err!(
+                        "An evaluation domain can only be chosen for a Vandermonde or systematic encoding"
+                    ));
+                }
                 // use of RNG in async: https://stackoverflow.com/a/75227719
                 let mut rng = rand::thread_rng();
                 Matrix::random(encode_mat_k, encode_mat_n, &mut rng)
             }
         };
-        let shards = fec::encode::<F>(&bytes, &encoding_mat)?;
-        let powers = get_powers(powers_path).await?;
-        let proof = komodo::semi_avid::prove::<F, G, P>(&bytes, &powers, encode_mat_k)?;
-        let blocks = komodo::semi_avid::build::<F, G, P>(&shards, &proof);
+        let powers = powers.get::<F, G>().await?;
+        powers_file::check_degree::<F, G>(&powers, encode_mat_k)?;
+        progress.stage("encoding");
+        progress.blocks_total(encode_mat_n);
+        let blocks =
+            packing::encode::<F, G, P>(packing, &bytes, &encoding_mat, &powers, encode_mat_k)?;
+        let commitment_digest = block_format::commitment_digest(
+            blocks
+                .first()
+                .ok_or(format_err!("The file was encoded into no block"))?,
+        )?;
         let block_dir = get_block_dir(&output_file_dir, file_hash.clone());
         info!(
             "Checking if the block directory already exists or not: {:?}",
             block_dir
         );
+        // the blocks must not be replaced while they are read to be sent to other peers
+        let _write_guard = file_locks.write(&file_hash).await;
+        progress.stage("writing blocks");
         let dir_exists = tokio::fs::try_exists(&block_dir).await?;
-        if dir_exists && replace_blocks {
+        block_quota.check_encode(&file_hash, blocks.len(), dir_exists && replace_blocks)?;
+        let block_hashes = if dir_exists && replace_blocks {
+            // write the new blocks next to the old ones, then swap the directories so the blocks are never missing
+            let mut new_block_dir = block_dir.clone();
+            new_block_dir.set_extension("new");
+            let mut old_block_dir = block_dir.clone();
+            old_block_dir.set_extension("old");
+            let _ = tokio::fs::remove_dir_all(&new_block_dir).await;
+            let _ = tokio::fs::remove_dir_all(&old_block_dir).await;
             info!(
-                "Replace block option has been chosen, removing the directory at {:?}",
-                block_dir
+                "Replace block option has been chosen, writing the new blocks at {:?}",
+                new_block_dir
             );
-            tokio::fs::remove_dir_all(&block_dir).await?;
+            tokio::fs::create_dir_all(&new_block_dir).await?;
+            let block_hashes = block_store.dump_blocks(&blocks, &new_block_dir)?;
+            tokio::fs::rename(&block_dir, &old_block_dir).await?;
+            tokio::fs::rename(&new_block_dir, &block_dir).await?;
+            tokio::fs::remove_dir_all(&old_block_dir).await?;
+            block_hashes
+        } else {
+            info!("Creating directory at {:?}", block_dir);
+            tokio::fs::create_dir_all(&block_dir).await?;
+            block_store.dump_blocks(&blocks, &block_dir)?
+        };
+        block_quota.rescan_file(&file_hash, &block_dir);
+        let hash_dir = get_file_dir(&output_file_dir, file_hash.clone());
+        lifecycle::start(&hash_dir).await?;
+        lifecycle::record(&hash_dir, &file_hash, LifecycleStage::Encoded, None, None).await;
+        manifest::write(
+            &hash_dir,
+            &FileManifest {
+                file_hash: file_hash.clone(),
+                file_name: Path::new(&file_path)
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().to_string()),
+                k: encode_mat_k,
+                n: encode_mat_n,
+                size: file_size,
+                encoding_method,
+                compression,
+                packing,
+                encrypted: file_key_path.is_some(),
+                archive,
+                commitment_digest: commitment_digest.clone(),
+                segments: manifest::read(&hash_dir)
+                    .await
+                    .map(|manifest| manifest.segments)
+                    .unwrap_or_default(),
+            },
+        )
+        .await?;
+        let coded_bytes = blocks
+            .iter()
+            .map(|block| {
+                let size = block.serialized_size(Compress::Yes);
+                progress.block_done(size);
+                size as u64
+            })
+            .sum();
+        let packing = PackingReport::new::<F>(packing, bytes.len(), encode_mat_k);
+        debug!(
+            "File {} packed with {} in {} field elements of {} bits each, {} bytes once serialized",
+            file_hash,
+            packing.packing,
+            packing.field_elements,
+            packing.bits_per_element,
+            packing.packed_bytes
+        );
+        metrics.record_stats(|stats| {
+            stats.record_encode(
+                &file_hash,
+                bytes.len() as u64,
+                coded_bytes,
+                packing.packed_bytes as u64,
+            )
+        });
+        Ok(EncodedFile {
+            file_hash,
+            block_hashes,
+            commitment_digest,
+        })
+    }
+
+    /// Remove the blocks of a file from the disk, and from the accounting of the blocks received through send requests
+    #[allow(clippy::too_many_arguments)]
+    async fn delete_file(
+        file_dir: PathBuf,
+        file_hash: String,
+        send_accounting: Arc<tokio::sync::Mutex<SendAccounting>>,
+        current_available_storage_for_send: Arc<AtomicUsize>,
+        storage_classes: Arc<StorageClasses>,
+        block_quota: Arc<BlockQuota>,
+        file_locks: Arc<FileLocks>,
+        metrics: Arc<Metrics>,
+    ) -> Result<DeleteFileOutcome> {
+        // wait for the blocks to be done being sent or encoded
+        let _write_guard = file_locks.write(&file_hash).await;
+        let hash_dir = get_file_dir(&file_dir, file_hash.clone());
+        if !tokio::fs::try_exists(&hash_dir).await? {
+            return Err(format_err!("There is no file {} on this node", file_hash));
         }
-        info!("Creating directory at {:?}", block_dir);
-        tokio::fs::create_dir_all(&block_dir).await?;
-        let formatted_output = fs::dump_blocks(&blocks, &block_dir, Compress::Yes)?;
-        Ok((file_hash, formatted_output))
+        let block_dir = get_block_dir(&file_dir, file_hash.clone());
+        let blocks = match tokio::fs::read_dir(&block_dir).await {
+            Ok(mut entries) => {
+                let mut blocks = 0;
+                while entries.next_entry().await?.is_some() {
+                    blocks += 1;
+                }
+                blocks
+            }
+            Err(_) => 0,
+        };
+        info!(
+            "Removing the {} blocks of {} in {:?}",
+            blocks, file_hash, hash_dir
+        );
+        tokio::fs::remove_dir_all(&hash_dir).await?;
+        storage_classes.remove_file(&file_hash).await?;
+        block_quota.rescan_file(&file_hash, &block_dir);
+        *metrics.storage_class_usage.lock().unwrap() = storage_classes.scan_usage();
+
+        let freed_send_storage = send_accounting
+            .lock()
+            .await
+            .remove_file(&file_dir, &file_hash)
+            .await?;
+        current_available_storage_for_send.fetch_add(freed_send_storage, Ordering::SeqCst);
+        Ok(DeleteFileOutcome {
+            file_hash,
+            blocks,
+            freed_send_storage,
+        })
+    }
+
+    /// Spawn the deletion of the blocks received through send requests whose TTL has passed, then announce again
+    /// the files that still have blocks and stop providing the others
+    fn collect_expired_blocks(&mut self) {
+        if self
+            .gc_task
+            .as_ref()
+            .is_some_and(|task| !task.is_finished())
+        {
+            debug!("The previous collection of the expired blocks is not done yet");
+            return;
+        }
+        let file_dir = self.file_dir.clone();
+        let cmd_sender = self.command_sender.clone();
+        let send_accounting = self.send_accounting.clone();
+        let current_available_storage_for_send = self.current_available_storage_for_send.clone();
+        let storage_classes = self.storage_classes.clone();
+        let block_quota = self.block_quota.clone();
+        let file_locks = self.file_locks.clone();
+        let metrics = self.metrics.clone();
+        self.gc_task = Some(tokio::spawn(async move {
+            let files = match Self::remove_expired_blocks(
+                file_dir,
+                send_accounting,
+                current_available_storage_for_send,
+                storage_classes,
+                block_quota,
+                file_locks,
+                metrics,
+            )
+            .await
+            {
+                Ok(files) => files,
+                Err(e) => {
+                    error!("Could not collect the expired blocks: {}", e);
+                    return;
+                }
+            };
+            for (file_hash, has_blocks) in files {
+                let res = if has_blocks {
+                    debug!(
+                        "Announcing {} again after deleting its expired blocks",
+                        file_hash
+                    );
+                    let (res_sender, res_recv) = oneshot::channel();
+                    match cmd_sender.send(DragoonCommand::StartProvide {
+                        key: file_hash.clone(),
+                        sender: Sender::SenderOneS(res_sender),
+                    }) {
+                        Ok(()) => res_recv.await.map(|res| res.map(|_| ())),
+                        Err(_) => {
+                            error!("Could not send the command to the swarm");
+                            return;
+                        }
+                    }
+                } else {
+                    info!(
+                        "No block of {} is left, it is not provided anymore",
+                        file_hash
+                    );
+                    let (res_sender, res_recv) = oneshot::channel();
+                    match cmd_sender.send(DragoonCommand::StopProvide {
+                        key: file_hash.clone(),
+                        sender: Sender::SenderOneS(res_sender),
+                    }) {
+                        Ok(()) => res_recv.await.map(|res| res.map(|_| ())),
+                        Err(_) => {
+                            error!("Could not send the command to the swarm");
+                            return;
+                        }
+                    }
+                };
+                match res {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!(
+                        "Could not update the provider record of {}: {}",
+                        file_hash, e
+                    ),
+                    Err(e) => warn!(
+                        "The update of the provider record of {} was canceled: {}",
+                        file_hash, e
+                    ),
+                }
+            }
+        }));
+    }
+
+    /// Delete the expired blocks, returns the files they belonged to and whether these still have blocks
+    #[allow(clippy::too_many_arguments)]
+    async fn remove_expired_blocks(
+        file_dir: PathBuf,
+        send_accounting: Arc<tokio::sync::Mutex<SendAccounting>>,
+        current_available_storage_for_send: Arc<AtomicUsize>,
+        storage_classes: Arc<StorageClasses>,
+        block_quota: Arc<BlockQuota>,
+        file_locks: Arc<FileLocks>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Vec<(String, bool)>> {
+        let expired = send_accounting.lock().await.expired_blocks(&file_dir)?;
+        if expired.is_empty() {
+            return Ok(vec![]);
+        }
+        info!("Deleting {} expired blocks", expired.len());
+        let mut files: BTreeSet<String> = BTreeSet::new();
+        for record in expired {
+            // wait for the block to be done being sent
+            let _write_guard = file_locks.write(&record.file_hash).await;
+            let block_dir = get_block_dir(&file_dir, record.file_hash.clone());
+            if let Err(e) = storage_classes
+                .remove_block(&block_dir, &record.block_hash)
+                .await
+            {
+                // the block may have been deleted with its file already, its record still has to go
+                warn!(
+                    "Could not delete the expired block {} of {}: {}",
+                    record.block_hash, record.file_hash, e
+                );
+            }
+            let freed = send_accounting
+                .lock()
+                .await
+                .remove_block(&file_dir, &record.file_hash, &record.block_hash)
+                .await?;
+            current_available_storage_for_send.fetch_add(freed, Ordering::SeqCst);
+            block_quota.rescan_file(&record.file_hash, &block_dir);
+            files.insert(record.file_hash);
+        }
+        *metrics.storage_class_usage.lock().unwrap() = storage_classes.scan_usage();
+
+        Ok(files
+            .into_iter()
+            .map(|file_hash| {
+                let has_blocks = sfs::read_dir(get_block_dir(&file_dir, file_hash.clone()))
+                    .map(|mut blocks| blocks.next().is_some())
+                    .unwrap_or(false);
+                (file_hash, has_blocks)
+            })
+            .collect())
     }
 
     fn send_block_to(
@@ -1474,30 +5248,92 @@ where {
         peer_id: PeerId,
         block_hash: String,
         file_hash: String,
+        storage_class: Option<StorageClass>,
+        ttl: Option<u64>,
         sender: Sender<(bool, SendId), DragoonError>,
     ) {
         let mut control = self.swarm.behaviour().send_block.new_control();
         let own_peer_id = *self.swarm.local_peer_id();
         let file_dir = self.file_dir.clone();
         let cmd_sender = self.command_sender.clone();
+        let file_locks = self.file_locks.clone();
+        let block_store = self.block_store.clone();
+        let retry_policy = self.send_retry_policy;
+        let in_flight = self.in_flight.clone();
+        let peer_reputations = self.peer_reputations.clone();
+        let recent_events = self.recent_events.clone();
+        // if we know the peer but lost the connection to it, dial it again so the next attempts can reach it
+        if self.known_peers.contains(&peer_id) && !self.swarm.is_connected(&peer_id) {
+            let _ = self.swarm.dial(
+                DialOpts::peer_id(peer_id)
+                    .condition(PeerCondition::DisconnectedAndNotDialing)
+                    .build(),
+            );
+        }
         tokio::spawn(async move {
-            let stream = match control.open_stream(peer_id, SEND_BLOCK_PROTOCOL).await {
-                Ok(stream) => stream,
-                Err(e) => {
-                    error!("{}", e);
-                    return;
+            let res = match in_flight.start() {
+                Some(_in_flight) => {
+                    send_block_to::send_block_to(
+                        &mut control,
+                        retry_policy,
+                        &file_locks,
+                        &block_store,
+                        own_peer_id,
+                        peer_id,
+                        block_hash.clone(),
+                        file_hash,
+                        file_dir,
+                        storage_class,
+                        ttl,
+                    )
+                    .await
+                }
+                None => {
+                    warn!(
+                        "Not sending the block {} to {}, the node is stopping",
+                        block_hash, peer_id
+                    );
+                    Err(SendBlockToError {
+                        send_id: SendId {
+                            peer_id,
+                            file_hash,
+                            block_hash: block_hash.clone(),
+                            attempts: 0,
+                        },
+                    })
                 }
             };
-            let res = send_block_to::send_block_to(
-                stream,
-                own_peer_id,
-                peer_id,
-                block_hash.clone(),
-                file_hash,
-                file_dir,
-            )
-            .await
-            .map_err(|send_id| SendBlockToError { send_id });
+            let behaviour = match &res {
+                Ok((true, _)) => Some(PeerBehaviour::SendAccepted),
+                Ok((false, _)) => Some(PeerBehaviour::SendRejected),
+                Err(SendBlockOpenStreamError {
+                    failure: OpenStreamFailure::PeerUnreachable,
+                    ..
+                }) => Some(PeerBehaviour::Timeout),
+                Err(SendBlockToError { send_id }) if send_id.attempts > 0 => {
+                    Some(PeerBehaviour::SendRejected)
+                }
+                // the peer is busy, or the block was not sent at all
+                Err(_) => None,
+            };
+            if let Some(behaviour) = behaviour {
+                peer_reputations.record(peer_id, behaviour);
+            }
+            match &res {
+                Ok((true, _)) => recent_events.record(
+                    RecentEventKind::BlockSent,
+                    Some(peer_id),
+                    format!("Block {}", block_hash),
+                ),
+                Ok((false, _)) => recent_events.record(
+                    RecentEventKind::Error,
+                    Some(peer_id),
+                    format!("The block {} was refused", block_hash),
+                ),
+                Err(e) => {
+                    recent_events.record(RecentEventKind::Error, Some(peer_id), e.to_string())
+                }
+            }
             let (remove_sender, remove_receiver) = oneshot::channel();
             if cmd_sender
                 .send(DragoonCommand::RemoveEntryFromSendBlockToSet {
@@ -1518,11 +5354,85 @@ where {
         });
     }
 
+    /// Request the block from the peer, the request is sent again to the next fallback provider if it fails
+    fn request_block(&mut self, peer_id: PeerId, pending: PendingBlockRequest) {
+        let request_id = self.swarm.behaviour_mut().request_block.send_request(
+            &peer_id,
+            BlockRequest {
+                file_hash: pending.file_hash.clone(),
+                block_hash: pending.block_hash.clone(),
+            },
+        );
+        if let Some(replaced) = self.pending_request_block.insert(request_id, pending) {
+            sender_send_match(
+                replaced.sender,
+                Err(format_err!("The request {} was replaced", request_id)),
+                format!("message response {}", request_id),
+            );
+        }
+    }
+
+    fn delegate_get_file(
+        &mut self,
+        peer_id: PeerId,
+        file_hash: String,
+        overwrite: bool,
+        sender: Sender<PathBuf>,
+    ) {
+        // the stream behaviour is shared by all the protocols based on raw streams
+        let mut control = self.swarm.behaviour().send_block.new_control();
+        let file_dir = get_file_dir(&self.file_dir, file_hash.clone());
+        let block_dir = get_block_dir(&self.file_dir, file_hash.clone());
+        let block_store = self.block_store.clone();
+        let cmd_sender = self.command_sender.clone();
+        tokio::spawn(async move {
+            let res = async {
+                let file_path = file_dir.join(&file_hash);
+                // fail before asking anything from the delegate, the check is done again when the file is written
+                if !overwrite && tfs::try_exists(&file_path).await? {
+                    return Err(FileExists(format!("{:?}", file_path)).into());
+                }
+                let stream = control
+                    .open_stream(peer_id, DELEGATE_GET_FILE_PROTOCOL)
+                    .await
+                    .map_err(|e| {
+                        format_err!(
+                            "Could not open a stream to {} to delegate the retrieval of {}: {}",
+                            peer_id,
+                            file_hash,
+                            e
+                        )
+                    })?;
+                tfs::create_dir_all(&file_dir).await?;
+                delegate_get_file::delegate_get_file(
+                    stream,
+                    peer_id,
+                    file_hash.clone(),
+                    file_path,
+                    overwrite,
+                    block_dir,
+                    &block_store,
+                    cmd_sender,
+                )
+                .await
+            }
+            .await;
+            if let Err(e) = &res {
+                error!("{}", e);
+            }
+            sender_send_match(sender, res, format!("DelegateGetFile {}", file_hash));
+        });
+    }
+
     async fn send_block_list(
         number_of_blocks_to_send: usize,
         send_stream: impl FusedStream<Item = SendId>,
+        timeout: Duration,
         cmd_sender: mpsc::UnboundedSender<DragoonCommand>,
+        progress: &ProgressHandle,
     ) -> Result<Vec<SendId>, DragoonError> {
+        progress.stage("sending");
+        progress.blocks_total(number_of_blocks_to_send);
         let mut final_block_distribution: Vec<SendId> = Default::default();
         let mut rejected_blocks: Vec<(String, String)> = Default::default();
         let mut accepted_peers: HashSet<PeerId> = Default::default();
@@ -1544,6 +5454,8 @@ where {
                     peer_id,
                     file_hash,
                     block_hash,
+                    storage_class: None,
+                    ttl: None,
                     sender: Sender::SenderMPSC(res_sender),
                 })
                 .is_err()
@@ -1552,6 +5464,7 @@ where {
             }
         }
 
+        #[allow(clippy::too_many_arguments)]
         async fn optimistic_loop(
             send_stream: impl FusedStream<Item = SendId>,
             cmd_sender: mpsc::UnboundedSender<DragoonCommand>,
@@ -1560,6 +5473,7 @@ where {
             rejected_peers: &mut HashSet<PeerId>,
             rejected_blocks: &mut Vec<(String, String)>,
             final_block_distribution: &mut Vec<SendId>,
+            progress: &ProgressHandle,
         ) -> Result<()> {
             let (res_sender, mut res_recv) = mpsc::unbounded_channel();
 
@@ -1572,7 +5486,7 @@ where {
                 tokio::select! {
                     biased;
                     Some(send_info) = send_stream.next() => {
-                        let SendId{peer_id, file_hash, block_hash} = send_info;
+                        let SendId{peer_id, file_hash, block_hash, ..} = send_info;
                         let res_sender = match res_sender_vec.pop() {
                             Some(res_sender) => res_sender,
                             None => {
@@ -1602,6 +5516,8 @@ where {
                                         let inserted_peer_set = accepted_peers.insert(send_id.peer_id);
                                         debug!("inserted {} in accepted set : {}", send_id.peer_id, inserted_peer_set);
                                     }
+                                    // the size of the blocks is only known by the task sending them
+                                    progress.block_done(0);
                                     final_block_distribution.push(send_id)
                                 },
                                 false => {
@@ -1613,7 +5529,7 @@ where {
                                 },
                             },
                             Err(dragoon_error) => match dragoon_error {
-                                SendBlockToError{send_id} => rejected_blocks.push((send_id.file_hash, send_id.block_hash)),
+                                SendBlockToError{send_id} | SendBlockOpenStreamError{send_id, ..} => rejected_blocks.push((send_id.file_hash, send_id.block_hash)),
                                 SendBlockToAlreadyStarted{send_id} => error!(
                                     "Unexpected multiple send to {:?} for file hash {} block hash {}",
                                     send_id.peer_id,
@@ -1634,10 +5550,8 @@ where {
             }
         }
 
-        let timeout_duration = Duration::from_secs(10);
-
         match time::timeout(
-            timeout_duration,
+            timeout,
             optimistic_loop(
                 send_stream,
                 cmd_sender.clone(),
@@ -1646,6 +5560,7 @@ where {
                 &mut rejected_peers,
                 &mut rejected_blocks,
                 &mut final_block_distribution,
+                progress,
             ),
         )
         .await
@@ -1766,10 +5681,12 @@ where {
         }
 
         info!("Now entering error handling for blocks that were not sent");
+        progress.stage("resending rejected blocks");
         'recuperation: while let Some(send_res) = res_recv.recv().await {
             match send_res {
                 Ok((boolean, send_id)) => {
                     if boolean {
+                        progress.block_done(0);
                         final_block_distribution.push(send_id.clone());
                         // remove the block from the list of rejected blocks
                         if let Some(index) =
@@ -1790,6 +5707,7 @@ where {
                             peer_id,
                             file_hash,
                             block_hash,
+                            ..
                         } = send_id;
                         match handle_rejected_block(
                             Some(peer_id),
@@ -1811,11 +5729,12 @@ where {
                     }
                 }
                 Err(dragoon_error) => match dragoon_error {
-                    SendBlockToError { send_id } => {
+                    SendBlockToError { send_id } | SendBlockOpenStreamError { send_id, .. } => {
                         let SendId {
                             peer_id,
                             file_hash,
                             block_hash,
+                            ..
                         } = send_id;
                         match handle_rejected_block(
                             Some(peer_id),
@@ -1853,6 +5772,123 @@ where {
     }
 }
 
+/// The hash identifying a file, computed from its content
+/// The evaluation points of a Vandermonde encoding.
+///
+/// The points end up in the linear combination of each block, which is what the proofs are verified and the
+/// blocks decoded with, so the domain of a file does not need to be known to use its blocks.
+fn evaluation_points<F: PrimeField>(
+    domain: EvaluationDomain,
+    domain_seed: Option<String>,
+    file_hash: &str,
+    n: usize,
+) -> Result<Vec<F>> {
+    let seed = match (domain, domain_seed) {
+        (EvaluationDomain::Indices, None) => {
+            return Ok((0..n)
+                .map(|i| F::from_le_bytes_mod_order(&(i as u64).to_le_bytes()))
+                .collect())
+        }
+        (EvaluationDomain::FileHash, None) => file_hash.to_string(),
+        (EvaluationDomain::Seed, Some(seed)) => seed,
+        (EvaluationDomain::Seed, None) => {
+            return Err(format_err!("The seed domain needs a domain_seed"))
+        }
+        (domain, Some(_)) => {
+            return Err(format_err!(
+                "A domain_seed can only be given with the seed domain, not {:?}",
+                domain
+            ))
+        }
+    };
+    let points: Vec<F> = (0..n as u64)
+        .map(|i| {
+            let mut bytes = seed.as_bytes().to_vec();
+            bytes.extend_from_slice(&i.to_le_bytes());
+            F::from_le_bytes_mod_order(&Sha256::hash(&bytes))
+        })
+        .collect();
+    if points.iter().collect::<HashSet<_>>().len() != n {
+        return Err(format_err!(
+            "The evaluation points derived from the seed {} are not distinct",
+            seed
+        ));
+    }
+    Ok(points)
+}
+
+/// Move a file written at `tmp_path` to `file_path`, an existing file is only replaced with `overwrite`. The temporary
+/// file is gone whatever happens
+pub(crate) async fn move_into_place(
+    tmp_path: &Path,
+    file_path: &Path,
+    overwrite: bool,
+) -> Result<()> {
+    let res = if overwrite {
+        tokio::fs::rename(tmp_path, file_path).await
+    } else {
+        // unlike a rename, a hard link fails if the file already exists
+        match tokio::fs::hard_link(tmp_path, file_path).await {
+            Ok(_) => tokio::fs::remove_file(tmp_path).await,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let _ = tokio::fs::remove_file(tmp_path).await;
+                return Err(FileExists(format!("{:?}", file_path)).into());
+            }
+            Err(e) => Err(e),
+        }
+    };
+    if let Err(e) = res {
+        let _ = tokio::fs::remove_file(tmp_path).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+pub(crate) fn hash_file(bytes: &[u8]) -> String {
+    Sha256::hash(bytes)
+        .iter()
+        .map(|x| format!("{:x}", x))
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+/// The files to encode for an encode-files, a directory standing for the regular files directly in it, by name
+async fn expand_file_paths(file_paths: Vec<String>) -> Vec<String> {
+    let mut expanded = vec![];
+    for file_path in file_paths {
+        let mut entries = match tfs::read_dir(&file_path).await {
+            Ok(entries) => entries,
+            // not a directory, the encoding of the path tells whether it is a file that can be read
+            Err(_) => {
+                expanded.push(file_path);
+                continue;
+            }
+        };
+        let mut files = vec![];
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry
+                .file_type()
+                .await
+                .is_ok_and(|file_type| file_type.is_file())
+            {
+                files.push(entry.path().to_string_lossy().to_string());
+            }
+        }
+        files.sort();
+        expanded.extend(files);
+    }
+    expanded
+}
+
+/// The directory of the nodes when `--storage-dir` is not given
+pub(crate) fn default_storage_dir() -> PathBuf {
+    "~/.share/dragoonfly".resolve().into_owned()
+}
+
+pub(crate) fn get_node_dir(storage_dir: &Path, peer_id: PeerId) -> PathBuf {
+    storage_dir.join(peer_id.to_base58())
+}
+
 pub(crate) fn get_block_dir(file_dir: &PathBuf, file_hash: String) -> PathBuf {
     [get_file_dir(file_dir, file_hash), PathBuf::from("blocks")]
         .iter()
@@ -1862,17 +5898,3 @@ pub(crate) fn get_block_dir(file_dir: &PathBuf, file_hash: String) -> PathBuf {
 pub(crate) fn get_file_dir(file_dir: &PathBuf, file_hash: String) -> PathBuf {
     [file_dir, &PathBuf::from(file_hash)].iter().collect()
 }
-
-pub(crate) async fn get_powers<F, G>(powers_path: PathBuf) -> Result<Powers<F, G>>
-where
-    F: PrimeField,
-    G: CurveGroup<ScalarField = F>,
-{
-    info!("Getting the powers from {:?}", powers_path);
-    let serialized = tokio::fs::read(powers_path).await?;
-    Ok(Powers::<F, G>::deserialize_with_mode(
-        &serialized[..],
-        Compress::Yes,
-        Validate::Yes,
-    )?)
-}
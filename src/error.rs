@@ -1,16 +1,75 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use libp2p::swarm;
+use serde::Serialize;
 use thiserror::Error;
+use utoipa::ToSchema;
 
+use crate::block_quota::BlockLimitScope;
+use crate::decode_diagnostics::DecodeDiagnostics;
+use crate::send_block_to::OpenStreamFailure;
 use crate::send_strategy::SendId;
+use crate::to_serialize::{Envelope, EnvelopeError};
+
+/// Why a dial failed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DialErrorKind {
+    InvalidMultiaddr,
+    AlreadyDialing,
+    Unreachable,
+    WrongPeerId,
+    LocalPeerId,
+    Denied,
+    Aborted,
+    /// The dial was not attempted because a previous one failed
+    Skipped,
+    /// The name of the multiaddr could not be resolved
+    DnsResolution,
+}
+
+impl DialErrorKind {
+    pub(crate) fn classify(error: &swarm::DialError) -> Self {
+        match error {
+            swarm::DialError::LocalPeerId { .. } => DialErrorKind::LocalPeerId,
+            swarm::DialError::NoAddresses | swarm::DialError::Transport(_) => {
+                DialErrorKind::Unreachable
+            }
+            swarm::DialError::DialPeerConditionFalse(_) => DialErrorKind::AlreadyDialing,
+            swarm::DialError::Aborted => DialErrorKind::Aborted,
+            swarm::DialError::WrongPeerId { .. } => DialErrorKind::WrongPeerId,
+            swarm::DialError::Denied { .. } => DialErrorKind::Denied,
+        }
+    }
+}
+
+impl std::fmt::Display for DialErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DialErrorKind::InvalidMultiaddr => write!(f, "invalid multiaddr"),
+            DialErrorKind::AlreadyDialing => write!(f, "already dialing"),
+            DialErrorKind::Unreachable => write!(f, "unreachable"),
+            DialErrorKind::WrongPeerId => write!(f, "wrong peer id"),
+            DialErrorKind::LocalPeerId => write!(f, "local peer id"),
+            DialErrorKind::Denied => write!(f, "denied"),
+            DialErrorKind::Aborted => write!(f, "aborted"),
+            DialErrorKind::Skipped => write!(f, "skipped"),
+            DialErrorKind::DnsResolution => write!(f, "dns resolution"),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Error, PartialEq)]
 pub enum DragoonError {
     #[error("Bad listener given")]
     BadListener(String),
-    #[error("Could not dial a peer")]
-    DialError(String),
+    #[error("Could not dial {} ({}): {}", multiaddr, kind, context)]
+    DialError {
+        multiaddr: String,
+        kind: DialErrorKind,
+        context: String,
+    },
     #[error("unexpected error from Dragoon")]
     UnexpectedError(String),
     #[error("Could not provide")]
@@ -27,6 +86,12 @@ pub enum DragoonError {
     SendBlockToError { send_id: SendId },
     #[error("This SendBlockTo request to {:?} for file hash {} / block hash {} is already being handled", send_id.peer_id, send_id.file_hash, send_id.block_hash)]
     SendBlockToAlreadyStarted { send_id: SendId },
+    #[error("Could not open a stream to send the block {} of file {} to {}, {}: {}", send_id.block_hash, send_id.file_hash, send_id.peer_id, failure, context)]
+    SendBlockOpenStreamError {
+        send_id: SendId,
+        failure: OpenStreamFailure,
+        context: String,
+    },
     #[error(
         "Send block list failed with a final block distribution of {:?}, due to {}",
         final_block_distribution,
@@ -36,10 +101,126 @@ pub enum DragoonError {
         final_block_distribution: Vec<SendId>,
         context: String,
     },
+    #[error("Could not rotate the identity of the node")]
+    IdentityRotationError(String),
+    #[error("Decoding failed with {}, due to {}", diagnostics, context)]
+    DecodeError {
+        diagnostics: DecodeDiagnostics,
+        context: String,
+    },
+    #[error("The body of the request is too large")]
+    PayloadTooLarge(String),
+    #[error("Too many requests")]
+    TooManyRequests(String),
+    #[error("The request timed out")]
+    RequestTimeout(String),
+    #[error("The request is not authorized")]
+    Unauthorized(String),
+    #[error("The delegated retrieval was rejected")]
+    DelegationRejected(String),
+    #[error(
+        "Storing the blocks of file {} would go over the limit of {} {} ({} requested)",
+        file_hash,
+        limit,
+        scope,
+        requested
+    )]
+    TooManyBlocks {
+        file_hash: String,
+        scope: BlockLimitScope,
+        limit: u64,
+        requested: u64,
+    },
+    #[error("The file already exists")]
+    FileExists(String),
+    #[error("The decoded file has the hash {} instead of {}", decoded, expected)]
+    FileHashMismatch { expected: String, decoded: String },
+    #[error("The weights of the send strategy are not valid")]
+    BadStrategyWeights(String),
+    #[error("Unknown send strategy")]
+    UnknownStrategy(String),
+    #[error("Unknown operation")]
+    UnknownOperation(String),
+    #[error("Unknown job")]
+    UnknownJob(String),
+    #[error("A job with the same id is already running")]
+    JobExists(String),
+    #[error("The uploaded file could not be read")]
+    BadUpload(String),
+    #[error("Nothing is known of the peer")]
+    UnknownPeer(String),
+    #[error("The timeout of the command is not valid")]
+    BadTimeout(String),
+}
+
+impl DragoonError {
+    /// The name of the variant in kebab-case, the `kind` of the error in the responses of the http interface
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            DragoonError::BadListener(_) => "bad-listener",
+            DragoonError::DialError { .. } => "dial-error",
+            DragoonError::UnexpectedError(_) => "unexpected-error",
+            DragoonError::ProviderError(_) => "provider-error",
+            DragoonError::BootstrapError(_) => "bootstrap-error",
+            DragoonError::NoParentDirectory(_) => "no-parent-directory",
+            DragoonError::CouldNotSendBlockResponse(..) => "could-not-send-block-response",
+            DragoonError::CouldNotSendInfoResponse(..) => "could-not-send-info-response",
+            DragoonError::SendBlockToError { .. } => "send-block-to-error",
+            DragoonError::SendBlockToAlreadyStarted { .. } => "send-block-to-already-started",
+            DragoonError::SendBlockOpenStreamError { .. } => "send-block-open-stream-error",
+            DragoonError::SendBlockListFailed { .. } => "send-block-list-failed",
+            DragoonError::IdentityRotationError(_) => "identity-rotation-error",
+            DragoonError::DecodeError { .. } => "decode-error",
+            DragoonError::PayloadTooLarge(_) => "payload-too-large",
+            DragoonError::TooManyRequests(_) => "too-many-requests",
+            DragoonError::RequestTimeout(_) => "request-timeout",
+            DragoonError::Unauthorized(_) => "unauthorized",
+            DragoonError::DelegationRejected(_) => "delegation-rejected",
+            DragoonError::TooManyBlocks { .. } => "too-many-blocks",
+            DragoonError::FileExists(_) => "file-exists",
+            DragoonError::FileHashMismatch { .. } => "file-hash-mismatch",
+            DragoonError::BadStrategyWeights(_) => "bad-strategy-weights",
+            DragoonError::UnknownStrategy(_) => "unknown-strategy",
+            DragoonError::UnknownOperation(_) => "unknown-operation",
+            DragoonError::UnknownJob(_) => "unknown-job",
+            DragoonError::JobExists(_) => "job-exists",
+            DragoonError::BadUpload(_) => "bad-upload",
+            DragoonError::UnknownPeer(_) => "unknown-peer",
+            DragoonError::BadTimeout(_) => "bad-timeout",
+        }
+    }
+
+    /// Whether the same request may succeed if it is sent again later, because the error comes from the load of
+    /// the node or from the state of the network rather than from the request itself
+    pub(crate) fn retryable(&self) -> bool {
+        match self {
+            DragoonError::DialError { kind, .. } => matches!(
+                kind,
+                DialErrorKind::AlreadyDialing
+                    | DialErrorKind::Unreachable
+                    | DialErrorKind::Aborted
+                    | DialErrorKind::DnsResolution
+            ),
+            DragoonError::SendBlockOpenStreamError { failure, .. } => {
+                !matches!(failure, OpenStreamFailure::ProtocolUnsupported)
+            }
+            DragoonError::CouldNotSendBlockResponse(..)
+            | DragoonError::CouldNotSendInfoResponse(..)
+            | DragoonError::SendBlockToError { .. }
+            | DragoonError::SendBlockToAlreadyStarted { .. }
+            | DragoonError::SendBlockListFailed { .. }
+            | DragoonError::TooManyRequests(_)
+            | DragoonError::RequestTimeout(_)
+            | DragoonError::JobExists(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl IntoResponse for DragoonError {
     fn into_response(self) -> Response {
+        let kind = self.kind().to_string();
+        let retryable = self.retryable();
         let (status, err_msg) = match self {
             DragoonError::UnexpectedError(ref msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -48,9 +229,7 @@ impl IntoResponse for DragoonError {
             DragoonError::BadListener(ref msg) => {
                 (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
             }
-            DragoonError::DialError(ref msg) => {
-                (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
-            }
+            DragoonError::DialError { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
             DragoonError::ProviderError(ref msg) => {
                 (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
             }
@@ -72,10 +251,72 @@ impl IntoResponse for DragoonError {
             DragoonError::SendBlockToAlreadyStarted{send_id} => {
                 (StatusCode::TOO_MANY_REQUESTS, format!("This SendBlockTo request to {:?} for file hash {} / block hash {} is already being handled", send_id.peer_id, send_id.file_hash, send_id.block_hash))
             }
+            DragoonError::SendBlockOpenStreamError{ref failure, ..} => {
+                let status = match failure {
+                    OpenStreamFailure::PeerUnreachable | OpenStreamFailure::ProtocolUnsupported => StatusCode::BAD_GATEWAY,
+                    OpenStreamFailure::Busy => StatusCode::SERVICE_UNAVAILABLE,
+                };
+                (status, self.to_string())
+            }
             DragoonError::SendBlockListFailed{final_block_distribution, context} => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("Send block list failed with a final block distribution of {:?}, due to {}", final_block_distribution, context))
             }
+            DragoonError::IdentityRotationError(ref msg) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{}: {}", self, msg))
+            }
+            DragoonError::DecodeError{diagnostics, context} => {
+                (StatusCode::BAD_REQUEST, format!("Decoding failed with {}, due to {}", diagnostics, context))
+            }
+            DragoonError::PayloadTooLarge(ref msg) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, format!("{}: {}", self, msg))
+            }
+            DragoonError::TooManyRequests(ref msg) => {
+                (StatusCode::TOO_MANY_REQUESTS, format!("{}: {}", self, msg))
+            }
+            DragoonError::RequestTimeout(ref msg) => {
+                (StatusCode::REQUEST_TIMEOUT, format!("{}: {}", self, msg))
+            }
+            DragoonError::Unauthorized(ref msg) => {
+                (StatusCode::UNAUTHORIZED, format!("{}: {}", self, msg))
+            }
+            DragoonError::DelegationRejected(ref msg) => {
+                (StatusCode::FORBIDDEN, format!("{}: {}", self, msg))
+            }
+            DragoonError::TooManyBlocks { .. } => (StatusCode::INSUFFICIENT_STORAGE, self.to_string()),
+            DragoonError::FileExists(ref msg) => {
+                (StatusCode::CONFLICT, format!("{}: {}", self, msg))
+            }
+            DragoonError::FileHashMismatch { .. } => (StatusCode::BAD_GATEWAY, self.to_string()),
+            DragoonError::BadStrategyWeights(ref msg) => {
+                (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
+            }
+            DragoonError::UnknownStrategy(ref msg) => {
+                (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
+            }
+            DragoonError::UnknownOperation(ref msg) => {
+                (StatusCode::NOT_FOUND, format!("{}: {}", self, msg))
+            }
+            DragoonError::UnknownJob(ref msg) => {
+                (StatusCode::NOT_FOUND, format!("{}: {}", self, msg))
+            }
+            DragoonError::JobExists(ref msg) => {
+                (StatusCode::CONFLICT, format!("{}: {}", self, msg))
+            }
+            DragoonError::BadUpload(ref msg) => {
+                (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
+            }
+            DragoonError::UnknownPeer(ref msg) => {
+                (StatusCode::NOT_FOUND, format!("{}: {}", self, msg))
+            }
+            DragoonError::BadTimeout(ref msg) => {
+                (StatusCode::BAD_REQUEST, format!("{}: {}", self, msg))
+            }
         };
-        (status, Json(err_msg.to_string())).into_response()
+        let envelope = Envelope::error(EnvelopeError {
+            kind,
+            message: err_msg,
+            retryable,
+        });
+        (status, Json(envelope)).into_response()
     }
 }
@@ -0,0 +1,88 @@
+//! Announcement of the newly encoded files on a gossipsub topic shared by the nodes of a network.
+//!
+//! Each node announces the files it encodes, and keeps an index of the files announced by the others,
+//! so the hash of a file does not have to be given out-of-band before it is retrieved.
+
+use chrono::{SecondsFormat, Utc};
+use libp2p::gossipsub::IdentTopic;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Above this number of known files, the file announced the longest time ago is forgotten
+const MAX_KNOWN_FILES: usize = 10_000;
+
+/// The topic the files of the network `network_name` are announced on
+pub(crate) fn files_topic(network_name: &str) -> IdentTopic {
+    IdentTopic::new(format!("/dragoonfly/{}/files/1", network_name))
+}
+
+/// The message published on the topic when a file is encoded
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct FileAnnouncement {
+    pub(crate) file_hash: String,
+    /// Number of blocks needed to decode the file
+    pub(crate) k: usize,
+    /// Number of blocks the file was encoded into
+    pub(crate) n: usize,
+    /// Size in bytes of the file before encoding
+    pub(crate) size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub(crate) struct KnownFile {
+    pub(crate) file_hash: String,
+    pub(crate) k: usize,
+    pub(crate) n: usize,
+    pub(crate) size: usize,
+    /// The peers that announced the file, in base 58
+    pub(crate) announced_by: Vec<String>,
+    pub(crate) last_announced: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct KnownFiles {
+    files: HashMap<String, KnownFile>,
+}
+
+impl KnownFiles {
+    /// Add the file of an announcement to the index, or update it if it was already announced
+    pub(crate) fn record(&mut self, peer_id: PeerId, announcement: FileAnnouncement) {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        let peer_id = peer_id.to_base58();
+        if !self.files.contains_key(&announcement.file_hash) && self.files.len() >= MAX_KNOWN_FILES
+        {
+            let oldest = self
+                .files
+                .values()
+                .min_by(|a, b| a.last_announced.cmp(&b.last_announced))
+                .map(|file| file.file_hash.clone());
+            if let Some(oldest) = oldest {
+                self.files.remove(&oldest);
+            }
+        }
+        let file = self
+            .files
+            .entry(announcement.file_hash.clone())
+            .or_insert_with(|| KnownFile {
+                file_hash: announcement.file_hash,
+                k: announcement.k,
+                n: announcement.n,
+                size: announcement.size,
+                announced_by: vec![],
+                last_announced: now.clone(),
+            });
+        if !file.announced_by.contains(&peer_id) {
+            file.announced_by.push(peer_id);
+        }
+        file.last_announced = now;
+    }
+
+    /// The known files, the last announced first
+    pub(crate) fn list(&self) -> Vec<KnownFile> {
+        let mut files: Vec<KnownFile> = self.files.values().cloned().collect();
+        files.sort_by(|a, b| b.last_announced.cmp(&a.last_announced));
+        files
+    }
+}
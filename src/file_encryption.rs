@@ -0,0 +1,113 @@
+//! Encryption of the files before they are encoded, with the file key of the node.
+//!
+//! An encode-file with `encrypt` encrypts the content of the file with AES-256-GCM before encoding and proving it,
+//! so the blocks, and the hash the file is announced under in the DHT, are the ones of the ciphertext and the
+//! plaintext never leaves the node. The key is read from `--file-key-file`, `file.key` in the storage directory by
+//! default, and generated there on the first encryption; a get-file with `decrypt` uses the same key to get the
+//! plaintext back, so the key must be copied to the nodes which should read the file.
+//!
+//! The nonce is derived from the key and the plaintext, so encoding the same file twice gives the same ciphertext
+//! and thus the same file hash.
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use anyhow::{format_err, Result};
+use rs_merkle::{algorithms::Sha256, Hasher};
+use std::path::Path;
+
+use crate::block_store::{load_or_generate_cipher, load_or_generate_key};
+
+/// The file key in the storage directory of the node, when `--file-key-file` is not given
+pub(crate) const FILE_KEY_FILE_NAME: &str = "file.key";
+
+/// Written at the start of the encrypted files, so decrypting a file which is not encrypted fails clearly
+const ENCRYPTED_FILE_HEADER: &[u8] = b"dragoonfly-file-aes-gcm-1";
+const NONCE_SIZE: usize = 12;
+
+pub(crate) fn encrypt(key_path: &Path, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let key = load_or_generate_key(key_path)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce: Vec<u8> = Sha256::hash(&[&key[..], plaintext].concat())[..NONCE_SIZE].to_vec();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|e| format_err!("Could not encrypt the file: {}", e))?;
+    Ok([ENCRYPTED_FILE_HEADER, &nonce, &ciphertext].concat())
+}
+
+pub(crate) fn decrypt(key_path: &Path, encrypted: &[u8]) -> Result<Vec<u8>> {
+    let encrypted = encrypted
+        .strip_prefix(ENCRYPTED_FILE_HEADER)
+        .ok_or(format_err!(
+            "The file was not encrypted when it was encoded"
+        ))?;
+    if encrypted.len() < NONCE_SIZE {
+        return Err(format_err!("The encrypted file is truncated"));
+    }
+    if !key_path.exists() {
+        return Err(format_err!(
+            "There is no file key in {:?} to decrypt the file",
+            key_path
+        ));
+    }
+    let cipher: Aes256Gcm = load_or_generate_cipher(key_path)?;
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_SIZE);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| format_err!("Could not decrypt the file, wrong file key?"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A key path of its own for each test, the key is generated on the first encryption
+    fn key_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "dragoonfly-file-encryption-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join(FILE_KEY_FILE_NAME)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let key_path = key_path("roundtrip");
+        let encrypted = encrypt(&key_path, b"plaintext").unwrap();
+        assert!(key_path.exists());
+        assert_ne!(&encrypted[ENCRYPTED_FILE_HEADER.len()..], b"plaintext");
+        assert_eq!(decrypt(&key_path, &encrypted).unwrap(), b"plaintext");
+    }
+
+    #[test]
+    fn same_file_same_ciphertext() {
+        let key_path = key_path("deterministic");
+        assert_eq!(
+            encrypt(&key_path, b"plaintext").unwrap(),
+            encrypt(&key_path, b"plaintext").unwrap()
+        );
+        assert_ne!(
+            encrypt(&key_path, b"plaintext").unwrap(),
+            encrypt(&key_path, b"other plaintext").unwrap()
+        );
+    }
+
+    #[test]
+    fn decrypt_with_another_key() {
+        let encrypted = encrypt(&key_path("key"), b"plaintext").unwrap();
+        let other_key_path = key_path("other-key");
+        encrypt(&other_key_path, b"").unwrap();
+        assert!(decrypt(&other_key_path, &encrypted).is_err());
+        assert!(decrypt(&key_path("missing-key"), &encrypted).is_err());
+    }
+
+    #[test]
+    fn decrypt_a_file_that_was_not_encrypted() {
+        let key_path = key_path("not-encrypted");
+        encrypt(&key_path, b"").unwrap();
+        assert!(decrypt(&key_path, b"plaintext").is_err());
+        assert!(decrypt(&key_path, ENCRYPTED_FILE_HEADER).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! Per-file read-write locks, so the blocks of a file are not replaced by a new encoding while they are being served
+
+use anyhow::{format_err, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+#[derive(Debug, Default)]
+pub(crate) struct FileLocks {
+    locks: Mutex<HashMap<String, Arc<RwLock<()>>>>,
+}
+
+impl FileLocks {
+    fn lock_of(&self, file_hash: &str) -> Arc<RwLock<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        // forget the locks no one holds anymore
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks.entry(file_hash.to_string()).or_default().clone()
+    }
+
+    /// Whether the blocks of some file are being read or replaced
+    pub(crate) fn any_held(&self) -> bool {
+        self.locks
+            .lock()
+            .unwrap()
+            .values()
+            .any(|lock| Arc::strong_count(lock) > 1)
+    }
+
+    /// Wait until the blocks of the file can be read
+    pub(crate) async fn read(&self, file_hash: &str) -> OwnedRwLockReadGuard<()> {
+        self.lock_of(file_hash).read_owned().await
+    }
+
+    /// Lock the blocks of the file for reading without waiting, for the paths that can't wait such as the event loop of the swarm
+    pub(crate) fn try_read(&self, file_hash: &str) -> Result<OwnedRwLockReadGuard<()>> {
+        self.lock_of(file_hash).try_read_owned().map_err(|_| {
+            format_err!(
+                "The blocks of the file {} are being replaced by a new encoding",
+                file_hash
+            )
+        })
+    }
+
+    /// Wait until no one reads the blocks of the file anymore, and keep them from being read until the guard is dropped
+    pub(crate) async fn write(&self, file_hash: &str) -> OwnedRwLockWriteGuard<()> {
+        self.lock_of(file_hash).write_owned().await
+    }
+}
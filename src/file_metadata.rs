@@ -0,0 +1,103 @@
+//! The metadata of the encoded files, published in the DHT so a node retrieving a file knows how it was encoded
+//! before it contacts any provider.
+//!
+//! The node that encodes a file puts a record under the key `/dragoonfly/file-metadata/FILE_HASH`, holding the
+//! metadata of the file signed with the key of the node. The signature only binds the record to the key it carries:
+//! any node can publish a well signed record for any file, so the record is a hint, not a proof that the metadata
+//! comes from the node that encoded the file. A get-file reads the record first to know the `k` of the file and how
+//! to decode it, but a block that does not match the record still has its proof checked, and a block with a valid
+//! proof wins over the record. The decoded file is then checked against its hash, whatever the record says. A
+//! record that is not well signed, or that is about another file, is ignored, and so are the files encoded before
+//! the records were published, which have none. The record of a file is put again each time a segment is appended
+//! to it, with the list of its segments, which are only followed when they match the segments root given to the
+//! get-file, see [`crate::manifest`].
+
+use anyhow::{format_err, Result};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::kad::{Record, RecordKey};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::commands::EncodingMethod;
+use crate::compression::Compression;
+use crate::packing::Packing;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub(crate) struct FileMetadata {
+    pub(crate) file_hash: String,
+    /// Number of blocks needed to decode the file
+    pub(crate) k: usize,
+    /// Number of blocks the file was encoded into
+    pub(crate) n: usize,
+    /// Size in bytes of the file before encoding
+    pub(crate) size: usize,
+    /// SHA-256 of the commitments shared by all the blocks of the file
+    pub(crate) commitment_digest: String,
+    pub(crate) encoding_method: EncodingMethod,
+    /// The compression of the file before it was encoded, which the file is decompressed with once decoded
+    #[serde(default)]
+    pub(crate) compression: Compression,
+    /// How the bytes were packed in field elements, which the decoded elements are unpacked with
+    #[serde(default)]
+    pub(crate) packing: Packing,
+    /// The files appended to this one, in order, see [`crate::manifest`]
+    #[serde(default)]
+    pub(crate) segments: Vec<String>,
+}
+
+/// The value of the record
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedFileMetadata {
+    /// The metadata in JSON, as signed
+    metadata: Vec<u8>,
+    /// The public key of the node that encoded the file, in protobuf
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+pub(crate) fn record_key(file_hash: &str) -> RecordKey {
+    RecordKey::new(&format!("/dragoonfly/file-metadata/{}", file_hash))
+}
+
+/// The record of the metadata, signed with `keypair`
+pub(crate) fn record(metadata: &FileMetadata, keypair: &Keypair) -> Result<Record> {
+    let json = serde_json::to_vec(metadata)?;
+    let signed = SignedFileMetadata {
+        signature: keypair.sign(&json)?,
+        public_key: keypair.public().encode_protobuf(),
+        metadata: json,
+    };
+    let mut record = Record::new(
+        record_key(&metadata.file_hash),
+        serde_json::to_vec(&signed)?,
+    );
+    record.publisher = Some(keypair.public().to_peer_id());
+    Ok(record)
+}
+
+/// The metadata of the record, checking it is signed with the key it carries, which is the one of its publisher if
+/// the record names one, and is about `file_hash`. Nothing binds that key to the file, see the module documentation.
+pub(crate) fn open(record: &Record, file_hash: &str) -> Result<FileMetadata> {
+    let signed: SignedFileMetadata = serde_json::from_slice(&record.value)?;
+    let public_key = PublicKey::try_decode_protobuf(&signed.public_key)?;
+    if !public_key.verify(&signed.metadata, &signed.signature) {
+        return Err(format_err!("The signature of the metadata is invalid"));
+    }
+    if record
+        .publisher
+        .is_some_and(|publisher| publisher != public_key.to_peer_id())
+    {
+        return Err(format_err!(
+            "The metadata is not signed by the publisher of the record"
+        ));
+    }
+    let metadata: FileMetadata = serde_json::from_slice(&signed.metadata)?;
+    if metadata.file_hash != file_hash {
+        return Err(format_err!(
+            "The record is about the file {} instead of {}",
+            metadata.file_hash,
+            file_hash
+        ));
+    }
+    Ok(metadata)
+}
@@ -0,0 +1,418 @@
+//! The gRPC interface of the node, served alongside the http interface when `--grpc-ip-port` is given.
+//!
+//! The methods run the same commands as the routes of the http interface. The long-running ones, encode-file,
+//! get-file and send-block-list, stream the progress of the command, as read with `/progress/{operation_id}`, and
+//! end the stream with its result. The methods require the bearer tokens of the http interface in an
+//! `authorization` metadata, unless the node runs with `--no-auth`.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::block_scheduler::RetrievalOrder;
+use crate::commands::{
+    check_timeout, DragoonCommand, EncodingMethod, GetFileOutcome, GetFileOutput, Sender,
+};
+use crate::http_auth::HttpAuth;
+use crate::progress::{self, OperationProgress};
+use crate::send_strategy_impl::StrategyName;
+use crate::shutdown;
+
+pub(crate) mod proto {
+    tonic::include_proto!("dragoonfly");
+}
+
+use proto::dragoonfly_server::{Dragoonfly, DragoonflyServer};
+use proto::{
+    encode_file_update, get_file_update, send_block_list_update, AddPeerRequest, DialRequest,
+    Empty, EncodeFileRequest, EncodeFileUpdate, EncodedFile, GetFileRequest, GetFileUpdate, Peer,
+    PeerList, Progress, SendBlockListRequest, SendBlockListUpdate, SentBlock, SentBlocks,
+};
+
+/// Time between two reads of the progress of a streamed command
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of updates of a streamed command waiting to be read by the client
+const UPDATE_BUFFER_SIZE: usize = 16;
+
+/// An update of a streamed command, either its progress or its result
+trait Update: Sized {
+    type Done;
+
+    fn progress(progress: Progress) -> Self;
+    fn done(done: Self::Done) -> Self;
+}
+
+impl Update for EncodeFileUpdate {
+    type Done = EncodedFile;
+
+    fn progress(progress: Progress) -> Self {
+        EncodeFileUpdate {
+            update: Some(encode_file_update::Update::Progress(progress)),
+        }
+    }
+
+    fn done(done: EncodedFile) -> Self {
+        EncodeFileUpdate {
+            update: Some(encode_file_update::Update::Done(done)),
+        }
+    }
+}
+
+impl Update for GetFileUpdate {
+    type Done = String;
+
+    fn progress(progress: Progress) -> Self {
+        GetFileUpdate {
+            update: Some(get_file_update::Update::Progress(progress)),
+        }
+    }
+
+    fn done(done: String) -> Self {
+        GetFileUpdate {
+            update: Some(get_file_update::Update::Done(done)),
+        }
+    }
+}
+
+impl Update for SendBlockListUpdate {
+    type Done = SentBlocks;
+
+    fn progress(progress: Progress) -> Self {
+        SendBlockListUpdate {
+            update: Some(send_block_list_update::Update::Progress(progress)),
+        }
+    }
+
+    fn done(done: SentBlocks) -> Self {
+        SendBlockListUpdate {
+            update: Some(send_block_list_update::Update::Done(done)),
+        }
+    }
+}
+
+impl From<OperationProgress> for Progress {
+    fn from(progress: OperationProgress) -> Self {
+        Progress {
+            operation_id: progress.operation_id,
+            stage: progress.stage,
+            blocks_done: progress.blocks_done as u64,
+            blocks_total: progress.blocks_total.map(|total| total as u64),
+            bytes_processed: progress.bytes_processed,
+        }
+    }
+}
+
+/// Parse a string field of a request as the kebab-case name of a variant, the default variant when it is empty
+fn parse_or_default<T: DeserializeOwned + Default>(
+    field: &str,
+    value: String,
+) -> Result<T, Status> {
+    if value.is_empty() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(serde_json::Value::String(value.clone()))
+        .map_err(|e| Status::invalid_argument(format!("Invalid {} {}: {}", field, value, e)))
+}
+
+/// The result of a command, or the status telling why it has none
+fn command_result<T, E: Display>(
+    cmd_name: &str,
+    res: Result<Result<T, E>, oneshot::error::RecvError>,
+) -> Result<T, Status> {
+    match res {
+        Ok(Ok(res)) => Ok(res),
+        Ok(Err(e)) => Err(Status::internal(format!(
+            "The command `{}` failed: {}",
+            cmd_name, e
+        ))),
+        Err(_) => Err(Status::internal(format!(
+            "Could not receive the result of the command `{}`",
+            cmd_name
+        ))),
+    }
+}
+
+struct DragoonflyService {
+    state: Arc<AppState>,
+}
+
+impl DragoonflyService {
+    fn send_command(&self, cmd: DragoonCommand) -> Result<String, Status> {
+        let cmd_name = cmd.to_string();
+        info!("Sending command `{:?}` from the gRPC interface", cmd);
+        self.state.cmd_sender.send(cmd).map_err(|e| {
+            Status::unavailable(format!("Could not send command `{}`: {:?}", cmd_name, e))
+        })?;
+        Ok(cmd_name)
+    }
+
+    /// Run a command and wait for its result
+    async fn run<T, E: Display>(
+        &self,
+        cmd: DragoonCommand,
+        receiver: oneshot::Receiver<Result<T, E>>,
+    ) -> Result<T, Status> {
+        let cmd_name = self.send_command(cmd)?;
+        command_result(&cmd_name, receiver.await)
+    }
+
+    /// Run a long-running command, streaming its progress until its result
+    fn stream<U, T, E>(
+        &self,
+        cmd: DragoonCommand,
+        operation_id: String,
+        mut receiver: oneshot::Receiver<Result<T, E>>,
+        into_done: impl FnOnce(T) -> Result<U::Done, Status> + Send + 'static,
+    ) -> Result<ReceiverStream<Result<U, Status>>, Status>
+    where
+        U: Update + Send + 'static,
+        T: Send + 'static,
+        E: Display + Send + 'static,
+    {
+        let cmd_name = self.send_command(cmd)?;
+        let progress = self.state.progress.clone();
+        let (updates, stream) = mpsc::channel(UPDATE_BUFFER_SIZE);
+        tokio::spawn(async move {
+            let mut interval = time::interval(PROGRESS_INTERVAL);
+            let mut last_progress = None;
+            let res = loop {
+                tokio::select! {
+                    res = &mut receiver => break res,
+                    _ = interval.tick() => {
+                        let Some(current) = progress.get(&operation_id).map(Progress::from) else {
+                            continue;
+                        };
+                        // only the progress that changed is streamed
+                        if last_progress.as_ref() != Some(&current) {
+                            last_progress = Some(current.clone());
+                            if updates.send(Ok(U::progress(current))).await.is_err() {
+                                warn!("The gRPC client of the operation {} left, the command `{}` goes on", operation_id, cmd_name);
+                                return;
+                            }
+                        }
+                    }
+                }
+            };
+            let update = command_result(&cmd_name, res)
+                .and_then(into_done)
+                .map(U::done);
+            let _ = updates.send(update).await;
+        });
+        Ok(ReceiverStream::new(stream))
+    }
+}
+
+#[tonic::async_trait]
+impl Dragoonfly for DragoonflyService {
+    type EncodeFileStream = ReceiverStream<Result<EncodeFileUpdate, Status>>;
+    type GetFileStream = ReceiverStream<Result<GetFileUpdate, Status>>;
+    type SendBlockListStream = ReceiverStream<Result<SendBlockListUpdate, Status>>;
+
+    async fn encode_file(
+        &self,
+        request: Request<EncodeFileRequest>,
+    ) -> Result<Response<Self::EncodeFileStream>, Status> {
+        let request = request.into_inner();
+        let encoding_method = match proto::EncodingMethod::try_from(request.encoding_method) {
+            Ok(proto::EncodingMethod::Random) => EncodingMethod::Random,
+            Ok(proto::EncodingMethod::Vandermonde) => EncodingMethod::Vandermonde,
+            Ok(proto::EncodingMethod::Systematic) => EncodingMethod::Systematic,
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid encoding method {}",
+                    request.encoding_method
+                )))
+            }
+        };
+        let operation_id = progress::new_operation_id();
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::EncodeFile {
+            file_path: request.file_path,
+            replace_blocks: request.replace_blocks,
+            encoding_method,
+            encode_mat_k: request.k as usize,
+            encode_mat_n: request.n as usize,
+            domain: parse_or_default("domain", request.domain)?,
+            domain_seed: request.domain_seed,
+            compression: parse_or_default("compression", request.compression)?,
+            packing: parse_or_default("packing", request.packing)?,
+            encrypt: request.encrypt,
+            archive: false,
+            operation_id: Some(operation_id.clone()),
+            sender: Sender::SenderOneS(sender),
+        };
+        let stream = self.stream(cmd, operation_id, receiver, |encoded| {
+            Ok(EncodedFile {
+                file_hash: encoded.file_hash,
+                block_hashes: encoded.block_hashes,
+            })
+        })?;
+        Ok(Response::new(stream))
+    }
+
+    async fn get_file(
+        &self,
+        request: Request<GetFileRequest>,
+    ) -> Result<Response<Self::GetFileStream>, Status> {
+        let request = request.into_inner();
+        check_timeout(request.timeout).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let operation_id = progress::new_operation_id();
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::GetFile {
+            file_hash: request.file_hash,
+            output_filename: request.output_filename,
+            output: GetFileOutput::File,
+            order: RetrievalOrder::default(),
+            overwrite: request.overwrite,
+            auto_concurrency: false,
+            parallelism: None,
+            decrypt: request.decrypt,
+            unpack: false,
+            segments_root: request.segments_root,
+            timeout: request.timeout,
+            operation_id: Some(operation_id.clone()),
+            sender: Sender::SenderOneS(sender),
+        };
+        let stream = self.stream(cmd, operation_id, receiver, |outcome| match outcome {
+            GetFileOutcome::Written(path) => Ok(path.to_string_lossy().into_owned()),
+            outcome => Err(Status::internal(format!(
+                "The file was not written: {:?}",
+                outcome
+            ))),
+        })?;
+        Ok(Response::new(stream))
+    }
+
+    async fn send_block_list(
+        &self,
+        request: Request<SendBlockListRequest>,
+    ) -> Result<Response<Self::SendBlockListStream>, Status> {
+        let request = request.into_inner();
+        let strategy_name = StrategyName::new(&request.strategy_name);
+        self.state
+            .send_strategies
+            .get(&strategy_name)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        check_timeout(request.timeout).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let operation_id = progress::new_operation_id();
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::SendBlockList {
+            strategy_name,
+            file_hash: request.file_hash,
+            block_list: request.block_hashes,
+            weights: request.weights,
+            timeout: request.timeout,
+            operation_id: Some(operation_id.clone()),
+            sender: Sender::SenderOneS(sender),
+        };
+        let stream = self.stream(cmd, operation_id, receiver, |send_ids| {
+            Ok(SentBlocks {
+                blocks: send_ids
+                    .into_iter()
+                    .map(|send_id| SentBlock {
+                        peer_id: send_id.peer_id.to_base58(),
+                        file_hash: send_id.file_hash,
+                        block_hash: send_id.block_hash,
+                        attempts: send_id.attempts,
+                    })
+                    .collect(),
+            })
+        })?;
+        Ok(Response::new(stream))
+    }
+
+    async fn get_connected_peers(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<PeerList>, Status> {
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::GetConnectedPeers {
+            sender: Sender::SenderOneS(sender),
+        };
+        let peers = self.run(cmd, receiver).await?;
+        Ok(Response::new(PeerList {
+            peers: peers
+                .into_iter()
+                .map(|peer_id| Peer {
+                    peer_id: peer_id.to_base58(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn dial_single(&self, request: Request<DialRequest>) -> Result<Response<Peer>, Status> {
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::DialSingle {
+            multiaddr: request.into_inner().multiaddr,
+            sender: Sender::SenderOneS(sender),
+        };
+        let peer_id = self.run(cmd, receiver).await?;
+        Ok(Response::new(Peer {
+            peer_id: peer_id.to_base58(),
+        }))
+    }
+
+    async fn add_peer(&self, request: Request<AddPeerRequest>) -> Result<Response<Empty>, Status> {
+        let (sender, receiver) = oneshot::channel();
+        let cmd = DragoonCommand::AddPeer {
+            multiaddr: request.into_inner().multiaddr,
+            sender: Sender::SenderOneS(sender),
+        };
+        self.run(cmd, receiver).await?;
+        Ok(Response::new(Empty {}))
+    }
+}
+
+/// Reject the requests without one of the bearer tokens of the http interface
+fn authenticate(auth: Option<&HttpAuth>, request: Request<()>) -> Result<Request<()>, Status> {
+    let Some(auth) = auth else {
+        return Ok(request);
+    };
+    let token = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    match token {
+        Some(token) if auth.is_allowed(token) => Ok(request),
+        token => {
+            auth.record_unauthorized();
+            let err_msg = match token {
+                Some(_) => "Invalid bearer token for the gRPC interface",
+                None => "Missing bearer token for the gRPC interface",
+            };
+            warn!(err_msg);
+            Err(Status::unauthenticated(err_msg))
+        }
+    }
+}
+
+/// Serve the gRPC interface on `ip_port` until the node stops
+pub(crate) async fn serve(
+    ip_port: SocketAddr,
+    state: Arc<AppState>,
+    auth: Option<Arc<HttpAuth>>,
+    stop: watch::Receiver<bool>,
+) -> Result<()> {
+    let service = DragoonflyServer::with_interceptor(DragoonflyService { state }, move |request| {
+        authenticate(auth.as_deref(), request)
+    });
+    info!("Serving the gRPC interface on {}", ip_port);
+    Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(ip_port, shutdown::stopping(stop))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Bearer-token authentication of the routes of the http interface that change the state of the node.
+//!
+//! The tokens are given on the command line or in a file, one token per line, empty lines and lines
+//! starting with `#` being ignored. A request to a protected route must carry one of them in an
+//! `Authorization: Bearer TOKEN` header.
+
+use anyhow::{format_err, Result};
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::error::DragoonError::Unauthorized;
+use crate::metrics::Metrics;
+
+pub(crate) struct HttpAuth {
+    tokens: Vec<String>,
+    metrics: Arc<Metrics>,
+}
+
+impl HttpAuth {
+    /// Gather the tokens given on the command line and the ones of the tokens file, at least one is required
+    pub(crate) async fn load(
+        mut tokens: Vec<String>,
+        tokens_file: Option<&Path>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
+        if let Some(path) = tokens_file {
+            let content = fs::read_to_string(path)
+                .await
+                .map_err(|e| format_err!("Could not read the tokens file {:?}: {}", path, e))?;
+            tokens.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        tokens.retain(|token| !token.is_empty());
+        if tokens.is_empty() {
+            return Err(format_err!(
+                "The http interface needs a token, give one with --auth-token or --auth-tokens-file, or disable the authentication with --no-auth"
+            ));
+        }
+        info!("{} tokens are allowed on the http interface", tokens.len());
+        Ok(HttpAuth { tokens, metrics })
+    }
+
+    pub(crate) fn is_allowed(&self, token: &str) -> bool {
+        // every token is compared, so the time taken does not tell which one is closest
+        self.tokens.iter().fold(false, |allowed, known| {
+            allowed | constant_time_eq(known, token)
+        })
+    }
+
+    /// Count a request rejected for its token
+    pub(crate) fn record_unauthorized(&self) {
+        Metrics::incr(&self.metrics.http_unauthorized);
+    }
+}
+
+/// Compare two strings in a time that only depends on their length
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Middleware rejecting the requests without a valid bearer token
+pub(crate) async fn require_token(
+    State(auth): State<Arc<HttpAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    match token {
+        Some(token) if auth.is_allowed(token) => next.run(request).await,
+        token => {
+            auth.record_unauthorized();
+            let err_msg = match token {
+                Some(_) => format!("Invalid bearer token for {}", request.uri().path()),
+                None => format!("Missing bearer token for {}", request.uri().path()),
+            };
+            warn!(err_msg);
+            Unauthorized(err_msg).into_response()
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Limits applied to every request of the http interface: body size, rate per client and duration
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header::CONTENT_LENGTH, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body_util::Limited;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{debug, warn};
+
+use crate::error::DragoonError::{PayloadTooLarge, RequestTimeout, TooManyRequests};
+use crate::metrics::Metrics;
+
+/// The route whose body is a whole file, see [`HttpLimitsConfig::upload_max_body_size`]
+const UPLOAD_ROUTE: &str = "upload-and-encode";
+
+/// The routes bounded by their own `timeout` query parameter, which may be longer than the timeout of the requests
+const OWN_TIMEOUT_ROUTES: [&str; 3] = ["get-file", "download", "send-block-list"];
+/// Time given to these routes after their own timeout, e.g. to decode the file once enough blocks were received
+const OWN_TIMEOUT_MARGIN: Duration = Duration::from_secs(60);
+
+/// Above this number of tracked clients, the clients whose bucket is full again are forgotten
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct HttpLimitsConfig {
+    /// Maximum size in bytes of the body of a request, for the routes without a specific limit
+    pub(crate) max_body_size: usize,
+    /// Maximum size in bytes of the body of an upload-and-encode, which is written to disk as it is received instead
+    /// of being held in memory
+    pub(crate) upload_max_body_size: usize,
+    /// Maximum size in bytes of the body of a request for specific routes, the key is the route name, e.g. `send-block-list`
+    pub(crate) route_max_body_size: HashMap<String, usize>,
+    /// Number of requests allowed per second for a single client ip, 0 disables the rate limit
+    pub(crate) rate_limit: u32,
+    /// Number of requests a client can send in a burst before being rate limited
+    pub(crate) rate_burst: u32,
+    /// Maximum duration of a request, `None` disables the timeout
+    pub(crate) request_timeout: Option<Duration>,
+}
+
+/// Parse a `ROUTE=BYTES` pair given on the command line
+pub(crate) fn parse_route_body_size(s: &str) -> Result<(String, usize), String> {
+    let (route, size) = s
+        .split_once('=')
+        .ok_or(format!("expected ROUTE=BYTES, got {}", s))?;
+    let size = size
+        .parse::<usize>()
+        .map_err(|e| format!("invalid size {} for route {}: {}", size, route, e))?;
+    Ok((route.trim_matches('/').to_string(), size))
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+pub(crate) struct HttpLimits {
+    config: HttpLimitsConfig,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    metrics: Arc<Metrics>,
+}
+
+impl HttpLimits {
+    pub(crate) fn new(config: HttpLimitsConfig, metrics: Arc<Metrics>) -> Self {
+        HttpLimits {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    fn max_body_size(&self, path: &str) -> usize {
+        let route = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        match self.config.route_max_body_size.get(route) {
+            Some(max_body_size) => *max_body_size,
+            None if route == UPLOAD_ROUTE => self.config.upload_max_body_size,
+            None => self.config.max_body_size,
+        }
+    }
+
+    /// The timeout of the request, extended for the routes given a longer timeout of their own
+    fn request_timeout(&self, uri: &Uri) -> Option<Duration> {
+        let timeout = self.config.request_timeout?;
+        let route = uri
+            .path()
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or("");
+        if !OWN_TIMEOUT_ROUTES.contains(&route) {
+            return Some(timeout);
+        }
+        let own_timeout = uri
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| pair.strip_prefix("timeout="))
+            .and_then(|secs| secs.parse::<u64>().ok());
+        match own_timeout {
+            Some(secs) => Some(timeout.max(Duration::from_secs(secs) + OWN_TIMEOUT_MARGIN)),
+            None => Some(timeout),
+        }
+    }
+
+    /// Take a token from the bucket of the client, returns false if the bucket is empty
+    fn take_token(&self, ip: IpAddr) -> bool {
+        if self.config.rate_limit == 0 {
+            return true;
+        }
+        let rate = self.config.rate_limit as f64;
+        let burst = self.config.rate_burst.max(1) as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.entry(ip).or_insert(TokenBucket {
+            tokens: burst,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Middleware enforcing the [`HttpLimits`] on all the routes
+pub(crate) async fn limit_requests(
+    State(limits): State<Arc<HttpLimits>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    Metrics::incr(&limits.metrics.http_requests);
+
+    // the requests of the control socket have no address, they all come from the machine of the node
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(client)| client.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    if !limits.take_token(client_ip) {
+        Metrics::incr(&limits.metrics.http_rate_limited);
+        let err_msg = format!(
+            "{} sent more than {} requests per second",
+            client_ip, limits.config.rate_limit
+        );
+        warn!(err_msg);
+        return TooManyRequests(err_msg).into_response();
+    }
+
+    let max_body_size = limits.max_body_size(request.uri().path());
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > max_body_size) {
+        Metrics::incr(&limits.metrics.http_body_too_large);
+        let err_msg = format!(
+            "The body of the request to {} is bigger than the limit of {} bytes",
+            request.uri().path(),
+            max_body_size
+        );
+        warn!(err_msg);
+        return PayloadTooLarge(err_msg).into_response();
+    }
+    // the body is not read here, the handler streams it and fails once it reads more than the limit, e.g. a chunked
+    // body without a length
+    let (parts, request_body) = request.into_parts();
+    let request = Request::from_parts(parts, Body::new(Limited::new(request_body, max_body_size)));
+
+    let path = request.uri().path().to_string();
+    let response = match limits.request_timeout(request.uri()) {
+        Some(timeout) => match time::timeout(timeout, next.run(request)).await {
+            Ok(response) => response,
+            Err(_) => {
+                Metrics::incr(&limits.metrics.http_timed_out);
+                let err_msg = format!("The request to {} took more than {:?}", path, timeout);
+                warn!(err_msg);
+                RequestTimeout(err_msg).into_response()
+            }
+        },
+        None => next.run(request).await,
+    };
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        debug!(
+            "The body of the request to {} went over the limit of {} bytes",
+            path, max_body_size
+        );
+        Metrics::incr(&limits.metrics.http_body_too_large);
+    }
+    response
+}
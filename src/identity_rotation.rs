@@ -0,0 +1,105 @@
+//! Everything needed to move a node to a new keypair while keeping a verifiable trace of the old identity
+
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::fs as sfs;
+use std::path::{Path, PathBuf};
+
+pub(crate) const IDENTITY_LINKS_FILE_NAME: &str = "identity_links.json";
+
+/// Signed proof that `old_peer_id` handed over its identity to `new_peer_id`.
+/// Both keys sign the same message, so anyone knowing either of the two peer ids can check the link.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct IdentityLink {
+    pub(crate) old_peer_id: String,
+    pub(crate) new_peer_id: String,
+    pub(crate) old_public_key: Vec<u8>,
+    pub(crate) new_public_key: Vec<u8>,
+    pub(crate) old_signature: Vec<u8>,
+    pub(crate) new_signature: Vec<u8>,
+    pub(crate) timestamp: String,
+}
+
+/// Sent to every connected peer before the node switches to its new identity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IdentityRotationNotice {
+    pub(crate) link: IdentityLink,
+    pub(crate) listen_addrs: Vec<Multiaddr>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IdentityRotationAck(pub(crate) bool);
+
+fn link_message(old_peer_id: &PeerId, new_peer_id: &PeerId) -> Vec<u8> {
+    format!(
+        "dragoonfly identity rotation: {} -> {}",
+        old_peer_id, new_peer_id
+    )
+    .into_bytes()
+}
+
+impl IdentityLink {
+    pub(crate) fn new(old_keypair: &Keypair, new_keypair: &Keypair) -> Result<Self> {
+        let old_peer_id = old_keypair.public().to_peer_id();
+        let new_peer_id = new_keypair.public().to_peer_id();
+        let message = link_message(&old_peer_id, &new_peer_id);
+        Ok(IdentityLink {
+            old_peer_id: old_peer_id.to_base58(),
+            new_peer_id: new_peer_id.to_base58(),
+            old_public_key: old_keypair.public().encode_protobuf(),
+            new_public_key: new_keypair.public().encode_protobuf(),
+            old_signature: old_keypair.sign(&message)?,
+            new_signature: new_keypair.sign(&message)?,
+            timestamp: Utc::now().to_string(),
+        })
+    }
+
+    /// Check that both public keys match the announced peer ids and that both signatures are valid.
+    /// Returns the (old, new) peer ids on success.
+    pub(crate) fn verify(&self) -> Result<(PeerId, PeerId)> {
+        let old_public_key = PublicKey::try_decode_protobuf(&self.old_public_key)?;
+        let new_public_key = PublicKey::try_decode_protobuf(&self.new_public_key)?;
+        let old_peer_id = old_public_key.to_peer_id();
+        let new_peer_id = new_public_key.to_peer_id();
+        if old_peer_id.to_base58() != self.old_peer_id
+            || new_peer_id.to_base58() != self.new_peer_id
+        {
+            return Err(format_err!(
+                "The public keys of the identity link do not match the peer ids {} and {}",
+                self.old_peer_id,
+                self.new_peer_id
+            ));
+        }
+        let message = link_message(&old_peer_id, &new_peer_id);
+        if !old_public_key.verify(&message, &self.old_signature) {
+            return Err(format_err!(
+                "Invalid signature from the old identity {}",
+                old_peer_id
+            ));
+        }
+        if !new_public_key.verify(&message, &self.new_signature) {
+            return Err(format_err!(
+                "Invalid signature from the new identity {}",
+                new_peer_id
+            ));
+        }
+        Ok((old_peer_id, new_peer_id))
+    }
+}
+
+/// Append the link to the list of links stored in `node_dir`, creating the list if needed
+pub(crate) fn save_identity_link(node_dir: &Path, link: IdentityLink) -> Result<()> {
+    let links_path: PathBuf = [node_dir, Path::new(IDENTITY_LINKS_FILE_NAME)]
+        .iter()
+        .collect();
+    let mut links: Vec<IdentityLink> = match sfs::read(&links_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(_) => vec![],
+    };
+    links.push(link);
+    sfs::write(links_path, serde_json::to_vec_pretty(&links)?)?;
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! The commands run in the background: encode-file, get-file and send-block-list with `background=true`.
+//!
+//! Such a command returns the id of its job right away, the id being the operation id of the command, see
+//! [`crate::progress`]. Its outcome is kept by the [`JobManager`], and the tasks of the node doing its work can be
+//! aborted to cancel it. Like for the progress, a finished job is kept until [`MAX_FINISHED_JOBS`] newer ones
+//! finished.
+
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json, Response};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::app::AppState;
+use crate::error::DragoonError;
+use crate::to_serialize::Envelope;
+
+/// Number of finished jobs whose outcome is kept
+const MAX_FINISHED_JOBS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JobStatus {
+    Running,
+    Done,
+    Failed,
+    Canceled,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct JobInfo {
+    pub(crate) job_id: String,
+    pub(crate) command: String,
+    pub(crate) status: JobStatus,
+    /// What the command would have returned, once it is done
+    pub(crate) result: Option<Value>,
+    /// The error the command would have returned, once it failed
+    pub(crate) error: Option<Value>,
+}
+
+#[derive(Debug)]
+struct Job {
+    info: JobInfo,
+    /// The tasks doing the work of the command, aborted when the job is canceled
+    tasks: Vec<AbortHandle>,
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    by_id: HashMap<String, Job>,
+    /// The finished jobs, oldest first
+    finished: VecDeque<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct JobManager {
+    jobs: Mutex<Jobs>,
+}
+
+impl JobManager {
+    /// Register a job before its command is sent, fails if a job with the same id is still running
+    pub(crate) fn start(&self, job_id: &str, command: &str) -> Result<(), DragoonError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs
+            .by_id
+            .get(job_id)
+            .is_some_and(|job| job.info.status == JobStatus::Running)
+        {
+            return Err(DragoonError::JobExists(job_id.to_string()));
+        }
+        jobs.finished.retain(|id| id != job_id);
+        jobs.by_id.insert(
+            job_id.to_string(),
+            Job {
+                info: JobInfo {
+                    job_id: job_id.to_string(),
+                    command: command.to_string(),
+                    status: JobStatus::Running,
+                    result: None,
+                    error: None,
+                },
+                tasks: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    /// Called with the task spawned by the node to run the command of the operation, if it is a running job
+    pub(crate) fn attach(&self, job_id: &str, task: AbortHandle) {
+        if let Some(job) = self.jobs.lock().unwrap().by_id.get_mut(job_id) {
+            if job.info.status == JobStatus::Running {
+                job.tasks.push(task);
+            }
+        }
+    }
+
+    /// Keep the response the command would have given, unless the job was canceled in the meantime
+    pub(crate) async fn complete(&self, job_id: &str, response: Response) {
+        let success = response.status().is_success();
+        let mut body: Value = match axum::body::to_bytes(response.into_body(), usize::MAX).await {
+            Ok(body) => serde_json::from_slice(&body).unwrap_or(Value::Null),
+            Err(e) => {
+                warn!("Could not read the outcome of the job {}: {}", job_id, e);
+                Value::Null
+            }
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.by_id.get_mut(job_id) else {
+            return;
+        };
+        if job.info.status != JobStatus::Running {
+            return;
+        }
+        // the data or the error of the envelope of the response
+        if success {
+            job.info.status = JobStatus::Done;
+            job.info.result = Some(body["data"].take());
+        } else {
+            job.info.status = JobStatus::Failed;
+            job.info.error = Some(body["error"].take());
+        }
+        job.tasks.clear();
+        Self::finished(&mut jobs, job_id);
+    }
+
+    pub(crate) fn get(&self, job_id: &str) -> Option<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .by_id
+            .get(job_id)
+            .map(|job| job.info.clone())
+    }
+
+    /// Abort the tasks of the job, returns the job as it is once canceled.
+    /// A job that already finished is left as is.
+    pub(crate) fn cancel(&self, job_id: &str) -> Option<JobInfo> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.by_id.get_mut(job_id)?;
+        if job.info.status == JobStatus::Running {
+            info!(
+                "Canceling the job {}, aborting {} tasks",
+                job_id,
+                job.tasks.len()
+            );
+            for task in job.tasks.drain(..) {
+                task.abort();
+            }
+            job.info.status = JobStatus::Canceled;
+            let info = job.info.clone();
+            Self::finished(&mut jobs, job_id);
+            Some(info)
+        } else {
+            Some(job.info.clone())
+        }
+    }
+
+    fn finished(jobs: &mut Jobs, job_id: &str) {
+        jobs.finished.push_back(job_id.to_string());
+        while jobs.finished.len() > MAX_FINISHED_JOBS {
+            if let Some(oldest) = jobs.finished.pop_front() {
+                jobs.by_id.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/job/{job_id}",
+    params(("job_id" = String, Path, description = "Id of the job")),
+    responses((status = 200, description = "The state of the job", body = Envelope<JobInfo>))
+)]
+pub(crate) async fn get_job(
+    Path(job_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match state.jobs.get(&job_id) {
+        Some(job) => Json(Envelope::ok(job)).into_response(),
+        None => DragoonError::UnknownJob(job_id).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/job/{job_id}",
+    params(("job_id" = String, Path, description = "Id of the job")),
+    responses((status = 200, description = "The state of the canceled job", body = Envelope<JobInfo>))
+)]
+pub(crate) async fn cancel_job(
+    Path(job_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let Some(job) = state.jobs.get(&job_id) else {
+        return DragoonError::UnknownJob(job_id).into_response();
+    };
+    if job.status == JobStatus::Running {
+        // before the tasks are aborted, so the progress does not show them as failed
+        state.progress.cancel(&job_id);
+    }
+    Json(Envelope::ok(state.jobs.cancel(&job_id).unwrap_or(job))).into_response()
+}
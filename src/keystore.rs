@@ -0,0 +1,169 @@
+//! Persistent identity of the node: the keypair is generated on the first run and stored in a file,
+//! optionally encrypted with a passphrase, so the node keeps the same peer id across restarts
+
+use anyhow::{format_err, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use libp2p::identity::Keypair;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs as sfs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// The environment variable holding the passphrase of the identity file, the file is not encrypted when it is not set
+pub(crate) const IDENTITY_PASSPHRASE_ENV: &str = "DRAGOONFLY_IDENTITY_PASSPHRASE";
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+/// The content of the identity file, the keypair is protobuf encoded
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "kebab-case")]
+enum IdentityFile {
+    Plain {
+        keypair: Vec<u8>,
+    },
+    Encrypted {
+        salt: Vec<u8>,
+        nonce: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Keystore {
+    path: PathBuf,
+    passphrase: Option<String>,
+}
+
+impl Keystore {
+    pub(crate) fn new(path: PathBuf, passphrase: Option<String>) -> Self {
+        Keystore { path, passphrase }
+    }
+
+    /// The identity file in the storage directory of the node
+    pub(crate) fn default_path(storage_dir: &Path) -> PathBuf {
+        storage_dir.join("identity.key")
+    }
+
+    /// Load the keypair of the identity file, or generate and save a new one if there is no file yet.
+    /// With `new_identity`, a new keypair replaces the one of the file, which is kept next to it as a `.old` file.
+    pub(crate) fn load_or_generate(&self, new_identity: bool) -> Result<Keypair> {
+        if self.path.exists() {
+            if !new_identity {
+                info!("Loading the identity of the node from {:?}", self.path);
+                return self.load();
+            }
+            let mut old_path = self.path.clone();
+            old_path.set_extension("old");
+            warn!(
+                "Replacing the identity in {:?}, the previous one is kept in {:?}",
+                self.path, old_path
+            );
+            sfs::rename(&self.path, old_path)?;
+        }
+        info!("Generating a new identity in {:?}", self.path);
+        let keypair = Keypair::generate_ed25519();
+        self.save(&keypair)?;
+        Ok(keypair)
+    }
+
+    fn load(&self) -> Result<Keypair> {
+        let identity_file: IdentityFile = serde_json::from_slice(&sfs::read(&self.path)?)?;
+        let encoded_keypair = match identity_file {
+            IdentityFile::Plain { keypair } => keypair,
+            IdentityFile::Encrypted {
+                salt,
+                nonce,
+                ciphertext,
+            } => {
+                let passphrase = self.passphrase.as_ref().ok_or(format_err!(
+                    "The identity file {:?} is encrypted, its passphrase must be given in {}",
+                    self.path,
+                    IDENTITY_PASSPHRASE_ENV
+                ))?;
+                if nonce.len() != NONCE_SIZE {
+                    return Err(format_err!(
+                        "The identity file {:?} has a nonce of {} bytes instead of {}",
+                        self.path,
+                        nonce.len(),
+                        NONCE_SIZE
+                    ));
+                }
+                cipher(passphrase, &salt)?
+                    .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+                    .map_err(|_| {
+                        format_err!(
+                            "Could not decrypt the identity file {:?}, wrong passphrase?",
+                            self.path
+                        )
+                    })?
+            }
+        };
+        Ok(Keypair::from_protobuf_encoding(&encoded_keypair)?)
+    }
+
+    /// Write the keypair to the identity file, only readable by the user running the node
+    pub(crate) fn save(&self, keypair: &Keypair) -> Result<()> {
+        let encoded_keypair = keypair.to_protobuf_encoding()?;
+        let identity_file = match &self.passphrase {
+            None => IdentityFile::Plain {
+                keypair: encoded_keypair,
+            },
+            Some(passphrase) => {
+                let mut salt = [0u8; SALT_SIZE];
+                let mut nonce = [0u8; NONCE_SIZE];
+                rand::thread_rng().fill_bytes(&mut salt);
+                rand::thread_rng().fill_bytes(&mut nonce);
+                let ciphertext = cipher(passphrase, &salt)?
+                    .encrypt(Nonce::from_slice(&nonce), encoded_keypair.as_slice())
+                    .map_err(|e| format_err!("Could not encrypt the identity: {}", e))?;
+                IdentityFile::Encrypted {
+                    salt: salt.to_vec(),
+                    nonce: nonce.to_vec(),
+                    ciphertext,
+                }
+            }
+        };
+        if let Some(parent) = self.path.parent() {
+            sfs::create_dir_all(parent)?;
+        }
+        let mut tmp_path = self.path.clone();
+        tmp_path.set_extension("tmp");
+        write_private(&tmp_path, &serde_json::to_vec(&identity_file)?)?;
+        sfs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Derive the key of the identity file from the passphrase
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format_err!("Could not derive the key of the identity file: {}", e))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(unix)]
+pub(crate) fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = sfs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    sfs::write(path, content)?;
+    Ok(())
+}
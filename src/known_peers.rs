@@ -0,0 +1,126 @@
+//! The peers known by the node, kept in `known_peers.json` with the addresses they were last seen on, so a
+//! restarted node can send blocks to them and dial them again right away.
+//!
+//! A peer is known once it told us its listen addresses, by identify or by peer exchange. The file is
+//! written by the loop of the network every 30 seconds when the peers changed, and when the node stops.
+
+use anyhow::Result;
+use chrono::Utc;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, warn};
+
+const KNOWN_PEERS_FILE_NAME: &str = "known_peers.json";
+/// The file is not written again when a peer is seen on the same addresses within this many seconds
+const LAST_SEEN_PRECISION_SECS: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct KnownPeer {
+    addrs: Vec<Multiaddr>,
+    /// Unix time in seconds of the last time the peer told us its addresses
+    last_seen: i64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct KnownPeers {
+    peers: HashMap<PeerId, KnownPeer>,
+    /// Changed since the last save
+    changed: bool,
+}
+
+impl KnownPeers {
+    /// Read the peers known before the node stopped, none if there are no such peers or they cannot be read
+    pub(crate) fn load(file_dir: &Path) -> Self {
+        let path = file_dir.join(KNOWN_PEERS_FILE_NAME);
+        let saved: BTreeMap<String, KnownPeer> = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Could not parse the known peers of {:?}: {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        let peers: HashMap<PeerId, KnownPeer> = saved
+            .into_iter()
+            .filter_map(|(peer_id_base_58, peer)| {
+                let bytes = bs58::decode(&peer_id_base_58).into_vec().ok()?;
+                Some((PeerId::from_bytes(&bytes).ok()?, peer))
+            })
+            .collect();
+        if !peers.is_empty() {
+            info!("{} peers were known before the node stopped", peers.len());
+        }
+        KnownPeers {
+            peers,
+            changed: false,
+        }
+    }
+
+    pub(crate) fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains_key(peer_id)
+    }
+
+    pub(crate) fn ids(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.keys()
+    }
+
+    /// The known peers with their addresses, the ones seen last first
+    pub(crate) fn last_seen(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let mut peers: Vec<_> = self.peers.iter().collect();
+        peers.sort_by_key(|(_, peer)| std::cmp::Reverse(peer.last_seen));
+        peers
+            .into_iter()
+            .map(|(peer_id, peer)| (*peer_id, peer.addrs.clone()))
+            .collect()
+    }
+
+    /// Add the peer or replace its addresses
+    pub(crate) fn insert(&mut self, peer_id: PeerId, addrs: Vec<Multiaddr>) {
+        let peer = KnownPeer {
+            addrs,
+            last_seen: Utc::now().timestamp(),
+        };
+        let unchanged = self.peers.get(&peer_id).is_some_and(|known| {
+            known.addrs == peer.addrs && peer.last_seen - known.last_seen < LAST_SEEN_PRECISION_SECS
+        });
+        if !unchanged {
+            self.peers.insert(peer_id, peer);
+            self.changed = true;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, peer_id: &PeerId) {
+        if self.peers.remove(peer_id).is_some() {
+            self.changed = true;
+        }
+    }
+
+    /// Write the peers if they changed since the last save, failing to save them only means they are not known
+    /// after a restart
+    pub(crate) async fn save(&mut self, file_dir: &Path) {
+        if !self.changed {
+            return;
+        }
+        match self.write(file_dir).await {
+            Ok(()) => self.changed = false,
+            Err(e) => warn!("Could not save the known peers in {:?}: {}", file_dir, e),
+        }
+    }
+
+    async fn write(&self, file_dir: &Path) -> Result<()> {
+        let path = file_dir.join(KNOWN_PEERS_FILE_NAME);
+        let saved: BTreeMap<String, &KnownPeer> = self
+            .peers
+            .iter()
+            .map(|(peer_id, peer)| (peer_id.to_base58(), peer))
+            .collect();
+        // written next to the file then renamed, so a crash never leaves a partial list
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&saved)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
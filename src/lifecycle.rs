@@ -0,0 +1,182 @@
+//! Lifecycle of a file across the commands and the nodes.
+//!
+//! A correlation id is drawn when a file is encoded, and stored next to its blocks in `FILE_HASH/lifecycle_id`.
+//! It travels with the blocks of the file, in the peer block info of the send and info exchanges and in the
+//! block responses, and the nodes that receive it keep it for the same file. Each node appends the events
+//! it takes part in to `FILE_HASH/lifecycle.jsonl`, which is read back to rebuild the local timeline of the file.
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+const LIFECYCLE_ID_FILE_NAME: &str = "lifecycle_id";
+const LIFECYCLE_EVENTS_FILE_NAME: &str = "lifecycle.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LifecycleStage {
+    Encoded,
+    Provided,
+    BlockSent,
+    BlockReceived,
+    InfoServed,
+    BlockServed,
+    BlockFetched,
+    Decoded,
+}
+
+impl std::fmt::Display for LifecycleStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LifecycleStage::Encoded => write!(f, "encoded"),
+            LifecycleStage::Provided => write!(f, "provided"),
+            LifecycleStage::BlockSent => write!(f, "block sent"),
+            LifecycleStage::BlockReceived => write!(f, "block received"),
+            LifecycleStage::InfoServed => write!(f, "info served"),
+            LifecycleStage::BlockServed => write!(f, "block served"),
+            LifecycleStage::BlockFetched => write!(f, "block fetched"),
+            LifecycleStage::Decoded => write!(f, "decoded"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub(crate) struct LifecycleEvent {
+    pub(crate) timestamp: String,
+    pub(crate) stage: LifecycleStage,
+    pub(crate) correlation_id: Option<String>,
+    /// The other peer taking part in the event, if any
+    pub(crate) peer_id: Option<String>,
+    pub(crate) block_hash: Option<String>,
+}
+
+/// The local timeline of a file
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct FileLifecycle {
+    pub(crate) file_hash: String,
+    pub(crate) correlation_id: Option<String>,
+    pub(crate) events: Vec<LifecycleEvent>,
+}
+
+/// The correlation id of the file whose directory is `hash_dir`, if it has one
+pub(crate) async fn correlation_id(hash_dir: &Path) -> Option<String> {
+    fs::read_to_string(hash_dir.join(LIFECYCLE_ID_FILE_NAME))
+        .await
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// Draw the correlation id of a newly encoded file, a file encoded again keeps its id
+pub(crate) async fn start(hash_dir: &Path) -> Result<String> {
+    if let Some(id) = correlation_id(hash_dir).await {
+        return Ok(id);
+    }
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    fs::create_dir_all(hash_dir).await?;
+    fs::write(hash_dir.join(LIFECYCLE_ID_FILE_NAME), &id).await?;
+    Ok(id)
+}
+
+/// Keep the correlation id received from another node, unless the file already has one
+pub(crate) async fn adopt(hash_dir: &Path, id: Option<&str>) {
+    let Some(id) = id else {
+        return;
+    };
+    match correlation_id(hash_dir).await {
+        Some(own_id) if own_id != id => debug!(
+            "Keeping the correlation id {} of {:?} instead of {}",
+            own_id, hash_dir, id
+        ),
+        Some(_) => {}
+        None => {
+            if let Err(e) = fs::write(hash_dir.join(LIFECYCLE_ID_FILE_NAME), id).await {
+                warn!("Could not keep the correlation id of {:?}: {}", hash_dir, e);
+            }
+        }
+    }
+}
+
+/// Append an event to the timeline of the file, failing to do so never fails the command that triggered it
+pub(crate) async fn record(
+    hash_dir: &Path,
+    file_hash: &str,
+    stage: LifecycleStage,
+    peer_id: Option<String>,
+    block_hash: Option<String>,
+) {
+    let event = LifecycleEvent {
+        timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+        stage,
+        correlation_id: correlation_id(hash_dir).await,
+        peer_id,
+        block_hash,
+    };
+    info!(
+        "[lifecycle {}] {}: {}{}{}",
+        event.correlation_id.as_deref().unwrap_or("-"),
+        file_hash,
+        stage,
+        event
+            .block_hash
+            .as_ref()
+            .map(|block_hash| format!(" {}", block_hash))
+            .unwrap_or_default(),
+        event
+            .peer_id
+            .as_ref()
+            .map(|peer_id| format!(" with {}", peer_id))
+            .unwrap_or_default(),
+    );
+    if let Err(e) = append(hash_dir, &event).await {
+        warn!(
+            "Could not record the {} event of {}: {}",
+            stage, file_hash, e
+        );
+    }
+}
+
+async fn append(hash_dir: &Path, event: &LifecycleEvent) -> Result<()> {
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    fs::create_dir_all(hash_dir).await?;
+    let mut events = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(hash_dir.join(LIFECYCLE_EVENTS_FILE_NAME))
+        .await?;
+    // a single write per event, so the events appended at the same time are not interleaved
+    events.write_all(&line).await?;
+    Ok(())
+}
+
+/// Read back the timeline of the file, oldest event first
+pub(crate) async fn timeline(hash_dir: &Path, file_hash: String) -> Result<FileLifecycle> {
+    let events = fs::read_to_string(hash_dir.join(LIFECYCLE_EVENTS_FILE_NAME))
+        .await
+        .unwrap_or_default();
+    let mut events: Vec<LifecycleEvent> = events
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                warn!("Skipping the lifecycle event {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect();
+    events.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    Ok(FileLifecycle {
+        file_hash,
+        correlation_id: correlation_id(hash_dir).await,
+        events,
+    })
+}
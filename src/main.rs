@@ -1,56 +1,468 @@
 mod app;
+mod archive;
+mod bandwidth;
+mod banned_peers;
+mod block_format;
+mod block_quota;
+mod block_repair;
+mod block_scheduler;
+mod block_serving;
+mod block_store;
 mod commands;
+mod compression;
+mod config;
+mod control_socket;
+mod curve;
+mod decode_diagnostics;
+mod delegate_get_file;
+mod dht_store;
+mod dns;
 mod dragoon_swarm;
 mod error;
+mod file_announce;
+mod file_encryption;
+mod file_locks;
+mod file_metadata;
+mod grpc;
+mod http_auth;
+mod http_limits;
+mod identity_rotation;
+mod jobs;
+mod keystore;
+mod known_peers;
+mod lifecycle;
+mod manifest;
+mod metrics;
+mod mirror;
+mod openapi;
+mod packing;
 mod peer_block_info;
+mod peer_exchange;
+mod peer_probe;
+mod peer_quota;
+mod peer_reputation;
+mod pending;
+mod powers_cache;
+mod powers_file;
+mod progress;
+mod provided_keys;
+mod recent_events;
+mod replication;
+mod row_echelon;
+mod scrub;
 mod send_block_to;
 mod send_strategy;
 mod send_strategy_impl;
+mod setup;
+mod shutdown;
+mod simulation;
+mod stats;
+mod storage_class;
+mod systematic;
 mod to_serialize;
+mod wire_codec;
 
-use axum::routing::{get, post};
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
+use axum::routing::{delete, get, post};
 use axum::Router;
-use clap::Parser;
+use axum_server::tls_rustls::RustlsConfig;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use libp2p::identity;
 use libp2p::identity::Keypair;
+use libp2p::PeerId;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
 };
 use tokio::signal;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time;
+use tracing::{error, info, warn};
 
 use anyhow::Result;
 
-use ark_bls12_381::{Fr, G1Projective};
-use ark_poly::univariate::DensePolynomial;
+use crate::bandwidth::Bandwidth;
+use crate::block_quota::BlockLimits;
+use crate::block_repair::RepairConfig;
+use crate::block_serving::BlockServingLimits;
+use crate::block_store::{BlockStore, STORAGE_KEY_FILE_NAME};
+use crate::config::NodeConfig;
+use crate::curve::Curve;
+use crate::delegate_get_file::DelegationConfig;
+use crate::dns::{DnsConfig, DnsFamily};
+use crate::dragoon_swarm::{CommandTimeouts, DragoonNetwork, Transport};
+use crate::file_encryption::FILE_KEY_FILE_NAME;
+use crate::http_auth::HttpAuth;
+use crate::http_limits::{HttpLimits, HttpLimitsConfig};
+use crate::jobs::JobManager;
+use crate::keystore::Keystore;
+use crate::metrics::Metrics;
+use crate::mirror::MirrorConfig;
+use crate::powers_cache::PowersCache;
+use crate::progress::ProgressRegistry;
+use crate::send_block_to::{BlockExpiryConfig, SendRetryPolicy};
+use crate::shutdown::InFlight;
+use crate::storage_class::{StorageClass, StorageClasses};
 
-use crate::dragoon_swarm::DragoonNetwork;
+/// Time given to the network to save its state when the node stops
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Parser)]
 #[command(name = "Dragoonfly")]
 #[command(version = "1.0")]
 #[command(about = "A Provable Coded P2P System", long_about = None)]
 struct Cli {
-    #[arg(long, short)]
-    powers_path: PathBuf,
+    #[arg(
+        long,
+        help = "TOML file giving the options of the node, the options given on the command line take precedence"
+    )]
+    config: Option<PathBuf>,
+    #[arg(long, short, required_unless_present_any = ["simulate", "config"])]
+    powers_path: Option<PathBuf>,
     #[arg(long, short, default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3000))]
     ip_port: SocketAddr,
-    #[arg(long, short, default_value_t = 0)]
-    seed: u8,
+    #[arg(
+        long,
+        short,
+        conflicts_with_all = ["identity_file", "new_identity"],
+        help = "Derive the keypair of the node from this seed instead of using an identity file, for tests only"
+    )]
+    seed: Option<u8>,
+    #[arg(
+        long,
+        help = "File storing the keypair of the node, generated on the first run [default: STORAGE_DIR/identity.key]"
+    )]
+    identity_file: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Directory holding the files of the node, in a sub-directory named after its peer id [default: ~/.share/dragoonfly]"
+    )]
+    storage_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Replace the keypair of the identity file with a new one"
+    )]
+    new_identity: bool,
     #[arg(long, default_value_t = 20)]
     storage_space: usize,
     #[arg(long, default_value_t = Units::G, help = "Standard power of 10 notation")]
     storage_unit: Units,
     #[arg(long, default_value_t = false)]
     replace_file_dir: bool,
+    #[arg(
+        long,
+        help = "Multiaddr of a peer, ending with its peer id, dialed and added to the routing table before bootstrapping the DHT when the node starts, can be repeated"
+    )]
+    bootstrap_peer: Vec<String>,
+    #[arg(
+        long,
+        help = "Multiaddr the node listens on, bound before the node starts, can be repeated"
+    )]
+    listen: Vec<String>,
     #[arg(short, long)]
     label: Option<String>,
+    #[arg(
+        long,
+        default_value = "dragoonfly",
+        help = "Name of the network the node announces its encoded files to, only the nodes with the same name receive them"
+    )]
+    network_name: String,
+    #[arg(
+        long,
+        help = "Address of the gRPC interface, served alongside the http interface, not served if not given"
+    )]
+    grpc_ip_port: Option<SocketAddr>,
+    #[arg(
+        long,
+        help = "PEM file of the certificate chain to serve the http interface over TLS with, along with --tls-key, served in cleartext if not given"
+    )]
+    tls_cert: Option<PathBuf>,
+    #[arg(long, help = "PEM file of the private key of the TLS certificate")]
+    tls_key: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Unix socket the http interface is also served on, for the tools running on the same machine"
+    )]
+    control_socket: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Serve the http interface only on --control-socket, not on --ip-port"
+    )]
+    control_socket_only: bool,
+    #[arg(
+        long,
+        default_value_t = 2_000_000,
+        help = "Maximum size in bytes of the body of an http request"
+    )]
+    http_max_body_size: usize,
+    #[arg(
+        long,
+        default_value_t = 10_000_000_000,
+        help = "Maximum size in bytes of the file sent to /upload-and-encode, which is streamed to disk"
+    )]
+    http_upload_max_body_size: usize,
+    #[arg(long, value_parser = http_limits::parse_route_body_size, help = "Maximum size in bytes of the body of an http request for a given route, as ROUTE=BYTES, can be repeated")]
+    http_route_max_body_size: Vec<(String, usize)>,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Number of http requests allowed per second for each client ip, 0 means no limit"
+    )]
+    http_rate_limit: u32,
+    #[arg(
+        long,
+        default_value_t = 20,
+        help = "Number of http requests a client can send in a burst before being rate limited"
+    )]
+    http_rate_burst: u32,
+    #[arg(
+        long,
+        default_value_t = 300,
+        help = "Maximum duration of an http request in seconds, 0 means no timeout"
+    )]
+    http_request_timeout: u64,
+    #[arg(
+        long,
+        help = "Bearer token allowed on the routes of the http interface that change the state of the node, can be repeated"
+    )]
+    auth_token: Vec<String>,
+    #[arg(
+        long,
+        help = "File of bearer tokens allowed on the routes of the http interface that change the state of the node, one per line"
+    )]
+    auth_tokens_file: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["auth_token", "auth_tokens_file"],
+        help = "Leave all the routes of the http interface open, for local tests only"
+    )]
+    no_auth: bool,
+    #[arg(
+        long,
+        help = "Peer id allowed to ask this node to retrieve files on its behalf, can be repeated"
+    )]
+    delegation_allowed_peer: Vec<PeerId>,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Allow all the peers to ask this node to retrieve files on their behalf"
+    )]
+    delegation_allow_all: bool,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Encrypt the blocks written on disk with the storage key of the node"
+    )]
+    encrypt_blocks: bool,
+    #[arg(
+        long,
+        requires = "encrypt_blocks",
+        help = "File holding the key the blocks are encrypted with, generated on the first run [default: STORAGE_DIR/storage.key]"
+    )]
+    storage_key_file: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "File holding the key the files encoded with `encrypt` are encrypted with, generated on the first use [default: STORAGE_DIR/file.key]"
+    )]
+    file_key_file: Option<PathBuf>,
+    #[arg(long, value_parser = storage_class::parse_storage_class_path, help = "Directory where to place the received blocks of a storage class (ssd or hdd), as CLASS=PATH, can be repeated")]
+    storage_class_path: Vec<(StorageClass, PathBuf)>,
+    #[arg(long, help = "Maximum number of blocks stored by the node")]
+    max_blocks: Option<u64>,
+    #[arg(
+        long,
+        help = "Maximum number of blocks stored by the node for a single file"
+    )]
+    max_blocks_per_file: Option<u64>,
+    #[arg(
+        long,
+        help = "Storage a single peer can use for the blocks it sends to the node, in --storage-unit, unlimited if not given"
+    )]
+    peer_quota: Option<usize>,
+    #[arg(
+        long,
+        help = "Peer id of the follower this node streams its manifest and accounting to"
+    )]
+    mirror_to: Option<PeerId>,
+    #[arg(
+        long,
+        help = "Peer id of the primary this node receives the manifest and accounting of"
+    )]
+    mirror_from: Option<PeerId>,
+    #[arg(
+        long,
+        help = "Run the send strategies on the virtual peers described by this JSON file, print the results and exit"
+    )]
+    simulate: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Write the powers of --powers-path for --curve to this file with a header giving their curve and degree, then exit"
+    )]
+    convert_powers: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Check the powers of this ptau file of a powers-of-tau ceremony and write them to --powers-path for --curve, then exit"
+    )]
+    import_ptau: Option<PathBuf>,
+    #[arg(
+        long,
+        requires = "import_ptau",
+        help = "Maximum degree of the powers imported from the ptau file, all its powers by default"
+    )]
+    ptau_max_degree: Option<usize>,
+    #[arg(
+        long,
+        value_enum,
+        default_values_t = [Transport::Tcp],
+        help = "Transport the node listens and dials on, can be repeated"
+    )]
+    transport: Vec<Transport>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Curve::Bls12_381,
+        help = "Curve the blocks are encoded, proven and verified over, the powers must be made for it"
+    )]
+    curve: Curve,
+    #[arg(
+        long,
+        default_value_t = 5000,
+        help = "Time in milliseconds given to the resolution of the name of a multiaddr before dialing it"
+    )]
+    dns_timeout: u64,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnsFamily::Any,
+        help = "Address family dialed first when the name of a /dns multiaddr resolves to both"
+    )]
+    dns_prefer: DnsFamily,
+    #[arg(
+        long,
+        default_value_t = 60,
+        help = "Time in seconds the resolved addresses of a name are reused, 0 disables the cache"
+    )]
+    dns_cache_ttl: u64,
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "Time in seconds given to a get-file to receive enough blocks to decode the file"
+    )]
+    get_file_timeout: u64,
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "Time in seconds given to the first loop of a send-block-list, before the rejected blocks are sent again"
+    )]
+    send_block_list_timeout: u64,
+    #[arg(
+        long,
+        help = "Time in seconds the blocks received through send requests are kept when their sender did not give one, forever if not given"
+    )]
+    block_ttl: Option<u64>,
+    #[arg(
+        long,
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Time in seconds between two deletions of the expired blocks"
+    )]
+    gc_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 3600,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Time in seconds between two announcements of the keys provided by the node, so their provider records do not expire"
+    )]
+    provide_refresh_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 300,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Time in seconds between two snapshots of the DHT records and routing table, which are also saved when the node stops"
+    )]
+    dht_snapshot_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 3600,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Time in seconds between two checks of the number of blocks left in the network for each file encoded by the node"
+    )]
+    repair_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 1.5,
+        value_parser = block_repair::parse_threshold,
+        help = "Number of distinct blocks of a file to keep on the other peers, as a multiple of its k, below which new blocks are recoded and sent"
+    )]
+    repair_threshold: f64,
+    #[arg(
+        long,
+        default_value_t = 86400,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Time in seconds between two checks of the integrity of the stored blocks, the corrupted ones being quarantined"
+    )]
+    scrub_interval: u64,
+    #[arg(
+        long,
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of times a block is sent to a peer before giving up, when the stream could not be opened or the exchange failed"
+    )]
+    send_retry_max_attempts: u32,
+    #[arg(
+        long,
+        default_value_t = 500,
+        help = "Time in milliseconds to wait before sending a block again, doubled after each failed attempt"
+    )]
+    send_retry_backoff: u64,
+    #[arg(
+        long,
+        default_value_t = 10_000,
+        help = "Longest time in milliseconds to wait between two attempts to send a block"
+    )]
+    send_retry_max_backoff: u64,
+    #[arg(
+        long,
+        default_value_t = 0.2,
+        value_parser = send_block_to::parse_jitter,
+        help = "Fraction of the wait between two attempts to send a block by which it is randomly changed, between 0 and 1"
+    )]
+    send_retry_jitter: f64,
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "Longest time in seconds to wait for the blocks being sent or received when the node stops"
+    )]
+    shutdown_timeout: u64,
+    #[arg(
+        long,
+        default_value_t = 32,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of block requests of the other peers read and answered at the same time"
+    )]
+    max_served_block_requests: u64,
+    #[arg(
+        long,
+        default_value_t = 256,
+        help = "Number of block requests of the other peers waiting for their turn, the next ones are answered that the node is busy"
+    )]
+    max_queued_block_requests: u64,
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of recent events of the node kept in memory for /recent-events, the oldest ones being dropped"
+    )]
+    recent_events: u64,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, clap::ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, clap::ValueEnum, serde::Deserialize)]
 #[clap(rename_all = "UPPER")]
 enum Units {
     B,
@@ -72,27 +484,225 @@ pub(crate) async fn main() -> Result<()> {
 
     let (cmd_sender, cmd_receiver) = mpsc::unbounded_channel();
 
-    let router = Router::new()
-        .route("/listen/{multiaddr}", get(commands::create_cmd_listen))
+    info!("Parsing the command line arguments");
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    if let Some(config_path) = cli.simulate {
+        let reports = simulation::run(&config_path)?;
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+        return Ok(());
+    }
+
+    let config = match &cli.config {
+        Some(path) => NodeConfig::load(path).await?,
+        None => NodeConfig::default(),
+    };
+    cli.powers_path = cli.powers_path.or(config.powers_path);
+    if let (Some(output), Some(powers_path)) = (&cli.convert_powers, &cli.powers_path) {
+        return cli.curve.convert_powers(powers_path, output).await;
+    }
+    if let (Some(ptau_path), Some(powers_path)) = (&cli.import_ptau, &cli.powers_path) {
+        return setup::from_ptau(ptau_path, powers_path, cli.curve, cli.ptau_max_degree).await;
+    }
+    cli.label = cli.label.or(config.label);
+    cli.storage_dir = cli.storage_dir.or(config.storage_dir);
+    cli.network_name = config::merge(
+        &matches,
+        "network_name",
+        cli.network_name,
+        config.network_name,
+    );
+    cli.bootstrap_peer = config::merge(
+        &matches,
+        "bootstrap_peer",
+        cli.bootstrap_peer,
+        Some(config.bootstrap_peer),
+    );
+    cli.listen = config::merge(&matches, "listen", cli.listen, Some(config.listen));
+    cli.ip_port = config::merge(&matches, "ip_port", cli.ip_port, config.ip_port);
+    cli.grpc_ip_port = cli.grpc_ip_port.or(config.grpc_ip_port);
+    cli.tls_cert = cli.tls_cert.or(config.tls_cert);
+    cli.tls_key = cli.tls_key.or(config.tls_key);
+    cli.control_socket = cli.control_socket.or(config.control_socket);
+    cli.control_socket_only = cli.control_socket_only || config.control_socket_only;
+    cli.storage_space = config::merge(
+        &matches,
+        "storage_space",
+        cli.storage_space,
+        config.storage_space,
+    );
+    cli.storage_unit = config::merge(
+        &matches,
+        "storage_unit",
+        cli.storage_unit,
+        config.storage_unit,
+    );
+    cli.http_max_body_size = config::merge(
+        &matches,
+        "http_max_body_size",
+        cli.http_max_body_size,
+        config.http_max_body_size,
+    );
+    cli.http_upload_max_body_size = config::merge(
+        &matches,
+        "http_upload_max_body_size",
+        cli.http_upload_max_body_size,
+        config.http_upload_max_body_size,
+    );
+    // a route given on the command line overrides the same route in the file
+    cli.http_route_max_body_size = config
+        .http_route_max_body_size
+        .into_iter()
+        .map(|(route, size)| (route.trim_matches('/').to_string(), size))
+        .chain(cli.http_route_max_body_size)
+        .collect();
+    cli.http_rate_limit = config::merge(
+        &matches,
+        "http_rate_limit",
+        cli.http_rate_limit,
+        config.http_rate_limit,
+    );
+    cli.http_rate_burst = config::merge(
+        &matches,
+        "http_rate_burst",
+        cli.http_rate_burst,
+        config.http_rate_burst,
+    );
+    cli.http_request_timeout = config::merge(
+        &matches,
+        "http_request_timeout",
+        cli.http_request_timeout,
+        config.http_request_timeout,
+    );
+    cli.dns_timeout = config::merge(&matches, "dns_timeout", cli.dns_timeout, config.dns_timeout);
+    cli.get_file_timeout = config::merge(
+        &matches,
+        "get_file_timeout",
+        cli.get_file_timeout,
+        config.get_file_timeout,
+    );
+    cli.send_block_list_timeout = config::merge(
+        &matches,
+        "send_block_list_timeout",
+        cli.send_block_list_timeout,
+        config.send_block_list_timeout,
+    );
+
+    let metrics = Arc::new(Metrics::default());
+    let send_strategies = Arc::new(send_strategy_impl::registry());
+    let progress = Arc::new(ProgressRegistry::default());
+    let jobs = Arc::new(JobManager::default());
+    let in_flight = Arc::new(InFlight::default());
+    // set when the node stops, so the servers stop accepting connections
+    let (stop_sender, stop_receiver) = watch::channel(false);
+    let http_limits = Arc::new(HttpLimits::new(
+        HttpLimitsConfig {
+            max_body_size: cli.http_max_body_size,
+            upload_max_body_size: cli.http_upload_max_body_size,
+            route_max_body_size: cli.http_route_max_body_size.into_iter().collect(),
+            rate_limit: cli.http_rate_limit,
+            rate_burst: cli.http_rate_burst,
+            request_timeout: match cli.http_request_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+        },
+        metrics.clone(),
+    ));
+
+    let http_auth = if cli.no_auth {
+        warn!("The authentication of the http interface is disabled");
+        None
+    } else {
+        Some(Arc::new(
+            HttpAuth::load(
+                cli.auth_token,
+                cli.auth_tokens_file.as_deref(),
+                metrics.clone(),
+            )
+            .await?,
+        ))
+    };
+
+    // the routes that only read the state of the node are always open
+    let read_only_router = Router::new()
         .route("/get-listeners", get(commands::create_cmd_get_listeners))
         .route(
             "/get-network-info",
             get(commands::create_cmd_get_network_info),
         )
-        .route(
-            "/remove-listener",
-            post(commands::create_cmd_remove_listener),
-        )
         .route(
             "/get-connected-peers",
             get(commands::create_cmd_get_connected_peers),
         )
+        .route("/get-providers", post(commands::create_cmd_get_providers))
+        .route(
+            "/get-block-list/{file_hash}",
+            get(commands::create_cmd_get_block_list),
+        )
+        .route(
+            "/get-blocks-info-from/{peer_id_base_58}/{file_hash}",
+            get(commands::create_cmd_get_blocks_info_from),
+        )
+        .route(
+            "/find-block/{file_hash}/{block_hash}",
+            get(commands::create_cmd_find_block),
+        )
+        .route("/node-info", get(commands::create_cmd_node_info))
+        .route("/mirror-status", get(commands::create_cmd_mirror_status))
+        .route("/known-files", get(commands::create_cmd_known_files))
+        .route(
+            "/lifecycle/{file_hash}",
+            get(commands::create_cmd_lifecycle),
+        )
+        .route("/manifest/{file_hash}", get(commands::create_cmd_manifest))
+        .route(
+            "/probe-peer/{peer_id_base_58}/{file_hash}",
+            get(commands::create_cmd_probe_peer),
+        )
+        .route(
+            "/get-replication/{file_hash}",
+            get(commands::create_cmd_get_replication),
+        )
+        .route(
+            "/get-global-block-map/{file_hash}",
+            get(commands::create_cmd_get_global_block_map),
+        )
+        .route(
+            "/get-available-send-storage",
+            get(commands::create_cmd_get_available_storage),
+        )
+        .route("/peer-quotas", get(commands::create_cmd_get_peer_quotas))
+        .route("/bandwidth", get(commands::create_cmd_get_bandwidth))
+        .route(
+            "/recent-events",
+            get(commands::create_cmd_get_recent_events),
+        )
+        .route(
+            "/peer-score/{peer_id_base_58}",
+            get(commands::create_cmd_get_peer_score),
+        )
+        .route("/scrub-status", get(commands::create_cmd_scrub_status))
+        .route("/metrics", get(metrics::get_metrics))
+        .route("/stats", get(metrics::get_stats))
+        .route("/progress", get(progress::list_progress))
+        .route("/progress/{operation_id}", get(progress::get_progress))
+        .route("/job/{job_id}", get(jobs::get_job))
+        .route("/openapi.json", get(openapi::get_openapi));
+
+    let mutating_router = Router::new()
+        .route("/listen/{multiaddr}", get(commands::create_cmd_listen))
+        .route(
+            "/remove-listener",
+            post(commands::create_cmd_remove_listener),
+        )
         .route("/dial-single", post(commands::create_cmd_dial_single))
         .route("/dial-multiple", post(commands::create_cmd_dial_multiple))
         .route("/add-peer", post(commands::create_cmd_add_peer))
         .route("/start-provide", post(commands::create_cmd_start_provide))
         .route("/stop-provide", post(commands::create_cmd_stop_provide))
-        .route("/get-providers", post(commands::create_cmd_get_providers))
+        .route("/reannounce-all", post(commands::create_cmd_reannounce_all))
         .route("/bootstrap", get(commands::create_cmd_bootstrap))
         // .route("/dragoon/peers", get(commands::create_cmd_dragoon_peers))
         // .route(
@@ -100,47 +710,115 @@ pub(crate) async fn main() -> Result<()> {
         //     get(commands::create_cmd_dragoon_send),
         // )
         .route("/decode-blocks", post(commands::create_cmd_decode_blocks))
+        .route("/recode-blocks", post(commands::create_cmd_recode_blocks))
+        .route(
+            "/file/{file_hash}",
+            delete(commands::create_cmd_delete_file),
+        )
+        .route("/job/{job_id}", delete(jobs::cancel_job))
         .route("/encode-file", post(commands::create_cmd_encode_file))
+        .route("/encode-files", post(commands::create_cmd_encode_files))
         .route(
-            "/get-block-from/{peer_id_base_58}/{file_hash}/{block_hash}/{save_to_disk}",
-            get(commands::create_cmd_get_block_from),
+            "/append-to-file/{file_hash}",
+            post(commands::create_cmd_append_to_file),
         )
         .route(
-            "/get-file/{file_hash}/{output_filename}",
-            get(commands::create_cmd_get_file),
+            "/generate-powers",
+            post(commands::create_cmd_generate_powers),
         )
         .route(
-            "/get-block-list/{file_hash}",
-            get(commands::create_cmd_get_block_list),
+            "/upload-and-encode",
+            post(commands::create_cmd_upload_and_encode),
         )
         .route(
-            "/get-blocks-info-from/{peer_id_base_58}/{file_hash}",
-            get(commands::create_cmd_get_blocks_info_from),
+            "/get-block-from/{peer_id_base_58}/{file_hash}/{block_hash}/{save_to_disk}",
+            get(commands::create_cmd_get_block_from),
         )
-        .route("/node-info", get(commands::create_cmd_node_info))
-        .route("/send-block-to", post(commands::create_cmd_send_block_to))
         .route(
-            "/get-available-send-storage",
-            get(commands::create_cmd_get_available_storage),
+            "/get-file/{file_hash}/{output_filename}",
+            get(commands::create_cmd_get_file),
         )
+        .route("/download/{file_hash}", get(commands::create_cmd_download))
+        .route("/send-block-to", post(commands::create_cmd_send_block_to))
         .route(
             "/send-block-list",
             post(commands::create_cmd_send_block_list),
         )
+        .route(
+            "/set-replication/{file_hash}/{replication}",
+            post(commands::create_cmd_set_replication),
+        )
         .route(
             "/change-available-send-storage",
             post(commands::create_cmd_change_available_send_storage),
+        )
+        .route(
+            "/set-peer-quota/{peer_id_base_58}",
+            post(commands::create_cmd_set_peer_quota),
+        )
+        .route(
+            "/ban-peer/{peer_id_base_58}",
+            post(commands::create_cmd_ban_peer),
+        )
+        .route(
+            "/unban-peer/{peer_id_base_58}",
+            post(commands::create_cmd_unban_peer),
+        )
+        .route(
+            "/disconnect-peer/{peer_id_base_58}",
+            post(commands::create_cmd_disconnect_peer),
+        )
+        .route(
+            "/rotate-identity",
+            post(commands::create_cmd_rotate_identity),
+        )
+        .route(
+            "/pause-receiving",
+            post(commands::create_cmd_pause_receiving),
+        )
+        .route(
+            "/resume-receiving",
+            post(commands::create_cmd_resume_receiving),
+        )
+        .route(
+            "/delegate-get-file/{peer_id_base_58}/{file_hash}",
+            get(commands::create_cmd_delegate_get_file),
         );
+    let mutating_router = match http_auth.clone() {
+        Some(http_auth) => mutating_router.route_layer(middleware::from_fn_with_state(
+            http_auth,
+            http_auth::require_token,
+        )),
+        None => mutating_router,
+    };
 
-    let router = router.with_state(Arc::new(app::AppState::new(cmd_sender.clone())));
+    let router = read_only_router.merge(mutating_router);
 
-    info!("Parsing the command line arguments");
-    let cli = Cli::parse();
+    let app_state = Arc::new(app::AppState::new(
+        cmd_sender.clone(),
+        metrics.clone(),
+        send_strategies.clone(),
+        progress.clone(),
+        jobs.clone(),
+    ));
+
+    let router = router
+        .with_state(app_state.clone())
+        // the body limits are enforced per route by the middleware
+        .layer(DefaultBodyLimit::disable())
+        .layer(middleware::from_fn_with_state(
+            http_limits,
+            http_limits::limit_requests,
+        ));
 
-    let powers_path = cli.powers_path;
+    let powers_path = cli.powers_path.ok_or(anyhow::format_err!(
+        "The powers path is required, give it with --powers-path or in the configuration file"
+    ))?;
     let ip_port: SocketAddr = cli.ip_port;
-    let seed = cli.seed;
     let replace_file_dir = cli.replace_file_dir;
+    let storage_dir = cli
+        .storage_dir
+        .unwrap_or_else(dragoon_swarm::default_storage_dir);
 
     let multiplier = match cli.storage_unit {
         Units::B => 1,
@@ -150,43 +828,227 @@ pub(crate) async fn main() -> Result<()> {
         Units::T => 10usize.pow(12),
     };
     let total_available_storage_for_send = cli.storage_space * multiplier;
-    let listener = tokio::net::TcpListener::bind(ip_port).await?;
-    info!("Spawning the http server");
-    tokio::spawn(async move {
-        if let Err(error) = axum::serve(listener, router.into_make_service()).await {
-            error!("server error: {}", error);
+    let tls_config = match (cli.tls_cert, cli.tls_key) {
+        (Some(cert), Some(key)) => Some(RustlsConfig::from_pem_file(&cert, &key).await.map_err(
+            |e| {
+                anyhow::format_err!(
+                    "Could not load the TLS certificate {:?} and key {:?}: {}",
+                    cert,
+                    key,
+                    e
+                )
+            },
+        )?),
+        (None, None) => None,
+        _ => {
+            return Err(anyhow::format_err!(
+                "--tls-cert and --tls-key have to be given together"
+            ))
+        }
+    };
+    match &cli.control_socket {
+        Some(control_socket) => {
+            control_socket::serve(control_socket, router.clone(), stop_receiver.clone())?
+        }
+        None if cli.control_socket_only => {
+            return Err(anyhow::format_err!(
+                "--control-socket-only needs a --control-socket"
+            ))
         }
-    });
-    let kp = get_keypair(seed);
+        None => {}
+    }
+    if !cli.control_socket_only {
+        let listener = tokio::net::TcpListener::bind(ip_port).await?;
+        let service = router.into_make_service_with_connect_info::<SocketAddr>();
+        match tls_config {
+            Some(tls_config) => {
+                info!("Spawning the https server");
+                let listener = listener.into_std()?;
+                let handle = axum_server::Handle::new();
+                let stop = stop_receiver.clone();
+                let stop_handle = handle.clone();
+                tokio::spawn(async move {
+                    shutdown::stopping(stop).await;
+                    stop_handle.graceful_shutdown(None);
+                });
+                tokio::spawn(async move {
+                    if let Err(error) = axum_server::from_tcp_rustls(listener, tls_config)
+                        .handle(handle)
+                        .serve(service)
+                        .await
+                    {
+                        error!("server error: {}", error);
+                    }
+                });
+            }
+            None => {
+                info!("Spawning the http server");
+                let stop = stop_receiver.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = axum::serve(listener, service)
+                        .with_graceful_shutdown(shutdown::stopping(stop))
+                        .await
+                    {
+                        error!("server error: {}", error);
+                    }
+                });
+            }
+        }
+    }
+    if let Some(grpc_ip_port) = cli.grpc_ip_port {
+        info!("Spawning the gRPC server");
+        tokio::spawn(async move {
+            if let Err(error) = grpc::serve(grpc_ip_port, app_state, http_auth, stop_receiver).await
+            {
+                error!("gRPC server error: {}", error);
+            }
+        });
+    }
+    let (kp, keystore) = match cli.seed {
+        Some(seed) => {
+            warn!(
+                "The keypair is derived from the seed {}, the identity is not persisted",
+                seed
+            );
+            (get_keypair(seed), None)
+        }
+        None => {
+            let keystore = Keystore::new(
+                cli.identity_file
+                    .unwrap_or_else(|| Keystore::default_path(&storage_dir)),
+                std::env::var(keystore::IDENTITY_PASSPHRASE_ENV).ok(),
+            );
+            (keystore.load_or_generate(cli.new_identity)?, Some(keystore))
+        }
+    };
+    let block_store = if cli.encrypt_blocks {
+        BlockStore::encrypted(
+            &cli.storage_key_file
+                .unwrap_or_else(|| storage_dir.join(STORAGE_KEY_FILE_NAME)),
+        )?
+    } else {
+        BlockStore::default()
+    };
+    let file_key_path = cli
+        .file_key_file
+        .unwrap_or_else(|| storage_dir.join(FILE_KEY_FILE_NAME));
     let peer_id = kp.public().to_peer_id();
     info!("IP/port: {}", ip_port);
-    info!("Peer ID: {} ({})", peer_id, seed);
+    info!("Peer ID: {}", peer_id);
 
     info!("Creating the swarm");
-    let swarm = dragoon_swarm::create_swarm(kp).await?;
-    let network = DragoonNetwork::new(
+    let files_topic = file_announce::files_topic(&cli.network_name);
+    let bandwidth = Arc::new(Bandwidth::default());
+    let swarm =
+        dragoon_swarm::create_swarm(kp.clone(), &cli.transport, &files_topic, &bandwidth).await?;
+    let mut network = DragoonNetwork::new(
         swarm,
         cmd_receiver,
-        cmd_sender,
-        powers_path,
+        cmd_sender.clone(),
+        Arc::new(PowersCache::new(powers_path.clone())),
         total_available_storage_for_send,
-        peer_id,
+        kp,
+        keystore,
+        storage_dir,
+        cli.transport,
+        files_topic,
+        DnsConfig {
+            timeout: Duration::from_millis(cli.dns_timeout),
+            prefer: cli.dns_prefer,
+            cache_ttl: Duration::from_secs(cli.dns_cache_ttl),
+        },
         cli.label,
         replace_file_dir,
+        DelegationConfig {
+            allowed_peers: cli.delegation_allowed_peer.into_iter().collect(),
+            allow_all: cli.delegation_allow_all,
+        },
+        StorageClasses::new(cli.storage_class_path.into_iter().collect()),
+        block_store,
+        file_key_path,
+        BlockLimits {
+            max_blocks: cli.max_blocks,
+            max_blocks_per_file: cli.max_blocks_per_file,
+        },
+        cli.peer_quota.map(|quota| quota * multiplier),
+        MirrorConfig {
+            follower: cli.mirror_to,
+            primary: cli.mirror_from,
+        },
+        BlockExpiryConfig {
+            default_ttl: cli.block_ttl.map(Duration::from_secs),
+            gc_interval: Duration::from_secs(cli.gc_interval),
+        },
+        CommandTimeouts {
+            get_file: Duration::from_secs(cli.get_file_timeout),
+            send_block_list: Duration::from_secs(cli.send_block_list_timeout),
+        },
+        BlockServingLimits {
+            max_served: cli.max_served_block_requests as usize,
+            max_queued: cli.max_queued_block_requests as usize,
+        },
+        bandwidth,
+        cli.recent_events as usize,
+        Duration::from_secs(cli.provide_refresh_interval),
+        Duration::from_secs(cli.dht_snapshot_interval),
+        RepairConfig {
+            interval: Duration::from_secs(cli.repair_interval),
+            threshold: cli.repair_threshold,
+        },
+        Duration::from_secs(cli.scrub_interval),
+        SendRetryPolicy {
+            max_attempts: cli.send_retry_max_attempts,
+            backoff: Duration::from_millis(cli.send_retry_backoff),
+            max_backoff: Duration::from_millis(cli.send_retry_max_backoff),
+            jitter: cli.send_retry_jitter,
+        },
+        send_strategies,
+        progress,
+        jobs,
+        in_flight.clone(),
+        metrics,
     );
 
+    network.listen_on_start(&cli.listen).await?;
     info!("Running the network");
-    tokio::spawn(network.run::<Fr, G1Projective, DensePolynomial<Fr>>());
+    cli.curve.spawn_network(network, powers_path).await?;
+    config::start_node(&cmd_sender, cli.bootstrap_peer);
 
-    let shutdown = signal::ctrl_c();
-    tokio::select! {
-        _ = shutdown => {
-            info!("shutdown Dragoon node");
-        }
+    signal::ctrl_c().await?;
+    info!("shutdown Dragoon node");
+    let _ = stop_sender.send(true);
+    in_flight.stop();
+    let shutdown_timeout = Duration::from_secs(cli.shutdown_timeout);
+    if !in_flight.wait_idle(shutdown_timeout).await {
+        warn!(
+            "{} blocks were still being sent or received after {:?}, they are cut",
+            in_flight.count(),
+            shutdown_timeout
+        );
     }
+    shutdown_network(&cmd_sender).await;
     Ok(())
 }
 
+/// Ask the network to save its state and close its connections, without waiting more than [`SHUTDOWN_TIMEOUT`]
+async fn shutdown_network(cmd_sender: &mpsc::UnboundedSender<commands::DragoonCommand>) {
+    let (sender, receiver) = oneshot::channel();
+    if cmd_sender
+        .send(commands::DragoonCommand::Shutdown {
+            sender: commands::Sender::SenderOneS(sender),
+        })
+        .is_err()
+    {
+        warn!("The network stopped before its state could be saved");
+        return;
+    }
+    match time::timeout(SHUTDOWN_TIMEOUT, receiver).await {
+        Ok(Ok(_)) => info!("Stopped the network"),
+        Ok(Err(_)) => warn!("The network stopped before its state could be saved"),
+        Err(_) => warn!("The network did not stop after {:?}", SHUTDOWN_TIMEOUT),
+    }
+}
+
 fn get_keypair(seed: u8) -> Keypair {
     let mut bytes = [0u8; 32];
     bytes[0] = seed;
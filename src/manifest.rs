@@ -0,0 +1,104 @@
+//! The manifest of an encoded file, written next to its blocks in `FILE_HASH/manifest.json`.
+//!
+//! The blocks only carry what is needed to decode them, the manifest keeps how the file was encoded: its original
+//! name, `k`, `n`, its size, the encoding method, the compression, the packing of its bytes in field elements,
+//! whether it was encrypted and whether it is the archive of a directory. It is written by an encode-file and read back by a decode-blocks, which checks it is given enough blocks, and by a get-file, which
+//! then does not need to read the metadata of the file in the DHT.
+//!
+//! Data can be appended to an encoded file without encoding it again: the new data is encoded as a file of its own,
+//! a segment, with the same encoding as the file, and its hash is added to the segments of the manifest of the file.
+//! The segments are published along with the metadata of the file, and a get-file of the file retrieves them one
+//! after the other and writes them after its bytes, in the order they were appended.
+//!
+//! The hash of the file only covers its own bytes, and anyone can publish a metadata record with other segments, so
+//! the segments of a record are only followed when they match the [`segments_root`] returned by the append.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::commands::EncodingMethod;
+use crate::compression::Compression;
+use crate::dragoon_swarm::hash_file;
+use crate::file_metadata::FileMetadata;
+use crate::packing::Packing;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub(crate) struct FileManifest {
+    pub(crate) file_hash: String,
+    /// The name of the file that was encoded, without its directory
+    pub(crate) file_name: Option<String>,
+    /// Number of blocks needed to decode the file
+    pub(crate) k: usize,
+    /// Number of blocks the file was encoded into
+    pub(crate) n: usize,
+    /// Size in bytes of the file before encoding
+    pub(crate) size: usize,
+    pub(crate) encoding_method: EncodingMethod,
+    pub(crate) compression: Compression,
+    /// How the bytes were packed in field elements, see [`crate::packing`]
+    #[serde(default)]
+    pub(crate) packing: Packing,
+    pub(crate) encrypted: bool,
+    /// Whether the file is a tar archive of a directory, see [`crate::archive`]
+    #[serde(default)]
+    pub(crate) archive: bool,
+    /// SHA-256 of the commitments shared by all the blocks of the file
+    pub(crate) commitment_digest: String,
+    /// The hashes of the segments appended to the file, in order
+    #[serde(default)]
+    pub(crate) segments: Vec<String>,
+}
+
+impl FileManifest {
+    /// The metadata published in the DHT for the same file
+    pub(crate) fn metadata(&self) -> FileMetadata {
+        FileMetadata {
+            file_hash: self.file_hash.clone(),
+            k: self.k,
+            n: self.n,
+            size: self.size,
+            commitment_digest: self.commitment_digest.clone(),
+            encoding_method: self.encoding_method,
+            compression: self.compression,
+            packing: self.packing,
+            segments: self.segments.clone(),
+        }
+    }
+}
+
+/// The SHA-256 of the hash of the file followed by the hashes of its segments in order, one per line, which stands
+/// for the file with all its segments
+pub(crate) fn segments_root(file_hash: &str, segments: &[String]) -> String {
+    let lines = std::iter::once(file_hash)
+        .chain(segments.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    hash_file(lines.join("\n").as_bytes())
+}
+
+/// Write the manifest of the file whose directory is `hash_dir`, replacing the one of a previous encoding
+pub(crate) async fn write(hash_dir: &Path, manifest: &FileManifest) -> Result<()> {
+    let tmp_path = hash_dir.join(format!(".{}.tmp", MANIFEST_FILE_NAME));
+    fs::create_dir_all(hash_dir).await?;
+    fs::write(&tmp_path, serde_json::to_vec_pretty(manifest)?).await?;
+    fs::rename(&tmp_path, hash_dir.join(MANIFEST_FILE_NAME)).await?;
+    Ok(())
+}
+
+/// The manifest of the file whose directory is `hash_dir`, `None` for a file this node did not encode
+pub(crate) async fn read(hash_dir: &Path) -> Option<FileManifest> {
+    let path = hash_dir.join(MANIFEST_FILE_NAME);
+    let bytes = fs::read(&path).await.ok()?;
+    match serde_json::from_slice(&bytes) {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            warn!("Ignoring the manifest {:?}: {}", path, e);
+            None
+        }
+    }
+}
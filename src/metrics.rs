@@ -0,0 +1,240 @@
+//! Counters of the node, exposed on `/metrics` in the Prometheus text format
+
+use axum::extract::State;
+use axum::Json;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::app::AppState;
+use crate::stats::Stats;
+use crate::storage_class::{StorageClass, StorageClassUsage};
+use crate::to_serialize::Envelope;
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) busy_block_requests: AtomicU64,
+    pub(crate) http_requests: AtomicU64,
+    pub(crate) http_body_too_large: AtomicU64,
+    pub(crate) http_rate_limited: AtomicU64,
+    pub(crate) http_timed_out: AtomicU64,
+    pub(crate) http_unauthorized: AtomicU64,
+    pub(crate) quarantined_blocks: AtomicU64,
+    pub(crate) repaired_blocks: AtomicU64,
+    pub(crate) stored_blocks: AtomicU64,
+    pub(crate) stored_files: AtomicU64,
+    pub(crate) too_many_blocks: AtomicU64,
+    pub(crate) stats: Mutex<Stats>,
+    pub(crate) storage_class_usage: Mutex<HashMap<StorageClass, StorageClassUsage>>,
+}
+
+impl Metrics {
+    pub(crate) fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Apply `record` to the encode and decode statistics
+    pub(crate) fn record_stats(&self, record: impl FnOnce(&mut Stats)) {
+        record(&mut self.stats.lock().unwrap());
+    }
+
+    /// Account for a block of `size` bytes placed on the storage class
+    pub(crate) fn record_stored_block(&self, storage_class: StorageClass, size: u64) {
+        let mut storage_class_usage = self.storage_class_usage.lock().unwrap();
+        let usage = storage_class_usage.entry(storage_class).or_default();
+        usage.blocks += 1;
+        usage.used_bytes += size;
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format
+    pub(crate) fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+        let global = self.stats.lock().unwrap().global.clone();
+        let metrics = [
+            (
+                "dragoonfly_busy_block_requests_total",
+                "Number of block requests of the other peers answered busy because too many were already served or queued",
+                "counter",
+                load(&self.busy_block_requests),
+            ),
+            (
+                "dragoonfly_http_requests_total",
+                "Number of requests received on the http interface",
+                "counter",
+                load(&self.http_requests),
+            ),
+            (
+                "dragoonfly_http_body_too_large_total",
+                "Number of requests rejected because their body was too large",
+                "counter",
+                load(&self.http_body_too_large),
+            ),
+            (
+                "dragoonfly_http_rate_limited_total",
+                "Number of requests rejected because the client sent too many requests",
+                "counter",
+                load(&self.http_rate_limited),
+            ),
+            (
+                "dragoonfly_http_timed_out_total",
+                "Number of requests that did not complete before the request timeout",
+                "counter",
+                load(&self.http_timed_out),
+            ),
+            (
+                "dragoonfly_http_unauthorized_total",
+                "Number of requests rejected because they had no valid bearer token",
+                "counter",
+                load(&self.http_unauthorized),
+            ),
+            (
+                "dragoonfly_quarantined_blocks_total",
+                "Number of stored blocks found corrupted and quarantined by the scrubs",
+                "counter",
+                load(&self.quarantined_blocks),
+            ),
+            (
+                "dragoonfly_repaired_blocks_total",
+                "Number of blocks recoded and sent to the network because too few blocks of a file were left",
+                "counter",
+                load(&self.repaired_blocks),
+            ),
+            (
+                "dragoonfly_stored_blocks",
+                "Number of blocks stored by the node",
+                "gauge",
+                load(&self.stored_blocks),
+            ),
+            (
+                "dragoonfly_stored_files",
+                "Number of files the node stores blocks of",
+                "gauge",
+                load(&self.stored_files),
+            ),
+            (
+                "dragoonfly_too_many_blocks_total",
+                "Number of blocks refused because they would go over the caps on the number of blocks",
+                "counter",
+                load(&self.too_many_blocks),
+            ),
+            (
+                "dragoonfly_files_encoded_total",
+                "Number of files encoded by the node",
+                "counter",
+                global.files_encoded.to_string(),
+            ),
+            (
+                "dragoonfly_encode_input_bytes_total",
+                "Total size in bytes of the files encoded by the node",
+                "counter",
+                global.input_bytes.to_string(),
+            ),
+            (
+                "dragoonfly_encode_coded_bytes_total",
+                "Total size in bytes of the blocks produced by the encoding",
+                "counter",
+                global.coded_bytes.to_string(),
+            ),
+            (
+                "dragoonfly_encode_expansion_factor",
+                "Ratio between the size of the blocks and the size of the encoded files",
+                "gauge",
+                global.expansion_factor.to_string(),
+            ),
+            (
+                "dragoonfly_encode_packed_bytes_total",
+                "Total size in bytes of the field elements the encoded files were packed into",
+                "counter",
+                global.packed_bytes.to_string(),
+            ),
+            (
+                "dragoonfly_encode_packing_efficiency",
+                "Ratio between the size of the encoded files and the size of the field elements they were packed into",
+                "gauge",
+                global.packing_efficiency.to_string(),
+            ),
+            (
+                "dragoonfly_decode_successes_total",
+                "Number of successful decodings",
+                "counter",
+                global.decode_successes.to_string(),
+            ),
+            (
+                "dragoonfly_decode_failures_total",
+                "Number of failed decodings",
+                "counter",
+                global.decode_failures.to_string(),
+            ),
+            (
+                "dragoonfly_get_file_total",
+                "Number of get-file that downloaded enough blocks to try decoding",
+                "counter",
+                global.get_files.to_string(),
+            ),
+            (
+                "dragoonfly_get_file_blocks_downloaded_total",
+                "Number of blocks downloaded by all the get-file",
+                "counter",
+                global.blocks_downloaded.to_string(),
+            ),
+            (
+                "dragoonfly_get_file_average_blocks",
+                "Average number of blocks downloaded by a get-file",
+                "gauge",
+                global.average_blocks_per_get_file.to_string(),
+            ),
+        ];
+        let mut res = String::new();
+        for (name, help, kind, value) in metrics {
+            // writing to a String can't fail
+            let _ = writeln!(res, "# HELP {} {}", name, help);
+            let _ = writeln!(res, "# TYPE {} {}", name, kind);
+            let _ = writeln!(res, "{} {}", name, value);
+        }
+        let storage_class_usage = self.storage_class_usage.lock().unwrap().clone();
+        let _ = writeln!(
+            res,
+            "# HELP dragoonfly_storage_class_used_bytes Size in bytes of the blocks placed on each storage class"
+        );
+        let _ = writeln!(res, "# TYPE dragoonfly_storage_class_used_bytes gauge");
+        for (class, usage) in &storage_class_usage {
+            let _ = writeln!(
+                res,
+                "dragoonfly_storage_class_used_bytes{{class=\"{}\"}} {}",
+                class, usage.used_bytes
+            );
+        }
+        let _ = writeln!(
+            res,
+            "# HELP dragoonfly_storage_class_blocks Number of blocks placed on each storage class"
+        );
+        let _ = writeln!(res, "# TYPE dragoonfly_storage_class_blocks gauge");
+        for (class, usage) in &storage_class_usage {
+            let _ = writeln!(
+                res,
+                "dragoonfly_storage_class_blocks{{class=\"{}\"}} {}",
+                class, usage.blocks
+            );
+        }
+        res
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "The metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub(crate) async fn get_metrics(State(state): State<Arc<AppState>>) -> String {
+    state.metrics.render()
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses((status = 200, description = "The statistics of the node", body = Envelope<Stats>))
+)]
+pub(crate) async fn get_stats(State(state): State<Arc<AppState>>) -> Json<Envelope<Stats>> {
+    Json(Envelope::ok(state.metrics.stats.lock().unwrap().clone()))
+}
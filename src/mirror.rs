@@ -0,0 +1,385 @@
+//! Warm standby: a primary node streams its manifest and accounting, never the block data, to a follower node.
+//!
+//! The primary sends a snapshot of the blocks it stores each time it connects to the follower, then every
+//! change as a numbered entry of a log. If the primary fails, the follower knows which blocks to fetch from
+//! the network to take over serving them.
+
+use anyhow::{format_err, Result};
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use libp2p::{PeerId, Stream, StreamProtocol};
+use libp2p_stream::{Control, IncomingStreams};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
+
+use crate::block_quota::BlockQuota;
+
+pub(crate) const MIRROR_PROTOCOL: StreamProtocol = StreamProtocol::new("/mirror/1.0.0");
+
+const MAX_FRAME_SIZE: usize = 256 * 1024 * 1024; // max size in bytes of a frame, the snapshots of large nodes included
+const MIRROR_LOG_CAPACITY: usize = 1024; // number of entries the primary can be late on before sending a new snapshot
+const MIRROR_HEARTBEAT: Duration = Duration::from_secs(5);
+const MIRROR_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MISSED_HEARTBEATS: u32 = 3; // the follower considers the primary down after this many missed heartbeats
+
+/// The blocks of each file, by file hash
+pub(crate) type Manifest = BTreeMap<String, BTreeSet<String>>;
+
+/// Which nodes this node mirrors to and from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MirrorConfig {
+    pub(crate) follower: Option<PeerId>,
+    pub(crate) primary: Option<PeerId>,
+}
+
+/// A change to the manifest of the primary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "op")]
+pub(crate) enum MirrorOp {
+    BlockStored {
+        file_hash: String,
+        block_hash: String,
+    },
+    /// All the blocks of the file, e.g. after encoding it again
+    FileBlocks {
+        file_hash: String,
+        block_hashes: BTreeSet<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct MirrorEntry {
+    seq: u64,
+    op: MirrorOp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum MirrorFrame {
+    Snapshot {
+        seq: u64,
+        files: Manifest,
+        available_storage: u64,
+    },
+    Entry {
+        seq: u64,
+        op: MirrorOp,
+    },
+    Heartbeat {
+        available_storage: u64,
+    },
+}
+
+/// What the follower knows of its primary
+#[derive(Debug, Default)]
+struct FollowerState {
+    connected: bool,
+    seq: Option<u64>,
+    last_heard: Option<Instant>,
+    files: Manifest,
+    available_storage: Option<u64>,
+}
+
+/// The state of the mirroring to the follower, as shown by `/mirror-status`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct MirrorToStatus {
+    pub(crate) peer_id: String,
+    pub(crate) connected: bool,
+    /// Sequence number of the last entry of the log
+    pub(crate) seq: u64,
+}
+
+/// The state of the mirroring from the primary, as shown by `/mirror-status`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct MirrorFromStatus {
+    pub(crate) peer_id: String,
+    pub(crate) connected: bool,
+    /// Whether the primary stopped sending its heartbeats
+    pub(crate) primary_down: bool,
+    pub(crate) seq: Option<u64>,
+    pub(crate) seconds_since_last_update: Option<u64>,
+    pub(crate) available_storage: Option<u64>,
+    pub(crate) files: usize,
+    pub(crate) blocks: usize,
+    /// The blocks of the primary this node does not have, to fetch from the network to take over
+    pub(crate) missing_blocks: Manifest,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct MirrorStatus {
+    pub(crate) to: Option<MirrorToStatus>,
+    pub(crate) from: Option<MirrorFromStatus>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Mirror {
+    config: MirrorConfig,
+    seq: AtomicU64,
+    log: broadcast::Sender<MirrorEntry>,
+    follower_connected: Mutex<bool>,
+    follower_state: Mutex<FollowerState>,
+}
+
+impl Mirror {
+    pub(crate) fn new(config: MirrorConfig) -> Self {
+        let (log, _) = broadcast::channel(MIRROR_LOG_CAPACITY);
+        Mirror {
+            config,
+            seq: AtomicU64::new(0),
+            log,
+            follower_connected: Mutex::new(false),
+            follower_state: Default::default(),
+        }
+    }
+
+    pub(crate) fn config(&self) -> &MirrorConfig {
+        &self.config
+    }
+
+    /// Add a change to the log sent to the follower, if there is one
+    pub(crate) fn record(&self, op: MirrorOp) {
+        if self.config.follower.is_none() {
+            return;
+        }
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+        // no one listens while the follower is not connected, it will get a snapshot instead
+        let _ = self.log.send(MirrorEntry { seq, op });
+    }
+
+    pub(crate) fn status(&self, local_files: &Manifest) -> MirrorStatus {
+        let to = self.config.follower.map(|follower| MirrorToStatus {
+            peer_id: follower.to_base58(),
+            connected: *self.follower_connected.lock().unwrap(),
+            seq: self.seq.load(Ordering::SeqCst),
+        });
+        let from = self.config.primary.map(|primary| {
+            let state = self.follower_state.lock().unwrap();
+            let silent_for = state.last_heard.map(|last_heard| last_heard.elapsed());
+            let missing_blocks = state
+                .files
+                .iter()
+                .filter_map(|(file_hash, block_hashes)| {
+                    let local = local_files.get(file_hash);
+                    let missing: BTreeSet<String> = block_hashes
+                        .iter()
+                        .filter(|block_hash| {
+                            !local.is_some_and(|local| local.contains(*block_hash))
+                        })
+                        .cloned()
+                        .collect();
+                    (!missing.is_empty()).then(|| (file_hash.clone(), missing))
+                })
+                .collect();
+            MirrorFromStatus {
+                peer_id: primary.to_base58(),
+                connected: state.connected,
+                primary_down: !state.connected
+                    || silent_for
+                        .is_none_or(|silent_for| silent_for > MIRROR_HEARTBEAT * MISSED_HEARTBEATS),
+                seq: state.seq,
+                seconds_since_last_update: silent_for.map(|silent_for| silent_for.as_secs()),
+                available_storage: state.available_storage,
+                files: state.files.len(),
+                blocks: state.files.values().map(BTreeSet::len).sum(),
+                missing_blocks,
+            }
+        });
+        MirrorStatus { to, from }
+    }
+
+    fn apply(&self, frame: MirrorFrame) -> Result<()> {
+        let mut state = self.follower_state.lock().unwrap();
+        state.last_heard = Some(Instant::now());
+        match frame {
+            MirrorFrame::Snapshot {
+                seq,
+                files,
+                available_storage,
+            } => {
+                debug!("Got a mirror snapshot of {} files at {}", files.len(), seq);
+                state.seq = Some(seq);
+                state.files = files;
+                state.available_storage = Some(available_storage);
+            }
+            MirrorFrame::Entry { seq, op } => {
+                let last_seq = state.seq.ok_or(format_err!(
+                    "Got the mirror entry {} before any snapshot",
+                    seq
+                ))?;
+                if seq <= last_seq {
+                    // already part of the snapshot
+                    return Ok(());
+                }
+                if seq != last_seq + 1 {
+                    return Err(format_err!(
+                        "Missing mirror entries between {} and {}",
+                        last_seq,
+                        seq
+                    ));
+                }
+                state.seq = Some(seq);
+                match op {
+                    MirrorOp::BlockStored {
+                        file_hash,
+                        block_hash,
+                    } => {
+                        state.files.entry(file_hash).or_default().insert(block_hash);
+                    }
+                    MirrorOp::FileBlocks {
+                        file_hash,
+                        block_hashes,
+                    } => {
+                        if block_hashes.is_empty() {
+                            state.files.remove(&file_hash);
+                        } else {
+                            state.files.insert(file_hash, block_hashes);
+                        }
+                    }
+                }
+            }
+            MirrorFrame::Heartbeat { available_storage } => {
+                state.available_storage = Some(available_storage);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn write_frame(stream: &mut Stream, frame: &MirrorFrame) -> Result<()> {
+    let ser_frame = serde_json::to_vec(frame)?;
+    stream
+        .write_all(&usize::to_be_bytes(ser_frame.len()))
+        .await?;
+    stream.write_all(&ser_frame).await?;
+    Ok(())
+}
+
+async fn read_frame(stream: &mut Stream) -> Result<MirrorFrame> {
+    let mut ser_frame_size = [0u8; size_of::<usize>()];
+    stream.read_exact(&mut ser_frame_size).await?;
+    let frame_size = usize::from_be_bytes(ser_frame_size);
+    if frame_size > MAX_FRAME_SIZE {
+        return Err(format_err!(
+            "The mirror frame's size of {} was bigger than the maximum size of {}",
+            frame_size,
+            MAX_FRAME_SIZE,
+        ));
+    }
+    let mut ser_frame = vec![0u8; frame_size];
+    stream.read_exact(&mut ser_frame).await?;
+    Ok(serde_json::from_slice(&ser_frame)?)
+}
+
+// -------------------- PRIMARY -------------------- //
+
+/// Spawn the task streaming the log to the follower, connecting again whenever the stream breaks
+pub(crate) fn run_primary(
+    mut control: Control,
+    follower: PeerId,
+    mirror: Arc<Mirror>,
+    block_quota: Arc<BlockQuota>,
+    available_storage: Arc<AtomicUsize>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match control.open_stream(follower, MIRROR_PROTOCOL).await {
+                Ok(mut stream) => {
+                    info!("Mirroring the manifest to {}", follower);
+                    *mirror.follower_connected.lock().unwrap() = true;
+                    if let Err(e) =
+                        stream_log(&mut stream, &mirror, &block_quota, &available_storage).await
+                    {
+                        warn!("The mirroring to {} stopped: {}", follower, e);
+                    }
+                    *mirror.follower_connected.lock().unwrap() = false;
+                    let _ = stream.close().await;
+                }
+                Err(e) => debug!("Could not open a mirror stream to {}: {}", follower, e),
+            }
+            time::sleep(MIRROR_RETRY_DELAY).await;
+        }
+    })
+}
+
+async fn stream_log(
+    stream: &mut Stream,
+    mirror: &Mirror,
+    block_quota: &BlockQuota,
+    available_storage: &AtomicUsize,
+) -> Result<()> {
+    // subscribe before taking the snapshot so no entry is lost in between
+    let mut log = mirror.log.subscribe();
+    let seq = mirror.seq.load(Ordering::SeqCst);
+    write_frame(
+        stream,
+        &MirrorFrame::Snapshot {
+            seq,
+            files: block_quota.manifest(),
+            available_storage: available_storage.load(Ordering::Relaxed) as u64,
+        },
+    )
+    .await?;
+    let mut heartbeat = time::interval(MIRROR_HEARTBEAT);
+    loop {
+        let frame = tokio::select! {
+            entry = log.recv() => match entry {
+                Ok(MirrorEntry { seq, op }) => MirrorFrame::Entry { seq, op },
+                Err(RecvError::Lagged(skipped)) => {
+                    return Err(format_err!("The follower is {} entries late, a new snapshot is needed", skipped));
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = heartbeat.tick() => MirrorFrame::Heartbeat {
+                available_storage: available_storage.load(Ordering::Relaxed) as u64,
+            },
+        };
+        write_frame(stream, &frame).await?;
+    }
+}
+
+// -------------------- FOLLOWER -------------------- //
+
+/// Spawn the task receiving the log of the primary
+pub(crate) fn run_follower(
+    mut incoming_streams: IncomingStreams,
+    primary: PeerId,
+    mirror: Arc<Mirror>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some((peer, mut stream)) = incoming_streams.next().await {
+            if peer != primary {
+                warn!(
+                    "Rejected a mirror stream from {}, only {} is mirrored",
+                    peer, primary
+                );
+                let _ = stream.close().await;
+                continue;
+            }
+            let mirror = mirror.clone();
+            tokio::spawn(async move {
+                info!("Receiving the manifest of {}", primary);
+                mirror.follower_state.lock().unwrap().connected = true;
+                if let Err(e) = receive_log(&mut stream, &mirror).await {
+                    error!("The mirror stream from {} stopped: {}", primary, e);
+                }
+                mirror.follower_state.lock().unwrap().connected = false;
+                let _ = stream.close().await;
+            });
+        }
+        debug!("We are done with the mirror streams");
+    })
+}
+
+async fn receive_log(stream: &mut Stream, mirror: &Mirror) -> Result<()> {
+    loop {
+        let frame = read_frame(stream).await?;
+        mirror.apply(frame)?;
+    }
+}
@@ -0,0 +1,86 @@
+//! The OpenAPI description of the http interface, served as JSON on `/openapi.json`.
+//!
+//! The description is built from the `#[utoipa::path]` annotations of the handlers, so a route added to the
+//! router must also be listed here to appear in it. The errors are not listed route by route: every route
+//! answers an error with a 4xx or 5xx status and an envelope whose `error` is an [`EnvelopeError`].
+
+use axum::Json;
+use utoipa::OpenApi;
+
+use crate::to_serialize::EnvelopeError;
+use crate::{commands, jobs, metrics, progress};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "dragoonfly",
+        description = "The http interface of a dragoonfly node, see documentation/API.md. Every route answers an error with a 4xx or 5xx status and an envelope whose `error` gives the kind and message of the error, and whether the request can be retried."
+    ),
+    components(schemas(EnvelopeError)),
+    paths(
+        commands::create_cmd_get_listeners,
+        commands::create_cmd_get_network_info,
+        commands::create_cmd_get_connected_peers,
+        commands::create_cmd_get_providers,
+        commands::create_cmd_get_block_list,
+        commands::create_cmd_get_blocks_info_from,
+        commands::create_cmd_find_block,
+        commands::create_cmd_node_info,
+        commands::create_cmd_mirror_status,
+        commands::create_cmd_known_files,
+        commands::create_cmd_get_recent_events,
+        commands::create_cmd_lifecycle,
+        commands::create_cmd_manifest,
+        commands::create_cmd_probe_peer,
+        commands::create_cmd_get_replication,
+        commands::create_cmd_get_global_block_map,
+        commands::create_cmd_get_available_storage,
+        commands::create_cmd_get_peer_quotas,
+        commands::create_cmd_get_bandwidth,
+        commands::create_cmd_get_peer_score,
+        commands::create_cmd_scrub_status,
+        metrics::get_metrics,
+        metrics::get_stats,
+        progress::list_progress,
+        progress::get_progress,
+        jobs::get_job,
+        jobs::cancel_job,
+        commands::create_cmd_listen,
+        commands::create_cmd_remove_listener,
+        commands::create_cmd_dial_single,
+        commands::create_cmd_dial_multiple,
+        commands::create_cmd_add_peer,
+        commands::create_cmd_start_provide,
+        commands::create_cmd_stop_provide,
+        commands::create_cmd_reannounce_all,
+        commands::create_cmd_bootstrap,
+        commands::create_cmd_decode_blocks,
+        commands::create_cmd_recode_blocks,
+        commands::create_cmd_delete_file,
+        commands::create_cmd_encode_file,
+        commands::create_cmd_encode_files,
+        commands::create_cmd_append_to_file,
+        commands::create_cmd_generate_powers,
+        commands::create_cmd_upload_and_encode,
+        commands::create_cmd_get_block_from,
+        commands::create_cmd_get_file,
+        commands::create_cmd_download,
+        commands::create_cmd_send_block_to,
+        commands::create_cmd_send_block_list,
+        commands::create_cmd_set_replication,
+        commands::create_cmd_change_available_send_storage,
+        commands::create_cmd_set_peer_quota,
+        commands::create_cmd_ban_peer,
+        commands::create_cmd_unban_peer,
+        commands::create_cmd_disconnect_peer,
+        commands::create_cmd_rotate_identity,
+        commands::create_cmd_pause_receiving,
+        commands::create_cmd_resume_receiving,
+        commands::create_cmd_delegate_get_file,
+    )
+)]
+struct ApiDoc;
+
+pub(crate) async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,334 @@
+//! How the bytes of a file are packed in the field elements used by the encoding, and how efficiently they fit.
+//!
+//! Two packings can be chosen when encoding a file:
+//! - `truncate`, the default, is the packing of komodo: the bytes are cut in chunks of `MODULUS_BIT_SIZE / 8`
+//!   bytes, each chunk becoming one field element, so the bits of the elements after their last whole byte are lost
+//! - `bit-packed` reads the bytes as a stream of bits cut in chunks of `MODULUS_BIT_SIZE - 1` bits, the most an
+//!   element always holds below the modulus. komodo only packs whole bytes, so the shards and their commitments
+//!   are built here from these elements, the same way komodo builds them, and the blocks are checked, recoded and
+//!   sent like any other
+//!
+//! In both cases the elements are padded so they can be split in `k` shards. The packing is recorded in the manifest
+//! of the file and in its metadata in the DHT, and get-file and decode-blocks unpack the decoded elements with it.
+
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::{BigInteger, PrimeField};
+use ark_poly::DenseUVPolynomial;
+use ark_serialize::Compress;
+use ark_std::ops::Div;
+use komodo::algebra::{self, linalg::Matrix};
+use komodo::fec::{self, Shard};
+use komodo::semi_avid::{self, Block};
+use komodo::zk::{self, Powers};
+use rs_merkle::{algorithms::Sha256, Hasher};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::row_echelon::RowEchelon;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Packing {
+    /// Whole bytes in each field element, packed by komodo
+    #[default]
+    Truncate,
+    /// `MODULUS_BIT_SIZE - 1` bits in each field element, whatever the bytes they are in
+    BitPacked,
+}
+
+impl Packing {
+    pub(crate) const ALL: [Packing; 2] = [Packing::Truncate, Packing::BitPacked];
+
+    /// Number of bits of the file stored in each field element
+    pub(crate) fn bits_per_element<F: PrimeField>(self) -> usize {
+        match self {
+            Packing::Truncate => (F::MODULUS_BIT_SIZE as usize / 8).max(1) * 8,
+            Packing::BitPacked => (F::MODULUS_BIT_SIZE as usize - 1).max(1),
+        }
+    }
+}
+
+impl fmt::Display for Packing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Packing::Truncate => write!(f, "truncate"),
+            Packing::BitPacked => write!(f, "bit-packed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PackingReport {
+    pub(crate) packing: Packing,
+    /// Number of bits of the file stored in each field element
+    pub(crate) bits_per_element: usize,
+    pub(crate) field_elements: usize,
+    /// Size of the field elements once serialized
+    pub(crate) packed_bytes: usize,
+}
+
+impl PackingReport {
+    pub(crate) fn new<F: PrimeField>(packing: Packing, file_size: usize, k: usize) -> Self {
+        let bits_per_element = packing.bits_per_element::<F>();
+        let field_elements = (file_size * 8).div_ceil(bits_per_element).max(1);
+        let field_elements = field_elements.div_ceil(k.max(1)) * k.max(1);
+        let element_size = F::zero().serialized_size(Compress::Yes);
+        PackingReport {
+            packing,
+            bits_per_element,
+            field_elements,
+            packed_bytes: field_elements * element_size,
+        }
+    }
+}
+
+/// The blocks of `bytes` packed with `packing`, one for each column of the encoding matrix
+pub(crate) fn encode<F, G, P>(
+    packing: Packing,
+    bytes: &[u8],
+    encoding_mat: &Matrix<F>,
+    powers: &Powers<F, G>,
+    k: usize,
+) -> Result<Vec<Block<F, G>>>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+    P: DenseUVPolynomial<F>,
+    for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+{
+    if packing == Packing::Truncate {
+        let shards = fec::encode::<F>(bytes, encoding_mat)?;
+        let proof = semi_avid::prove::<F, G, P>(bytes, powers, k)?;
+        return Ok(semi_avid::build::<F, G, P>(&shards, &proof));
+    }
+    if k == 0 {
+        return Err(format_err!("A file can't be encoded with k = 0"));
+    }
+    let elements: Vec<F> = pack_bits(bytes, packing.bits_per_element::<F>(), k);
+    let rows = elements.len() / k;
+    // the linear combinations are the columns of the encoding matrix, as komodo gives them to the shards it encodes
+    let columns = fec::encode::<F>(&[0], encoding_mat)?;
+    let hash = Sha256::hash(bytes).to_vec();
+    let shards: Vec<Shard<F>> = columns
+        .into_iter()
+        .map(|column| {
+            let data = elements
+                .chunks(k)
+                .map(|row| {
+                    row.iter()
+                        .zip(&column.linear_combination)
+                        .map(|(element, c)| *element * c)
+                        .sum()
+                })
+                .collect();
+            Shard {
+                k: k as u32,
+                linear_combination: column.linear_combination,
+                hash: hash.clone(),
+                data,
+                size: bytes.len(),
+            }
+        })
+        .collect();
+    // one polynomial for each of the `k` source columns, like the proof of komodo
+    let polynomials: Vec<P> = (0..k)
+        .map(|i| P::from_coefficients_vec((0..rows).map(|row| elements[row * k + i]).collect()))
+        .collect();
+    let proof = zk::batch_commit::<F, G, P>(powers, &polynomials)?;
+    Ok(semi_avid::build::<F, G, P>(&shards, &proof))
+}
+
+/// The bytes of the file in the shards, packed with `packing`
+pub(crate) fn decode<F: PrimeField>(packing: Packing, shards: Vec<Shard<F>>) -> Result<Vec<u8>> {
+    if packing == Packing::Truncate {
+        return Ok(fec::decode::<F>(shards)?);
+    }
+    let size = shards
+        .first()
+        .ok_or(format_err!("There are no shards to decode"))?
+        .size;
+    Ok(unpack(packing, &source_elements(&shards)?, size))
+}
+
+/// The `size` bytes of the file packed in `elements` with `packing`, the padding is dropped
+pub(crate) fn unpack<F: PrimeField>(packing: Packing, elements: &[F], size: usize) -> Vec<u8> {
+    match packing {
+        Packing::Truncate => {
+            let mut bytes = algebra::merge_elements_into_bytes(elements);
+            bytes.resize(size, 0);
+            bytes
+        }
+        Packing::BitPacked => unpack_bits(elements, packing.bits_per_element::<F>(), size),
+    }
+}
+
+/// The elements holding `bits` bits of `bytes` each, padded with zeros so they can be split in `k` shards
+fn pack_bits<F: PrimeField>(bytes: &[u8], bits: usize, k: usize) -> Vec<F> {
+    let k = k.max(1);
+    let nb_elements = (bytes.len() * 8).div_ceil(bits).max(1);
+    let mut elements: Vec<F> = (0..nb_elements)
+        .map(|element| {
+            let mut chunk = vec![0u8; bits.div_ceil(8)];
+            for i in 0..bits {
+                let bit = element * bits + i;
+                if bytes
+                    .get(bit / 8)
+                    .is_some_and(|byte| (byte >> (bit % 8)) & 1 == 1)
+                {
+                    chunk[i / 8] |= 1 << (i % 8);
+                }
+            }
+            F::from_le_bytes_mod_order(&chunk)
+        })
+        .collect();
+    elements.resize(nb_elements.div_ceil(k) * k, F::zero());
+    elements
+}
+
+/// The first `size` bytes of the bits held by the elements, see [`pack_bits`]
+fn unpack_bits<F: PrimeField>(elements: &[F], bits: usize, size: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; size];
+    for (element, value) in elements.iter().enumerate() {
+        let chunk = value.into_bigint().to_bytes_le();
+        for i in 0..bits {
+            let bit = element * bits + i;
+            if bit / 8 >= size {
+                return bytes;
+            }
+            if chunk
+                .get(i / 8)
+                .is_some_and(|byte| (byte >> (i % 8)) & 1 == 1)
+            {
+                bytes[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+    bytes
+}
+
+/// The elements the shards were encoded from, by solving the linear system of their linear combinations.
+/// Any `k` linearly independent shards are enough, whether they were encoded or recoded.
+fn source_elements<F: PrimeField>(shards: &[Shard<F>]) -> Result<Vec<F>> {
+    let first = shards
+        .first()
+        .ok_or(format_err!("There are no shards to decode"))?;
+    let k = first.k as usize;
+    let rows = first.data.len();
+    if shards
+        .iter()
+        .any(|shard| shard.linear_combination.len() != k || shard.data.len() != rows)
+    {
+        return Err(format_err!("The shards are not the ones of the same file"));
+    }
+    let mut lines = RowEchelon::<F>::new();
+    for shard in shards {
+        lines.add_augmented_row(shard.linear_combination.clone(), shard.data.clone());
+    }
+    // once solved, the data of each line is the source column of its pivot
+    let columns = lines.solve(k).ok_or(format_err!(
+        "Not enough linearly independent shards to decode, {} are needed",
+        k
+    ))?;
+    Ok((0..rows)
+        .flat_map(|row| columns.iter().map(move |column| column[row]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bls12_381::Fr;
+    use rand::Rng;
+
+    fn random_bytes(size: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        (0..size).map(|_| rng.gen()).collect()
+    }
+
+    /// The shards of the elements for each linear combination, the way [`encode`] builds them
+    fn shards(
+        elements: &[Fr],
+        k: usize,
+        linear_combinations: &[Vec<Fr>],
+        size: usize,
+    ) -> Vec<Shard<Fr>> {
+        linear_combinations
+            .iter()
+            .map(|linear_combination| Shard {
+                k: k as u32,
+                linear_combination: linear_combination.clone(),
+                hash: vec![],
+                data: elements
+                    .chunks(k)
+                    .map(|row| {
+                        row.iter()
+                            .zip(linear_combination)
+                            .map(|(e, c)| *e * c)
+                            .sum()
+                    })
+                    .collect(),
+                size,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bit_packing_roundtrip() {
+        let bits = Packing::BitPacked.bits_per_element::<Fr>();
+        for size in [0, 1, 31, 32, 33, 1000] {
+            let bytes = random_bytes(size);
+            for k in [1, 3, 4] {
+                let elements: Vec<Fr> = pack_bits(&bytes, bits, k);
+                assert_eq!(elements.len() % k, 0);
+                assert_eq!(unpack(Packing::BitPacked, &elements, size), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn bit_packing_uses_fewer_elements() {
+        let truncate = PackingReport::new::<Fr>(Packing::Truncate, 1 << 20, 1);
+        let bit_packed = PackingReport::new::<Fr>(Packing::BitPacked, 1 << 20, 1);
+        assert_eq!(truncate.bits_per_element, 248);
+        assert_eq!(bit_packed.bits_per_element, 254);
+        assert!(bit_packed.field_elements < truncate.field_elements);
+        assert!(bit_packed.packed_bytes < truncate.packed_bytes);
+    }
+
+    #[test]
+    fn decode_any_independent_shards() {
+        let mut rng = rand::thread_rng();
+        let (k, n, size) = (3, 5, 500);
+        let bytes = random_bytes(size);
+        let elements: Vec<Fr> = pack_bits(&bytes, Packing::BitPacked.bits_per_element::<Fr>(), k);
+        let linear_combinations: Vec<Vec<Fr>> = (0..n)
+            .map(|_| (0..k).map(|_| Fr::from(rng.gen::<u64>())).collect())
+            .collect();
+        let shards = shards(&elements, k, &linear_combinations, size);
+        assert_eq!(source_elements(&shards[n - k..]).unwrap(), elements);
+        assert_eq!(
+            decode(Packing::BitPacked, shards[..k].to_vec()).unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn decode_dependent_shards() {
+        let k = 2;
+        let elements: Vec<Fr> = pack_bits(
+            &random_bytes(100),
+            Packing::BitPacked.bits_per_element::<Fr>(),
+            k,
+        );
+        let linear_combination = vec![Fr::from(2u64), Fr::from(3u64)];
+        let shards = shards(
+            &elements,
+            k,
+            &[linear_combination.clone(), linear_combination],
+            100,
+        );
+        assert!(source_elements(&shards).is_err());
+    }
+}
@@ -1,9 +1,21 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+use crate::storage_class::StorageClass;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub(crate) struct PeerBlockInfo {
     pub(crate) peer_id_base_58: String,
     pub(crate) file_hash: String,
     pub(crate) block_hashes: Vec<String>,
     pub(crate) block_sizes: Option<Vec<usize>>,
+    /// The storage class on which the receiver of a sent block should place it
+    #[serde(default)]
+    pub(crate) storage_class: Option<StorageClass>,
+    /// The id following the file across the commands and the nodes, see [`crate::lifecycle`]
+    #[serde(default)]
+    pub(crate) correlation_id: Option<String>,
+    /// Seconds the receiver of a sent block should keep it, after which it deletes it
+    #[serde(default)]
+    pub(crate) ttl: Option<u64>,
 }
@@ -0,0 +1,45 @@
+//! Peer exchange (PEX): connected peers share the reachable peers they know,
+//! so that a new node discovers the network without waiting for the Kademlia walks
+
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of peers sent in a single peer exchange response
+pub(crate) const MAX_PEX_PEERS: usize = 32;
+/// The peers learned through a peer exchange are dialed as long as we have less connected peers than this
+pub(crate) const PEX_TARGET_CONNECTIONS: usize = 16;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum NodeRole {
+    /// The node accepts blocks sent by other nodes
+    Storage,
+    /// The node does not have any storage left for the blocks sent by other nodes
+    Client,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct PexPeer {
+    pub(crate) peer_id_base_58: String,
+    pub(crate) addrs: Vec<Multiaddr>,
+    pub(crate) label: Option<String>,
+    pub(crate) role: Option<NodeRole>,
+}
+
+impl PexPeer {
+    pub(crate) fn peer_id(&self) -> Option<PeerId> {
+        let bytes = bs58::decode(&self.peer_id_base_58).into_vec().ok()?;
+        PeerId::from_bytes(&bytes).ok()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PexRequest {
+    pub(crate) max_peers: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PexResponse {
+    /// Information about the responding node itself
+    pub(crate) own: PexPeer,
+    pub(crate) peers: Vec<PexPeer>,
+}
@@ -0,0 +1,166 @@
+//! Health probe of a replica: ask a peer for its blocks of a file, download one of them at random and
+//! verify it, then record the outcome in the reputation of the peer
+
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_poly::DenseUVPolynomial;
+use ark_serialize::Validate;
+use komodo::semi_avid::{verify, Block};
+use libp2p::PeerId;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use std::future::Future;
+use std::ops::Div;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tokio::time;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::block_format;
+use crate::commands::{DragoonCommand, Sender};
+use crate::peer_block_info::PeerBlockInfo;
+use crate::peer_reputation::{PeerReputation, PeerReputations};
+use crate::powers_cache::PowersCache;
+
+const PROBE_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The outcome of a probe
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct ProbeReport {
+    pub(crate) peer_id: String,
+    pub(crate) file_hash: String,
+    /// Number of blocks of the file the peer says it has
+    pub(crate) blocks: usize,
+    /// The block that was downloaded
+    pub(crate) block_hash: Option<String>,
+    pub(crate) info_latency_ms: Option<u64>,
+    pub(crate) block_latency_ms: Option<u64>,
+    pub(crate) block_size: Option<usize>,
+    /// Bytes per second while downloading the block
+    pub(crate) throughput: Option<f64>,
+    /// Whether the peer served a block of the file with a valid proof
+    pub(crate) correct: bool,
+    pub(crate) error: Option<String>,
+    pub(crate) reputation: PeerReputation,
+}
+
+/// Send a command to the swarm and wait for its answer, at most [`PROBE_STEP_TIMEOUT`]
+async fn ask<T>(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    command: impl FnOnce(Sender<T>) -> DragoonCommand,
+) -> Result<T> {
+    let (sender, receiver) = oneshot::channel();
+    cmd_sender
+        .send(command(Sender::SenderOneS(sender)))
+        .map_err(|_| format_err!("Could not send the command to the swarm"))?;
+    timed(receiver)
+        .await?
+        .map_err(|_| format_err!("The swarm dropped the command"))?
+}
+
+async fn timed<T>(future: impl Future<Output = T>) -> Result<T> {
+    time::timeout(PROBE_STEP_TIMEOUT, future)
+        .await
+        .map_err(|_| format_err!("No answer after {:?}", PROBE_STEP_TIMEOUT))
+}
+
+pub(crate) async fn probe_peer<F, G, P>(
+    cmd_sender: UnboundedSender<DragoonCommand>,
+    peer_id: PeerId,
+    file_hash: String,
+    powers: Arc<PowersCache>,
+    reputations: &PeerReputations,
+) -> ProbeReport
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+    P: DenseUVPolynomial<F>,
+    for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+{
+    let mut report = ProbeReport {
+        peer_id: peer_id.to_base58(),
+        file_hash: file_hash.clone(),
+        ..Default::default()
+    };
+    if let Err(e) = run_probe::<F, G, P>(&cmd_sender, peer_id, file_hash, powers, &mut report).await
+    {
+        warn!("Probe of {} failed: {}", peer_id, e);
+        report.correct = false;
+        report.error = Some(e.to_string());
+    }
+    report.reputation = reputations.record_probe(peer_id, &report);
+    info!(
+        "Probe of {} for {}: correct {}, score {}",
+        peer_id, report.file_hash, report.correct, report.reputation.score
+    );
+    report
+}
+
+async fn run_probe<F, G, P>(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    peer_id: PeerId,
+    file_hash: String,
+    powers: Arc<PowersCache>,
+    report: &mut ProbeReport,
+) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+    P: DenseUVPolynomial<F>,
+    for<'a, 'b> &'a P: Div<&'b P, Output = P>,
+{
+    let start = Instant::now();
+    let PeerBlockInfo { block_hashes, .. } =
+        ask(cmd_sender, |sender| DragoonCommand::GetBlocksInfoFrom {
+            peer_id,
+            file_hash: file_hash.clone(),
+            block_hash: None,
+            sender,
+        })
+        .await?;
+    report.info_latency_ms = Some(start.elapsed().as_millis() as u64);
+    report.blocks = block_hashes.len();
+    let block_hash = block_hashes
+        .choose(&mut rand::thread_rng())
+        .ok_or(format_err!("The peer has no block of {}", file_hash))?
+        .clone();
+    report.block_hash = Some(block_hash.clone());
+
+    let start = Instant::now();
+    let block_response = ask(cmd_sender, |sender| DragoonCommand::GetBlockFrom {
+        peer_id,
+        file_hash: file_hash.clone(),
+        block_hash: block_hash.clone(),
+        save_to_disk: false,
+        fallback_providers: vec![],
+        sender,
+    })
+    .await?
+    .ok_or(format_err!("The block was not sent back"))?;
+    let elapsed = start.elapsed();
+    report.block_latency_ms = Some(elapsed.as_millis() as u64);
+    report.block_size = Some(block_response.block_data.len());
+    report.throughput = Some(block_response.block_data.len() as f64 / elapsed.as_secs_f64());
+
+    if block_response.file_hash != file_hash || block_response.block_hash != block_hash {
+        return Err(format_err!(
+            "The peer sent the block {} of {} instead",
+            block_response.block_hash,
+            block_response.file_hash
+        ));
+    }
+    let block: Block<F, G> = block_format::deserialize(&block_response.block_data, Validate::Yes)
+        .map_err(|e| format_err!("Could not deserialize the block: {}", e))?;
+    let powers = powers.get::<F, G>().await?;
+    if !verify::<F, G, P>(&block, &powers)? {
+        return Err(format_err!(
+            "The proof of the block {} is invalid",
+            block_hash
+        ));
+    }
+    report.correct = true;
+    Ok(())
+}
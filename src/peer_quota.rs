@@ -0,0 +1,114 @@
+//! Caps on the storage taken by the blocks each peer sends to the node.
+//!
+//! The storage given to send requests is shared by all the peers, so a single peer sending many blocks could take
+//! it all. Each peer can use at most its own quota of it, the default one given with `--peer-quota` unless another
+//! was set for the peer. The storage used by each peer and the quotas set for some peers are kept in the send block
+//! database, see [`crate::send_block_to::SendAccounting`], and loaded here when the node starts. The blocks being
+//! received are counted as soon as they are accepted, so the concurrent sends of a peer cannot go over its quota.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+#[derive(Debug, Default)]
+struct Usage {
+    /// Quota of the peers for which it was set, in bytes
+    overrides: HashMap<String, usize>,
+    /// Storage used by the blocks of each peer, including the blocks being received, in bytes
+    used: HashMap<String, usize>,
+}
+
+/// The quota and the usage of a peer, as shown by get-peer-quotas
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct PeerQuotaInfo {
+    pub(crate) peer_id: String,
+    /// `None` when the peer has no quota
+    pub(crate) quota: Option<usize>,
+    pub(crate) used: usize,
+    /// Whether the quota was set for this peer instead of being the default one
+    pub(crate) overridden: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct PeerQuotasInfo {
+    pub(crate) default_quota: Option<usize>,
+    pub(crate) peers: Vec<PeerQuotaInfo>,
+}
+
+#[derive(Debug)]
+pub(crate) struct PeerQuotas {
+    /// Quota of the peers for which none was set, `None` means no quota
+    default_quota: Option<usize>,
+    usage: Mutex<Usage>,
+}
+
+impl PeerQuotas {
+    pub(crate) fn new(default_quota: Option<usize>) -> Self {
+        PeerQuotas {
+            default_quota,
+            usage: Default::default(),
+        }
+    }
+
+    fn quota(&self, usage: &Usage, peer_id: &str) -> Option<usize> {
+        usage.overrides.get(peer_id).copied().or(self.default_quota)
+    }
+
+    /// Count `size` more bytes for the peer, unless it would go over its quota
+    pub(crate) fn reserve(&self, peer_id: &str, size: usize) -> bool {
+        let mut usage = self.usage.lock().unwrap();
+        let used = usage.used.get(peer_id).copied().unwrap_or(0);
+        if self
+            .quota(&usage, peer_id)
+            .is_some_and(|quota| used + size > quota)
+        {
+            return false;
+        }
+        usage.used.insert(peer_id.to_string(), used + size);
+        true
+    }
+
+    /// Give back `size` bytes to the peer, when its blocks were removed or could not be received
+    pub(crate) fn release(&self, peer_id: &str, size: usize) {
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(used) = usage.used.get_mut(peer_id) {
+            *used = used.saturating_sub(size);
+            if *used == 0 {
+                usage.used.remove(peer_id);
+            }
+        }
+    }
+
+    /// Replace the usage and the quotas with the ones read from the send block database
+    pub(crate) fn restore(&self, used: HashMap<String, usize>, overrides: HashMap<String, usize>) {
+        *self.usage.lock().unwrap() = Usage { overrides, used };
+    }
+
+    /// Set the quota of the peer, back to the default one if `None`
+    pub(crate) fn set(&self, peer_id: &str, quota: Option<usize>) {
+        let mut usage = self.usage.lock().unwrap();
+        match quota {
+            Some(quota) => usage.overrides.insert(peer_id.to_string(), quota),
+            None => usage.overrides.remove(peer_id),
+        };
+    }
+
+    /// The peers with blocks on the node or a quota of their own
+    pub(crate) fn info(&self) -> PeerQuotasInfo {
+        let usage = self.usage.lock().unwrap();
+        let peer_ids: BTreeSet<&String> = usage.used.keys().chain(usage.overrides.keys()).collect();
+        PeerQuotasInfo {
+            default_quota: self.default_quota,
+            peers: peer_ids
+                .into_iter()
+                .map(|peer_id| PeerQuotaInfo {
+                    peer_id: peer_id.clone(),
+                    quota: self.quota(&usage, peer_id),
+                    used: usage.used.get(peer_id).copied().unwrap_or(0),
+                    overridden: usage.overrides.contains_key(peer_id),
+                })
+                .collect(),
+        }
+    }
+}
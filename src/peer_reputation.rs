@@ -0,0 +1,123 @@
+//! The reputation of the other peers, built from how they behaved with the node: the probes they answered, the
+//! blocks they served or failed to serve, and the blocks they accepted or refused.
+//!
+//! Every outcome moves the score of the peer, a moving average between 0 (always failed) and 1 (always behaved).
+//! A get-file requests the blocks from the providers with a low score only when no other provider has them, and a
+//! send-block-list does not send blocks to the peers with a low score.
+
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::peer_probe::ProbeReport;
+
+/// Weight of the last outcome in the score of a peer
+const SCORE_WEIGHT: f64 = 0.2;
+/// The peers with a score below this are avoided
+pub(crate) const LOW_SCORE: f64 = 0.3;
+
+/// Something a peer did that changes its reputation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PeerBehaviour {
+    /// The peer sent a block it was asked for
+    BlockServed,
+    /// The request for a block failed, the peer did not answer or had no such block
+    BlockRequestFailed,
+    /// The peer sent a block that could not be read or has an invalid proof
+    InvalidBlock,
+    /// The peer accepted a block sent to it
+    SendAccepted,
+    /// The peer refused a block sent to it or the exchange failed
+    SendRejected,
+    /// The peer did not answer in time, to a block request or to a send
+    Timeout,
+}
+
+impl PeerBehaviour {
+    fn is_good(self) -> bool {
+        matches!(
+            self,
+            PeerBehaviour::BlockServed | PeerBehaviour::SendAccepted
+        )
+    }
+}
+
+/// What is known of a peer from how it behaved with the node
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct PeerReputation {
+    pub(crate) probes: u64,
+    pub(crate) failures: u64,
+    pub(crate) failed_block_requests: u64,
+    pub(crate) invalid_blocks: u64,
+    pub(crate) send_rejections: u64,
+    pub(crate) timeouts: u64,
+    /// Number of outcomes the score was computed from
+    pub(crate) outcomes: u64,
+    /// Moving average of the outcomes, from 0 (always failed) to 1 (always behaved)
+    pub(crate) score: f64,
+    pub(crate) last_latency_ms: Option<u64>,
+    pub(crate) last_throughput: Option<f64>,
+}
+
+impl PeerReputation {
+    fn add_outcome(&mut self, good: bool) {
+        let outcome = if good { 1.0 } else { 0.0 };
+        self.score = if self.outcomes == 0 {
+            outcome
+        } else {
+            (1.0 - SCORE_WEIGHT) * self.score + SCORE_WEIGHT * outcome
+        };
+        self.outcomes += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PeerReputations {
+    peers: Mutex<HashMap<PeerId, PeerReputation>>,
+}
+
+impl PeerReputations {
+    pub(crate) fn record(&self, peer_id: PeerId, behaviour: PeerBehaviour) {
+        let mut peers = self.peers.lock().unwrap();
+        let reputation = peers.entry(peer_id).or_default();
+        match behaviour {
+            PeerBehaviour::BlockRequestFailed => reputation.failed_block_requests += 1,
+            PeerBehaviour::InvalidBlock => reputation.invalid_blocks += 1,
+            PeerBehaviour::SendRejected => reputation.send_rejections += 1,
+            PeerBehaviour::Timeout => reputation.timeouts += 1,
+            PeerBehaviour::BlockServed | PeerBehaviour::SendAccepted => {}
+        }
+        reputation.add_outcome(behaviour.is_good());
+    }
+
+    pub(crate) fn record_probe(&self, peer_id: PeerId, report: &ProbeReport) -> PeerReputation {
+        let mut peers = self.peers.lock().unwrap();
+        let reputation = peers.entry(peer_id).or_default();
+        reputation.add_outcome(report.correct);
+        reputation.probes += 1;
+        if !report.correct {
+            reputation.failures += 1;
+        }
+        if report.block_latency_ms.is_some() {
+            reputation.last_latency_ms = report.block_latency_ms;
+            reputation.last_throughput = report.throughput;
+        }
+        reputation.clone()
+    }
+
+    /// `None` if nothing is known of the peer yet
+    pub(crate) fn get(&self, peer_id: &PeerId) -> Option<PeerReputation> {
+        self.peers.lock().unwrap().get(peer_id).cloned()
+    }
+
+    /// Whether the peer should be avoided, the peers nothing is known of are not
+    pub(crate) fn is_low(&self, peer_id: &PeerId) -> bool {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .is_some_and(|reputation| reputation.score < LOW_SCORE)
+    }
+}
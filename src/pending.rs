@@ -0,0 +1,184 @@
+//! Bookkeeping of the requests waiting for an answer from the swarm.
+//!
+//! Each entry is pending until a terminal event completes it, which hands its sender back exactly once.
+//! Some queries emit several events (e.g. one per batch of providers found), only the events marked as
+//! terminal complete the entry. The ids of the last completed entries are remembered, so an event arriving
+//! after the entry completed is reported as late instead of as unknown, and is never answered twice.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use tracing::{debug, warn};
+
+/// How many completed ids are remembered to recognize late events
+const COMPLETED_HISTORY: usize = 1024;
+
+#[derive(Debug)]
+pub(crate) struct PendingMap<K, V> {
+    name: &'static str,
+    pending: HashMap<K, V>,
+    completed: HashSet<K>,
+    completed_order: VecDeque<K>,
+}
+
+impl<K: Eq + Hash + Clone + Debug, V> PendingMap<K, V> {
+    pub(crate) fn new(name: &'static str) -> Self {
+        PendingMap {
+            name,
+            pending: HashMap::new(),
+            completed: HashSet::new(),
+            completed_order: VecDeque::new(),
+        }
+    }
+
+    /// Start waiting for `id`. An entry already pending for the same id is returned, it will never be completed.
+    pub(crate) fn insert(&mut self, id: K, value: V) -> Option<V> {
+        // an id can be used again once completed, e.g. the multiaddr of a dial
+        self.completed.remove(&id);
+        let previous = self.pending.insert(id.clone(), value);
+        if previous.is_some() {
+            warn!(
+                "{}: the id {:?} is already pending, replacing it",
+                self.name, id
+            );
+        }
+        previous
+    }
+
+    pub(crate) fn contains(&self, id: &K) -> bool {
+        self.pending.contains_key(id)
+    }
+
+    /// The entry for a non terminal event, which leaves it pending
+    pub(crate) fn progress(&mut self, id: &K) -> Option<&mut V> {
+        if !self.pending.contains_key(id) {
+            self.report_missing(id);
+        }
+        self.pending.get_mut(id)
+    }
+
+    /// Take the entry out for a terminal event, `None` if it is unknown or already completed
+    pub(crate) fn complete(&mut self, id: &K) -> Option<V> {
+        match self.pending.remove(id) {
+            Some(value) => {
+                self.remember(id.clone());
+                Some(value)
+            }
+            None => {
+                self.report_missing(id);
+                None
+            }
+        }
+    }
+
+    /// Complete all the entries matching `predicate`, e.g. to cancel them
+    pub(crate) fn complete_where(&mut self, predicate: impl Fn(&K, &V) -> bool) -> Vec<(K, V)> {
+        let ids: Vec<K> = self
+            .pending
+            .iter()
+            .filter(|(id, value)| predicate(id, value))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| self.complete(&id).map(|value| (id, value)))
+            .collect()
+    }
+
+    /// Complete all the entries, e.g. to answer them before the ids stop meaning anything
+    pub(crate) fn complete_all(&mut self) -> Vec<(K, V)> {
+        self.complete_where(|_, _| true)
+    }
+
+    /// Forget everything, e.g. when the ids stop meaning anything
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+        self.completed.clear();
+        self.completed_order.clear();
+    }
+
+    fn remember(&mut self, id: K) {
+        if self.completed.insert(id.clone()) {
+            self.completed_order.push_back(id);
+        }
+        if self.completed_order.len() > COMPLETED_HISTORY {
+            if let Some(oldest) = self.completed_order.pop_front() {
+                self.completed.remove(&oldest);
+            }
+        }
+    }
+
+    fn report_missing(&self, id: &K) {
+        if self.completed.contains(id) {
+            warn!(
+                "{}: late event for {:?}, which already completed, ignoring it",
+                self.name, id
+            );
+        } else {
+            debug!("{}: no pending entry for {:?}", self.name, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn several_events_for_one_query() {
+        let mut pending = PendingMap::new("test");
+        pending.insert(1, vec![]);
+        for batch in ["a", "b"] {
+            pending.progress(&1).unwrap().push(batch);
+            assert!(pending.contains(&1));
+        }
+        assert_eq!(pending.complete(&1), Some(vec!["a", "b"]));
+        assert!(!pending.contains(&1));
+    }
+
+    #[test]
+    fn late_events_are_not_answered() {
+        let mut pending = PendingMap::new("test");
+        pending.insert(1, "sender");
+        assert_eq!(pending.complete(&1), Some("sender"));
+        assert!(pending.progress(&1).is_none());
+        assert_eq!(pending.complete(&1), None);
+        assert!(pending.completed.contains(&1));
+        // the id can be used again once completed
+        pending.insert(1, "new sender");
+        assert!(!pending.completed.contains(&1));
+        assert_eq!(pending.complete(&1), Some("new sender"));
+    }
+
+    #[test]
+    fn unknown_events_are_not_late() {
+        let mut pending: PendingMap<u32, ()> = PendingMap::new("test");
+        assert!(pending.progress(&1).is_none());
+        assert_eq!(pending.complete(&1), None);
+        assert!(!pending.completed.contains(&1));
+    }
+
+    #[test]
+    fn completed_history_is_bounded() {
+        let mut pending = PendingMap::new("test");
+        for id in 0..COMPLETED_HISTORY + 1 {
+            pending.insert(id, ());
+            pending.complete(&id);
+        }
+        assert_eq!(pending.completed.len(), COMPLETED_HISTORY);
+        assert!(!pending.completed.contains(&0));
+        assert!(pending.completed.contains(&COMPLETED_HISTORY));
+    }
+
+    #[test]
+    fn complete_where_takes_the_matching_entries() {
+        let mut pending = PendingMap::new("test");
+        for id in 0..4 {
+            pending.insert(id, id % 2 == 0);
+        }
+        let mut cancelled = pending.complete_where(|_, even| *even);
+        cancelled.sort();
+        assert_eq!(cancelled, vec![(0, true), (2, true)]);
+        assert!(pending.contains(&1) && pending.contains(&3));
+        assert!(pending.progress(&0).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+//! The powers of the node, kept in memory instead of being read from `--powers-path` each time they are needed.
+//!
+//! The powers are read on the first use, then reused as long as the powers file keeps the same modification time
+//! and size, so a powers file replaced by generate-powers, or by hand, is read again on the next use.
+
+use anyhow::Result;
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use komodo::zk::Powers;
+use std::any::Any;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::powers_file;
+
+struct CachedPowers {
+    modified: SystemTime,
+    size: u64,
+    /// The `Powers<F, G>` of the curve the node runs over
+    powers: Arc<dyn Any + Send + Sync>,
+}
+
+pub(crate) struct PowersCache {
+    path: PathBuf,
+    cached: Mutex<Option<CachedPowers>>,
+}
+
+impl PowersCache {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        PowersCache {
+            path,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The powers of the powers file, read again if the file changed since they were last read
+    pub(crate) async fn get<F, G>(&self) -> Result<Arc<Powers<F, G>>>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        // held while the powers are read, so they are only read once when they are needed by several tasks
+        let mut cached = self.cached.lock().await;
+        let metadata = tokio::fs::metadata(&self.path).await?;
+        let (modified, size) = (metadata.modified()?, metadata.len());
+        if let Some(cached) = cached
+            .as_ref()
+            .filter(|cached| cached.modified == modified && cached.size == size)
+        {
+            if let Ok(powers) = cached.powers.clone().downcast::<Powers<F, G>>() {
+                return Ok(powers);
+            }
+        }
+        info!("Getting the powers from {:?}", self.path);
+        let powers = Arc::new(powers_file::read::<F, G>(&self.path).await?);
+        *cached = Some(CachedPowers {
+            modified,
+            size,
+            powers: powers.clone(),
+        });
+        Ok(powers)
+    }
+}
@@ -0,0 +1,222 @@
+//! The format of the powers files.
+//!
+//! A powers file starts with a header giving the curve the powers were made for and their maximum degree, along
+//! with the SHA-256 checksum of the serialized powers that follow it:
+//!
+//! ```text
+//! "dragoonfly-powers" | version (1 byte) | curve id (1 byte) | max degree (8 bytes, LE) | checksum (32 bytes) | powers
+//! ```
+//!
+//! so reading the powers of another curve, truncated powers or powers too small for an encoding fail with a clear
+//! error. The files made before this format, with only the serialized powers, are still read, and are converted
+//! with `--convert-powers`.
+
+use anyhow::{format_err, Result};
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
+use komodo::zk::Powers;
+use rs_merkle::{algorithms::Sha256, Hasher};
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::curve::Curve;
+
+const MAGIC: &[u8] = b"dragoonfly-powers";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 1 + 8 + 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PowersHeader {
+    pub(crate) curve: Curve,
+    pub(crate) max_degree: u64,
+    checksum: [u8; 32],
+}
+
+impl PowersHeader {
+    fn write(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        header.push(self.curve.id());
+        header.extend_from_slice(&self.max_degree.to_le_bytes());
+        header.extend_from_slice(&self.checksum);
+        header
+    }
+
+    /// The header at the start of `content` and the serialized powers after it, `None` if the powers have no header
+    fn read(content: &[u8]) -> Result<Option<(Self, &[u8])>> {
+        let Some(framed) = content.strip_prefix(MAGIC) else {
+            return Ok(None);
+        };
+        if content.len() < HEADER_SIZE {
+            return Err(format_err!("The header of the powers is truncated"));
+        }
+        if framed[0] != VERSION {
+            return Err(format_err!(
+                "The powers are in the version {} of the format, only the version {} is supported",
+                framed[0],
+                VERSION
+            ));
+        }
+        let curve = Curve::from_id(framed[1])?;
+        let max_degree = u64::from_le_bytes(framed[2..10].try_into()?);
+        let checksum = framed[10..42].try_into()?;
+        Ok(Some((
+            PowersHeader {
+                curve,
+                max_degree,
+                checksum,
+            },
+            &content[HEADER_SIZE..],
+        )))
+    }
+}
+
+/// Read the powers of `path` for the curve of `F`
+pub(crate) async fn read<F, G>(path: &Path) -> Result<Powers<F, G>>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let content = tokio::fs::read(path).await?;
+    let Some((header, serialized)) = PowersHeader::read(&content)? else {
+        return Ok(Powers::<F, G>::deserialize_with_mode(
+            &content[..],
+            Compress::Yes,
+            Validate::Yes,
+        )?);
+    };
+    if let Some(curve) = Curve::of::<F>() {
+        if header.curve != curve {
+            return Err(format_err!(
+                "The powers in {:?} were made for the curve {}, not {}",
+                path,
+                header.curve,
+                curve
+            ));
+        }
+    }
+    if Sha256::hash(serialized) != header.checksum {
+        return Err(format_err!(
+            "The powers in {:?} do not match their checksum, the file is corrupted or truncated",
+            path
+        ));
+    }
+    let powers = Powers::<F, G>::deserialize_with_mode(serialized, Compress::Yes, Validate::Yes)?;
+    if powers.len() as u64 != header.max_degree + 1 {
+        return Err(format_err!(
+            "The powers in {:?} have a max degree of {} instead of the {} of their header",
+            path,
+            powers.len().saturating_sub(1),
+            header.max_degree
+        ));
+    }
+    Ok(powers)
+}
+
+/// Check that the powers are large enough to prove the polynomials of an encoding with `k` source blocks
+pub(crate) fn check_degree<F, G>(powers: &Powers<F, G>, k: usize) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    // the polynomials have k coefficients
+    if powers.len() < k {
+        return Err(format_err!(
+            "The powers have a max degree of {}, an encoding with k = {} needs a max degree of at least {}",
+            powers.len().saturating_sub(1),
+            k,
+            k - 1
+        ));
+    }
+    Ok(())
+}
+
+/// Check the powers of `path` for the curve when the node starts, and warn about the powers without header
+pub(crate) async fn check<F, G>(path: &Path, curve: Curve) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    // the points of the powers are checked to be on the curve when they are read
+    let powers = read::<F, G>(path).await.map_err(|e| {
+        format_err!(
+            "The powers in {:?} could not be read for the curve {}: {}",
+            path,
+            curve,
+            e
+        )
+    })?;
+    if PowersHeader::read(&tokio::fs::read(path).await?)?.is_none() {
+        warn!(
+            "The powers in {:?} have no header, convert them with --convert-powers",
+            path
+        );
+    }
+    info!(
+        "Read the powers in {:?}, with a max degree of {}",
+        path,
+        powers.len().saturating_sub(1)
+    );
+    Ok(())
+}
+
+/// Write the powers of the curve to `output` with a header.
+/// The powers are written next to `output` and read back before replacing it, so the ones being read by the node
+/// are never half written nor replaced by invalid ones.
+pub(crate) async fn write<F, G>(powers: &Powers<F, G>, output: &Path, curve: Curve) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let mut serialized = vec![0; powers.serialized_size(Compress::Yes)];
+    powers.serialize_with_mode(&mut serialized[..], Compress::Yes)?;
+    let header = PowersHeader {
+        curve,
+        max_degree: powers.len().saturating_sub(1) as u64,
+        checksum: Sha256::hash(&serialized),
+    };
+    let mut tmp_output = output.to_path_buf();
+    tmp_output.set_extension("tmp");
+    tokio::fs::write(&tmp_output, [header.write(), serialized].concat()).await?;
+    if let Err(e) = read::<F, G>(&tmp_output).await {
+        let _ = tokio::fs::remove_file(&tmp_output).await;
+        return Err(format_err!("The written powers are invalid: {}", e));
+    }
+    tokio::fs::rename(&tmp_output, output).await?;
+    info!(
+        "Wrote the powers for the curve {} to {:?}, with a max degree of {}",
+        curve, output, header.max_degree
+    );
+    Ok(())
+}
+
+/// Generate new powers up to `max_degree` for the curve of `F` and write them to `output`
+pub(crate) async fn generate<F, G>(max_degree: usize, output: &Path) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let curve = Curve::of::<F>().ok_or(format_err!("The node runs over an unknown curve"))?;
+    info!(
+        "Generating the powers up to the degree {} for the curve {}",
+        max_degree, curve
+    );
+    let powers = tokio::task::spawn_blocking(move || {
+        let mut rng = rand::thread_rng();
+        komodo::zk::setup::<F, G>(max_degree, &mut rng)
+    })
+    .await??;
+    write(&powers, output, curve).await
+}
+
+/// Write the powers of `input`, read for the curve, to `output` with a header
+pub(crate) async fn convert<F, G>(input: &Path, output: &Path, curve: Curve) -> Result<()>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let powers = read::<F, G>(input).await?;
+    write(&powers, output, curve).await
+}
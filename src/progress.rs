@@ -0,0 +1,243 @@
+//! Progress of the long-running commands: encode-file, get-file and send-block-list.
+//!
+//! Each run of one of these commands is registered under an operation id, given with the `operation_id` query
+//! parameter of the command or drawn by the node, and the tasks doing the work update it as they go. The progress
+//! of a finished operation is kept until [`MAX_FINISHED_OPERATIONS`] newer ones finished, so it can still be read
+//! once the command returned.
+
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json, Response};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::app::AppState;
+use crate::error::DragoonError;
+use crate::to_serialize::Envelope;
+
+/// Number of finished operations whose progress is kept
+const MAX_FINISHED_OPERATIONS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct OperationProgress {
+    pub(crate) operation_id: String,
+    /// The command being run, e.g. `get-file`
+    pub(crate) command: String,
+    /// What the command is currently doing, e.g. `downloading`
+    pub(crate) stage: String,
+    /// Blocks encoded, downloaded and verified, or sent, depending on the command
+    pub(crate) blocks_done: usize,
+    /// Number of blocks the command has to go through, once it is known
+    pub(crate) blocks_total: Option<usize>,
+    /// Size of the blocks written or downloaded, the blocks sent are not counted
+    pub(crate) bytes_processed: u64,
+    pub(crate) started_at: String,
+    pub(crate) updated_at: String,
+    pub(crate) finished: bool,
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Operations {
+    by_id: HashMap<String, OperationProgress>,
+    /// The finished operations, oldest first
+    finished: VecDeque<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ProgressRegistry {
+    operations: Mutex<Operations>,
+}
+
+/// A random operation id, for the commands run without one
+pub(crate) fn new_operation_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl ProgressRegistry {
+    /// Register a run of the command, under a random id if none was given
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        operation_id: Option<String>,
+        command: &str,
+    ) -> ProgressHandle {
+        let operation_id = operation_id.unwrap_or_else(new_operation_id);
+        let started_at = now();
+        let mut operations = self.operations.lock().unwrap();
+        if operations
+            .by_id
+            .get(&operation_id)
+            .is_some_and(|operation| !operation.finished)
+        {
+            warn!(
+                "The operation {} is already running, its progress is replaced by the one of a new {}",
+                operation_id, command
+            );
+        }
+        operations.finished.retain(|id| *id != operation_id);
+        operations.by_id.insert(
+            operation_id.clone(),
+            OperationProgress {
+                operation_id: operation_id.clone(),
+                command: command.to_string(),
+                stage: String::from("started"),
+                blocks_done: 0,
+                blocks_total: None,
+                bytes_processed: 0,
+                started_at: started_at.clone(),
+                updated_at: started_at,
+                finished: false,
+                error: None,
+            },
+        );
+        ProgressHandle {
+            registry: self.clone(),
+            operation_id,
+        }
+    }
+
+    pub(crate) fn get(&self, operation_id: &str) -> Option<OperationProgress> {
+        self.operations
+            .lock()
+            .unwrap()
+            .by_id
+            .get(operation_id)
+            .cloned()
+    }
+
+    /// All the operations still running or recently finished, the oldest first
+    pub(crate) fn list(&self) -> Vec<OperationProgress> {
+        let mut operations: Vec<OperationProgress> = self
+            .operations
+            .lock()
+            .unwrap()
+            .by_id
+            .values()
+            .cloned()
+            .collect();
+        operations.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+        operations
+    }
+
+    /// The operations whose tasks are still running
+    pub(crate) fn running(&self) -> Vec<OperationProgress> {
+        self.list()
+            .into_iter()
+            .filter(|operation| !operation.finished)
+            .collect()
+    }
+
+    fn update(&self, operation_id: &str, update: impl FnOnce(&mut OperationProgress)) {
+        if let Some(operation) = self.operations.lock().unwrap().by_id.get_mut(operation_id) {
+            update(operation);
+            operation.updated_at = now();
+        }
+    }
+
+    /// Mark the operation as canceled, its tasks were aborted so they do not finish it
+    pub(crate) fn cancel(&self, operation_id: &str) {
+        self.finish(
+            operation_id,
+            "canceled",
+            Some(String::from("The operation was canceled")),
+        );
+    }
+
+    fn finish(&self, operation_id: &str, stage: &str, error: Option<String>) {
+        let mut operations = self.operations.lock().unwrap();
+        let Some(operation) = operations.by_id.get_mut(operation_id) else {
+            return;
+        };
+        if operation.finished {
+            return;
+        }
+        operation.stage = stage.to_string();
+        operation.finished = true;
+        operation.error = error;
+        operation.updated_at = now();
+        operations.finished.push_back(operation_id.to_string());
+        while operations.finished.len() > MAX_FINISHED_OPERATIONS {
+            if let Some(oldest) = operations.finished.pop_front() {
+                operations.by_id.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Updates the progress of a single operation, from any of the tasks doing its work
+#[derive(Debug, Clone)]
+pub(crate) struct ProgressHandle {
+    registry: Arc<ProgressRegistry>,
+    operation_id: String,
+}
+
+impl ProgressHandle {
+    pub(crate) fn operation_id(&self) -> &str {
+        &self.operation_id
+    }
+
+    pub(crate) fn stage(&self, stage: &str) {
+        self.registry.update(&self.operation_id, |operation| {
+            operation.stage = stage.to_string()
+        });
+    }
+
+    pub(crate) fn blocks_total(&self, blocks_total: usize) {
+        self.registry.update(&self.operation_id, |operation| {
+            operation.blocks_total = Some(blocks_total)
+        });
+    }
+
+    /// One more block went through, `bytes` being its size
+    pub(crate) fn block_done(&self, bytes: usize) {
+        self.registry.update(&self.operation_id, |operation| {
+            operation.blocks_done += 1;
+            operation.bytes_processed += bytes as u64;
+        });
+    }
+
+    /// Mark the operation as done or failed, from the result of the command
+    pub(crate) fn finish<T, E: Display>(&self, res: &Result<T, E>) {
+        let stage = if res.is_ok() { "done" } else { "failed" };
+        self.registry.finish(
+            &self.operation_id,
+            stage,
+            res.as_ref().err().map(|e| e.to_string()),
+        );
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/progress/{operation_id}",
+    params(("operation_id" = String, Path, description = "Id of the operation")),
+    responses((status = 200, description = "The progress of the operation", body = Envelope<OperationProgress>))
+)]
+pub(crate) async fn get_progress(
+    Path(operation_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    match state.progress.get(&operation_id) {
+        Some(progress) => Json(Envelope::ok(progress)).into_response(),
+        None => DragoonError::UnknownOperation(operation_id).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/progress",
+    responses((status = 200, description = "The progress of the running operations", body = Envelope<Vec<OperationProgress>>))
+)]
+pub(crate) async fn list_progress(
+    State(state): State<Arc<AppState>>,
+) -> Json<Envelope<Vec<OperationProgress>>> {
+    Json(Envelope::ok(state.progress.list()))
+}
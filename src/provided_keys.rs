@@ -0,0 +1,75 @@
+//! The keys provided by the node, kept in `provided_keys.json` so they are provided again after a restart.
+//!
+//! The provider records of the DHT expire and the node loses its own records when it stops, so the keys
+//! are announced again every `--provide-refresh-interval`, the first time right after the node starts.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, warn};
+
+const PROVIDED_KEYS_FILE_NAME: &str = "provided_keys.json";
+
+#[derive(Debug, Default)]
+pub(crate) struct ProvidedKeys {
+    keys: BTreeSet<String>,
+}
+
+impl ProvidedKeys {
+    /// Read the keys provided before the node stopped, none if there are no such keys or they cannot be read
+    pub(crate) fn load(file_dir: &Path) -> Self {
+        let path = file_dir.join(PROVIDED_KEYS_FILE_NAME);
+        let keys = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Could not parse the provided keys of {:?}: {}", path, e);
+                BTreeSet::new()
+            }),
+            Err(_) => BTreeSet::new(),
+        };
+        if !keys.is_empty() {
+            info!("{} keys were provided before the node stopped", keys.len());
+        }
+        ProvidedKeys { keys }
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub(crate) fn keys(&self) -> Vec<String> {
+        self.keys.iter().cloned().collect()
+    }
+
+    pub(crate) async fn insert(&mut self, file_dir: &Path, key: String) {
+        if self.keys.insert(key) {
+            self.save(file_dir).await;
+        }
+    }
+
+    /// Whether the key was provided
+    pub(crate) async fn remove(&mut self, file_dir: &Path, key: &str) -> bool {
+        let removed = self.keys.remove(key);
+        if removed {
+            self.save(file_dir).await;
+        }
+        removed
+    }
+
+    /// Failing to save the keys only means they are not provided again after a restart
+    async fn save(&self, file_dir: &Path) {
+        if let Err(e) = self.write(file_dir).await {
+            warn!("Could not save the provided keys in {:?}: {}", file_dir, e);
+        }
+    }
+
+    async fn write(&self, file_dir: &Path) -> Result<()> {
+        let path = file_dir.join(PROVIDED_KEYS_FILE_NAME);
+        // written next to the file then renamed, so a crash never leaves a partial list
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&self.keys)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
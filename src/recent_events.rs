@@ -0,0 +1,91 @@
+//! The last events of the node, kept in memory so an operator can follow what the node did without its logs.
+//!
+//! The events are the connections opened, closed or failed, the commands run, the blocks exchanged with the other
+//! peers and the errors of these exchanges. Each event gets a sequence number, growing by one from the start of
+//! the node, so a client can ask for the events after the last one it saw. Once `--recent-events` events are
+//! kept, the oldest one is dropped for each new one.
+
+use chrono::{SecondsFormat, Utc};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RecentEventKind {
+    ConnectionEstablished,
+    ConnectionClosed,
+    ConnectionFailed,
+    Command,
+    BlockServed,
+    BlockFetched,
+    BlockSent,
+    BlockReceived,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct RecentEvent {
+    pub(crate) seq: u64,
+    pub(crate) timestamp: String,
+    pub(crate) kind: RecentEventKind,
+    /// The other peer taking part in the event, if any
+    pub(crate) peer_id: Option<String>,
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    next_seq: u64,
+    events: VecDeque<RecentEvent>,
+}
+
+#[derive(Debug)]
+pub(crate) struct RecentEvents {
+    capacity: usize,
+    ring: Mutex<Ring>,
+}
+
+impl RecentEvents {
+    pub(crate) fn new(capacity: usize) -> Self {
+        RecentEvents {
+            capacity,
+            ring: Default::default(),
+        }
+    }
+
+    pub(crate) fn record(
+        &self,
+        kind: RecentEventKind,
+        peer_id: Option<PeerId>,
+        message: impl Into<String>,
+    ) {
+        let mut ring = self.ring.lock().unwrap();
+        let event = RecentEvent {
+            seq: ring.next_seq,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            kind,
+            peer_id: peer_id.map(|peer_id| peer_id.to_base58()),
+            message: message.into(),
+        };
+        ring.next_seq += 1;
+        if ring.events.len() == self.capacity {
+            ring.events.pop_front();
+        }
+        ring.events.push_back(event);
+    }
+
+    /// The events kept with a sequence number above `since`, all of them if `since` is not given, the oldest first
+    pub(crate) fn since(&self, since: Option<u64>) -> Vec<RecentEvent> {
+        self.ring
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .filter(|event| since.is_none_or(|since| event.seq > since))
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,424 @@
+//! The replication factor of the files, kept in `replication.json`.
+//!
+//! Each block of a file given a replication factor `r` should be held by at least `r` distinct other peers. The
+//! local blocks held by fewer peers are sent to the connected peers that do not have them yet, and the blocks that
+//! only other peers still hold are replaced by as many blocks recoded from the local ones. This is done right after
+//! the factor is set, then every `--repair-interval` along with the repair of the files.
+
+use anyhow::Result;
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use tokio::fs;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use crate::block_repair::ask;
+use crate::commands::DragoonCommand;
+
+const REPLICATION_FILE_NAME: &str = "replication.json";
+
+#[derive(Debug, Default)]
+pub(crate) struct ReplicationFactors {
+    factors: BTreeMap<String, usize>,
+}
+
+impl ReplicationFactors {
+    /// Read the factors set before the node stopped, none if there are no such factors or they cannot be read
+    pub(crate) fn load(file_dir: &Path) -> Self {
+        let path = file_dir.join(REPLICATION_FILE_NAME);
+        let factors = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Could not parse the replication factors of {:?}: {}",
+                    path, e
+                );
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        ReplicationFactors { factors }
+    }
+
+    /// The factor of the file, 0 if none was set
+    pub(crate) fn get(&self, file_hash: &str) -> usize {
+        self.factors.get(file_hash).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn factors(&self) -> Vec<(String, usize)> {
+        self.factors
+            .iter()
+            .map(|(file_hash, factor)| (file_hash.clone(), *factor))
+            .collect()
+    }
+
+    /// A factor of 0 removes the one of the file
+    pub(crate) async fn set(&mut self, file_dir: &Path, file_hash: String, factor: usize) {
+        let changed = if factor == 0 {
+            self.factors.remove(&file_hash).is_some()
+        } else {
+            self.factors.insert(file_hash, factor) != Some(factor)
+        };
+        if changed {
+            self.save(file_dir).await;
+        }
+    }
+
+    /// Failing to save the factors only means they are forgotten after a restart
+    async fn save(&self, file_dir: &Path) {
+        if let Err(e) = self.write(file_dir).await {
+            warn!(
+                "Could not save the replication factors in {:?}: {}",
+                file_dir, e
+            );
+        }
+    }
+
+    async fn write(&self, file_dir: &Path) -> Result<()> {
+        let path = file_dir.join(REPLICATION_FILE_NAME);
+        // written next to the file then renamed, so a crash never leaves a partial list
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&self.factors)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
+
+/// The replication of a file, as seen by the node
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct ReplicationStatus {
+    pub(crate) file_hash: String,
+    /// The factor set with set-replication, 0 if none was set
+    pub(crate) desired: usize,
+    /// The smallest number of other peers holding one of the blocks
+    pub(crate) current: usize,
+    /// The number of other peers holding each block, 0 for the local blocks no other peer holds
+    pub(crate) blocks: BTreeMap<String, usize>,
+}
+
+/// The peers holding each block of a file across the network, as told by its providers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct GlobalBlockMap {
+    pub(crate) file_hash: String,
+    /// The base 58 peer ids holding each block, this node included
+    pub(crate) blocks: BTreeMap<String, Vec<String>>,
+    /// The providers that did not say which blocks they have, their blocks are missing from the map
+    pub(crate) unanswered_providers: Vec<String>,
+}
+
+/// The peers holding a block, as told by the providers of its file and the connected peers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct BlockLocation {
+    pub(crate) file_hash: String,
+    pub(crate) block_hash: String,
+    /// The base 58 peer ids holding the block, this node included
+    pub(crate) holders: Vec<String>,
+    /// The peers that did not say whether they have the block
+    pub(crate) unanswered_peers: Vec<String>,
+}
+
+/// The other peers holding each block of the file, from the providers of the file
+async fn block_holders(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    local_peer_id: PeerId,
+    file_hash: &str,
+) -> Result<HashMap<String, HashSet<PeerId>>> {
+    let providers = ask(cmd_sender, |sender| DragoonCommand::GetProviders {
+        key: file_hash.to_string(),
+        sender,
+    })
+    .await?;
+    let mut holders: HashMap<String, HashSet<PeerId>> = HashMap::new();
+    for peer_id in providers
+        .into_iter()
+        .filter(|peer_id| *peer_id != local_peer_id)
+    {
+        match ask(cmd_sender, |sender| DragoonCommand::GetBlocksInfoFrom {
+            peer_id,
+            file_hash: file_hash.to_string(),
+            block_hash: None,
+            sender,
+        })
+        .await
+        {
+            Ok(info) => {
+                for block_hash in info.block_hashes {
+                    holders.entry(block_hash).or_default().insert(peer_id);
+                }
+            }
+            Err(e) => debug!(
+                "{} did not say which blocks of {} it has: {}",
+                peer_id, file_hash, e
+            ),
+        }
+    }
+    Ok(holders)
+}
+
+/// Ask every provider of the file which blocks it has, the local blocks are read from the disk
+pub(crate) async fn global_block_map(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    local_peer_id: PeerId,
+    file_hash: String,
+) -> Result<GlobalBlockMap> {
+    let local_blocks = ask(cmd_sender, |sender| DragoonCommand::GetBlockList {
+        file_hash: file_hash.clone(),
+        sender,
+    })
+    .await
+    .unwrap_or_default();
+    let providers = ask(cmd_sender, |sender| DragoonCommand::GetProviders {
+        key: file_hash.clone(),
+        sender,
+    })
+    .await?;
+    let mut map = GlobalBlockMap {
+        file_hash,
+        blocks: local_blocks
+            .into_iter()
+            .map(|block_hash| (block_hash, vec![local_peer_id.to_base58()]))
+            .collect(),
+        unanswered_providers: vec![],
+    };
+    for peer_id in providers
+        .into_iter()
+        .filter(|peer_id| *peer_id != local_peer_id)
+    {
+        match ask(cmd_sender, |sender| DragoonCommand::GetBlocksInfoFrom {
+            peer_id,
+            file_hash: map.file_hash.clone(),
+            block_hash: None,
+            sender,
+        })
+        .await
+        {
+            Ok(info) => {
+                for block_hash in info.block_hashes {
+                    map.blocks
+                        .entry(block_hash)
+                        .or_default()
+                        .push(peer_id.to_base58());
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "{} did not say which blocks of {} it has: {}",
+                    peer_id, map.file_hash, e
+                );
+                map.unanswered_providers.push(peer_id.to_base58());
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// Ask the providers of the file and the connected peers whether they have the block, only this block is listed in
+/// their answers so the whole block list of the file is not sent by each of them
+pub(crate) async fn find_block(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    local_peer_id: PeerId,
+    file_hash: String,
+    block_hash: String,
+) -> Result<BlockLocation> {
+    let local_blocks = ask(cmd_sender, |sender| DragoonCommand::GetBlockList {
+        file_hash: file_hash.clone(),
+        sender,
+    })
+    .await
+    .unwrap_or_default();
+    // a peer the block was sent to does not provide the file until it announces it
+    let mut peers: BTreeSet<PeerId> = ask(cmd_sender, |sender| DragoonCommand::GetProviders {
+        key: file_hash.clone(),
+        sender,
+    })
+    .await?
+    .into_iter()
+    .collect();
+    peers.extend(
+        ask(cmd_sender, |sender| DragoonCommand::GetConnectedPeers {
+            sender,
+        })
+        .await?,
+    );
+    peers.remove(&local_peer_id);
+    let mut location = BlockLocation {
+        holders: if local_blocks.contains(&block_hash) {
+            vec![local_peer_id.to_base58()]
+        } else {
+            vec![]
+        },
+        file_hash,
+        block_hash,
+        unanswered_peers: vec![],
+    };
+    for peer_id in peers {
+        match ask(cmd_sender, |sender| DragoonCommand::GetBlocksInfoFrom {
+            peer_id,
+            file_hash: location.file_hash.clone(),
+            block_hash: Some(location.block_hash.clone()),
+            sender,
+        })
+        .await
+        {
+            Ok(info) => {
+                if info.block_hashes.contains(&location.block_hash) {
+                    location.holders.push(peer_id.to_base58());
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "{} did not say whether it has the block {} of {}: {}",
+                    peer_id, location.block_hash, location.file_hash, e
+                );
+                location.unanswered_peers.push(peer_id.to_base58());
+            }
+        }
+    }
+    Ok(location)
+}
+
+pub(crate) async fn replication_status(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    local_peer_id: PeerId,
+    file_hash: String,
+    desired: usize,
+) -> Result<ReplicationStatus> {
+    let local_blocks = ask(cmd_sender, |sender| DragoonCommand::GetBlockList {
+        file_hash: file_hash.clone(),
+        sender,
+    })
+    .await
+    .unwrap_or_default();
+    let holders = block_holders(cmd_sender, local_peer_id, &file_hash).await?;
+    let mut blocks: BTreeMap<String, usize> = local_blocks
+        .into_iter()
+        .map(|block_hash| (block_hash, 0))
+        .collect();
+    blocks.extend(
+        holders
+            .into_iter()
+            .map(|(block_hash, peers)| (block_hash, peers.len())),
+    );
+    Ok(ReplicationStatus {
+        file_hash,
+        desired,
+        current: blocks.values().copied().min().unwrap_or(0),
+        blocks,
+    })
+}
+
+/// Ensure the replication of each file given a factor, see [`replicate_file`]
+pub(crate) async fn replicate_files(
+    cmd_sender: UnboundedSender<DragoonCommand>,
+    local_peer_id: PeerId,
+    factors: Vec<(String, usize)>,
+) {
+    for (file_hash, desired) in factors {
+        match replicate_file(&cmd_sender, local_peer_id, &file_hash, desired).await {
+            Ok(0) => {}
+            Ok(sent) => info!("Sent {} copies of the blocks of {}", sent, file_hash),
+            Err(e) => warn!("Could not replicate {}: {}", file_hash, e),
+        }
+    }
+}
+
+/// Send the blocks of the file held by fewer than `desired` other peers to the connected peers that do not have them,
+/// after recoding new blocks in place of the ones only other peers hold.
+/// Returns the number of blocks accepted by the other peers.
+async fn replicate_file(
+    cmd_sender: &UnboundedSender<DragoonCommand>,
+    local_peer_id: PeerId,
+    file_hash: &str,
+    desired: usize,
+) -> Result<usize> {
+    let local_blocks = ask(cmd_sender, |sender| DragoonCommand::GetBlockList {
+        file_hash: file_hash.to_string(),
+        sender,
+    })
+    .await?;
+    let mut holders = block_holders(cmd_sender, local_peer_id, file_hash).await?;
+    let mut to_place: Vec<(String, HashSet<PeerId>)> = local_blocks
+        .iter()
+        .map(|block_hash| {
+            let peers = holders.remove(block_hash).unwrap_or_default();
+            (block_hash.clone(), peers)
+        })
+        .filter(|(_, peers)| peers.len() < desired)
+        .collect();
+    // the blocks left in `holders` are not on this node, so they cannot be copied
+    let lost = holders
+        .values()
+        .filter(|peers| peers.len() < desired)
+        .count();
+    if lost > 0 && !local_blocks.is_empty() {
+        let new_blocks = ask(cmd_sender, |sender| DragoonCommand::RecodeBlocks {
+            file_hash: file_hash.to_string(),
+            block_hashes: vec![],
+            count: lost,
+            sender,
+        })
+        .await?;
+        debug!(
+            "Recoded {} blocks of {} in place of the ones only other peers hold",
+            new_blocks.len(),
+            file_hash
+        );
+        to_place.extend(
+            new_blocks
+                .into_iter()
+                .map(|block_hash| (block_hash, HashSet::new())),
+        );
+    }
+    if to_place.is_empty() {
+        return Ok(0);
+    }
+
+    let peers = ask(cmd_sender, |sender| DragoonCommand::GetConnectedPeers {
+        sender,
+    })
+    .await?;
+    let mut sent = 0;
+    for (block_hash, mut block_holders) in to_place {
+        for peer_id in peers.iter().filter(|peer_id| **peer_id != local_peer_id) {
+            if block_holders.len() >= desired {
+                break;
+            }
+            if block_holders.contains(peer_id) {
+                continue;
+            }
+            match ask(cmd_sender, |sender| DragoonCommand::SendBlockTo {
+                peer_id: *peer_id,
+                file_hash: file_hash.to_string(),
+                block_hash: block_hash.clone(),
+                storage_class: None,
+                ttl: None,
+                sender,
+            })
+            .await
+            {
+                Ok((true, _)) => {
+                    block_holders.insert(*peer_id);
+                    sent += 1;
+                }
+                Ok((false, _)) => debug!("{} refused the block {}", peer_id, block_hash),
+                Err(e) => debug!(
+                    "Could not send the block {} to {}: {}",
+                    block_hash, peer_id, e
+                ),
+            }
+        }
+        if block_holders.len() < desired {
+            warn!(
+                "The block {} of {} is held by {} other peers, {} are wanted",
+                block_hash,
+                file_hash,
+                block_holders.len(),
+                desired
+            );
+        }
+    }
+    Ok(sent)
+}
@@ -0,0 +1,135 @@
+//! Gaussian elimination of the linear combinations of the blocks, to tell which blocks are linearly independent and
+//! to solve for the source elements of a file
+
+use ark_ff::PrimeField;
+
+struct Row<F> {
+    pivot: usize,
+    coefficients: Vec<F>,
+    /// Goes through the same operations as the coefficients, without being looked at for the pivots, e.g. the data
+    /// of a shard
+    augmented: Vec<F>,
+}
+
+/// Rows kept in row echelon form as they are added: each row has a coefficient of 1 at its pivot, and the rows added
+/// after it have a 0 there
+pub(crate) struct RowEchelon<F: PrimeField> {
+    rows: Vec<Row<F>>,
+}
+
+impl<F: PrimeField> RowEchelon<F> {
+    pub(crate) fn new() -> Self {
+        RowEchelon { rows: vec![] }
+    }
+
+    /// Number of linearly independent rows added so far
+    pub(crate) fn rank(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Add a row, returns false and leaves it out if it is a combination of the rows already added
+    pub(crate) fn add_row(&mut self, coefficients: Vec<F>) -> bool {
+        self.add_augmented_row(coefficients, vec![])
+    }
+
+    /// Same as [`Self::add_row`], `augmented` being reduced along with the coefficients
+    pub(crate) fn add_augmented_row(
+        &mut self,
+        mut coefficients: Vec<F>,
+        mut augmented: Vec<F>,
+    ) -> bool {
+        // a new row only changes the pivots of the rows added before it
+        for row in &self.rows {
+            let factor = coefficients.get(row.pivot).copied().unwrap_or_else(F::zero);
+            if factor.is_zero() {
+                continue;
+            }
+            for (c, r) in coefficients.iter_mut().zip(&row.coefficients) {
+                *c -= factor * r;
+            }
+            for (a, r) in augmented.iter_mut().zip(&row.augmented) {
+                *a -= factor * r;
+            }
+        }
+        let Some(pivot) = coefficients.iter().position(|c| !c.is_zero()) else {
+            return false;
+        };
+        // the pivot is not zero so it can always be inverted
+        let inverse = coefficients[pivot].inverse().unwrap();
+        coefficients.iter_mut().for_each(|c| *c *= inverse);
+        augmented.iter_mut().for_each(|a| *a *= inverse);
+        self.rows.push(Row {
+            pivot,
+            coefficients,
+            augmented,
+        });
+        true
+    }
+
+    /// The augmented part of each row once the coefficients are reduced to the identity, in the order of the pivots,
+    /// `None` if fewer than `width` independent rows were added
+    pub(crate) fn solve(mut self, width: usize) -> Option<Vec<Vec<F>>> {
+        if self.rank() < width {
+            return None;
+        }
+        // back substitution, the last rows already have a 0 at the pivots of the rows before them
+        for i in (0..self.rows.len()).rev() {
+            let (before, after) = self.rows.split_at_mut(i);
+            let row = &after[0];
+            for other in before.iter_mut() {
+                let factor = other.coefficients[row.pivot];
+                if factor.is_zero() {
+                    continue;
+                }
+                for (c, r) in other.coefficients.iter_mut().zip(&row.coefficients) {
+                    *c -= factor * r;
+                }
+                for (a, r) in other.augmented.iter_mut().zip(&row.augmented) {
+                    *a -= factor * r;
+                }
+            }
+        }
+        self.rows.sort_by_key(|row| row.pivot);
+        Some(self.rows.into_iter().map(|row| row.augmented).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bls12_381::Fr;
+
+    fn row(c: &[u64]) -> Vec<Fr> {
+        c.iter().copied().map(Fr::from).collect()
+    }
+
+    #[test]
+    fn dependent_rows_are_left_out() {
+        let mut rows = RowEchelon::<Fr>::new();
+        assert!(rows.add_row(row(&[1, 2, 3])));
+        assert!(rows.add_row(row(&[0, 1, 1])));
+        // the sum of the first row and twice the second one
+        assert!(!rows.add_row(row(&[1, 4, 5])));
+        assert!(!rows.add_row(row(&[0, 0, 0])));
+        assert_eq!(rows.rank(), 2);
+        assert!(rows.add_row(row(&[0, 0, 7])));
+        assert_eq!(rows.rank(), 3);
+        // k independent rows span everything
+        assert!(!rows.add_row(row(&[5, 6, 7])));
+    }
+
+    #[test]
+    fn solve_gives_the_source_of_the_augmented_rows() {
+        // x = 2, y = 3, z = 5, with one column of data each
+        let mut rows = RowEchelon::<Fr>::new();
+        assert!(rows.add_augmented_row(row(&[0, 1, 1]), row(&[8])));
+        assert!(rows.add_augmented_row(row(&[1, 1, 0]), row(&[5])));
+        assert!(!rows.add_augmented_row(row(&[1, 2, 1]), row(&[13])));
+        assert!(rows.add_augmented_row(row(&[1, 0, 2]), row(&[12])));
+        assert_eq!(rows.solve(3), Some(vec![row(&[2]), row(&[3]), row(&[5])]));
+
+        let mut rows = RowEchelon::<Fr>::new();
+        rows.add_augmented_row(row(&[1, 1, 0]), row(&[5]));
+        assert_eq!(rows.solve(3), None);
+    }
+}
@@ -0,0 +1,221 @@
+//! Background check of the integrity of the stored blocks.
+//!
+//! Every `--scrub-interval`, the node reads each block it stores, checks that its content still hashes to the name
+//! of the block and verifies it again against the powers. A block failing one of these checks is moved to the
+//! `quarantine` directory of its file, next to `blocks`, so it is not listed, served nor used to decode the file
+//! anymore but is kept for inspection. The last scrub and the quarantined blocks are shown by scrub-status.
+
+use anyhow::Result;
+use ark_ec::CurveGroup;
+use ark_ff::PrimeField;
+use ark_serialize::Validate;
+use chrono::{SecondsFormat, Utc};
+use komodo::semi_avid::verify;
+use komodo::zk::Powers;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs as sfs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs as tfs;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::block_format;
+use crate::block_quota::BlockQuota;
+use crate::block_store::BlockStore;
+use crate::dragoon_swarm::{get_block_dir, get_file_dir};
+use crate::file_locks::FileLocks;
+use crate::metrics::Metrics;
+use crate::powers_cache::PowersCache;
+
+/// Name of the directory of a file the corrupted blocks are moved to
+pub(crate) const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct QuarantinedBlock {
+    pub(crate) file_hash: String,
+    pub(crate) block_hash: String,
+    /// Why the block was quarantined, unknown for the blocks quarantined before the node started
+    pub(crate) reason: Option<String>,
+    pub(crate) quarantined_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub(crate) struct ScrubStatus {
+    pub(crate) last_started_at: Option<String>,
+    pub(crate) last_finished_at: Option<String>,
+    /// Number of blocks checked by the last scrub
+    pub(crate) blocks_checked: u64,
+    pub(crate) quarantined: Vec<QuarantinedBlock>,
+}
+
+#[derive(Debug, Default)]
+struct ScrubState {
+    last_started_at: Option<String>,
+    last_finished_at: Option<String>,
+    blocks_checked: u64,
+    /// Reason and time of the quarantines done since the node started, by file and block hash
+    quarantines: HashMap<(String, String), (String, String)>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Scrubber {
+    state: Mutex<ScrubState>,
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl Scrubber {
+    /// The last scrub, along with the blocks found in the quarantine directories of `file_dir`
+    pub(crate) fn status(&self, file_dir: &Path) -> ScrubStatus {
+        let state = self.state.lock().unwrap();
+        let mut quarantined = vec![];
+        for file in sfs::read_dir(file_dir).into_iter().flatten().flatten() {
+            let file_hash = file.file_name().to_string_lossy().to_string();
+            for block in sfs::read_dir(file.path().join(QUARANTINE_DIR_NAME))
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let block_hash = block.file_name().to_string_lossy().to_string();
+                let quarantine = state
+                    .quarantines
+                    .get(&(file_hash.clone(), block_hash.clone()));
+                quarantined.push(QuarantinedBlock {
+                    file_hash: file_hash.clone(),
+                    block_hash,
+                    reason: quarantine.map(|(reason, _)| reason.clone()),
+                    quarantined_at: quarantine.map(|(_, at)| at.clone()),
+                });
+            }
+        }
+        ScrubStatus {
+            last_started_at: state.last_started_at.clone(),
+            last_finished_at: state.last_finished_at.clone(),
+            blocks_checked: state.blocks_checked,
+            quarantined,
+        }
+    }
+
+    /// Check all the blocks stored in `file_dir` and quarantine the corrupted ones
+    pub(crate) async fn scrub<F, G>(
+        self: Arc<Self>,
+        file_dir: PathBuf,
+        powers: Arc<PowersCache>,
+        file_locks: Arc<FileLocks>,
+        block_quota: Arc<BlockQuota>,
+        block_store: Arc<BlockStore>,
+        metrics: Arc<Metrics>,
+    ) -> Result<()>
+    where
+        F: PrimeField,
+        G: CurveGroup<ScalarField = F>,
+    {
+        self.state.lock().unwrap().last_started_at = Some(now());
+        let powers = powers.get::<F, G>().await?;
+        let mut checked = 0;
+        let mut quarantined = 0;
+        let mut files = tfs::read_dir(&file_dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            if !file.file_type().await?.is_dir() {
+                continue;
+            }
+            let file_hash = file.file_name().to_string_lossy().to_string();
+            let block_dir = get_block_dir(&file_dir, file_hash.clone());
+            let mut corrupted = vec![];
+            {
+                // the blocks are only read, they can still be sent in the meantime
+                let _read_guard = file_locks.read(&file_hash).await;
+                let Ok(mut blocks) = tfs::read_dir(&block_dir).await else {
+                    continue;
+                };
+                while let Some(block) = blocks.next_entry().await? {
+                    let block_hash = block.file_name().to_string_lossy().to_string();
+                    checked += 1;
+                    if let Err(reason) =
+                        check_block::<F, G>(&block_store, &block.path(), &block_hash, &powers).await
+                    {
+                        corrupted.push((block_hash, reason));
+                    }
+                }
+            }
+            if corrupted.is_empty() {
+                continue;
+            }
+            let quarantine_dir =
+                get_file_dir(&file_dir, file_hash.clone()).join(QUARANTINE_DIR_NAME);
+            let _write_guard = file_locks.write(&file_hash).await;
+            for (block_hash, reason) in corrupted {
+                match quarantine(&block_dir, &quarantine_dir, &block_hash).await {
+                    Ok(()) => {
+                        warn!(
+                            "Quarantined the block {} of {}: {}",
+                            block_hash, file_hash, reason
+                        );
+                        Metrics::incr(&metrics.quarantined_blocks);
+                        quarantined += 1;
+                        self.state
+                            .lock()
+                            .unwrap()
+                            .quarantines
+                            .insert((file_hash.clone(), block_hash), (reason, now()));
+                    }
+                    Err(e) => warn!(
+                        "Could not quarantine the block {} of {} ({}): {}",
+                        block_hash, file_hash, reason, e
+                    ),
+                }
+            }
+            block_quota.rescan_file(&file_hash, &block_dir);
+        }
+        info!(
+            "Scrubbed {} blocks, {} of them were quarantined",
+            checked, quarantined
+        );
+        let mut state = self.state.lock().unwrap();
+        state.last_finished_at = Some(now());
+        state.blocks_checked = checked;
+        Ok(())
+    }
+}
+
+/// Check that the block still hashes to its name and is still valid, returns why it is not
+async fn check_block<F, G>(
+    block_store: &BlockStore,
+    block_path: &Path,
+    block_hash: &str,
+    powers: &Powers<F, G>,
+) -> Result<(), String>
+where
+    F: PrimeField,
+    G: CurveGroup<ScalarField = F>,
+{
+    let ser_block = block_store
+        .read_async(block_path)
+        .await
+        .map_err(|e| format!("the block could not be read: {}", e))?;
+    let content_hash = block_format::block_hash(&ser_block)
+        .map_err(|e| format!("the block could not be read: {}", e))?;
+    if content_hash != block_hash {
+        return Err(String::from(
+            "the content of the block does not match its hash",
+        ));
+    }
+    let block = block_format::deserialize::<F, G>(&ser_block, Validate::Yes)
+        .map_err(|e| format!("the block could not be deserialized: {}", e))?;
+    match verify(&block, powers) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(String::from("the block does not verify against the powers")),
+        Err(e) => Err(format!("the block could not be verified: {}", e)),
+    }
+}
+
+/// Move the block from the blocks of its file to the quarantine directory of the file
+async fn quarantine(block_dir: &Path, quarantine_dir: &Path, block_hash: &str) -> Result<()> {
+    tfs::create_dir_all(quarantine_dir).await?;
+    tfs::rename(block_dir.join(block_hash), quarantine_dir.join(block_hash)).await?;
+    Ok(())
+}
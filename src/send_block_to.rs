@@ -1,11 +1,13 @@
+mod accounting;
 mod protocol;
 
-use std::fs as sfs;
-use std::io::{BufRead, Write};
+use std::fmt;
+use std::io;
+use std::time::Duration;
 use std::{
     path::PathBuf,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize},
         Arc,
     },
 };
@@ -15,30 +17,240 @@ use ark_ec::CurveGroup;
 use ark_ff::PrimeField;
 use ark_poly::DenseUVPolynomial;
 use ark_std::ops::Div;
-use chrono::Utc;
 use futures::StreamExt;
-use libp2p_stream::IncomingStreams;
+use libp2p::{PeerId, Stream};
+use libp2p_stream::{Control, OpenStreamError};
+use rand::Rng;
 use tokio::sync::{
     mpsc::{self, Receiver},
-    Semaphore,
+    Mutex, Semaphore,
 };
+use tokio::time;
 use tracing::{debug, error};
 
-use crate::dragoon_swarm;
+use crate::block_quota::BlockQuota;
+use crate::block_store::BlockStore;
+use crate::dragoon_swarm::{SEND_BLOCK_PROTOCOL, SEND_BLOCK_PROTOCOL_V1};
+use crate::error::DragoonError::{self, SendBlockOpenStreamError, SendBlockToError};
+use crate::file_locks::FileLocks;
+use crate::metrics::Metrics;
+use crate::peer_quota::PeerQuotas;
+use crate::powers_cache::PowersCache;
+use crate::recent_events::{RecentEventKind, RecentEvents};
+use crate::send_strategy::SendId;
+use crate::shutdown::InFlight;
+use crate::storage_class::{StorageClass, StorageClasses};
+use crate::wire_codec::WireFormat;
+use protocol::SendFileEntry;
 
-pub(crate) use protocol::handle_send_block_exchange_sender_side as send_block_to;
+pub(crate) use accounting::SendAccounting;
+
+/// Why the stream to send a block to a peer could not be opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OpenStreamFailure {
+    /// We are not connected to the peer and could not reach it
+    PeerUnreachable,
+    /// The peer does not support the protocol to send blocks
+    ProtocolUnsupported,
+    /// The peer or the connection could not handle another stream for now
+    Busy,
+}
+
+impl OpenStreamFailure {
+    pub(crate) fn classify(error: &OpenStreamError) -> Self {
+        match error {
+            OpenStreamError::UnsupportedProtocol(_) => OpenStreamFailure::ProtocolUnsupported,
+            OpenStreamError::Io(e) => match e.kind() {
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted => OpenStreamFailure::Busy,
+                _ => OpenStreamFailure::PeerUnreachable,
+            },
+            // the other failures all come from the connection to the peer
+            _ => OpenStreamFailure::PeerUnreachable,
+        }
+    }
+}
+
+impl fmt::Display for OpenStreamFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenStreamFailure::PeerUnreachable => write!(f, "the peer is unreachable"),
+            OpenStreamFailure::ProtocolUnsupported => {
+                write!(f, "the peer does not support the send block protocol")
+            }
+            OpenStreamFailure::Busy => write!(f, "the peer is busy"),
+        }
+    }
+}
+
+/// How a block is sent again to a peer when the stream could not be opened or the exchange failed
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SendRetryPolicy {
+    /// Number of times the block is sent before giving up, at least 1
+    pub(crate) max_attempts: u32,
+    /// Wait before the second attempt, doubled after each failed attempt
+    pub(crate) backoff: Duration,
+    /// The wait between two attempts never goes above this
+    pub(crate) max_backoff: Duration,
+    /// Each wait is changed by a random fraction of itself up to this, between 0 and 1
+    pub(crate) jitter: f64,
+}
+
+impl SendRetryPolicy {
+    /// The wait after the failure of the attempt `attempt`, counted from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let jitter = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        backoff.mul_f64(1.0 + jitter)
+    }
+}
+
+/// Parse a `--send-retry-jitter`, a fraction of the wait between 0 and 1
+pub(crate) fn parse_jitter(s: &str) -> Result<f64, String> {
+    let jitter: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid jitter {:?}: {}", s, e))?;
+    if !(0.0..=1.0).contains(&jitter) {
+        return Err(format!("the jitter must be between 0 and 1, got {}", s));
+    }
+    Ok(jitter)
+}
+
+/// Open a stream with the version 2 of the send protocol, or the version 1 if the peer does not know it yet
+async fn open_send_stream(
+    control: &mut Control,
+    peer_id: PeerId,
+) -> Result<(Stream, WireFormat), OpenStreamError> {
+    match control.open_stream(peer_id, SEND_BLOCK_PROTOCOL).await {
+        Ok(stream) => Ok((stream, WireFormat::Bincode)),
+        Err(OpenStreamError::UnsupportedProtocol(_)) => {
+            debug!(
+                "The peer {} does not know the version 2 of the send protocol",
+                peer_id
+            );
+            let stream = control.open_stream(peer_id, SEND_BLOCK_PROTOCOL_V1).await?;
+            Ok((stream, WireFormat::Json))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Open a stream to the peer and send it the block, again after a backoff when the stream could not be opened or
+/// the exchange failed, as long as the retry policy allows. A peer refusing the block answered, so it is not retried.
+/// The returned [`SendId`] tells how many attempts were made.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn send_block_to(
+    control: &mut Control,
+    retry_policy: SendRetryPolicy,
+    file_locks: &FileLocks,
+    block_store: &BlockStore,
+    own_peer_id: PeerId,
+    peer_id: PeerId,
+    block_hash: String,
+    file_hash: String,
+    file_dir: PathBuf,
+    storage_class: Option<StorageClass>,
+    ttl: Option<u64>,
+) -> Result<(bool, SendId), DragoonError> {
+    let mut attempt = 1;
+    loop {
+        let mut res = match open_send_stream(control, peer_id).await {
+            Ok((stream, format)) => {
+                let _read_guard = file_locks.read(&file_hash).await;
+                protocol::handle_send_block_exchange_sender_side(
+                    stream,
+                    format,
+                    block_store,
+                    own_peer_id,
+                    peer_id,
+                    block_hash.clone(),
+                    file_hash.clone(),
+                    file_dir.clone(),
+                    storage_class,
+                    ttl,
+                )
+                .await
+                .map_err(|send_id| SendBlockToError { send_id })
+            }
+            Err(e) => Err(SendBlockOpenStreamError {
+                send_id: SendId {
+                    peer_id,
+                    file_hash: file_hash.clone(),
+                    block_hash: block_hash.clone(),
+                    attempts: 1,
+                },
+                failure: OpenStreamFailure::classify(&e),
+                context: e.to_string(),
+            }),
+        };
+        let retryable = match &res {
+            Ok(_) => false,
+            // the peer will not learn the protocol in the meantime
+            Err(SendBlockOpenStreamError { failure, .. }) => {
+                *failure != OpenStreamFailure::ProtocolUnsupported
+            }
+            Err(_) => true,
+        };
+        if !retryable || attempt >= retry_policy.max_attempts {
+            if let Ok((_, send_id))
+            | Err(SendBlockToError { send_id })
+            | Err(SendBlockOpenStreamError { send_id, .. }) = &mut res
+            {
+                send_id.attempts = attempt;
+            }
+            if let Err(e) = &res {
+                error!("{} after {} attempts", e, attempt);
+            }
+            return res;
+        }
+        let backoff = retry_policy.backoff(attempt);
+        if let Err(e) = &res {
+            debug!(
+                "Attempt {} to send the block {} to {} failed, retrying in {:?}: {}",
+                attempt, block_hash, peer_id, backoff, e
+            );
+        }
+        time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// How long the blocks received through send requests are kept
+#[derive(Debug, Clone)]
+pub(crate) struct BlockExpiryConfig {
+    /// Applied to the blocks whose sender did not give a TTL, they are kept forever if not given
+    pub(crate) default_ttl: Option<Duration>,
+    /// Time between two collections of the expired blocks
+    pub(crate) gc_interval: Duration,
+}
 
 #[derive(Clone)]
 pub(crate) struct SendBlockHandler {}
 
 /// An async handler to spawn on a node when we want to automatically manage receiving blocks coming from send requests
 impl SendBlockHandler {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn run<F, G, P>(
-        mut incoming_streams: IncomingStreams,
-        powers_path: PathBuf,
+        mut incoming_streams: impl futures::Stream<Item = (PeerId, Stream, WireFormat)>
+            + Unpin
+            + Send
+            + 'static,
+        powers: Arc<PowersCache>,
         file_dir: PathBuf,
         current_available_storage: Arc<AtomicUsize>,
-        total_block_size_on_disk: Arc<AtomicUsize>,
+        receiving_paused: Arc<AtomicBool>,
+        send_accounting: Arc<Mutex<SendAccounting>>,
+        storage_classes: Arc<StorageClasses>,
+        block_store: Arc<BlockStore>,
+        block_quota: Arc<BlockQuota>,
+        peer_quotas: Arc<PeerQuotas>,
+        metrics: Arc<Metrics>,
+        in_flight: Arc<InFlight>,
+        recent_events: Arc<RecentEvents>,
     ) -> Result<()>
     where
         F: PrimeField,
@@ -51,18 +263,30 @@ impl SendBlockHandler {
             let max_send_request = 10;
             let semaphore = Arc::new(Semaphore::new(max_send_request));
             let (write_to_file_sender, write_to_file_recv) = mpsc::channel(max_send_request);
-            tokio::task::spawn_blocking(move || {
-                Self::add_new_block_info_to_send_file(write_to_file_recv, total_block_size_on_disk)
-            });
+            tokio::spawn(Self::record_received_blocks(
+                write_to_file_recv,
+                send_accounting,
+                recent_events,
+            ));
             loop {
                 let permit = semaphore.clone().acquire_owned().await.unwrap();
-                if let Some((peer, stream)) = incoming_streams.next().await {
-                    let p_path = powers_path.clone();
+                if let Some((peer, stream, format)) = incoming_streams.next().await {
+                    let Some(in_flight_guard) = in_flight.start() else {
+                        debug!("Dropping the block sent by {}, the node is stopping", peer);
+                        continue;
+                    };
+                    let powers = powers.clone();
                     let f_dir = file_dir.clone();
                     let new_current_available_storage = current_available_storage.clone();
+                    let receiving_paused = receiving_paused.clone();
                     let new_write_to_file_sender = write_to_file_sender.clone();
+                    let storage_classes = storage_classes.clone();
+                    let block_store = block_store.clone();
+                    let block_quota = block_quota.clone();
+                    let peer_quotas = peer_quotas.clone();
+                    let metrics = metrics.clone();
                     tokio::spawn(async move {
-                        match protocol::handle_send_block_exchange_recv_side::<F, G, P>(stream, p_path, f_dir, new_current_available_storage, new_write_to_file_sender).await {
+                        match protocol::handle_send_block_exchange_recv_side::<F, G, P>(peer, stream, format, powers, f_dir, new_current_available_storage, receiving_paused, new_write_to_file_sender, storage_classes, block_store, block_quota, peer_quotas, metrics, in_flight_guard).await {
                             Ok(_) => {debug!("Finished getting block from peer {} without issue", peer)},
                             Err(e) => error!("The stream with the peer {} for receiving a block due to a send request has been dropped due to an handling error: {}", peer, e)
                         }
@@ -77,75 +301,22 @@ impl SendBlockHandler {
         Ok(())
     }
 
-    /// Used to synchronously modify the file that lists all the blocks
-    fn add_new_block_info_to_send_file(
-        mut receiver: Receiver<(PathBuf, usize, String, String, String)>,
-        total_block_size_on_disk: Arc<AtomicUsize>,
+    /// Record the received blocks one at a time, so the list and the total are never written concurrently
+    async fn record_received_blocks(
+        mut receiver: Receiver<SendFileEntry>,
+        send_accounting: Arc<Mutex<SendAccounting>>,
+        recent_events: Arc<RecentEvents>,
     ) {
-        while let Some((file_dir, size_of_block, file_hash, block_hash, peer_id_base_58)) =
-            receiver.blocking_recv()
-        {
-            match Self::add_send_file_inner(
-                file_dir,
-                total_block_size_on_disk.clone(),
-                size_of_block,
-                file_hash,
-                block_hash,
-                peer_id_base_58,
-            ) {
-                Ok(_) => {}
-                Err(e) => error!("{}", e),
+        while let Some(entry) = receiver.recv().await {
+            let peer_id = bs58::decode(&entry.peer_id_base_58)
+                .into_vec()
+                .ok()
+                .and_then(|bytes| PeerId::from_bytes(&bytes).ok());
+            let message = format!("Block {} of file {}", entry.block_hash, entry.file_hash);
+            match send_accounting.lock().await.record_block(entry).await {
+                Ok(()) => recent_events.record(RecentEventKind::BlockReceived, peer_id, message),
+                Err(e) => error!("Could not record the received block: {}", e),
             }
         }
     }
-    fn add_send_file_inner(
-        file_dir: PathBuf,
-        total_block_size_on_disk: Arc<AtomicUsize>,
-        size_of_block: usize,
-        file_hash: String,
-        block_hash: String,
-        peer_id_base_58: String,
-    ) -> Result<()> {
-        total_block_size_on_disk.fetch_add(size_of_block, Ordering::SeqCst);
-        let old_send_file_path: PathBuf =
-            [file_dir, PathBuf::from(dragoon_swarm::SEND_BLOCK_FILE_NAME)]
-                .iter()
-                .collect();
-        let mut new_send_file_path = old_send_file_path.clone();
-        new_send_file_path.set_extension("new.txt");
-        //TODO remove the created file if we return on an error
-        let mut new_send_file = sfs::File::options()
-            .read(true)
-            .append(true)
-            .create_new(true)
-            .open(&new_send_file_path)?;
-        new_send_file.write_all(
-            format!(
-                "Total: {}\n",
-                total_block_size_on_disk.load(Ordering::Relaxed)
-            )
-            .as_bytes(),
-        )?;
-        let old_file = sfs::File::open(&old_send_file_path)?;
-        let mut old_file = std::io::BufReader::new(old_file);
-        // skip the first line (which is the old total)
-        old_file.read_line(&mut String::new())?;
-        //file is in append mode so we are putting the content of the old file in the new file (except the first line)
-        std::io::copy(&mut old_file, &mut new_send_file)?;
-        // now append the information about the new block
-        new_send_file.write_all(
-            format!(
-                "Size: {} | Timestamp: {} | file_hash: {} | block_hash: {} | peer_id: {}\n",
-                size_of_block,
-                Utc::now(),
-                file_hash,
-                block_hash,
-                peer_id_base_58,
-            )
-            .as_bytes(),
-        )?;
-        // move the new file on the name of the old file
-        sfs::rename(new_send_file_path, old_send_file_path)?;
-        Ok(())
-    }
 }
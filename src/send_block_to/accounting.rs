@@ -0,0 +1,427 @@
+//! Accounting of the blocks received through send requests.
+//!
+//! The blocks are recorded in an embedded database, `send_blocks.db`, with their file, size, origin peer and
+//! reception time, next to the total size they take on disk. A block and the total are updated in a single
+//! transaction, so the database never holds a total that does not match its blocks.
+//! The storage used by the blocks of each peer is kept in the same transactions, in the `peer_usage` tree, and the
+//! quotas set for some peers in the `peer_quotas` tree, see [`crate::peer_quota`].
+//! The `send_block_list.txt` and `send_block_total.txt` files written by older versions are imported when the
+//! node starts, and kept with a `.migrated` extension.
+
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree,
+};
+use sled::{Db, Transactional, Tree};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tracing::{info, warn};
+
+use super::protocol::SendFileEntry;
+use crate::dragoon_swarm::SEND_BLOCK_FILE_NAME;
+use crate::peer_quota::PeerQuotas;
+use crate::storage_class::StorageClass;
+
+pub(crate) const SEND_BLOCK_DB_NAME: &str = "send_blocks.db";
+const SEND_BLOCK_TOTAL_FILE_NAME: &str = "send_block_total.txt";
+const BLOCKS_TREE: &str = "blocks";
+/// Size of the blocks of each peer, by peer id
+const USAGE_TREE: &str = "peer_usage";
+/// Quota of the peers for which one was set, by peer id
+const QUOTAS_TREE: &str = "peer_quotas";
+const TOTAL_KEY: &str = "total";
+/// Set once the text files have been imported, so they are never imported twice
+const MIGRATED_KEY: &str = "migrated";
+
+/// A block received through a send request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SendBlockRecord {
+    pub(crate) size: usize,
+    pub(crate) file_hash: String,
+    pub(crate) block_hash: String,
+    pub(crate) peer_id: String,
+    pub(crate) timestamp: String,
+    pub(crate) storage_class: Option<StorageClass>,
+    /// Unix time in seconds after which the block is deleted, never if not given
+    #[serde(default)]
+    pub(crate) expires_at: Option<i64>,
+}
+
+impl SendBlockRecord {
+    fn key(&self) -> String {
+        block_key(&self.file_hash, &self.block_hash)
+    }
+
+    /// Parse a line of the `send_block_list.txt` of older versions
+    fn from_line(line: &str) -> Option<Self> {
+        let field = |name: &str| {
+            line.split(" | ")
+                .find_map(|field| field.strip_prefix(name)?.strip_prefix(": "))
+        };
+        Some(SendBlockRecord {
+            size: field("Size")?.parse().ok()?,
+            file_hash: field("file_hash")?.to_string(),
+            block_hash: field("block_hash")?.to_string(),
+            peer_id: field("peer_id").unwrap_or_default().to_string(),
+            timestamp: field("Timestamp").unwrap_or_default().to_string(),
+            storage_class: field("class")
+                .and_then(|class| serde_json::from_value(class.into()).ok()),
+            expires_at: None,
+        })
+    }
+}
+
+fn block_key(file_hash: &str, block_hash: &str) -> String {
+    format!("{}/{}", file_hash, block_hash)
+}
+
+fn decode_total(value: Option<sled::IVec>) -> usize {
+    value
+        .and_then(|value| value.as_ref().try_into().ok())
+        .map_or(0, |bytes| u64::from_be_bytes(bytes) as usize)
+}
+
+fn encode_total(total: usize) -> [u8; 8] {
+    (total as u64).to_be_bytes()
+}
+
+/// Change the storage used by the peer in the `peer_usage` tree, as part of a transaction
+fn update_usage(
+    usage: &TransactionalTree,
+    peer_id: &str,
+    added: usize,
+    removed: usize,
+) -> ConflictableTransactionResult<(), ()> {
+    let used = (decode_total(usage.get(peer_id.as_bytes())?) + added).saturating_sub(removed);
+    if used == 0 {
+        usage.remove(peer_id.as_bytes())?;
+    } else {
+        usage.insert(peer_id.as_bytes(), &encode_total(used))?;
+    }
+    Ok(())
+}
+
+/// Read a tree of sizes by peer id
+fn read_sizes(tree: &Tree) -> Result<HashMap<String, usize>> {
+    let mut sizes = HashMap::new();
+    for entry in tree.iter() {
+        let (peer_id, size) = entry?;
+        let size = decode_total(Some(size));
+        if size > 0 {
+            sizes.insert(String::from_utf8_lossy(&peer_id).to_string(), size);
+        }
+    }
+    Ok(sizes)
+}
+
+#[derive(Debug)]
+pub(crate) struct SendAccounting {
+    total_block_size_on_disk: Arc<AtomicUsize>,
+    peer_quotas: Arc<PeerQuotas>,
+    /// How long the blocks are kept when their sender did not say
+    default_block_ttl: Option<Duration>,
+    /// The database of the file directory it was opened in, which changes when the identity is rotated
+    db: Option<(PathBuf, Db)>,
+}
+
+impl SendAccounting {
+    pub(crate) fn new(
+        total_block_size_on_disk: Arc<AtomicUsize>,
+        peer_quotas: Arc<PeerQuotas>,
+        default_block_ttl: Option<Duration>,
+    ) -> Self {
+        SendAccounting {
+            total_block_size_on_disk,
+            peer_quotas,
+            default_block_ttl,
+            db: None,
+        }
+    }
+
+    fn open(&mut self, file_dir: &Path) -> Result<(Tree, Tree, Tree)> {
+        if self.db.as_ref().map(|(dir, _)| dir.as_path()) != Some(file_dir) {
+            // the database is locked while open, so the previous one is closed first
+            self.db = None;
+            let db = sled::open(file_dir.join(SEND_BLOCK_DB_NAME))?;
+            self.db = Some((file_dir.to_path_buf(), db));
+        }
+        let (_, db) = self.db.as_ref().unwrap();
+        Ok((
+            db.open_tree(BLOCKS_TREE)?,
+            (**db).clone(),
+            db.open_tree(USAGE_TREE)?,
+        ))
+    }
+
+    fn quotas_tree(&mut self, file_dir: &Path) -> Result<Tree> {
+        self.open(file_dir)?;
+        let (_, db) = self.db.as_ref().unwrap();
+        Ok(db.open_tree(QUOTAS_TREE)?)
+    }
+
+    /// Flush the database and close it, it is opened again when it is used next
+    pub(crate) async fn close(&mut self) -> Result<()> {
+        if let Some((_, db)) = self.db.take() {
+            db.flush_async().await?;
+        }
+        Ok(())
+    }
+
+    /// Read the total size of the blocks received through send requests that are stored in `file_dir`,
+    /// importing the text files of older versions first. The usage and the quotas of the peers are loaded too.
+    pub(crate) async fn load(&mut self, file_dir: &Path) -> Result<usize> {
+        let (blocks, meta, usage) = self.open(file_dir)?;
+        let list_path = file_dir.join(SEND_BLOCK_FILE_NAME);
+        if fs::try_exists(&list_path).await? {
+            migrate_text_files(file_dir, &blocks, &meta).await?;
+        }
+        if usage.is_empty() && !blocks.is_empty() {
+            rebuild_usage(&blocks, &usage).await?;
+        }
+        let total = decode_total(meta.get(TOTAL_KEY)?);
+        self.total_block_size_on_disk.store(total, Ordering::SeqCst);
+        let quotas = self.quotas_tree(file_dir)?;
+        self.peer_quotas
+            .restore(read_sizes(&usage)?, read_sizes(&quotas)?);
+        Ok(total)
+    }
+
+    /// Set the quota of a peer, back to the default one if `None`
+    pub(crate) async fn set_quota(
+        &mut self,
+        file_dir: &Path,
+        peer_id: &str,
+        quota: Option<usize>,
+    ) -> Result<()> {
+        let quotas = self.quotas_tree(file_dir)?;
+        match quota {
+            Some(quota) => quotas.insert(peer_id.as_bytes(), &encode_total(quota))?,
+            None => quotas.remove(peer_id.as_bytes())?,
+        };
+        quotas.flush_async().await?;
+        self.peer_quotas.set(peer_id, quota);
+        Ok(())
+    }
+
+    /// Add a received block to the database and to the total
+    pub(super) async fn record_block(&mut self, entry: SendFileEntry) -> Result<()> {
+        let SendFileEntry {
+            file_dir,
+            size_of_block,
+            file_hash,
+            block_hash,
+            peer_id_base_58,
+            storage_class,
+            ttl,
+            in_flight: _in_flight,
+        } = entry;
+        let now = Utc::now();
+        let ttl = ttl.map(Duration::from_secs).or(self.default_block_ttl);
+        let record = SendBlockRecord {
+            size: size_of_block,
+            file_hash,
+            block_hash,
+            peer_id: peer_id_base_58,
+            timestamp: now.to_rfc3339(),
+            storage_class,
+            expires_at: ttl.map(|ttl| now.timestamp().saturating_add(ttl.as_secs() as i64)),
+        };
+        let (blocks, meta, usage) = self.open(&file_dir)?;
+        let value = serde_json::to_vec(&record)?;
+        let (total, previous) = (&blocks, &meta, &usage)
+            .transaction(|(blocks, meta, usage)| {
+                let previous = blocks
+                    .insert(record.key().as_bytes(), value.as_slice())?
+                    .and_then(|previous| serde_json::from_slice::<SendBlockRecord>(&previous).ok());
+                let previous_size = previous.as_ref().map_or(0, |previous| previous.size);
+                let total = (decode_total(meta.get(TOTAL_KEY)?) + record.size)
+                    .saturating_sub(previous_size);
+                meta.insert(TOTAL_KEY, &encode_total(total))?;
+                if let Some(previous) = &previous {
+                    update_usage(usage, &previous.peer_id, 0, previous.size)?;
+                }
+                update_usage(usage, &record.peer_id, record.size, 0)?;
+                Ok::<_, ConflictableTransactionError<()>>((total, previous))
+            })
+            .map_err(|e| format_err!("Could not record the block {}: {:?}", record.key(), e))?;
+        if let Some(previous) = previous {
+            warn!(
+                "The block {} had already been received, its record is replaced",
+                record.key()
+            );
+            // the new block was counted for its peer when it was accepted
+            self.peer_quotas.release(&previous.peer_id, previous.size);
+        }
+        blocks.flush_async().await?;
+        self.total_block_size_on_disk.store(total, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Remove the blocks of a file from the database and from the total, returns the size they took
+    pub(crate) async fn remove_file(&mut self, file_dir: &Path, file_hash: &str) -> Result<usize> {
+        let (blocks, _, _) = self.open(file_dir)?;
+        let keys: Vec<sled::IVec> = blocks
+            .scan_prefix(format!("{}/", file_hash).as_bytes())
+            .keys()
+            .collect::<Result<_, _>>()?;
+        self.remove_keys(file_dir, keys)
+            .await
+            .map_err(|e| format_err!("Could not remove the blocks of {}: {}", file_hash, e))
+    }
+
+    /// Remove a block from the database and from the total, returns the size it took
+    pub(crate) async fn remove_block(
+        &mut self,
+        file_dir: &Path,
+        file_hash: &str,
+        block_hash: &str,
+    ) -> Result<usize> {
+        let key = block_key(file_hash, block_hash);
+        self.remove_keys(file_dir, vec![key.as_bytes().into()])
+            .await
+            .map_err(|e| format_err!("Could not remove the block {}: {}", key, e))
+    }
+
+    /// The blocks whose expiry has passed
+    pub(crate) fn expired_blocks(&mut self, file_dir: &Path) -> Result<Vec<SendBlockRecord>> {
+        let (blocks, _, _) = self.open(file_dir)?;
+        let now = Utc::now().timestamp();
+        let mut expired = vec![];
+        for entry in blocks.iter() {
+            let (_, value) = entry?;
+            match serde_json::from_slice::<SendBlockRecord>(&value) {
+                Ok(record) if record.expires_at.is_some_and(|expiry| expiry <= now) => {
+                    expired.push(record)
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Skipping an unreadable send block record: {}", e),
+            }
+        }
+        Ok(expired)
+    }
+
+    async fn remove_keys(&mut self, file_dir: &Path, keys: Vec<sled::IVec>) -> Result<usize> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let (blocks, meta, usage) = self.open(file_dir)?;
+        let (freed, total, freed_by_peer) = (&blocks, &meta, &usage)
+            .transaction(|(blocks, meta, usage)| {
+                let mut freed_by_peer: HashMap<String, usize> = HashMap::new();
+                for key in &keys {
+                    if let Some(record) = blocks.remove(key)? {
+                        if let Ok(record) = serde_json::from_slice::<SendBlockRecord>(&record) {
+                            *freed_by_peer.entry(record.peer_id).or_default() += record.size;
+                        }
+                    }
+                }
+                for (peer_id, size) in &freed_by_peer {
+                    update_usage(usage, peer_id, 0, *size)?;
+                }
+                let freed: usize = freed_by_peer.values().sum();
+                let total = decode_total(meta.get(TOTAL_KEY)?).saturating_sub(freed);
+                meta.insert(TOTAL_KEY, &encode_total(total))?;
+                Ok::<_, ConflictableTransactionError<()>>((freed, total, freed_by_peer))
+            })
+            .map_err(|e| format_err!("{:?}", e))?;
+        blocks.flush_async().await?;
+        self.total_block_size_on_disk.store(total, Ordering::SeqCst);
+        for (peer_id, size) in freed_by_peer {
+            self.peer_quotas.release(&peer_id, size);
+        }
+        Ok(freed)
+    }
+}
+
+/// Import the blocks listed in the `send_block_list.txt` of older versions, then move the text files away
+async fn migrate_text_files(file_dir: &Path, blocks: &Tree, meta: &Tree) -> Result<()> {
+    let list_path = file_dir.join(SEND_BLOCK_FILE_NAME);
+    let total_path = file_dir.join(SEND_BLOCK_TOTAL_FILE_NAME);
+    info!("Importing {:?} in the send block database", list_path);
+    let list = fs::read_to_string(&list_path).await?;
+    // the oldest versions kept the total on the first line of the list
+    let listed_total = match list.lines().next().and_then(|l| l.strip_prefix("Total: ")) {
+        Some(total) => Some(total.trim().parse::<usize>()?),
+        None => None,
+    };
+    let mut records = vec![];
+    for line in list
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with("Total: "))
+    {
+        match SendBlockRecord::from_line(line) {
+            Some(record) => records.push(record),
+            None => warn!("Could not parse the line {:?} of {:?}", line, list_path),
+        }
+    }
+    let total = match fs::read_to_string(&total_path).await {
+        Ok(total) => total
+            .trim()
+            .parse::<usize>()
+            .map_err(|e| format_err!("Could not parse the total in {:?}: {}", total_path, e))?,
+        Err(_) => listed_total.unwrap_or_else(|| records.iter().map(|r| r.size).sum()),
+    };
+
+    if meta.contains_key(MIGRATED_KEY)? {
+        warn!("{:?} has already been imported", list_path);
+        return move_text_files(list_path, total_path).await;
+    }
+    let values = records
+        .iter()
+        .map(|record| Ok((record.key(), serde_json::to_vec(record)?)))
+        .collect::<Result<Vec<_>>>()?;
+    (blocks, meta)
+        .transaction(|(blocks, meta)| {
+            for (key, value) in &values {
+                blocks.insert(key.as_bytes(), value.as_slice())?;
+            }
+            let total = decode_total(meta.get(TOTAL_KEY)?) + total;
+            meta.insert(TOTAL_KEY, &encode_total(total))?;
+            meta.insert(MIGRATED_KEY, &[1])?;
+            Ok::<_, ConflictableTransactionError<()>>(())
+        })
+        .map_err(|e| format_err!("Could not import {:?}: {:?}", list_path, e))?;
+    blocks.flush_async().await?;
+    info!(
+        "Imported {} blocks taking {} bytes from {:?}",
+        records.len(),
+        total,
+        list_path
+    );
+    move_text_files(list_path, total_path).await
+}
+
+/// Compute the storage used by each peer from the blocks, for the databases written before it was kept
+async fn rebuild_usage(blocks: &Tree, usage: &Tree) -> Result<()> {
+    let mut used: HashMap<String, usize> = HashMap::new();
+    for entry in blocks.iter() {
+        let (_, value) = entry?;
+        match serde_json::from_slice::<SendBlockRecord>(&value) {
+            Ok(record) => *used.entry(record.peer_id).or_default() += record.size,
+            Err(e) => warn!("Skipping an unreadable send block record: {}", e),
+        }
+    }
+    for (peer_id, size) in &used {
+        usage.insert(peer_id.as_bytes(), &encode_total(*size))?;
+    }
+    usage.flush_async().await?;
+    info!("Computed the storage used by {} peers", used.len());
+    Ok(())
+}
+
+async fn move_text_files(list_path: PathBuf, total_path: PathBuf) -> Result<()> {
+    for path in [list_path, total_path] {
+        if fs::try_exists(&path).await? {
+            let mut migrated = path.clone().into_os_string();
+            migrated.push(".migrated");
+            fs::rename(&path, migrated).await?;
+        }
+    }
+    Ok(())
+}
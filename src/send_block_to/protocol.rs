@@ -2,7 +2,7 @@ use anyhow::{format_err, Result};
 use ark_ec::CurveGroup;
 use ark_ff::PrimeField;
 use ark_poly::DenseUVPolynomial;
-use ark_serialize::{CanonicalDeserialize, Compress, Validate};
+use ark_serialize::Validate;
 use ark_std::ops::Div;
 use futures::{AsyncReadExt, AsyncWriteExt};
 use komodo::semi_avid::{verify, Block};
@@ -11,21 +11,28 @@ use std::path::PathBuf;
 use std::{
     mem::size_of,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
 };
 use strum::FromRepr;
-use tokio::fs::{self, File};
 use tokio::sync::mpsc::Sender;
 
 use tracing::{debug, error, info, warn};
 
-use komodo::zk::Powers;
-
+use crate::block_format;
+use crate::block_quota::{BlockQuota, BlockReservation};
+use crate::block_store::BlockStore;
+use crate::lifecycle::{self, LifecycleStage};
+use crate::metrics::Metrics;
+use crate::peer_quota::PeerQuotas;
+use crate::powers_cache::PowersCache;
 use crate::send_strategy::SendId;
+use crate::shutdown::InFlightGuard;
+use crate::storage_class::{StorageClass, StorageClasses};
+use crate::wire_codec::WireFormat;
 use crate::{
-    dragoon_swarm::{get_block_dir, get_powers},
+    dragoon_swarm::{get_block_dir, get_file_dir},
     peer_block_info::PeerBlockInfo,
 };
 
@@ -38,43 +45,65 @@ enum ExchangeCode {
     RejectBlockSend,
     BlockIsCorrect,
     BlockIsIncorrect,
+    /// The receiver does not accept blocks for now, e.g. during a maintenance
+    ReceivingPaused,
 }
 
 // -------------------- SENDER -------------------- //
 
 /// Build the information regarding the block to be sent, includes the block hash, file hash, and size of the block
 async fn build_peer_block_info(
+    block_store: &BlockStore,
     peer_id: PeerId,
     block_hash: String,
     file_hash: String,
     file_dir: PathBuf,
+    storage_class: Option<StorageClass>,
+    ttl: Option<u64>,
 ) -> Result<PeerBlockInfo> {
     let block_dir = get_block_dir(&file_dir, file_hash.clone());
     let block_path: PathBuf = [block_dir, PathBuf::from(block_hash.clone())]
         .iter()
         .collect();
-    let block_file = File::open(block_path).await?;
-    let block_size = block_file.metadata().await?.len();
+    // the size of the block once read, which is not the size of the file when it is encrypted
+    let block_size = block_store.read_async(&block_path).await?.len();
+    let correlation_id =
+        lifecycle::correlation_id(&get_file_dir(&file_dir, file_hash.clone())).await;
 
     Ok(PeerBlockInfo {
         peer_id_base_58: peer_id.to_base58(),
         file_hash,
         block_hashes: vec![block_hash],
-        block_sizes: Some(vec![block_size as usize]),
+        block_sizes: Some(vec![block_size]),
+        storage_class,
+        correlation_id,
+        ttl,
     })
 }
 
 /// Send the peer block info to the other end of the stream
 async fn send_peer_block_info(
     stream: &mut Stream,
+    format: WireFormat,
+    block_store: &BlockStore,
     own_peer_id: PeerId,
     block_hash: String,
     file_hash: String,
     file_dir: PathBuf,
+    storage_class: Option<StorageClass>,
+    ttl: Option<u64>,
 ) -> Result<()> {
-    let peer_block_info =
-        build_peer_block_info(own_peer_id, block_hash, file_hash, file_dir).await?;
-    let ser_peer_block_info = serde_json::to_vec(&peer_block_info)?;
+    let peer_block_info = build_peer_block_info(
+        block_store,
+        own_peer_id,
+        block_hash,
+        file_hash,
+        file_dir,
+        storage_class,
+        ttl,
+    )
+    .await?;
+    let ser_peer_block_info = format.encode(&peer_block_info)?;
     let size_of_pbi = ser_peer_block_info.len();
     stream.write_all(&usize::to_be_bytes(size_of_pbi)).await?;
     stream.write_all(&ser_peer_block_info).await?;
@@ -84,6 +113,7 @@ async fn send_peer_block_info(
 /// Send the block to the other end of the stream
 async fn send_block(
     stream: &mut Stream,
+    block_store: &BlockStore,
     block_hash: String,
     file_hash: String,
     file_dir: PathBuf,
@@ -92,7 +122,7 @@ async fn send_block(
     let block_path: PathBuf = [block_dir, PathBuf::from(block_hash.clone())]
         .iter()
         .collect();
-    let ser_block = fs::read(block_path).await?;
+    let ser_block = block_store.read_async(&block_path).await?;
     stream.write_all(&ser_block).await?;
 
     Ok(())
@@ -100,44 +130,63 @@ async fn send_block(
 
 /// Main function for the sender side, will attempt to send the block, can fail if the other end refuses to get the block.
 /// This is a oneshot try, meaning there is no logic behind to try to find another peer to get the block.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn handle_send_block_exchange_sender_side(
     stream: Stream, //TODO give a &mut stream instead so the caller can close the stream on all errors
+    format: WireFormat,
+    block_store: &BlockStore,
     own_peer_id: PeerId,
     recv_peer_id: PeerId,
     block_hash: String,
     file_hash: String,
     file_dir: PathBuf,
+    storage_class: Option<StorageClass>,
+    ttl: Option<u64>,
 ) -> Result<(bool, SendId), SendId> {
     handle_send_block_exchange_sender_side_inner(
         stream,
+        format,
+        block_store,
         own_peer_id,
         recv_peer_id,
         block_hash.clone(),
         file_hash.clone(),
         file_dir,
+        storage_class,
+        ttl,
     )
     .await
     .map_err(|_| SendId {
         peer_id: recv_peer_id,
         file_hash,
         block_hash,
+        attempts: 1,
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_send_block_exchange_sender_side_inner(
     mut stream: Stream, //TODO give a &mut stream instead so the caller can close the stream on all errors
+    format: WireFormat,
+    block_store: &BlockStore,
     own_peer_id: PeerId,
     recv_peer_id: PeerId,
     block_hash: String,
     file_hash: String,
     file_dir: PathBuf,
+    storage_class: Option<StorageClass>,
+    ttl: Option<u64>,
 ) -> Result<(bool, SendId)> {
     send_peer_block_info(
         &mut stream,
+        format,
+        block_store,
         own_peer_id,
         block_hash.clone(),
         file_hash.clone(),
         file_dir.clone(),
+        storage_class,
+        ttl,
     )
     .await?;
     let mut ser_answer = [0u8; 1];
@@ -146,6 +195,7 @@ async fn handle_send_block_exchange_sender_side_inner(
         peer_id: recv_peer_id,
         file_hash: file_hash.clone(),
         block_hash: block_hash.clone(),
+        attempts: 1,
     };
     if let Some(answer) = ExchangeCode::from_repr(ser_answer[0]) {
         match answer {
@@ -154,6 +204,14 @@ async fn handle_send_block_exchange_sender_side_inner(
                 stream.close().await?;
                 return Ok((false, send_id));
             }
+            ExchangeCode::ReceivingPaused => {
+                info!(
+                    "The peer {} paused the reception of blocks, it did not take the block {}",
+                    recv_peer_id, block_hash
+                );
+                stream.close().await?;
+                return Ok((false, send_id));
+            }
             a => {
                 let err_string = format!("Unexpected ExchangeCode variant for answer {:?}", a);
                 warn!(err_string);
@@ -172,14 +230,31 @@ async fn handle_send_block_exchange_sender_side_inner(
     }
 
     // block got accepted, we send it
-    send_block(&mut stream, block_hash, file_hash, file_dir).await?;
+    send_block(
+        &mut stream,
+        block_store,
+        block_hash.clone(),
+        file_hash.clone(),
+        file_dir.clone(),
+    )
+    .await?;
     let mut ser_block_status = [0u8; 1];
     stream.read_exact(&mut ser_block_status).await?;
     stream.close().await?;
     debug!("ser block status: {:?}", ser_block_status);
     if let Some(block_status) = ExchangeCode::from_repr(ser_block_status[0]) {
         match block_status {
-            ExchangeCode::BlockIsCorrect => Ok((true, send_id)),
+            ExchangeCode::BlockIsCorrect => {
+                lifecycle::record(
+                    &get_file_dir(&file_dir, file_hash.clone()),
+                    &file_hash,
+                    LifecycleStage::BlockSent,
+                    Some(recv_peer_id.to_base58()),
+                    Some(block_hash),
+                )
+                .await;
+                Ok((true, send_id))
+            }
             ExchangeCode::BlockIsIncorrect => Ok((false, send_id)),
             a => {
                 let err_string = format!("Unexpected ExchangeCode variant for block status{:?}", a);
@@ -201,6 +276,20 @@ async fn handle_send_block_exchange_sender_side_inner(
 
 // -------------------- RECEIVER -------------------- //
 
+/// A block received from a send request, to add to the file listing the received blocks
+pub(super) struct SendFileEntry {
+    pub(super) file_dir: PathBuf,
+    pub(super) size_of_block: usize,
+    pub(super) file_hash: String,
+    pub(super) block_hash: String,
+    pub(super) peer_id_base_58: String,
+    pub(super) storage_class: Option<StorageClass>,
+    /// Seconds the sender asked the block to be kept
+    pub(super) ttl: Option<u64>,
+    /// Held until the block is recorded, so the node does not stop before
+    pub(super) in_flight: InFlightGuard,
+}
+
 /// Choose whether or not to accept the send request.
 /// Remove from the total available storage when choosing to accept the block, returning the choice to accept or reject the block and the size by which the total storage space was changed.
 /// Returning the change of storage space allows to revert the change later on if we end up rejecting the block for other reasons.
@@ -259,7 +348,7 @@ where
         if let Some(size) = vec_size.first() {
             let mut ser_block = vec![0u8; *size];
             stream.read_exact(&mut ser_block[..]).await?;
-            let block = Block::deserialize_with_mode(&ser_block[..], Compress::Yes, Validate::Yes)?;
+            let block = block_format::deserialize(&ser_block, Validate::Yes)?;
             Ok((ser_block, block))
         } else {
             Err(format_err!("A size vector was provided to read the block that was sent, but the vector was empty"))
@@ -272,12 +361,22 @@ where
 }
 
 /// Handles the entire transaction for the receiver side of the block send
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn handle_send_block_exchange_recv_side<F, G, P>(
+    peer: PeerId,
     mut stream: Stream,
-    powers_path: PathBuf,
+    format: WireFormat,
+    powers: Arc<PowersCache>,
     file_dir: PathBuf,
     current_available_storage: Arc<AtomicUsize>,
-    write_to_file_sender: Sender<(PathBuf, usize, String, String, String)>,
+    receiving_paused: Arc<AtomicBool>,
+    write_to_file_sender: Sender<SendFileEntry>,
+    storage_classes: Arc<StorageClasses>,
+    block_store: Arc<BlockStore>,
+    block_quota: Arc<BlockQuota>,
+    peer_quotas: Arc<PeerQuotas>,
+    metrics: Arc<Metrics>,
+    in_flight: InFlightGuard,
 ) -> Result<()>
 where
     F: PrimeField,
@@ -301,28 +400,87 @@ where
     // receive the peer block info
     let mut ser_peer_block_info = vec![0u8; peer_block_info_size];
     stream.read_exact(&mut ser_peer_block_info[..]).await?;
-    let peer_block_info: PeerBlockInfo = serde_json::de::from_slice(&ser_peer_block_info)?;
-    let (answer, size_change) =
-        choose_response_to_send_request(&peer_block_info, current_available_storage.clone()).await;
+    let mut peer_block_info: PeerBlockInfo = format.decode(&ser_peer_block_info)?;
+    // the blocks are counted for the peer at the other end of the stream, whatever it claims to be
+    let sender = peer.to_base58();
+    if peer_block_info.peer_id_base_58 != sender {
+        warn!(
+            "The peer {} sent a block as {}",
+            sender, peer_block_info.peer_id_base_58
+        );
+        peer_block_info.peer_id_base_58 = sender.clone();
+    }
+    let ttl = peer_block_info.ttl;
+    let paused = receiving_paused.load(Ordering::SeqCst);
+    if paused {
+        info!(
+            "Rejecting the block sent by {}, the reception of blocks is paused",
+            peer_block_info.peer_id_base_58
+        );
+    }
+    // reject the block right away if storing it would go over the caps on the number of blocks
+    let reservation = match peer_block_info.block_hashes.first() {
+        Some(block_hash) if !paused => block_quota
+            .reserve(&peer_block_info.file_hash, block_hash)
+            .map_err(|e| warn!("Rejecting the block sent by a peer: {}", e))
+            .ok(),
+        _ => None,
+    };
+    let block_size = peer_block_info
+        .block_sizes
+        .as_ref()
+        .and_then(|sizes| sizes.first().copied())
+        .unwrap_or(0);
+    let (answer, size_change) = match reservation {
+        Some(_) if !peer_quotas.reserve(&sender, block_size) => {
+            info!(
+                "Rejecting the block sent by {}, it would go over the quota of the peer",
+                sender
+            );
+            (ExchangeCode::RejectBlockSend, 0)
+        }
+        Some(_) => {
+            let response = choose_response_to_send_request(
+                &peer_block_info,
+                current_available_storage.clone(),
+            )
+            .await;
+            if response.1 == 0 {
+                peer_quotas.release(&sender, block_size);
+            }
+            response
+        }
+        None if paused => (ExchangeCode::ReceivingPaused, 0),
+        None => (ExchangeCode::RejectBlockSend, 0),
+    };
 
     match send_block_recv_wrapper::<F, G, P>(
         &mut stream,
         answer,
-        powers_path,
+        powers,
         &file_dir,
         peer_block_info,
+        &storage_classes,
+        &block_store,
+        reservation,
     )
     .await
     {
-        Ok((file_hash, block_hash, peer_id_base_58)) => {
+        Ok((file_hash, block_hash, peer_id_base_58, storage_class)) => {
+            if let Some(storage_class) = storage_class {
+                metrics.record_stored_block(storage_class, size_change as u64);
+            }
             match write_to_file_sender
-                .send((
+                .send(SendFileEntry {
                     file_dir,
-                    size_change,
+                    size_of_block: size_change,
                     file_hash,
                     block_hash,
                     peer_id_base_58,
-                ))
+                    storage_class,
+                    ttl,
+                    in_flight,
+                })
                 .await
             {
                 Ok(_) => {}
@@ -336,6 +494,7 @@ where
         } //TODO change the available size in the send block file and add information about the block by sending the information through a sender
         Err(e) => {
             current_available_storage.fetch_add(size_change, Ordering::Relaxed);
+            peer_quotas.release(&sender, size_change);
 
             stream.close().await?;
             return Err(e);
@@ -349,10 +508,13 @@ where
 async fn send_block_recv_wrapper<F, G, P>(
     stream: &mut Stream,
     answer: ExchangeCode,
-    powers_path: PathBuf,
+    powers: Arc<PowersCache>,
     file_dir: &PathBuf,
     peer_block_info: PeerBlockInfo,
-) -> Result<(String, String, String)>
+    storage_classes: &StorageClasses,
+    block_store: &BlockStore,
+    reservation: Option<BlockReservation>,
+) -> Result<(String, String, String, Option<StorageClass>)>
 where
     F: PrimeField,
     G: CurveGroup<ScalarField = F>,
@@ -362,7 +524,7 @@ where
     respond_to_send_request(stream, answer).await?;
     match answer {
         ExchangeCode::AcceptBlockSend => {}
-        ExchangeCode::RejectBlockSend => {
+        ExchangeCode::RejectBlockSend | ExchangeCode::ReceivingPaused => {
             stream.close().await?;
             return Ok(Default::default());
         }
@@ -381,6 +543,8 @@ where
         peer_id_base_58,
         file_hash,
         block_hashes,
+        storage_class,
+        correlation_id,
         ..
     } = peer_block_info;
     let block_hash = if let Some(block_hash) = block_hashes.first() {
@@ -394,20 +558,38 @@ where
         return Err(format_err!(err_msg));
     };
     // at this point we have the block deserialized, but we don't know if it's correct or not
-    let powers: Powers<F, G> = get_powers(powers_path).await?;
+    let powers = powers.get::<F, G>().await?;
     // check that the block is correct
-    if verify(&block, &powers)? {
+    let placed_class = if verify::<F, G, P>(&block, &powers)? {
         let block_dir = get_block_dir(file_dir, file_hash.clone());
-        tokio::fs::create_dir_all(&block_dir).await?;
-        let block_path: PathBuf = [block_dir, PathBuf::from(block_hash.clone())]
-            .iter()
-            .collect();
-        debug!("Will write the received block to {:?}", block_path);
-        tokio::fs::write(block_path, ser_block).await?;
+        let placed_class = storage_classes
+            .place_block(
+                storage_class,
+                &block_dir,
+                &file_hash,
+                block_hash,
+                &block_store.seal(&ser_block)?,
+            )
+            .await?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+        let hash_dir = get_file_dir(file_dir, file_hash.clone());
+        lifecycle::adopt(&hash_dir, correlation_id.as_deref()).await;
+        lifecycle::record(
+            &hash_dir,
+            &file_hash,
+            LifecycleStage::BlockReceived,
+            Some(peer_id_base_58.clone()),
+            Some(block_hash.clone()),
+        )
+        .await;
         send_block_status(stream, ExchangeCode::BlockIsCorrect).await?;
+        placed_class
     } else {
         send_block_status(stream, ExchangeCode::BlockIsIncorrect).await?;
-    }
+        None
+    };
     stream.close().await?;
-    Ok((file_hash, block_hash.clone(), peer_id_base_58))
+    Ok((file_hash, block_hash.clone(), peer_id_base_58, placed_class))
 }
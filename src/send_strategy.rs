@@ -1,8 +1,15 @@
 use anyhow::Result;
-use futures::stream::FusedStream;
+use futures::stream::{self as f_stream, FusedStream};
 use futures::StreamExt;
 use libp2p::PeerId;
+use std::collections::{BTreeMap, HashMap};
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::error::DragoonError;
+use crate::send_strategy_impl::StrategyName;
 
 pub(crate) trait SendStrategy {
     type PeerInput;
@@ -45,4 +52,82 @@ pub(crate) struct SendId {
     pub(crate) peer_id: PeerId,
     pub(crate) file_hash: String,
     pub(crate) block_hash: String,
+    /// Number of times the block was sent to the peer, 0 while the send is only planned
+    pub(crate) attempts: u32,
+}
+
+/// What the node knows about its peers when a send strategy starts
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerKnowledge {
+    /// The peers known by the node, in no particular order, without the ones with a low score
+    pub(crate) known_peers: Vec<PeerId>,
+    /// The storage the peers have left for the blocks sent by other peers, only asked when the strategy needs it
+    pub(crate) available_storage: HashMap<PeerId, usize>,
+    /// The last round trip time measured to the peers
+    pub(crate) rtts: HashMap<PeerId, Duration>,
+    /// The weights given to the peers in the send-block-list
+    pub(crate) weights: HashMap<PeerId, u64>,
+}
+
+pub(crate) type SendStream = Pin<Box<dyn FusedStream<Item = SendId> + Send>>;
+
+/// Builds the stream of the choices of a strategy from the peers and the (file hash, block hash) to send
+pub(crate) type StrategyFactory =
+    Arc<dyn Fn(PeerKnowledge, Vec<(String, String)>) -> SendStream + Send + Sync>;
+
+pub(crate) struct RegisteredStrategy {
+    /// The peers are asked for their available storage before the strategy starts
+    pub(crate) needs_available_storage: bool,
+    /// The strategy takes the weights of the peers given in the send-block-list
+    pub(crate) takes_weights: bool,
+    pub(crate) factory: StrategyFactory,
+}
+
+/// The send strategies of the node by name, see [`crate::send_strategy_impl::registry`]
+#[derive(Default)]
+pub(crate) struct StrategyRegistry {
+    strategies: BTreeMap<String, RegisteredStrategy>,
+}
+
+impl StrategyRegistry {
+    pub(crate) fn register(&mut self, name: &str, strategy: RegisteredStrategy) {
+        if self.strategies.insert(name.to_string(), strategy).is_some() {
+            warn!(
+                "The send strategy {} was registered twice, keeping the last one",
+                name
+            );
+        }
+    }
+
+    pub(crate) fn get(&self, name: &StrategyName) -> Result<&RegisteredStrategy, DragoonError> {
+        self.strategies.get(&name.0).ok_or_else(|| {
+            DragoonError::UnknownStrategy(format!(
+                "{}, the known strategies are {}",
+                name,
+                self.names().collect::<Vec<_>>().join(", ")
+            ))
+        })
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.strategies.keys().map(String::as_str)
+    }
+}
+
+/// The stream of the choices of `strategy`, fed with the peers and the blocks one after the other
+pub(crate) fn send_stream<S>(
+    strategy: S,
+    peer_inputs: Vec<S::PeerInput>,
+    block_inputs: Vec<(String, String)>,
+) -> SendStream
+where
+    S: SendStrategy<BlockInput = (String, String)> + Send + 'static,
+    S::PeerInput: Send + 'static,
+{
+    let peer_input_stream = f_stream::iter(peer_inputs).fuse();
+    let block_input_stream = f_stream::iter(block_inputs).fuse();
+    Box::pin(
+        Box::new(strategy)
+            .get_send_stream(Box::pin(peer_input_stream), Box::pin(block_input_stream)),
+    )
 }
@@ -1,10 +1,128 @@
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::sync::Arc;
 
+use crate::send_strategy::{send_stream, PeerKnowledge, RegisteredStrategy, StrategyRegistry};
+
+pub(crate) mod capacity_weighted;
+pub(crate) mod lowest_latency;
 pub(crate) mod random;
 pub(crate) mod round_robin;
+pub(crate) mod user_weighted;
+mod weighted_round_robin;
+
+pub(crate) const RANDOM: &str = "Random";
+pub(crate) const ROUND_ROBIN: &str = "RoundRobin";
+pub(crate) const CAPACITY_WEIGHTED: &str = "CapacityWeighted";
+pub(crate) const LOWEST_LATENCY: &str = "LowestLatency";
+pub(crate) const USER_WEIGHTED: &str = "UserWeighted";
+
+/// The name a send strategy is registered under in the [`registry`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub(crate) struct StrategyName(pub(crate) String);
+
+impl StrategyName {
+    pub(crate) fn new(name: &str) -> Self {
+        StrategyName(name.to_string())
+    }
+}
+
+impl std::fmt::Display for StrategyName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
-pub(crate) enum StrategyName {
-    Random,
-    RoundRobin,
+/// All the send strategies of the node, a new strategy only has to be registered here
+pub(crate) fn registry() -> StrategyRegistry {
+    let mut registry = StrategyRegistry::default();
+    registry.register(
+        RANDOM,
+        RegisteredStrategy {
+            needs_available_storage: false,
+            takes_weights: false,
+            factory: Arc::new(|peers: PeerKnowledge, blocks| {
+                send_stream(
+                    random::RandomDistribution::default(),
+                    peers.known_peers,
+                    blocks,
+                )
+            }),
+        },
+    );
+    registry.register(
+        ROUND_ROBIN,
+        RegisteredStrategy {
+            needs_available_storage: false,
+            takes_weights: false,
+            factory: Arc::new(|peers: PeerKnowledge, blocks| {
+                let mut known_peers = peers.known_peers;
+                //sort to ensure the ordering for the tests is not random
+                known_peers.sort();
+                send_stream(
+                    round_robin::RobinDistribution::default(),
+                    known_peers,
+                    blocks,
+                )
+            }),
+        },
+    );
+    registry.register(
+        CAPACITY_WEIGHTED,
+        RegisteredStrategy {
+            needs_available_storage: true,
+            takes_weights: false,
+            factory: Arc::new(|peers: PeerKnowledge, blocks| {
+                // the peers that did not answer are left out
+                let mut available_storage = peers.available_storage.into_iter().collect::<Vec<_>>();
+                // the largest first, the ones with as much storage sorted by id to ensure the ordering for the tests is not random
+                available_storage.sort_by_key(|(peer_id, storage)| (Reverse(*storage), *peer_id));
+                send_stream(
+                    capacity_weighted::CapacityWeightedDistribution::default(),
+                    available_storage,
+                    blocks,
+                )
+            }),
+        },
+    );
+    registry.register(
+        LOWEST_LATENCY,
+        RegisteredStrategy {
+            needs_available_storage: false,
+            takes_weights: false,
+            factory: Arc::new(|peers: PeerKnowledge, blocks| {
+                let mut known_peers = peers
+                    .known_peers
+                    .iter()
+                    .map(|peer_id| (*peer_id, peers.rtts.get(peer_id).copied()))
+                    .collect::<Vec<_>>();
+                // the fastest peers first and the ones never measured last, sorted by id to ensure the ordering for the tests is not random
+                known_peers.sort_by_key(|(peer_id, rtt)| (rtt.is_none(), *rtt, *peer_id));
+                send_stream(
+                    lowest_latency::LowestLatencyDistribution::default(),
+                    known_peers,
+                    blocks,
+                )
+            }),
+        },
+    );
+    registry.register(
+        USER_WEIGHTED,
+        RegisteredStrategy {
+            needs_available_storage: false,
+            takes_weights: true,
+            factory: Arc::new(|peers: PeerKnowledge, blocks| {
+                let mut weighted_peers = peers.weights.into_iter().collect::<Vec<_>>();
+                // the heaviest first, the ones with the same weight sorted by id to ensure the ordering for the tests is not random
+                weighted_peers.sort_by_key(|(peer_id, weight)| (Reverse(*weight), *peer_id));
+                send_stream(
+                    user_weighted::UserWeightedDistribution::default(),
+                    weighted_peers,
+                    blocks,
+                )
+            }),
+        },
+    );
+    registry
 }
@@ -0,0 +1,33 @@
+//! Give each peer a share of the blocks proportional to the storage it has left for the blocks sent by other peers.
+//! Start by returning the peers as they come, the largest first, then take the known peers with a smooth weighted
+//! round robin, so the blocks of a large peer are spread over the list instead of coming one after the other.
+//! The peers without any storage left never receive a block.
+
+use anyhow::Result;
+use libp2p::PeerId;
+
+use super::weighted_round_robin::WeightedRoundRobin;
+use crate::send_strategy::{SendId, SendStrategy};
+
+#[derive(Default)]
+pub(crate) struct CapacityWeightedDistribution {
+    peers: WeightedRoundRobin,
+}
+
+impl SendStrategy for CapacityWeightedDistribution {
+    /// A peer and the storage it has left for the blocks sent by other peers
+    type PeerInput = (PeerId, usize);
+    type BlockInput = (String, String);
+
+    fn choose_next_peer_block(
+        &mut self,
+        peer_input: Option<Self::PeerInput>,
+        block_input: Self::BlockInput,
+    ) -> Result<SendId> {
+        self.peers.choose(
+            peer_input.map(|(peer_id, available_storage)| (peer_id, available_storage as i128)),
+            block_input,
+            "None of the peers to choose who to send blocks to has storage left",
+        )
+    }
+}
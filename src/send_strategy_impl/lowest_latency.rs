@@ -0,0 +1,47 @@
+//! Give each peer a share of the blocks inversely proportional to its round trip time, measured with pings.
+//! Start by returning the peers as they come, from the fastest to the slowest, then take the known peers with a
+//! smooth weighted round robin. The peers without a measurement come last and are given the share of the slowest
+//! measured peer, so when no peer was measured at all this is a round robin.
+
+use anyhow::Result;
+use libp2p::PeerId;
+use std::time::Duration;
+
+use super::weighted_round_robin::WeightedRoundRobin;
+use crate::send_strategy::{SendId, SendStrategy};
+
+#[derive(Default)]
+pub(crate) struct LowestLatencyDistribution {
+    peers: WeightedRoundRobin,
+    /// The weight of the slowest measured peer seen so far
+    last_measured_weight: Option<i128>,
+}
+
+impl SendStrategy for LowestLatencyDistribution {
+    /// A peer and its last measured round trip time, if any
+    type PeerInput = (PeerId, Option<Duration>);
+    type BlockInput = (String, String);
+
+    fn choose_next_peer_block(
+        &mut self,
+        peer_input: Option<Self::PeerInput>,
+        block_input: Self::BlockInput,
+    ) -> Result<SendId> {
+        let new_peer = peer_input.map(|(peer_id, rtt)| {
+            let weight = match rtt {
+                Some(rtt) => {
+                    let weight = (1_000_000_000 / (rtt.as_micros() as i128).max(1)).max(1);
+                    self.last_measured_weight = Some(weight);
+                    weight
+                }
+                None => self.last_measured_weight.unwrap_or(1),
+            };
+            (peer_id, weight)
+        });
+        self.peers.choose(
+            new_peer,
+            block_input,
+            "The stream of peers to choose who to send blocks to was empty",
+        )
+    }
+}
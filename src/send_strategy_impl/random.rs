@@ -27,12 +27,14 @@ impl SendStrategy for RandomDistribution {
                 peer_id,
                 file_hash,
                 block_hash,
+                attempts: 0,
             })
         } else if let Some(peer_id) = self.already_seen_peers.choose(&mut rand::thread_rng()) {
             Ok(SendId {
                 peer_id: *peer_id,
                 file_hash,
                 block_hash,
+                attempts: 0,
             })
         } else {
             let err_msg =
@@ -29,6 +29,7 @@ impl SendStrategy for RobinDistribution {
                 peer_id,
                 file_hash,
                 block_hash,
+                attempts: 0,
             })
         } else if let Some(peer_id) = self.already_seen_peers.get(self.round_index) {
             self.round_index += 1;
@@ -39,6 +40,7 @@ impl SendStrategy for RobinDistribution {
                 peer_id: *peer_id,
                 file_hash,
                 block_hash,
+                attempts: 0,
             })
         } else {
             let err_msg =
@@ -0,0 +1,33 @@
+//! Give each peer a share of the blocks proportional to the weight the user gave it.
+//! Start by returning the peers as they come, the heaviest first, then take the known peers with a smooth weighted
+//! round robin, so the blocks of a heavy peer are spread over the list instead of coming one after the other.
+//! The peers with a weight of 0 never receive a block.
+
+use anyhow::Result;
+use libp2p::PeerId;
+
+use super::weighted_round_robin::WeightedRoundRobin;
+use crate::send_strategy::{SendId, SendStrategy};
+
+#[derive(Default)]
+pub(crate) struct UserWeightedDistribution {
+    peers: WeightedRoundRobin,
+}
+
+impl SendStrategy for UserWeightedDistribution {
+    /// A peer and its weight
+    type PeerInput = (PeerId, u64);
+    type BlockInput = (String, String);
+
+    fn choose_next_peer_block(
+        &mut self,
+        peer_input: Option<Self::PeerInput>,
+        block_input: Self::BlockInput,
+    ) -> Result<SendId> {
+        self.peers.choose(
+            peer_input.map(|(peer_id, weight)| (peer_id, weight as i128)),
+            block_input,
+            "None of the peers to choose who to send blocks to has a positive weight",
+        )
+    }
+}
@@ -0,0 +1,140 @@
+//! The smooth weighted round robin shared by the weighted send strategies, which only map their peers to a weight.
+//! The peers are returned as they come, then taken in turn, each one a number of times proportional to its weight,
+//! spread over the list instead of one after the other. The peers without a positive weight are never taken.
+
+use anyhow::{format_err, Result};
+use libp2p::PeerId;
+use tracing::error;
+
+use crate::send_strategy::SendId;
+
+struct WeightedPeer {
+    peer_id: PeerId,
+    weight: i128,
+    current: i128,
+}
+
+#[derive(Default)]
+pub(crate) struct WeightedRoundRobin {
+    peers: Vec<WeightedPeer>,
+    total_weight: i128,
+}
+
+impl WeightedRoundRobin {
+    /// Add the peer, `false` if it is never taken because its weight is not positive
+    pub(crate) fn add(&mut self, peer_id: PeerId, weight: i128) -> bool {
+        if weight <= 0 {
+            return false;
+        }
+        self.peers.push(WeightedPeer {
+            peer_id,
+            weight,
+            current: 0,
+        });
+        self.total_weight += weight;
+        true
+    }
+
+    /// The next peer of the round robin, on a tie the one added first, `None` if there are no peers
+    pub(crate) fn next_peer(&mut self) -> Option<PeerId> {
+        for peer in self.peers.iter_mut() {
+            peer.current += peer.weight;
+        }
+        let peer = self
+            .peers
+            .iter_mut()
+            .rev()
+            .max_by_key(|peer| peer.current)?;
+        peer.current -= self.total_weight;
+        Some(peer.peer_id)
+    }
+
+    /// The peer to send the block to: the new peer if it has a positive weight, the next one of the round robin
+    /// otherwise, an error with `no_peer_msg` when there are no peers
+    pub(crate) fn choose(
+        &mut self,
+        new_peer: Option<(PeerId, i128)>,
+        block_input: (String, String),
+        no_peer_msg: &str,
+    ) -> Result<SendId> {
+        let (file_hash, block_hash) = block_input;
+        let peer_id = match new_peer {
+            Some((peer_id, weight)) if self.add(peer_id, weight) => Some(peer_id),
+            _ => self.next_peer(),
+        };
+        match peer_id {
+            Some(peer_id) => Ok(SendId {
+                peer_id,
+                file_hash,
+                block_hash,
+                attempts: 0,
+            }),
+            None => {
+                error!(no_peer_msg);
+                Err(format_err!(no_peer_msg.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_input() -> (String, String) {
+        (String::from("file"), String::from("block"))
+    }
+
+    #[test]
+    fn peers_are_taken_in_proportion_to_their_weight() {
+        let mut round_robin = WeightedRoundRobin::default();
+        let (heavy, light) = (PeerId::random(), PeerId::random());
+        assert!(round_robin.add(heavy, 3));
+        assert!(round_robin.add(light, 1));
+        let turns: Vec<PeerId> = (0..8).filter_map(|_| round_robin.next_peer()).collect();
+        assert_eq!(turns.iter().filter(|peer| **peer == heavy).count(), 6);
+        assert_eq!(turns.iter().filter(|peer| **peer == light).count(), 2);
+        // smooth: the light peer is not left for the end of each round
+        assert_eq!(turns[..4], [heavy, heavy, light, heavy]);
+    }
+
+    #[test]
+    fn ties_go_to_the_peer_added_first() {
+        let mut round_robin = WeightedRoundRobin::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        round_robin.add(first, 1);
+        round_robin.add(second, 1);
+        assert_eq!(round_robin.next_peer(), Some(first));
+        assert_eq!(round_robin.next_peer(), Some(second));
+    }
+
+    #[test]
+    fn peers_without_a_positive_weight_are_never_taken() {
+        let mut round_robin = WeightedRoundRobin::default();
+        assert!(!round_robin.add(PeerId::random(), 0));
+        assert!(!round_robin.add(PeerId::random(), -1));
+        assert_eq!(round_robin.next_peer(), None);
+        assert!(round_robin
+            .choose(Some((PeerId::random(), 0)), block_input(), "no peer")
+            .is_err());
+    }
+
+    #[test]
+    fn choose_takes_the_new_peer_first() {
+        let mut round_robin = WeightedRoundRobin::default();
+        let (old, new) = (PeerId::random(), PeerId::random());
+        round_robin.add(old, 10);
+        let send_id = round_robin
+            .choose(Some((new, 1)), block_input(), "no peer")
+            .unwrap();
+        assert_eq!(send_id.peer_id, new);
+        assert_eq!((send_id.file_hash, send_id.block_hash), block_input());
+        assert_eq!(
+            round_robin
+                .choose(None, block_input(), "no peer")
+                .unwrap()
+                .peer_id,
+            old
+        );
+    }
+}
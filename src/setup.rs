@@ -0,0 +1,255 @@
+//! Powers taken from a public powers-of-tau ceremony instead of being generated locally.
+//!
+//! The `.ptau` files of the SnarkJS ceremonies hold the powers of a secret `tau` nobody knows as long as one of
+//! the participants was honest, so the blocks proven with them cannot be forged by the one who made the powers.
+//! A `.ptau` file is made of sections, the first one gives the field of the curve and the number of powers, the
+//! second and third ones the powers of `tau` in G1 and G2. The powers are checked to be consecutive powers of the
+//! same `tau` with pairings before they are used.
+
+use anyhow::{format_err, Result};
+use ark_ec::pairing::Pairing;
+use ark_ec::short_weierstrass::{Affine, SWCurveConfig};
+use ark_ec::{AffineRepr, VariableBaseMSM};
+use ark_ff::{BigInteger, Field, PrimeField, UniformRand, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
+use komodo::zk::Powers;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+
+use crate::curve::Curve;
+use crate::powers_file;
+
+const PTAU_MAGIC: &[u8] = b"ptau";
+const HEADER_SECTION: u32 = 1;
+const TAU_G1_SECTION: u32 = 2;
+const TAU_G2_SECTION: u32 = 3;
+
+/// The sections of a `.ptau` file, by type
+struct Ptau<'a> {
+    sections: HashMap<u32, &'a [u8]>,
+}
+
+impl<'a> Ptau<'a> {
+    fn parse(content: &'a [u8]) -> Result<Self> {
+        let truncated = || format_err!("The ptau file is truncated");
+        let rest = content
+            .strip_prefix(PTAU_MAGIC)
+            .ok_or(format_err!("The file is not a ptau file"))?;
+        let (_version, rest) = read_u32(rest).ok_or_else(truncated)?;
+        let (section_count, mut rest) = read_u32(rest).ok_or_else(truncated)?;
+        let mut sections = HashMap::new();
+        for _ in 0..section_count {
+            let (section_type, after_type) = read_u32(rest).ok_or_else(truncated)?;
+            let (size, after_size) = read_u64(after_type).ok_or_else(truncated)?;
+            let size = usize::try_from(size)?;
+            if after_size.len() < size {
+                return Err(truncated());
+            }
+            let (section, after_section) = after_size.split_at(size);
+            sections.insert(section_type, section);
+            rest = after_section;
+        }
+        Ok(Ptau { sections })
+    }
+
+    fn section(&self, section_type: u32) -> Result<&'a [u8]> {
+        self.sections
+            .get(&section_type)
+            .copied()
+            .ok_or(format_err!("The ptau file has no section {}", section_type))
+    }
+
+    /// The size of the field elements, the modulus of the base field and the log2 of the number of powers
+    fn header(&self) -> Result<(usize, &'a [u8], u32)> {
+        let truncated = || format_err!("The header of the ptau file is truncated");
+        let header = self.section(HEADER_SECTION)?;
+        let (n8, rest) = read_u32(header).ok_or_else(truncated)?;
+        let n8 = n8 as usize;
+        if rest.len() < n8 {
+            return Err(truncated());
+        }
+        let (modulus, rest) = rest.split_at(n8);
+        let (power, _) = read_u32(rest).ok_or_else(truncated)?;
+        Ok((n8, modulus, power))
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let (value, rest) = bytes.split_first_chunk::<4>()?;
+    Some((u32::from_le_bytes(*value), rest))
+}
+
+fn read_u64(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (value, rest) = bytes.split_first_chunk::<8>()?;
+    Some((u64::from_le_bytes(*value), rest))
+}
+
+/// The curve whose base field has the modulus of the ptau file, SnarkJS only supports BN254 and BLS12-381
+fn ptau_curve(modulus: &[u8]) -> Result<Curve> {
+    if modulus == <ark_bn254::Fq as PrimeField>::MODULUS.to_bytes_le() {
+        Ok(Curve::Bn254)
+    } else if modulus == <ark_bls12_381::Fq as PrimeField>::MODULUS.to_bytes_le() {
+        Ok(Curve::Bls12_381)
+    } else {
+        Err(format_err!("The ptau file is for an unsupported curve"))
+    }
+}
+
+/// An element of `F` written in Montgomery form, as its coordinates over the prime field, each on `n8` bytes
+fn read_field<F: Field>(bytes: &[u8], n8: usize) -> Result<F> {
+    let r_inv = F::BasePrimeField::from(2u64)
+        .pow([(n8 * 8) as u64])
+        .inverse()
+        .ok_or(format_err!("The Montgomery factor is not invertible"))?;
+    let coordinates: Vec<F::BasePrimeField> = bytes
+        .chunks(n8)
+        .map(|coordinate| F::BasePrimeField::from_le_bytes_mod_order(coordinate) * r_inv)
+        .collect();
+    F::from_base_prime_field_elems(&coordinates).ok_or(format_err!(
+        "Could not read a field element of the ptau file"
+    ))
+}
+
+/// The first `count` points of `section`, checked to be on the curve and in its prime order subgroup
+fn read_points<P: SWCurveConfig>(
+    section: &[u8],
+    n8: usize,
+    count: usize,
+) -> Result<Vec<Affine<P>>> {
+    let coordinate_size = n8 * P::BaseField::extension_degree() as usize;
+    let point_size = 2 * coordinate_size;
+    if section.len() < count * point_size {
+        return Err(format_err!(
+            "The ptau file has less than the {} expected points",
+            count
+        ));
+    }
+    section
+        .chunks(point_size)
+        .take(count)
+        .map(|point| {
+            let (x, y) = point.split_at(coordinate_size);
+            let (x, y) = (read_field(x, n8)?, read_field(y, n8)?);
+            if x.is_zero() && y.is_zero() {
+                return Ok(Affine::<P>::zero());
+            }
+            let point = Affine::<P>::new_unchecked(x, y);
+            if !point.is_on_curve() || !point.is_in_correct_subgroup_assuming_on_curve() {
+                return Err(format_err!("A point of the ptau file is not on the curve"));
+            }
+            Ok(point)
+        })
+        .collect()
+}
+
+/// Check that the powers are the consecutive powers of the `tau` of `tau_g2`, with a random linear combination
+/// of the pairings `e(tau^i G1, G2) = e(tau^(i - 1) G1, tau G2)`
+fn check_pairings<E: Pairing>(tau_g1: &[E::G1Affine], tau_g2: &[E::G2Affine]) -> Result<()> {
+    if tau_g1.first() != Some(&E::G1Affine::generator())
+        || tau_g2.first() != Some(&E::G2Affine::generator())
+    {
+        return Err(format_err!(
+            "The powers of the ptau file do not start with the generators"
+        ));
+    }
+    if tau_g1.len() < 2 {
+        return Ok(());
+    }
+    let mut rng = rand::thread_rng();
+    let weights: Vec<E::ScalarField> = (1..tau_g1.len())
+        .map(|_| E::ScalarField::rand(&mut rng))
+        .collect();
+    let higher = E::G1::msm(&tau_g1[1..], &weights)
+        .map_err(|_| format_err!("Could not combine the powers of the ptau file"))?;
+    let lower = E::G1::msm(&tau_g1[..tau_g1.len() - 1], &weights)
+        .map_err(|_| format_err!("Could not combine the powers of the ptau file"))?;
+    if E::pairing(higher, tau_g2[0]) != E::pairing(lower, tau_g2[1]) {
+        return Err(format_err!(
+            "The powers of the ptau file are not the consecutive powers of a same tau"
+        ));
+    }
+    Ok(())
+}
+
+/// The first `count` powers of `ptau`, checked with pairings
+fn from_ptau_points<E, P1, P2>(
+    ptau: &Ptau,
+    n8: usize,
+    count: usize,
+) -> Result<Powers<E::ScalarField, E::G1>>
+where
+    E: Pairing<G1Affine = Affine<P1>, G2Affine = Affine<P2>>,
+    P1: SWCurveConfig,
+    P2: SWCurveConfig,
+{
+    let tau_g1 = read_points::<P1>(ptau.section(TAU_G1_SECTION)?, n8, count)?;
+    let tau_g2 = read_points::<P2>(ptau.section(TAU_G2_SECTION)?, n8, 2)?;
+    check_pairings::<E>(&tau_g1, &tau_g2)?;
+    // the powers are serialized as the list of their points
+    let mut serialized = vec![0; tau_g1.serialized_size(Compress::Yes)];
+    tau_g1.serialize_with_mode(&mut serialized[..], Compress::Yes)?;
+    Ok(Powers::deserialize_with_mode(
+        &serialized[..],
+        Compress::Yes,
+        Validate::No,
+    )?)
+}
+
+/// Read the powers of the ceremony file `ptau_path` for the curve, check them and write them to `output`, in the
+/// format of [`powers_file`]. Only the powers up to `max_degree` are kept, all the powers of `tau` of the ceremony
+/// otherwise.
+pub(crate) async fn from_ptau(
+    ptau_path: &Path,
+    output: &Path,
+    curve: Curve,
+    max_degree: Option<usize>,
+) -> Result<()> {
+    let content = tokio::fs::read(ptau_path).await?;
+    let ptau = Ptau::parse(&content)?;
+    let (n8, modulus, power) = ptau.header()?;
+    let ptau_curve = ptau_curve(modulus)?;
+    if ptau_curve != curve {
+        return Err(format_err!(
+            "The ptau file {:?} is for the curve {}, not {}",
+            ptau_path,
+            ptau_curve,
+            curve
+        ));
+    }
+    let available = 1usize << power;
+    let count = match max_degree {
+        Some(max_degree) if max_degree + 1 > available => {
+            return Err(format_err!(
+                "The ptau file {:?} has powers up to the degree {}, not {}",
+                ptau_path,
+                available - 1,
+                max_degree
+            ))
+        }
+        Some(max_degree) => max_degree + 1,
+        None => available,
+    };
+    info!(
+        "Checking the {} powers of the ptau file {:?} for the curve {}",
+        count, ptau_path, curve
+    );
+    match curve {
+        Curve::Bn254 => {
+            let powers =
+                from_ptau_points::<ark_bn254::Bn254, ark_bn254::g1::Config, ark_bn254::g2::Config>(
+                    &ptau, n8, count,
+                )?;
+            powers_file::write(&powers, output, curve).await
+        }
+        Curve::Bls12_381 => {
+            let powers = from_ptau_points::<
+                ark_bls12_381::Bls12_381,
+                ark_bls12_381::g1::Config,
+                ark_bls12_381::g2::Config,
+            >(&ptau, n8, count)?;
+            powers_file::write(&powers, output, curve).await
+        }
+        Curve::Bls12_377 => Err(format_err!("There is no ptau file for the curve {}", curve)),
+    }
+}
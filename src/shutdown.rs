@@ -0,0 +1,75 @@
+//! Stopping the node on ctrl-c without cutting the blocks being written.
+//!
+//! The node stops in this order:
+//! 1. the http, control socket and gRPC servers stop accepting connections
+//! 2. the block exchanges of send requests that have not started yet are refused, and the ones in flight, in both
+//!    directions, are waited for until `--shutdown-timeout`
+//! 3. the network flushes the accounting of the received blocks, saves the DHT and closes its connections, then its
+//!    loop stops
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time;
+
+/// The block exchanges of send requests in flight, both the blocks sent and the blocks received
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    count: AtomicUsize,
+    stopping: AtomicBool,
+    idle: Notify,
+}
+
+/// Held for as long as a block exchange runs
+#[derive(Debug)]
+pub(crate) struct InFlightGuard(Arc<InFlight>);
+
+impl InFlight {
+    /// Count a new exchange, `None` once the node is stopping, the exchange should then not start
+    pub(crate) fn start(self: &Arc<Self>) -> Option<InFlightGuard> {
+        // counted before checking, so an exchange is never missed by `wait_idle`
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self.clone());
+        if self.stopping.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(guard)
+    }
+
+    pub(crate) fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the exchanges in flight to be over, false if some are still running after `timeout`
+    pub(crate) async fn wait_idle(&self, timeout: Duration) -> bool {
+        time::timeout(timeout, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.count() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Resolves once the node is stopping, given to the servers so they stop accepting connections
+pub(crate) async fn stopping(mut receiver: watch::Receiver<bool>) {
+    let _ = receiver.wait_for(|stopping| *stopping).await;
+}
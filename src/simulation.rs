@@ -0,0 +1,373 @@
+//! Evaluation of the send strategies without a real network.
+//!
+//! Virtual peers, each with a storage capacity and a latency, receive synthetic files through the same
+//! steps as [`SendBlockList`](crate::commands::DragoonCommand::SendBlockList): the blocks are first spread
+//! by the strategy, then the rejected ones are sent again, in turn, to the peers that never rejected a block.
+//! Time is simulated: a peer receives one block at a time, each taking its latency plus the transfer time.
+
+use anyhow::{format_err, Result};
+use futures::{executor, StreamExt};
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::fs as sfs;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+use crate::send_strategy::{PeerKnowledge, RegisteredStrategy, SendId, StrategyRegistry};
+use crate::send_strategy_impl::{self, StrategyName};
+
+/// A group of identical virtual peers
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct VirtualPeerConfig {
+    #[serde(default = "default_count")]
+    count: usize,
+    /// Storage in bytes available for the blocks sent by other peers
+    capacity: usize,
+    #[serde(default)]
+    latency_ms: u64,
+    /// Bytes per second, no transfer time if not given
+    #[serde(default)]
+    bandwidth: Option<u64>,
+    /// Weight of the peers for the UserWeighted strategy
+    #[serde(default = "default_weight")]
+    weight: u64,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+fn default_weight() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SimulationConfig {
+    peers: Vec<VirtualPeerConfig>,
+    #[serde(default = "default_count")]
+    files: usize,
+    blocks_per_file: usize,
+    block_size: usize,
+    strategies: Vec<StrategyName>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PeerReport {
+    peer_id: String,
+    capacity: usize,
+    latency_ms: u64,
+    blocks: usize,
+    used: usize,
+    rejections: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct StrategyReport {
+    strategy: StrategyName,
+    blocks: usize,
+    placed_blocks: usize,
+    /// Files for which SendBlockList would have failed, because no peer was left to take a block
+    failed_files: usize,
+    rejections: usize,
+    time_to_complete_ms: u64,
+    /// Jain's fairness index of the number of blocks per peer, 1 when all the peers have as many blocks
+    block_fairness: f64,
+    /// Jain's fairness index of the share of its capacity used by each peer
+    storage_fairness: f64,
+    peers: Vec<PeerReport>,
+}
+
+struct VirtualPeer {
+    peer_id: PeerId,
+    capacity: usize,
+    latency_ms: u64,
+    bandwidth: Option<u64>,
+    weight: u64,
+    used: usize,
+    blocks: usize,
+    rejections: usize,
+    busy_until: u64,
+}
+
+impl VirtualPeer {
+    fn transfer_time(&self, block_size: usize) -> u64 {
+        self.latency_ms
+            + self.bandwidth.map_or(0, |bandwidth| {
+                (block_size as u64 * 1000).div_ceil(bandwidth.max(1))
+            })
+    }
+}
+
+/// The blocks on their way to the peers, by arrival time
+struct Network {
+    peers: Vec<VirtualPeer>,
+    in_flight: BinaryHeap<Reverse<(u64, usize, usize, String)>>,
+    seq: usize,
+    block_size: usize,
+}
+
+impl Network {
+    fn index_of(&self, peer_id: &PeerId) -> Result<usize> {
+        self.peers
+            .iter()
+            .position(|peer| peer.peer_id == *peer_id)
+            .ok_or(format_err!("Unknown virtual peer {}", peer_id))
+    }
+
+    fn send(&mut self, now: u64, peer: usize, block_hash: String) {
+        let transfer_time = self.peers[peer].transfer_time(self.block_size);
+        let arrival = self.peers[peer].busy_until.max(now) + transfer_time;
+        self.peers[peer].busy_until = arrival;
+        self.in_flight
+            .push(Reverse((arrival, self.seq, peer, block_hash)));
+        self.seq += 1;
+    }
+
+    /// The next block to arrive, with whether its peer accepted it
+    fn receive(&mut self) -> Option<(u64, usize, String, bool)> {
+        let Reverse((time, _, peer, block_hash)) = self.in_flight.pop()?;
+        let virtual_peer = &mut self.peers[peer];
+        let accepted = virtual_peer.used + self.block_size <= virtual_peer.capacity;
+        if accepted {
+            virtual_peer.used += self.block_size;
+            virtual_peer.blocks += 1;
+        } else {
+            virtual_peer.rejections += 1;
+        }
+        Some((time, peer, block_hash, accepted))
+    }
+}
+
+/// Derive the keypair of a virtual peer from its index, so the peer ids are the same from one run to the other
+fn virtual_peer_id(index: usize) -> PeerId {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&(index as u64).to_be_bytes());
+    Keypair::ed25519_from_bytes(bytes)
+        .expect("32 bytes are a valid ed25519 secret key")
+        .public()
+        .to_peer_id()
+}
+
+fn jain_index(values: &[f64]) -> f64 {
+    let sum: f64 = values.iter().sum();
+    let sum_of_squares: f64 = values.iter().map(|v| v * v).sum();
+    if sum_of_squares == 0.0 {
+        1.0
+    } else {
+        sum * sum / (values.len() as f64 * sum_of_squares)
+    }
+}
+
+/// The choices of the strategy for the blocks of a file, fed with what the node would know about the peers
+fn first_distribution(
+    strategy: &RegisteredStrategy,
+    peers: &[VirtualPeer],
+    file_hash: &str,
+    block_hashes: &[String],
+) -> Vec<SendId> {
+    // the node takes its known peers from a hash set, in no particular order
+    let known_peers = peers
+        .iter()
+        .map(|peer| peer.peer_id)
+        .collect::<HashSet<_>>();
+    let knowledge = PeerKnowledge {
+        known_peers: known_peers.into_iter().collect(),
+        // every virtual peer answers with the storage it has left
+        available_storage: peers
+            .iter()
+            .map(|peer| (peer.peer_id, peer.capacity.saturating_sub(peer.used)))
+            .collect(),
+        // the virtual peers are always measured, with their latency as round trip time
+        rtts: peers
+            .iter()
+            .map(|peer| (peer.peer_id, Duration::from_millis(peer.latency_ms)))
+            .collect(),
+        weights: peers
+            .iter()
+            .map(|peer| (peer.peer_id, peer.weight))
+            .collect(),
+    };
+    let block_inputs = block_hashes
+        .iter()
+        .map(|block_hash| (file_hash.to_string(), block_hash.clone()))
+        .collect();
+    // the inputs of the strategy are all there, so its stream never waits
+    executor::block_on((strategy.factory)(knowledge, block_inputs).collect())
+}
+
+/// Send the blocks of a file, returns the time at which all of them are placed and whether they all were
+fn send_file(
+    network: &mut Network,
+    strategy: &RegisteredStrategy,
+    start: u64,
+    file_hash: &str,
+    block_hashes: &[String],
+) -> Result<(u64, bool)> {
+    let distribution = first_distribution(strategy, &network.peers, file_hash, block_hashes);
+    if distribution.len() != block_hashes.len() {
+        return Ok((start, false));
+    }
+    for send_id in distribution {
+        let peer = network.index_of(&send_id.peer_id)?;
+        network.send(start, peer, send_id.block_hash);
+    }
+
+    let mut now = start;
+    let mut accepted_peers = HashSet::new();
+    let mut rejected_peers = HashSet::new();
+    let mut rejected_blocks = VecDeque::new();
+    while let Some((time, peer, block_hash, accepted)) = network.receive() {
+        now = time;
+        if accepted {
+            if !rejected_peers.contains(&peer) {
+                accepted_peers.insert(peer);
+            }
+        } else {
+            accepted_peers.remove(&peer);
+            rejected_peers.insert(peer);
+            rejected_blocks.push_back(block_hash);
+        }
+    }
+    if rejected_blocks.is_empty() {
+        return Ok((now, true));
+    }
+
+    // the rejected blocks are sent in turn to the peers that accepted all their blocks
+    let mut accepted_peers: Vec<usize> = accepted_peers.into_iter().collect();
+    accepted_peers.sort_by_key(|peer| network.peers[*peer].peer_id);
+    let mut index = 0;
+    for block_hash in rejected_blocks {
+        match next_peer(&accepted_peers, &mut index) {
+            Some(peer) => network.send(now, peer, block_hash),
+            None => return Ok((now, false)),
+        }
+    }
+    while let Some((time, peer, block_hash, accepted)) = network.receive() {
+        now = time;
+        if !accepted {
+            if let Some(position) = accepted_peers.iter().position(|p| *p == peer) {
+                accepted_peers.remove(position);
+                index = index.saturating_sub(1);
+                if index >= accepted_peers.len() {
+                    index = 0;
+                }
+            }
+            match next_peer(&accepted_peers, &mut index) {
+                Some(peer) => network.send(now, peer, block_hash),
+                None => {
+                    // SendBlockList gives up, the blocks already on their way still arrive
+                    while network.receive().is_some() {}
+                    return Ok((now, false));
+                }
+            }
+        }
+    }
+    Ok((now, true))
+}
+
+fn next_peer(accepted_peers: &[usize], index: &mut usize) -> Option<usize> {
+    let peer = *accepted_peers.get(*index)?;
+    *index = (*index + 1) % accepted_peers.len();
+    Some(peer)
+}
+
+fn simulate_strategy(
+    config: &SimulationConfig,
+    strategies: &StrategyRegistry,
+    strategy_name: StrategyName,
+) -> Result<StrategyReport> {
+    let strategy = strategies.get(&strategy_name)?;
+    let peers = config
+        .peers
+        .iter()
+        .flat_map(|peer| std::iter::repeat(peer).take(peer.count))
+        .enumerate()
+        .map(|(index, peer)| VirtualPeer {
+            peer_id: virtual_peer_id(index),
+            capacity: peer.capacity,
+            latency_ms: peer.latency_ms,
+            bandwidth: peer.bandwidth,
+            weight: peer.weight,
+            used: 0,
+            blocks: 0,
+            rejections: 0,
+            busy_until: 0,
+        })
+        .collect::<Vec<_>>();
+    if peers.is_empty() {
+        return Err(format_err!(
+            "The simulation needs at least one virtual peer"
+        ));
+    }
+    let mut network = Network {
+        peers,
+        in_flight: BinaryHeap::new(),
+        seq: 0,
+        block_size: config.block_size,
+    };
+
+    let mut now = 0;
+    let mut failed_files = 0;
+    for file in 0..config.files {
+        let file_hash = format!("file-{}", file);
+        let block_hashes: Vec<String> = (0..config.blocks_per_file)
+            .map(|block| format!("{}-block-{}", file_hash, block))
+            .collect();
+        let (end, complete) = send_file(&mut network, strategy, now, &file_hash, &block_hashes)?;
+        if !complete {
+            failed_files += 1;
+        }
+        now = end;
+    }
+
+    let peers = network.peers;
+    let block_fairness = jain_index(&peers.iter().map(|p| p.blocks as f64).collect::<Vec<_>>());
+    let storage_fairness = jain_index(
+        &peers
+            .iter()
+            .map(|p| match p.capacity {
+                0 => 0.0,
+                capacity => p.used as f64 / capacity as f64,
+            })
+            .collect::<Vec<_>>(),
+    );
+    Ok(StrategyReport {
+        strategy: strategy_name,
+        blocks: config.files * config.blocks_per_file,
+        placed_blocks: peers.iter().map(|p| p.blocks).sum(),
+        failed_files,
+        rejections: peers.iter().map(|p| p.rejections).sum(),
+        time_to_complete_ms: now,
+        block_fairness,
+        storage_fairness,
+        peers: peers
+            .iter()
+            .map(|p| PeerReport {
+                peer_id: p.peer_id.to_base58(),
+                capacity: p.capacity,
+                latency_ms: p.latency_ms,
+                blocks: p.blocks,
+                used: p.used,
+                rejections: p.rejections,
+            })
+            .collect(),
+    })
+}
+
+/// Run the simulation described by the JSON file at `config_path`, one report per strategy
+pub(crate) fn run(config_path: &Path) -> Result<Vec<StrategyReport>> {
+    let config: SimulationConfig = serde_json::from_slice(&sfs::read(config_path)?)?;
+    let strategies = send_strategy_impl::registry();
+    config
+        .strategies
+        .iter()
+        .map(|strategy_name| {
+            info!("Simulating the {} strategy", strategy_name);
+            simulate_strategy(&config, &strategies, strategy_name.clone())
+        })
+        .collect()
+}
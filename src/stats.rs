@@ -0,0 +1,106 @@
+//! Cumulative encode and decode statistics of the node, globally and for each file, used for capacity planning
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::block_scheduler::ConcurrencySample;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub(crate) struct FileStats {
+    pub(crate) files_encoded: u64,
+    /// Total size of the files given to encode
+    pub(crate) input_bytes: u64,
+    /// Total size of the blocks written when encoding
+    pub(crate) coded_bytes: u64,
+    /// Ratio between the coded bytes and the input bytes
+    pub(crate) expansion_factor: f64,
+    /// Total size of the field elements the encoded files were packed into
+    pub(crate) packed_bytes: u64,
+    /// Ratio between the input bytes and the packed bytes, the rest is lost to the packing
+    pub(crate) packing_efficiency: f64,
+    pub(crate) decode_successes: u64,
+    pub(crate) decode_failures: u64,
+    pub(crate) get_files: u64,
+    /// Total number of blocks downloaded by all the get-file
+    pub(crate) blocks_downloaded: u64,
+    pub(crate) average_blocks_per_get_file: f64,
+}
+
+impl FileStats {
+    fn update_averages(&mut self) {
+        if self.input_bytes != 0 {
+            self.expansion_factor = self.coded_bytes as f64 / self.input_bytes as f64;
+        }
+        if self.packed_bytes != 0 {
+            self.packing_efficiency = self.input_bytes as f64 / self.packed_bytes as f64;
+        }
+        if self.get_files != 0 {
+            self.average_blocks_per_get_file =
+                self.blocks_downloaded as f64 / self.get_files as f64;
+        }
+    }
+}
+
+/// The statistics since the node started, the files are identified by their hash
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub(crate) struct Stats {
+    pub(crate) global: FileStats,
+    pub(crate) files: HashMap<String, FileStats>,
+    /// The throughput and blocks in flight of the last automatically tuned get-file of each file
+    #[serde(default)]
+    pub(crate) concurrency_curves: HashMap<String, Vec<ConcurrencySample>>,
+}
+
+impl Stats {
+    /// Apply `update` to the global statistics and to the ones of the file, if it is known
+    fn update(&mut self, file_hash: Option<&str>, update: impl Fn(&mut FileStats)) {
+        update(&mut self.global);
+        self.global.update_averages();
+        if let Some(file_hash) = file_hash {
+            let file_stats = self.files.entry(file_hash.to_string()).or_default();
+            update(file_stats);
+            file_stats.update_averages();
+        }
+    }
+
+    pub(crate) fn record_encode(
+        &mut self,
+        file_hash: &str,
+        input_bytes: u64,
+        coded_bytes: u64,
+        packed_bytes: u64,
+    ) {
+        self.update(Some(file_hash), |stats| {
+            stats.files_encoded += 1;
+            stats.input_bytes += input_bytes;
+            stats.coded_bytes += coded_bytes;
+            stats.packed_bytes += packed_bytes;
+        });
+    }
+
+    pub(crate) fn record_decode(&mut self, file_hash: Option<&str>, success: bool) {
+        self.update(file_hash, |stats| {
+            if success {
+                stats.decode_successes += 1;
+            } else {
+                stats.decode_failures += 1;
+            }
+        });
+    }
+
+    pub(crate) fn record_concurrency_curve(
+        &mut self,
+        file_hash: &str,
+        curve: Vec<ConcurrencySample>,
+    ) {
+        self.concurrency_curves.insert(file_hash.to_string(), curve);
+    }
+
+    pub(crate) fn record_get_file(&mut self, file_hash: &str, blocks_downloaded: u64) {
+        self.update(Some(file_hash), |stats| {
+            stats.get_files += 1;
+            stats.blocks_downloaded += blocks_downloaded;
+        });
+    }
+}
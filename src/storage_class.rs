@@ -0,0 +1,180 @@
+//! Storage classes let a node with mixed disks place the blocks it receives on a given tier,
+//! e.g. the hot blocks on an SSD and the bulk of the blocks on an HDD
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs as sfs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StorageClass {
+    Ssd,
+    Hdd,
+}
+
+impl FromStr for StorageClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ssd" => Ok(StorageClass::Ssd),
+            "hdd" => Ok(StorageClass::Hdd),
+            _ => Err(format!("unknown storage class {}, expected ssd or hdd", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for StorageClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageClass::Ssd => write!(f, "ssd"),
+            StorageClass::Hdd => write!(f, "hdd"),
+        }
+    }
+}
+
+/// Parse a storage path given on the command line as CLASS=PATH
+pub(crate) fn parse_storage_class_path(s: &str) -> Result<(StorageClass, PathBuf), String> {
+    let (class, path) = s
+        .split_once('=')
+        .ok_or(format!("expected CLASS=PATH, got {}", s))?;
+    Ok((class.parse()?, PathBuf::from(path)))
+}
+
+/// How much of a storage class is used by the blocks placed on it
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub(crate) struct StorageClassUsage {
+    #[schema(value_type = String)]
+    pub(crate) path: PathBuf,
+    pub(crate) blocks: u64,
+    pub(crate) used_bytes: u64,
+}
+
+/// The directory of each configured storage class
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StorageClasses {
+    paths: HashMap<StorageClass, PathBuf>,
+}
+
+impl StorageClasses {
+    pub(crate) fn new(paths: HashMap<StorageClass, PathBuf>) -> Self {
+        // the links to the blocks must not depend on the directory they are in
+        let paths = paths
+            .into_iter()
+            .map(|(class, path)| (class, std::path::absolute(&path).unwrap_or(path)))
+            .collect();
+        StorageClasses { paths }
+    }
+
+    /// The usage of each configured class, from the blocks already on disk
+    pub(crate) fn scan_usage(&self) -> HashMap<StorageClass, StorageClassUsage> {
+        self.paths
+            .iter()
+            .map(|(class, path)| {
+                let mut usage = StorageClassUsage {
+                    path: path.clone(),
+                    ..Default::default()
+                };
+                // the blocks are stored as CLASS_PATH/FILE_HASH/BLOCK_HASH
+                for file_dir in sfs::read_dir(path).into_iter().flatten().flatten() {
+                    for block in sfs::read_dir(file_dir.path())
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                    {
+                        if let Ok(metadata) = block.metadata() {
+                            usage.blocks += 1;
+                            usage.used_bytes += metadata.len();
+                        }
+                    }
+                }
+                (*class, usage)
+            })
+            .collect()
+    }
+
+    /// Write the block in `block_dir`, or in the directory of the storage class if one is given and configured.
+    /// In the latter case, a link to the block is written in `block_dir` so the block is found as any other block.
+    ///
+    /// Returns the class where the block was placed, if any.
+    pub(crate) async fn place_block(
+        &self,
+        storage_class: Option<StorageClass>,
+        block_dir: &Path,
+        file_hash: &str,
+        block_hash: &str,
+        ser_block: &[u8],
+    ) -> Result<Option<StorageClass>> {
+        tokio::fs::create_dir_all(block_dir).await?;
+        let block_path = block_dir.join(block_hash);
+        let class_dir = match storage_class {
+            Some(class) => match self.paths.get(&class) {
+                Some(class_path) => Some((class, class_path.join(file_hash))),
+                None => {
+                    warn!(
+                        "No path is configured for the storage class {}, writing the block {} with the other blocks",
+                        class, block_hash
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        match class_dir {
+            Some((class, class_dir)) => {
+                tokio::fs::create_dir_all(&class_dir).await?;
+                let class_block_path = class_dir.join(block_hash);
+                debug!(
+                    "Will write the received block to {:?}, linked from {:?}",
+                    class_block_path, block_path
+                );
+                tokio::fs::write(&class_block_path, ser_block).await?;
+                // replace an older copy of the block
+                let _ = tokio::fs::remove_file(&block_path).await;
+                tokio::fs::symlink(&class_block_path, &block_path).await?;
+                Ok(Some(class))
+            }
+            None => {
+                debug!("Will write the received block to {:?}", block_path);
+                tokio::fs::write(block_path, ser_block).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Remove a block from `block_dir`, and from its storage class if it is a link to it
+    pub(crate) async fn remove_block(&self, block_dir: &Path, block_hash: &str) -> Result<()> {
+        let block_path = block_dir.join(block_hash);
+        if tokio::fs::symlink_metadata(&block_path)
+            .await?
+            .file_type()
+            .is_symlink()
+        {
+            let class_block_path = tokio::fs::read_link(&block_path).await?;
+            debug!("Removing {:?}", class_block_path);
+            if let Err(e) = tokio::fs::remove_file(&class_block_path).await {
+                warn!("Could not remove {:?}: {}", class_block_path, e);
+            }
+        }
+        debug!("Removing {:?}", block_path);
+        tokio::fs::remove_file(&block_path).await?;
+        Ok(())
+    }
+
+    /// Remove the blocks of a file placed on any of the storage classes
+    pub(crate) async fn remove_file(&self, file_hash: &str) -> Result<()> {
+        for class_path in self.paths.values() {
+            let class_dir = class_path.join(file_hash);
+            if tokio::fs::try_exists(&class_dir).await? {
+                debug!("Removing {:?}", class_dir);
+                tokio::fs::remove_dir_all(&class_dir).await?;
+            }
+        }
+        Ok(())
+    }
+}
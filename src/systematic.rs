@@ -0,0 +1,55 @@
+//! Systematic Reed-Solomon encoding, where the first `k` blocks of a file hold the chunks of the file as they are.
+//!
+//! The encoding matrix is the Vandermonde matrix of the evaluation points, multiplied by the inverse of its first
+//! `k` columns, so its first `k` columns are the identity while any `k` of its columns still form an invertible
+//! matrix. A node holding the `k` systematic blocks of a file reads the file from them, without solving the linear
+//! system of the other blocks.
+
+use anyhow::Result;
+use ark_ff::PrimeField;
+use komodo::algebra::linalg::Matrix;
+use komodo::fec::Shard;
+
+use crate::packing::{self, Packing};
+
+/// The systematic encoding matrix over the `n` evaluation points, with `k` rows
+pub(crate) fn encoding_matrix<F: PrimeField>(points: &[F], k: usize) -> Result<Matrix<F>> {
+    let vandermonde = Matrix::vandermonde(points, k)?;
+    let source_columns = Matrix::vandermonde(&points[..k.min(points.len())], k)?;
+    Ok(source_columns.invert()?.mul(&vandermonde)?)
+}
+
+/// The position of the chunk of the file held as is by a block, `None` if the block mixes several chunks
+pub(crate) fn source_chunk<F: PrimeField>(linear_combination: &[F]) -> Option<usize> {
+    let mut non_zero = linear_combination
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !c.is_zero());
+    match (non_zero.next(), non_zero.next()) {
+        (Some((chunk, c)), None) if c.is_one() => Some(chunk),
+        _ => None,
+    }
+}
+
+/// The bytes of the file, packed with `packing`, when the shards hold all its `k` chunks as they are, `None` if the
+/// shards have to be decoded
+pub(crate) fn decode<F: PrimeField>(packing: Packing, shards: &[Shard<F>]) -> Option<Vec<u8>> {
+    let first = shards.first()?;
+    let k = first.k as usize;
+    let mut chunks: Vec<Option<&Shard<F>>> = vec![None; k];
+    for shard in shards {
+        if let Some(chunk) = source_chunk(&shard.linear_combination).filter(|chunk| *chunk < k) {
+            chunks[chunk] = Some(shard);
+        }
+    }
+    let chunks: Vec<&Shard<F>> = chunks.into_iter().collect::<Option<_>>()?;
+    let rows = chunks.first()?.data.len();
+    if chunks.iter().any(|shard| shard.data.len() != rows) {
+        return None;
+    }
+    // the elements of the file are spread over the chunks one after the other
+    let elements: Vec<F> = (0..rows)
+        .flat_map(|row| chunks.iter().map(move |shard| shard.data[row]))
+        .collect();
+    Some(packing::unpack(packing, &elements, first.size))
+}
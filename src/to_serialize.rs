@@ -1,17 +1,86 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use axum::response::{IntoResponse, Json, Response};
 use libp2p::{swarm::NetworkInfo, Multiaddr, PeerId};
 use serde::ser::Serialize;
-
+use utoipa::ToSchema;
+
+use crate::bandwidth::BandwidthReport;
+use crate::block_quota::BlockCountInfo;
+use crate::file_announce::KnownFile;
+use crate::lifecycle::FileLifecycle;
+use crate::manifest::FileManifest;
+use crate::mirror::MirrorStatus;
+use crate::peer_probe::ProbeReport;
+use crate::peer_quota::PeerQuotasInfo;
+use crate::peer_reputation::PeerReputation;
+use crate::recent_events::RecentEvent;
+use crate::replication::{BlockLocation, GlobalBlockMap, ReplicationStatus};
+use crate::scrub::ScrubStatus;
 use crate::send_strategy::SendId;
+use crate::storage_class::{StorageClass, StorageClassUsage};
 use crate::{
-    commands::SerNetworkInfo, dragoon_swarm::BlockResponse, peer_block_info::PeerBlockInfo,
+    commands::{
+        AppendedSegment, DeleteFileOutcome, DialOutcome, EncodeFilesOutcome, EncodedFile,
+        GetFileOutcome, NodeInfo, ReannounceOutcome, SerIdentityRotation, SerNetworkInfo,
+        SerSendBlockTo, SerSendId, StopProvideOutcome,
+    },
+    dragoon_swarm::BlockResponse,
+    peer_block_info::PeerBlockInfo,
 };
 
 // can't implement Serialize for Json as those are a external Trait and Struct, so we need a wrapper
 pub(crate) struct JsonWrapper<T>(pub Json<T>);
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EnvelopeStatus {
+    Ok,
+    Error,
+}
+
+/// Why a request failed
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct EnvelopeError {
+    /// The kind of the error, the name of the [`crate::error::DragoonError`] in kebab-case
+    pub(crate) kind: String,
+    pub(crate) message: String,
+    /// Whether the same request may succeed if it is sent again later
+    pub(crate) retryable: bool,
+}
+
+/// The body of every JSON response of the http interface, `data` being set when the request succeeded and `error`
+/// when it failed
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct Envelope<T> {
+    pub(crate) status: EnvelopeStatus,
+    pub(crate) data: Option<T>,
+    pub(crate) error: Option<EnvelopeError>,
+}
+
+impl<T> Envelope<T> {
+    pub(crate) fn ok(data: T) -> Self {
+        Envelope {
+            status: EnvelopeStatus::Ok,
+            data: Some(data),
+            error: None,
+        }
+    }
+}
+
+impl Envelope<()> {
+    pub(crate) fn error(error: EnvelopeError) -> Self {
+        Envelope {
+            status: EnvelopeStatus::Error,
+            data: None,
+            error: Some(error),
+        }
+    }
+}
+
 pub(crate) trait ConvertSer {
     fn convert_ser(&self) -> impl Serialize;
 }
@@ -31,7 +100,7 @@ macro_rules! impl_Convert {
 }
 
 // impl convert for all the types that are already Serialize and thus just return themselves
-impl_Convert!(for u64, String, bool, &str, Vec<Multiaddr>, Vec<u8>, PeerBlockInfo, BlockResponse, PathBuf, usize);
+impl_Convert!(for u64, String, bool, &str, Vec<Multiaddr>, Vec<u8>, PeerBlockInfo, BlockResponse, PathBuf, usize, GetFileOutcome, HashMap<StorageClass, StorageClassUsage>, ReannounceOutcome, DialOutcome, BlockCountInfo, MirrorStatus, ProbeReport, PeerReputation, ReplicationStatus, GlobalBlockMap, BlockLocation, DeleteFileOutcome, StopProvideOutcome, FileLifecycle, FileManifest, KnownFile, PeerQuotasInfo, ScrubStatus, EncodedFile, AppendedSegment, EncodeFilesOutcome, NodeInfo, BandwidthReport, RecentEvent);
 
 impl ConvertSer for PeerId {
     fn convert_ser(&self) -> impl Serialize {
@@ -93,6 +162,8 @@ impl ConvertSer for Option<BlockResponse> {
                 file_hash: "None".to_string(),
                 block_hash: "None".to_string(),
                 block_data: vec![],
+                correlation_id: None,
+                busy: false,
             },
         }
     }
@@ -100,23 +171,21 @@ impl ConvertSer for Option<BlockResponse> {
 
 impl ConvertSer for SendId {
     fn convert_ser(&self) -> impl Serialize {
-        let SendId {
-            peer_id,
-            file_hash,
-            block_hash,
-        } = self;
-        (peer_id.to_base58(), file_hash, block_hash)
+        SerSendId::new(self)
     }
 }
 
-impl<U, V> ConvertSer for (U, V)
-where
-    U: ConvertSer,
-    V: ConvertSer,
-{
+/// The result of a send-block-to
+impl ConvertSer for (bool, SendId) {
+    fn convert_ser(&self) -> impl Serialize {
+        SerSendBlockTo::new(self)
+    }
+}
+
+/// The old and new peer ids of a rotate-identity
+impl ConvertSer for (PeerId, PeerId) {
     fn convert_ser(&self) -> impl Serialize {
-        let (u, v) = self;
-        (u.convert_ser(), v.convert_ser())
+        SerIdentityRotation::new(self)
     }
 }
 
@@ -126,7 +195,7 @@ where
 {
     fn into_response(self) -> Response {
         // Json already has impl<T> IntoResponse for Json<T> where T: Serialize
-        // so we just need to extract the Json from the wrapper and use the into_response
-        self.0.into_response()
+        // so we just need to extract the value from the wrapper and put it in the envelope
+        Json(Envelope::ok(self.0 .0)).into_response()
     }
 }
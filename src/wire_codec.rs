@@ -0,0 +1,165 @@
+//! The encoding of the messages exchanged with the other nodes, chosen by the version of the protocol.
+//!
+//! The version 1 of the block and info exchanges encodes their messages in CBOR, and the version 1 of the send
+//! protocol its peer block info in JSON. The version 2 of the three of them encodes their messages with bincode,
+//! which writes the bytes of a block as they are where CBOR takes up to two bytes for each of them. A node offers
+//! the version 2 first and falls back to the version 1 with the nodes that do not know it yet.
+//!
+//! bincode does not describe the fields it writes, so all the fields of a message are always written, even the
+//! empty optional ones.
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::StreamProtocol;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+use std::marker::PhantomData;
+
+/// Maximum size of a request, as for the CBOR codec of libp2p
+const REQUEST_SIZE_MAXIMUM: u64 = 1024 * 1024;
+/// Maximum size of a response, as for the CBOR codec of libp2p
+const RESPONSE_SIZE_MAXIMUM: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WireFormat {
+    /// The version 1 of the send protocol
+    Json,
+    /// The version 1 of the block and info exchanges
+    Cbor,
+    /// The version 2 of all the exchanges
+    Bincode,
+}
+
+impl WireFormat {
+    /// The format of a request-response protocol, bincode for its version 2
+    fn of(protocol: &StreamProtocol) -> Self {
+        if protocol.as_ref().ends_with("/2") {
+            WireFormat::Bincode
+        } else {
+            WireFormat::Cbor
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize>(self, message: &T) -> io::Result<Vec<u8>> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self {
+            WireFormat::Json => serde_json::to_vec(message).map_err(|e| invalid(e.to_string())),
+            WireFormat::Cbor => {
+                cbor4ii::serde::to_vec(Vec::new(), message).map_err(|e| invalid(e.to_string()))
+            }
+            WireFormat::Bincode => bincode::serialize(message).map_err(|e| invalid(e.to_string())),
+        }
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> io::Result<T> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        match self {
+            WireFormat::Json => serde_json::from_slice(bytes).map_err(|e| invalid(e.to_string())),
+            WireFormat::Cbor => {
+                cbor4ii::serde::from_slice(bytes).map_err(|e| invalid(e.to_string()))
+            }
+            WireFormat::Bincode => bincode::deserialize(bytes).map_err(|e| invalid(e.to_string())),
+        }
+    }
+}
+
+/// The versions of a request-response protocol, the version 2 first so it is chosen when both nodes know it
+pub(crate) fn protocols(
+    version_1: &'static str,
+    version_2: &'static str,
+) -> [(StreamProtocol, ProtocolSupport); 2] {
+    [
+        (StreamProtocol::new(version_2), ProtocolSupport::Full),
+        (StreamProtocol::new(version_1), ProtocolSupport::Full),
+    ]
+}
+
+/// A request-response codec encoding the messages in the format of the version of the protocol
+pub(crate) struct Codec<Req, Resp> {
+    phantom: PhantomData<(Req, Resp)>,
+}
+
+impl<Req, Resp> Default for Codec<Req, Resp> {
+    fn default() -> Self {
+        Codec {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for Codec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+pub(crate) type Behaviour<Req, Resp> = request_response::Behaviour<Codec<Req, Resp>>;
+
+async fn read_message<T, M>(io: &mut T, protocol: &StreamProtocol, maximum: u64) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut bytes = Vec::new();
+    io.take(maximum).read_to_end(&mut bytes).await?;
+    WireFormat::of(protocol).decode(&bytes)
+}
+
+async fn write_message<T, M>(io: &mut T, protocol: &StreamProtocol, message: M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let bytes = WireFormat::of(protocol).encode(&message)?;
+    io.write_all(&bytes).await
+}
+
+#[async_trait]
+impl<Req, Resp> request_response::Codec for Codec<Req, Resp>
+where
+    Req: Send + Serialize + DeserializeOwned,
+    Resp: Send + Serialize + DeserializeOwned,
+{
+    type Protocol = StreamProtocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, protocol, REQUEST_SIZE_MAXIMUM).await
+    }
+
+    async fn read_response<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, protocol, RESPONSE_SIZE_MAXIMUM).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        protocol: &StreamProtocol,
+        io: &mut T,
+        request: Req,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, protocol, request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        protocol: &StreamProtocol,
+        io: &mut T,
+        response: Resp,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, protocol, response).await
+    }
+}